@@ -0,0 +1,46 @@
+/* C interface to the recursive dice roller, built with `--features ffi`.
+ * Regenerate with cbindgen (the command is in cbindgen.toml) after changing src/ffi.rs. */
+
+#ifndef RDR_H
+#define RDR_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+/**
+ * The outcome of rolling an expression. When `ok` is false, `total` is zero,
+ * `tree` holds the error message, and `json` is null.
+ */
+typedef struct RdrResult {
+  bool ok;
+  int32_t total;
+  /**
+   * The same tree the command line draws, without colors
+   */
+  char *tree;
+  /**
+   * The result document, as JSON
+   */
+  char *json;
+} RdrResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/**
+ * Parses and rolls a nul-terminated UTF-8 expression. Returns null only if
+ * `input` is null.
+ */
+RdrResult *rdr_evaluate(const char *input);
+
+/**
+ * Frees a result from `rdr_evaluate`, along with its strings
+ */
+void rdr_free_result(RdrResult *result);
+
+#ifdef __cplusplus
+}  /* extern "C" */
+#endif
+
+#endif  /* RDR_H */
@@ -0,0 +1,220 @@
+//! Named rolls. An alias maps a name to one or more expressions (separated by
+//! semicolons), and can be rolled on its own or dropped into the middle of
+//! another expression, e.g. `smite * 2`.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::collection::Collection;
+use crate::tokenize::Tokenizer;
+
+/// Names that would be ambiguous on the command line
+const RESERVED: &[&str] = &["alias", "roll", "help"];
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Aliases {
+    #[serde(default)]
+    aliases: BTreeMap<String, String>,
+    /// The `.dice` collection given with `--rolls`, expanded before any alias
+    #[serde(skip)]
+    rolls: Collection,
+}
+
+impl Aliases {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(Aliases::default());
+        }
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        toml::from_str(&contents).map_err(|e| format!("Could not parse {}: {e}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {e}", parent.display()))?;
+        }
+        let contents = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    pub fn add(&mut self, name: &str, expression: &str) -> Result<(), String> {
+        validate_name(name)?;
+        self.aliases
+            .insert(name.to_string(), expression.trim().to_string());
+        Ok(())
+    }
+
+    /// Adds one of a library's macros, unless an alias already has its name.
+    /// Unlike [`Aliases::add`], the name isn't checked, so library macros can
+    /// have colons in theirs and never clash with anyone's own aliases.
+    pub fn define(&mut self, name: &str, expression: &str) {
+        self.aliases
+            .entry(name.to_string())
+            .or_insert_with(|| expression.to_string());
+    }
+
+    /// Makes a collection's rolls and macros usable alongside the aliases
+    pub fn with_rolls(mut self, rolls: Collection) -> Self {
+        self.rolls = rolls;
+        self
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        match self.aliases.remove(name) {
+            Some(_) => Ok(()),
+            None => Err(format!("No alias named '{name}'")),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.aliases.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    /// Replaces every alias in the input with its expression. An alias on its
+    /// own is swapped out verbatim, so it may hold several expressions; inside
+    /// a larger expression it's parenthesized and may only hold one.
+    pub fn expand(&self, input: &str) -> Result<String, String> {
+        self.expand_nested(&self.rolls.expand(input)?, &mut Vec::new())
+    }
+
+    fn expand_nested(&self, input: &str, stack: &mut Vec<String>) -> Result<String, String> {
+        if let Some(expression) = self.aliases.get(input.trim()) {
+            return self.substitute(input.trim(), expression, stack);
+        }
+        let mut output = String::new();
+        let mut word = String::new();
+        for c in input.chars().chain(std::iter::once('\0')) {
+            // a colon only ever appears in the middle of a library macro's
+            // name, like `check:athletics`
+            if c.is_ascii_alphanumeric() || c == '_' || (c == ':' && !word.is_empty()) {
+                word.push(c);
+                continue;
+            }
+            // `@name` is a variable and `$name` an earlier result, never an
+            // alias
+            let alias = match output.ends_with(['@', '$']) {
+                true => None,
+                false => self.aliases.get(&word),
+            };
+            match alias {
+                Some(expression) => {
+                    let expanded = self.substitute(&word, expression, stack)?;
+                    if expanded.contains(';') {
+                        return Err(format!(
+                            "Alias '{word}' holds several expressions, so it can't be used inside another one"
+                        ));
+                    }
+                    output.push_str(&format!("({expanded})"));
+                }
+                None => output.push_str(&word),
+            }
+            word.clear();
+            if c != '\0' {
+                output.push(c);
+            }
+        }
+        Ok(output)
+    }
+
+    fn substitute(
+        &self,
+        name: &str,
+        expression: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<String, String> {
+        if stack.iter().any(|n| n == name) {
+            return Err(format!("Alias '{name}' refers to itself"));
+        }
+        stack.push(name.to_string());
+        let expanded = self.expand_nested(expression, stack);
+        stack.pop();
+        expanded
+    }
+}
+
+fn validate_name(name: &str) -> Result<(), String> {
+    let well_formed = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !well_formed {
+        return Err(format!(
+            "'{name}' is not a valid alias name; use letters, digits, and underscores"
+        ));
+    }
+    if RESERVED.contains(&name) {
+        return Err(format!(
+            "'{name}' is reserved and can't be used as an alias"
+        ));
+    }
+    // if the name is perfectly good dice notation (like "d" or "kh") then we'd
+    // never be able to tell the two apart
+    if Tokenizer::new(name).all(|token| token.is_ok()) {
+        return Err(format!("'{name}' looks like dice notation"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alias::Aliases;
+
+    fn aliases() -> Aliases {
+        let mut aliases = Aliases::default();
+        aliases.add("smite", "d20+9; 3d8+2d6").unwrap();
+        aliases.add("str", "3").unwrap();
+        aliases.add("longsword", "d8 + str").unwrap();
+        aliases
+    }
+
+    #[test]
+    fn alias_on_its_own() {
+        assert_eq!("d20+9; 3d8+2d6", aliases().expand("smite").unwrap());
+    }
+
+    #[test]
+    fn alias_inside_expression() {
+        assert_eq!("2d20k1 + (3)", aliases().expand("2d20k1 + str").unwrap());
+        assert_eq!("(d8 + (3)) * 2", aliases().expand("longsword * 2").unwrap());
+    }
+
+    #[test]
+    fn dice_are_not_aliases() {
+        assert_eq!("4d6kh3 + d20", aliases().expand("4d6kh3 + d20").unwrap());
+    }
+
+    #[test]
+    fn variables_are_not_aliases() {
+        assert_eq!("d20 + @str", aliases().expand("d20 + @str").unwrap());
+        assert_eq!("$str + 1", aliases().expand("$str + 1").unwrap());
+    }
+
+    #[test]
+    fn multiple_expressions_inside_expression() {
+        assert!(aliases().expand("smite + 2").is_err());
+    }
+
+    #[test]
+    fn recursive_alias() {
+        let mut aliases = aliases();
+        aliases.add("loop", "d6 + loop").unwrap();
+        assert!(aliases.expand("loop").is_err());
+    }
+
+    #[test]
+    fn library_macros() {
+        let mut aliases = aliases();
+        aliases.define("check:str", "d20 + @str");
+        assert_eq!("d20 + @str", aliases.expand("check:str").unwrap());
+        assert_eq!("(d20 + @str) + 1", aliases.expand("check:str + 1").unwrap());
+        assert!(aliases.add("check:dex", "d20").is_err());
+    }
+
+    #[test]
+    fn invalid_names() {
+        let mut aliases = Aliases::default();
+        for name in ["d", "kh", "roll", "2fast", "has space", ""] {
+            assert!(aliases.add(name, "d6").is_err(), "{name} was accepted");
+        }
+    }
+}
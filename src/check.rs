@@ -0,0 +1,229 @@
+//! `rdr check`, which reads expressions, dice scripts, and `.dice`
+//! collections the way they'd be rolled, without rolling any of them, and
+//! gathers up everything wrong with them. Anything that wouldn't parse is an
+//! error, and anything [lint](crate::lint) has doubts about is a warning.
+
+use std::fmt::{self, Display};
+
+use recursive_dice_roller::collection::Collection;
+
+use crate::alias::Aliases;
+use crate::lint;
+use crate::parse::{parse, split};
+use crate::results::Results;
+use crate::script::{self, Statement};
+use crate::vars::Variables;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// One thing wrong with what was checked
+#[derive(Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// Where it was found, like `attacks.txt:3`
+    pub place: String,
+    pub severity: Severity,
+    pub message: String,
+    /// The expression that wouldn't parse, as it was after any aliases were
+    /// expanded, and the character the problem was noticed at
+    pub span: Option<(String, usize)>,
+}
+
+#[derive(Debug, Default)]
+pub struct Checker {
+    pub findings: Vec<Finding>,
+}
+
+impl Checker {
+    pub fn errors(&self) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.findings.len() - self.errors()
+    }
+
+    fn error(&mut self, place: &str, message: impl Into<String>) {
+        self.findings.push(Finding {
+            place: place.to_string(),
+            severity: Severity::Error,
+            message: message.into(),
+            span: None,
+        });
+    }
+
+    /// Checks one line of input, which may hold several expressions and use
+    /// aliases, variables, and earlier results
+    pub fn expression(&mut self, place: &str, input: &str, aliases: &Aliases) {
+        let expanded = aliases
+            .expand(input)
+            .and_then(|expanded| Variables::placeholders(&expanded));
+        let expanded = match expanded {
+            Ok(expanded) => expanded,
+            Err(message) => return self.error(place, message),
+        };
+        let pieces = match split(&expanded) {
+            Ok(pieces) => pieces,
+            Err(error) => return self.error(place, error.to_string()),
+        };
+        for piece in pieces {
+            let piece = match Results::placeholders(piece.trim()) {
+                Ok(piece) => piece,
+                Err(message) => {
+                    self.error(place, message);
+                    continue;
+                }
+            };
+            match parse(&piece) {
+                Ok(exp) => {
+                    for warning in lint::lint(&exp) {
+                        self.findings.push(Finding {
+                            place: place.to_string(),
+                            severity: Severity::Warning,
+                            message: warning,
+                            span: None,
+                        });
+                    }
+                }
+                Err(error) => self.findings.push(Finding {
+                    place: place.to_string(),
+                    severity: Severity::Error,
+                    message: error.to_string(),
+                    span: error.position().map(|position| (piece.clone(), position)),
+                }),
+            }
+        }
+    }
+
+    /// Checks every line of a dice script, with the aliases it defines along
+    /// the way
+    pub fn script(&mut self, path: &str, source: &str, aliases: &Aliases) {
+        let lines = match script::parse_script(source) {
+            Ok(lines) => lines,
+            Err(message) => return self.error(path, message),
+        };
+        let mut aliases = aliases.clone();
+        for line in lines {
+            let place = format!("{path}:{}", line.number);
+            match line.statement {
+                Statement::Variable { assignment } => {
+                    if let Err(message) = Variables::default().assign(&assignment) {
+                        self.error(&place, message);
+                    }
+                }
+                Statement::Alias { name, expression } => {
+                    self.expression(&place, &expression, &aliases);
+                    if let Err(message) = aliases.add(&name, &expression) {
+                        self.error(&place, message);
+                    }
+                }
+                Statement::Roll { expression, .. } => {
+                    self.expression(&place, &expression, &aliases)
+                }
+            }
+        }
+    }
+
+    /// Checks every roll in a `.dice` collection, calling each macro with
+    /// zeros for its arguments
+    pub fn collection(&mut self, path: &str, source: &str, aliases: &Aliases) {
+        let collection = match Collection::parse(source) {
+            Ok(collection) => collection,
+            Err(error) => return self.error(path, error.to_string()),
+        };
+        for entry in collection.entries() {
+            let place = match &entry.system {
+                Some(system) => format!("{path} [{system}] {}", entry.name),
+                None => format!("{path} {}", entry.name),
+            };
+            let call = match entry.parameters.len() {
+                0 => entry.name.clone(),
+                n => format!("{}({})", entry.name, vec!["0"; n].join(", ")),
+            };
+            match collection.select(entry.system.as_deref()).expand(&call) {
+                Ok(expanded) => self.expression(&place, &expanded, aliases),
+                Err(error) => self.error(&place, error.to_string()),
+            }
+        }
+    }
+}
+
+/// Like a compiler's messages, with a caret under the problem when there's
+/// one to point at
+impl Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        writeln!(f, "{}: {severity}: {}", self.place, self.message)?;
+        if let Some((expression, position)) = &self.span {
+            writeln!(f, "    {expression}")?;
+            writeln!(f, "    {}^", " ".repeat(*position))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Checker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for finding in &self.findings {
+            write!(f, "{finding}")?;
+        }
+        match (self.errors(), self.warnings()) {
+            (0, 0) => writeln!(f, "no problems found"),
+            (errors, warnings) => writeln!(
+                f,
+                "{errors} error{}, {warnings} warning{}",
+                if errors == 1 { "" } else { "s" },
+                if warnings == 1 { "" } else { "s" },
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alias::Aliases;
+    use crate::check::*;
+
+    #[test]
+    fn expressions() {
+        let mut checker = Checker::default();
+        checker.expression("'4d6k3'", "4d6k3; d20 + @str + $1", &Aliases::default());
+        assert!(checker.findings.is_empty());
+
+        checker.expression("'2d20k3'", "2d20k3", &Aliases::default());
+        checker.expression("'d20 + x'", "d20 + x", &Aliases::default());
+        assert_eq!((1, 1), (checker.errors(), checker.warnings()));
+        let error = &checker.findings[1];
+        assert_eq!(Some(("d20 + x".to_string(), 6)), error.span);
+        assert!(error.to_string().ends_with("\n    d20 + x\n          ^\n"));
+        assert!(checker.to_string().ends_with("1 error, 1 warning\n"));
+    }
+
+    #[test]
+    fn scripts_and_collections() {
+        let mut checker = Checker::default();
+        let script = "@prof = 2\nalias hit = d20 + @prof\nAttack: hit\nDamage: 2d\n";
+        checker.script("encounter.txt", script, &Aliases::default());
+        assert_eq!(1, checker.errors());
+        assert_eq!("encounter.txt:4", checker.findings[0].place);
+
+        let mut checker = Checker::default();
+        let rolls = "smite(level) = (@level + 1)d8\n[5e]\nattack = d20 +\n";
+        checker.collection("rolls.dice", rolls, &Aliases::default());
+        assert_eq!(1, checker.errors());
+        assert_eq!("rolls.dice [5e] attack", checker.findings[0].place);
+
+        let mut checker = Checker::default();
+        checker.collection("rolls.dice", "[5e\n", &Aliases::default());
+        assert!(checker.findings[0].message.starts_with("line 1:"));
+    }
+}
@@ -0,0 +1,80 @@
+//! Putting text on the system clipboard. Rather than link against every
+//! platform's clipboard API, we hand the text to whichever clipboard tool is
+//! installed, and fall back to asking the terminal to do it.
+
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+/// The tools we know how to use, in order of preference
+const TOOLS: &[&[&str]] = &[
+    &["pbcopy"],
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["clip.exe"],
+];
+
+pub fn copy(text: &str) -> Result<(), String> {
+    for tool in TOOLS {
+        if pipe_to(tool, text).is_ok() {
+            return Ok(());
+        }
+    }
+    // most modern terminals (even over ssh) understand OSC 52, which sets the
+    // clipboard from an escape sequence
+    let mut stdout = io::stdout();
+    if stdout.is_terminal() {
+        write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))
+            .and_then(|_| stdout.flush())
+            .map_err(|e| format!("Could not copy to the clipboard: {e}"))?;
+        return Ok(());
+    }
+    Err("Could not find a way to copy to the clipboard".to_string())
+}
+
+fn pipe_to(tool: &[&str], text: &str) -> io::Result<()> {
+    let mut child = Command::new(tool[0])
+        .args(&tool[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes())?;
+    }
+    match child.wait()?.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("{} failed", tool[0]))),
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clipboard::base64;
+
+    #[test]
+    fn encoding() {
+        assert_eq!("", base64(b""));
+        assert_eq!("MjI=", base64(b"22"));
+        assert_eq!("MTd8NA==", base64(b"17|4"));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+    }
+}
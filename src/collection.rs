@@ -0,0 +1,349 @@
+//! Collections of saved rolls, kept in `.dice` files that every front-end
+//! reads the same way:
+//!
+//! ```text
+//! # comments run from a '#' to the end of the line
+//! fireball = 8d6
+//!
+//! # a macro names its parameters, uses them like variables, and is called
+//! # with its arguments in parentheses, like smite(2)
+//! smite(level) = (@level + 1)d8
+//!
+//! # a system in brackets tags the rolls after it, up to the next tag, and
+//! # [*] goes back to rolls for every system
+//! [5e]
+//! attack = d20 + @str + @prof
+//! ```
+//!
+//! A name can be given once per system, and a system's own roll wins over
+//! the untagged one of the same name.
+//!
+//! ```
+//! use recursive_dice_roller::collection::Collection;
+//!
+//! let rolls = Collection::parse("smite(level) = (@level + 1)d8").unwrap();
+//! assert_eq!("(((2) + 1)d8) + 4", rolls.expand("smite(2) + 4").unwrap());
+//! ```
+
+use crate::error::Error;
+use crate::tokenize::Tokenizer;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Entry {
+    pub name: String,
+    /// Empty for a plain roll
+    pub parameters: Vec<String>,
+    pub expression: String,
+    pub system: Option<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Collection {
+    entries: Vec<Entry>,
+}
+
+impl Collection {
+    pub fn parse(source: &str) -> Result<Collection, Error> {
+        let mut collection = Collection::default();
+        let mut system = None;
+        for (i, line) in source.lines().enumerate() {
+            let at_line =
+                |message: String| Error::Input(format!("line {}: {message}", i + 1).into());
+            let line = line.split_once('#').map_or(line, |(code, _)| code).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(tag) = line.strip_prefix('[') {
+                let tag = tag
+                    .strip_suffix(']')
+                    .ok_or_else(|| at_line("a system tag should look like [5e]".to_string()))?
+                    .trim();
+                system = match tag {
+                    "*" => None,
+                    "" => {
+                        return Err(at_line(
+                            "the system tag is empty; use [*] for every system".to_string(),
+                        ))
+                    }
+                    tag => Some(tag.to_string()),
+                };
+                continue;
+            }
+            let entry = entry(line, system.clone()).map_err(at_line)?;
+            if collection
+                .entries
+                .iter()
+                .any(|other| other.name == entry.name && other.system == entry.system)
+            {
+                return Err(at_line(format!("'{}' is already defined", entry.name)));
+            }
+            collection.entries.push(entry);
+        }
+        Ok(collection)
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The rolls usable under a system: the untagged ones, and the system's
+    /// own, which take the place of untagged ones with the same name. With no
+    /// system, every roll is usable, the untagged ones first.
+    pub fn select(&self, system: Option<&str>) -> Collection {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut candidates = self.entries.iter().collect::<Vec<_>>();
+        candidates.sort_by_key(|entry| entry.system.is_some());
+        for entry in candidates {
+            let usable = match (system, entry.system.as_deref()) {
+                (_, None) | (None, _) => true,
+                (Some(system), Some(tag)) => system == tag,
+            };
+            if !usable {
+                continue;
+            }
+            match entries.iter_mut().find(|other| other.name == entry.name) {
+                Some(other) if system.is_some() => *other = entry.clone(),
+                Some(_) => {}
+                None => entries.push(entry.clone()),
+            }
+        }
+        Collection { entries }
+    }
+
+    fn get(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Replaces every saved roll in the input with its (parenthesized)
+    /// expression, and every macro call with its expression for those
+    /// arguments
+    pub fn expand(&self, input: &str) -> Result<String, Error> {
+        self.expand_nested(input, &mut Vec::new())
+            .map_err(|e| Error::Input(e.into()))
+    }
+
+    fn expand_nested(&self, input: &str, stack: &mut Vec<String>) -> Result<String, String> {
+        let mut output = String::new();
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            if !c.is_ascii_alphabetic()
+                || output.ends_with(|c: char| is_name_char(c) || c == '@' || c == '$')
+            {
+                output.push(c);
+                continue;
+            }
+            let mut word = c.to_string();
+            while let Some(c) = chars.next_if(|c| is_name_char(*c)) {
+                word.push(c);
+            }
+            let Some(entry) = self.get(&word) else {
+                output.push_str(&word);
+                continue;
+            };
+            let arguments = match chars.peek() {
+                Some('(') if !entry.parameters.is_empty() => {
+                    chars.next();
+                    arguments(&mut chars)
+                        .ok_or(format!("The call to '{word}' is missing its ')'"))?
+                }
+                _ => Vec::new(),
+            };
+            if arguments.len() != entry.parameters.len() {
+                return Err(format!(
+                    "'{word}' takes {} argument(s), like {word}({}), but was given {}",
+                    entry.parameters.len(),
+                    entry.parameters.join(", "),
+                    arguments.len()
+                ));
+            }
+            if stack.contains(&word) {
+                return Err(format!("'{word}' refers to itself"));
+            }
+            let mut body = entry.expression.clone();
+            for (parameter, argument) in entry.parameters.iter().zip(&arguments) {
+                let argument = self.expand_nested(argument, stack)?;
+                body = replace_variable(&body, parameter, &format!("({})", argument.trim()));
+            }
+            stack.push(word);
+            let expanded = self.expand_nested(&body, stack)?;
+            stack.pop();
+            output.push_str(&format!("({expanded})"));
+        }
+        Ok(output)
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Reads a line like `smite(level) = (@level + 1)d8`
+fn entry(line: &str, system: Option<String>) -> Result<Entry, String> {
+    let (head, expression) = line
+        .split_once('=')
+        .ok_or("a roll should look like `name = expression`")?;
+    let expression = expression.trim();
+    if expression.is_empty() {
+        return Err("there's nothing to roll after the '='".to_string());
+    }
+    let head = head.trim();
+    let (name, parameters) = match head.split_once('(') {
+        Some((name, parameters)) => {
+            let parameters = parameters
+                .trim()
+                .strip_suffix(')')
+                .ok_or("a macro's parameters should look like `name(a, b)`")?;
+            let parameters: Vec<String> = parameters
+                .split(',')
+                .map(|p| p.trim().to_string())
+                .collect();
+            for parameter in &parameters {
+                if !is_name(parameter) {
+                    return Err(format!("'{parameter}' is not a valid parameter name"));
+                }
+            }
+            (name.trim(), parameters)
+        }
+        None => (head, Vec::new()),
+    };
+    if !is_name(name) {
+        return Err(format!(
+            "'{name}' is not a valid name; use letters, digits, and underscores"
+        ));
+    }
+    // a name that is itself dice notation, like `d` or `kh`, could never be
+    // told apart from a roll
+    if Tokenizer::new(name).all(|token| token.is_ok()) {
+        return Err(format!("'{name}' looks like dice notation"));
+    }
+    Ok(Entry {
+        name: name.to_string(),
+        parameters,
+        expression: expression.to_string(),
+        system,
+    })
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic()) && name.chars().all(is_name_char)
+}
+
+/// The comma-separated arguments of a call, read up to its closing
+/// parenthesis
+fn arguments(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Vec<String>> {
+    let mut arguments = vec![String::new()];
+    let mut depth = 0;
+    for c in chars.by_ref() {
+        match c {
+            ')' if depth == 0 => {
+                return match arguments.as_slice() {
+                    [only] if only.trim().is_empty() => Some(Vec::new()),
+                    _ => Some(arguments),
+                };
+            }
+            ',' if depth == 0 => {
+                arguments.push(String::new());
+                continue;
+            }
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        arguments
+            .last_mut()
+            .expect("there's always an argument")
+            .push(c);
+    }
+    None
+}
+
+/// Replaces `@name` (and not `@names`) with the value
+fn replace_variable(body: &str, name: &str, value: &str) -> String {
+    let mut output = String::new();
+    let mut rest = body;
+    let pattern = format!("@{name}");
+    while let Some(at) = rest.find(&pattern) {
+        let after = &rest[at + pattern.len()..];
+        output.push_str(&rest[..at]);
+        match after.starts_with(is_name_char) {
+            true => output.push_str(&pattern),
+            false => output.push_str(value),
+        }
+        rest = after;
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collection::*;
+
+    const ROLLS: &str = "\
+# every system
+fireball = 8d6
+smite(level) = (@level + 1)d8 # with a comment
+sneak(dice, bonus) = (@dice)d6 + @bonus + @bonus_extra
+
+[5e]
+attack = d20 + @str + @prof
+fireball = 8d6 + 0
+
+[*]
+volley = attack + attack
+";
+
+    #[test]
+    fn entries() {
+        let rolls = Collection::parse(ROLLS).unwrap();
+        assert_eq!(6, rolls.entries().len());
+        assert_eq!(vec!["level"], rolls.entries()[1].parameters);
+        assert_eq!(Some("5e"), rolls.entries()[3].system.as_deref());
+        assert_eq!(None, rolls.entries()[5].system);
+        for bad in ["d = 1", "x =", "[5e", "f(1) = 2", "a = 1\na = 2", "[]"] {
+            assert!(Collection::parse(bad).is_err(), "{bad} was accepted");
+        }
+    }
+
+    #[test]
+    fn expansion() {
+        let rolls = Collection::parse(ROLLS).unwrap().select(Some("5e"));
+        assert_eq!("(8d6 + 0) + 3", rolls.expand("fireball + 3").unwrap());
+        assert_eq!("(((2) + 1)d8)", rolls.expand("smite(2)").unwrap());
+        assert_eq!(
+            "(((1d4))d6 + (3) + @bonus_extra)",
+            rolls.expand("sneak(1d4, 3)").unwrap()
+        );
+        assert_eq!(
+            "((d20 + @str + @prof) + (d20 + @str + @prof))",
+            rolls.expand("volley").unwrap()
+        );
+        // 'fireball' here is a variable, and 'dd6' not a name at all
+        assert_eq!(
+            "@fireball + 2dd6",
+            rolls.expand("@fireball + 2dd6").unwrap()
+        );
+        assert!(rolls.expand("smite(1, 2)").is_err());
+        assert!(rolls.expand("smite(1").is_err());
+    }
+
+    #[test]
+    fn systems() {
+        let rolls = Collection::parse(ROLLS).unwrap();
+        assert_eq!("(8d6)", rolls.select(None).expand("fireball").unwrap());
+        assert_eq!(
+            "(8d6)",
+            rolls.select(Some("pf2e")).expand("fireball").unwrap()
+        );
+        assert_eq!(
+            "attack",
+            rolls.select(Some("pf2e")).expand("attack").unwrap()
+        );
+    }
+
+    #[test]
+    fn recursion() {
+        let rolls = Collection::parse("a = b + 1\nb = a").unwrap();
+        assert!(rolls.expand("a").is_err());
+    }
+}
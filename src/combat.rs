@@ -0,0 +1,197 @@
+//! Helpers for the rolls that come up over and over in a fight, where the
+//! result of one roll decides whether (and how) to make the next.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::eval::{Exp, Int, Keep, Op, Repeat, Roll, Value};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Outcome {
+    Miss,
+    Hit,
+    Critical,
+}
+
+impl Outcome {
+    /// A natural 1 always misses, and a natural `crit_on` or better always
+    /// hits (critically). Otherwise the attack needs to meet the target's AC.
+    pub fn of(total: Int, natural: Option<i32>, ac: Int, crit_on: i32) -> Outcome {
+        match natural {
+            Some(1) => Outcome::Miss,
+            Some(natural) if natural >= crit_on => Outcome::Critical,
+            _ if total >= ac => Outcome::Hit,
+            _ => Outcome::Miss,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Outcome::Miss => "miss",
+            Outcome::Hit => "hit",
+            Outcome::Critical => "critical hit",
+        }
+    }
+}
+
+/// One target's saving throw against an area effect
+#[derive(Debug, PartialEq, Eq)]
+pub struct Save {
+    pub roll: Int,
+    pub saved: bool,
+    pub damage: Int,
+}
+
+impl Save {
+    /// A successful save takes half damage (rounded down) if the effect
+    /// allows it, and none otherwise
+    pub fn new(roll: Int, dc: Int, damage: Int, half_on_save: bool) -> Save {
+        let saved = roll >= dc;
+        let damage = match (saved, half_on_save) {
+            (false, _) => damage,
+            (true, true) => damage / 2,
+            (true, false) => 0,
+        };
+        Save {
+            roll,
+            saved,
+            damage,
+        }
+    }
+}
+
+/// Lines up every target's save, followed by the total damage dealt
+pub fn table(saves: &[Save]) -> String {
+    let mut output = String::new();
+    writeln!(output, "target  save  result  damage").unwrap();
+    for (i, save) in saves.iter().enumerate() {
+        let result = if save.saved { "saved" } else { "failed" };
+        writeln!(
+            output,
+            "{:>6}  {:>4}  {result:<6}  {:>6}",
+            i + 1,
+            save.roll,
+            save.damage
+        )
+        .unwrap();
+    }
+    let total = saves.iter().map(|save| save.damage).sum::<Int>();
+    writeln!(output, "total damage: {total}").unwrap();
+    output
+}
+
+/// The face of the first d20 that counted towards the total, if any did. With
+/// advantage that's the higher of the two, which is the one that matters.
+pub fn natural_d20(value: &Value) -> Option<i32> {
+    match value {
+        Value::Const(_) => None,
+        Value::Rolled(rolled) => {
+            if rolled.sides.value().abs() == 20 {
+                if let Some(&die) = rolled.kept.kept().first() {
+                    return Some(die);
+                }
+            }
+            natural_d20(&rolled.dice).or_else(|| natural_d20(&rolled.sides))
+        }
+        Value::Op { values, .. } => values.iter().find_map(natural_d20),
+        Value::Repeated { pick, values } => values
+            .iter()
+            .zip(pick.counted(values))
+            .filter(|(_, counted)| *counted)
+            .find_map(|(value, _)| natural_d20(value)),
+    }
+}
+
+/// Rolls twice as many of every die in the expression, for a critical hit.
+/// Modifiers are left alone, so `1d8+4` becomes `2d8+4`.
+pub fn double_dice(exp: &Exp) -> Exp {
+    match exp {
+        Exp::Const(_) => exp.clone(),
+        Exp::Op(op) => {
+            let arguments = op.arguments.borrow().iter().map(double_dice).collect();
+            Exp::Op(Op {
+                operation: op.operation.clone(),
+                arguments: Rc::new(RefCell::new(arguments)),
+            })
+        }
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            let keep = match &roll.keep {
+                Keep::Highest(count) => Keep::Highest(doubled(count)),
+                Keep::Lowest(count) => Keep::Lowest(doubled(count)),
+                Keep::DropLowest(count) => Keep::DropLowest(doubled(count)),
+                Keep::DropHighest(count) => Keep::DropHighest(doubled(count)),
+                // all of twice the dice, or half of them, needs no doubling
+                keep => keep.clone(),
+            };
+            Exp::roll(Roll {
+                dice: doubled(&roll.dice),
+                sides: roll.sides.clone(),
+                keep,
+                explode: roll.explode.clone(),
+                reroll: roll.reroll.clone(),
+            })
+        }
+        Exp::Repeat(repeat) => Exp::Repeat(Repeat {
+            exp: Box::new(double_dice(&repeat.exp)),
+            ..repeat.clone()
+        }),
+    }
+}
+
+fn doubled(count: &Exp) -> Exp {
+    match count {
+        Exp::Const(n) => Exp::Const(n * 2),
+        other => Exp::mul(VecDeque::from([other.clone(), Exp::Const(2)])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::combat::*;
+    use crate::parse::parse;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn outcomes() {
+        assert_eq!(Outcome::Hit, Outcome::of(16, Some(9), 16, 20));
+        assert_eq!(Outcome::Miss, Outcome::of(15, Some(8), 16, 20));
+        assert_eq!(Outcome::Miss, Outcome::of(30, Some(1), 16, 20));
+        assert_eq!(Outcome::Critical, Outcome::of(10, Some(20), 30, 20));
+        assert_eq!(Outcome::Critical, Outcome::of(26, Some(19), 16, 19));
+    }
+
+    #[test]
+    fn saving_throws() {
+        let saves = [
+            Save::new(15, 15, 27, true),
+            Save::new(14, 15, 27, true),
+            Save::new(20, 15, 27, false),
+        ];
+        assert_eq!(
+            vec![13, 27, 0],
+            saves.iter().map(|s| s.damage).collect::<Vec<_>>()
+        );
+        assert!(table(&saves).ends_with("total damage: 40\n"));
+    }
+
+    #[test]
+    fn natural_rolls() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let value = parse("2d20kh1 + 7").unwrap().evaluate(&mut rng);
+        let natural = natural_d20(&value).map(|natural| natural as Int);
+        assert_eq!(Some(value.value() - 7), natural);
+        assert_eq!(
+            None,
+            natural_d20(&parse("d8 + 7").unwrap().evaluate(&mut rng))
+        );
+    }
+
+    #[test]
+    fn doubling() {
+        let doubled = double_dice(&parse("1d8 + 4 + 4d6kh3").unwrap());
+        assert_eq!(parse("2d8 + 4 + 8d6kh6").unwrap(), doubled);
+    }
+}
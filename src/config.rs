@@ -0,0 +1,27 @@
+//! Locating the files `rdr` keeps between runs. Everything lives in a single
+//! directory, which can be moved with the `RDR_CONFIG_DIR` environment
+//! variable.
+
+use std::{env, path::PathBuf};
+
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("RDR_CONFIG_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(dir).join("rdr"));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = env::var_os("APPDATA") {
+            return Some(PathBuf::from(dir).join("rdr"));
+        }
+    }
+    env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("rdr"))
+}
+
+/// The path to a file inside the config directory
+pub fn config_file(name: &str) -> Result<PathBuf, String> {
+    config_dir()
+        .map(|dir| dir.join(name))
+        .ok_or_else(|| "Could not determine where to store configuration files".to_string())
+}
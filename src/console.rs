@@ -1,23 +1,198 @@
-//! This is a separate module so we can exclude it from WASM compilation
+//! Everything that talks to the terminal directly. Under WASI there's no
+//! crossterm, so the output is plain text and nothing is ever paged.
 
+#[cfg(not(target_arch = "wasm32"))]
 use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
     style::{Attribute, Color, Print, SetAttribute, SetForegroundColor},
     QueueableCommand,
 };
 
-use std::io::{stdout, Stdout, Write};
+use std::env;
+use std::io::{stdin, stdout, IsTerminal, Write};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
 
-use crate::render::{HORIZONTAL_PIPE, RIGHT_FORK, VERTICAL_PIPE};
+use crate::error::Error;
+use crate::eval::Value;
+use crate::render::{self, RenderOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::render::{CRIT_MARK, FUMBLE_MARK, HORIZONTAL_PIPE, RIGHT_FORK, VERTICAL_PIPE};
 
+pub const THEMES: &[&str] = &[
+    "auto",
+    "dark",
+    "light",
+    "deuteranopia",
+    "protanopia",
+    "tritanopia",
+];
+
+/// The colors the output is painted in. Besides the ones for dark and light
+/// backgrounds, each keeps crits, fumbles, and dropped dice apart for one
+/// kind of color blindness; those also turn on the ▲ and ▼ marks, so nothing
+/// rests on color alone.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+    /// Red-green, with the greens muted
+    Deuteranopia,
+    /// Red-green, with the reds muted
+    Protanopia,
+    /// Blue-yellow
+    Tritanopia,
+}
+
+impl Theme {
+    /// The theme with this name. `auto` picks between `dark` and `light`
+    /// according to the terminal's background, or `RDR_THEME` if it's set.
+    pub fn named(name: &str) -> Result<Theme, String> {
+        match name {
+            "auto" => match env::var("RDR_THEME") {
+                Ok(theme) if theme != "auto" => {
+                    Theme::named(&theme).map_err(|e| format!("RDR_THEME is set, but {e}"))
+                }
+                _ => Ok(match background() {
+                    Some(Background::Light) => Theme::Light,
+                    _ => Theme::Dark,
+                }),
+            },
+            "dark" => Ok(Theme::Dark),
+            "light" => Ok(Theme::Light),
+            "deuteranopia" => Ok(Theme::Deuteranopia),
+            "protanopia" => Ok(Theme::Protanopia),
+            "tritanopia" => Ok(Theme::Tritanopia),
+            other => Err(format!(
+                "'{other}' is not a theme we have; try one of {}",
+                THEMES.join(", ")
+            )),
+        }
+    }
+
+    /// Whether the theme is meant for color blindness, and so should mark
+    /// crits and fumbles
+    pub fn marked(self) -> bool {
+        !matches!(self, Theme::Dark | Theme::Light)
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn palette(self) -> Palette {
+        match self {
+            Theme::Dark => Palette {
+                number: Color::Magenta,
+                operator: Color::DarkYellow,
+                word: Color::Green,
+                crit: Color::Green,
+                fumble: Color::Red,
+                dropped: Color::DarkGrey,
+            },
+            // the darker shades of the same, which hold up against white
+            Theme::Light => Palette {
+                number: Color::AnsiValue(90),
+                operator: Color::AnsiValue(130),
+                word: Color::AnsiValue(28),
+                crit: Color::AnsiValue(28),
+                fumble: Color::AnsiValue(124),
+                dropped: Color::AnsiValue(245),
+            },
+            // blue against orange, from the Okabe-Ito palette
+            Theme::Deuteranopia => Palette {
+                number: Color::AnsiValue(75),
+                operator: Color::AnsiValue(222),
+                word: Color::AnsiValue(39),
+                crit: Color::AnsiValue(33),
+                fumble: Color::AnsiValue(208),
+                dropped: Color::DarkGrey,
+            },
+            // reds look dark, so the fumbles go yellow instead of orange
+            Theme::Protanopia => Palette {
+                number: Color::AnsiValue(75),
+                operator: Color::AnsiValue(250),
+                word: Color::AnsiValue(39),
+                crit: Color::AnsiValue(33),
+                fumble: Color::AnsiValue(226),
+                dropped: Color::DarkGrey,
+            },
+            // red against cyan, staying off blue-yellow pairs
+            Theme::Tritanopia => Palette {
+                number: Color::AnsiValue(205),
+                operator: Color::AnsiValue(250),
+                word: Color::AnsiValue(37),
+                crit: Color::AnsiValue(44),
+                fumble: Color::AnsiValue(196),
+                dropped: Color::DarkGrey,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Dark,
+    Light,
+}
+
+/// The terminal's background, as far as it's letting on. Terminals that
+/// follow rxvt's lead set `COLORFGBG` to their foreground and background
+/// colors, like `15;0`; the background is the last of them, and only the
+/// whites and light grey (7 and 9 to 15) count as light.
+pub fn background() -> Option<Background> {
+    let colors = env::var("COLORFGBG").ok()?;
+    let background: u8 = colors.rsplit(';').next()?.parse().ok()?;
+    match background {
+        7 | 9..=15 => Some(Background::Light),
+        0..=6 | 8 => Some(Background::Dark),
+        _ => None,
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// Picks the theme everything is painted with from now on. Only the first
+/// call counts.
+pub fn set_theme(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct Palette {
+    number: Color,
+    operator: Color,
+    word: Color,
+    crit: Color,
+    fumble: Color,
+    dropped: Color,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Palette {
+    /// The color for a crit or fumble mark, or nothing if the character isn't
+    /// one. With `--ascii` they're `^` and `_`, which are only marks among the
+    /// dice.
+    fn mark(&self, c: char, in_dice: bool) -> Option<Color> {
+        match c {
+            CRIT_MARK => Some(self.crit),
+            FUMBLE_MARK => Some(self.fumble),
+            '^' if in_dice => Some(self.crit),
+            '_' if in_dice => Some(self.fumble),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 struct Style {
     color: Color,
     attribute: Attribute,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Style {
-    fn set_color(&mut self, stdout: &mut Stdout, color: Color) -> Result<(), std::io::Error> {
+    fn set_color(&mut self, out: &mut impl Write, color: Color) -> Result<(), std::io::Error> {
         if self.color != color {
-            stdout.queue(SetForegroundColor(color))?;
+            out.queue(SetForegroundColor(color))?;
             self.color = color;
         }
         Ok(())
@@ -25,17 +200,18 @@ impl Style {
 
     fn set_attribute(
         &mut self,
-        stdout: &mut Stdout,
+        out: &mut impl Write,
         attribute: Attribute,
     ) -> Result<(), std::io::Error> {
         if self.attribute != attribute {
-            stdout.queue(SetAttribute(attribute))?;
+            out.queue(SetAttribute(attribute))?;
             self.attribute = attribute;
         }
         Ok(())
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl Default for Style {
     fn default() -> Self {
         Self {
@@ -45,48 +221,573 @@ impl Default for Style {
     }
 }
 
-pub fn colorful(input: &str) -> Result<(), std::io::Error> {
+/// Whether the terminal understands escape sequences. Older Windows consoles
+/// don't unless virtual terminal processing is switched on, which this tries
+/// to do; if that fails, they get plain text instead.
+pub fn supports_ansi() -> bool {
+    static SUPPORTED: OnceLock<bool> = OnceLock::new();
+    *SUPPORTED.get_or_init(|| {
+        #[cfg(windows)]
+        return crossterm::ansi_support::supports_ansi();
+        // a WASI runtime could be writing anywhere, terminal or not
+        #[cfg(target_arch = "wasm32")]
+        return false;
+        #[cfg(not(any(windows, target_arch = "wasm32")))]
+        return true;
+    })
+}
+
+/// Prints a tree drawn for the value as it's drawn, in color if the terminal
+/// has any
+pub fn colorful(value: &Value, options: &RenderOptions) -> Result<(), Error> {
+    let mut stdout = stdout().lock();
+    // escape sequences would only get in the way of whatever's reading a pipe
+    if !supports_ansi() || !stdout.is_terminal() {
+        render::write(&mut stdout, value, options)?;
+        return Ok(stdout.flush()?);
+    }
+    painted(&mut stdout, value, options)
+}
+
+/// Like [`colorful`], but anything too tall to fit in the terminal is sent
+/// to a pager (`$PAGER`, or `less`) instead of scrolling off the top
+pub fn paged(value: &Value, options: &RenderOptions) -> Result<(), Error> {
+    if !supports_ansi() || !stdout().is_terminal() {
+        return colorful(value, options);
+    }
+    let fits = match rows() {
+        // drawn once just to count the lines, none of which are kept
+        Some(rows) => {
+            let mut lines = LineCount(0);
+            render::write(&mut lines, value, options)?;
+            lines.0 < rows
+        }
+        None => true,
+    };
+    if fits {
+        return colorful(value, options);
+    }
+
+    let pager = env::var("PAGER").unwrap_or_else(|_| "less -R".to_string());
+    let mut words = pager.split_whitespace();
+    let Some(program) = words.next() else {
+        return colorful(value, options);
+    };
+    let mut command = Command::new(program);
+    command.args(words).stdin(Stdio::piped());
+    // the same defaults git uses: keep the colors, and don't bother paging
+    // if it all fits on one screen after all
+    if env::var_os("LESS").is_none() {
+        command.env("LESS", "FRX");
+    }
+    let Ok(mut child) = command.spawn() else {
+        return colorful(value, options);
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // the pager may well be closed before reading everything
+        let _ = painted(&mut stdin, value, options);
+    }
+    child.wait()?;
+    Ok(())
+}
+
+/// Counts the lines written to it, and throws them away
+struct LineCount(usize);
+
+impl Write for LineCount {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.0 += buf.iter().filter(|&&byte| byte == b'\n').count();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+}
+
+/// Waits for Enter or `r` (roll again, returning true) or `q` (stop). When
+/// input isn't coming from a terminal, whole lines are read instead.
+pub fn roll_again() -> Result<bool, std::io::Error> {
     let mut stdout = stdout();
-    stdout.queue(SetAttribute(Attribute::Bold))?;
-    let mut style = Style::default();
-    let mut chars = input.chars().peekable();
-    while let Some(c) = chars.next() {
-        match c {
-            '0'..='9' => {
-                style.set_color(&mut stdout, Color::Magenta)?;
-                style.set_attribute(&mut stdout, Attribute::Reset)?;
+    write!(stdout, "[enter/r] roll again  [q] quit ")?;
+    stdout.flush()?;
+    #[cfg(not(target_arch = "wasm32"))]
+    if stdin().is_terminal() {
+        let again = pressed_again()?;
+        // raw mode left the cursor at the end of the prompt
+        writeln!(stdout)?;
+        return Ok(again);
+    }
+    let mut line = String::new();
+    let again = stdin().read_line(&mut line)? > 0 && matches!(line.trim(), "" | "r");
+    writeln!(stdout)?;
+    Ok(again)
+}
+
+/// How many rows the terminal has, if that can be found out
+#[cfg(not(target_arch = "wasm32"))]
+fn rows() -> Option<usize> {
+    match crossterm::terminal::size() {
+        Ok((_, rows)) if rows > 0 => Some(rows as usize),
+        _ => None,
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn rows() -> Option<usize> {
+    None
+}
+
+/// Reads single keypresses in raw mode until one of them says what to do
+#[cfg(not(target_arch = "wasm32"))]
+fn pressed_again() -> Result<bool, std::io::Error> {
+    crossterm::terminal::enable_raw_mode()?;
+    let again = loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        // Windows reports releasing the key as well as pressing it
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter | KeyCode::Char('r') => break true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break false,
+            KeyCode::Char('q') | KeyCode::Esc => break false,
+            _ => {}
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
+    Ok(again)
+}
+
+/// Reads a line after showing the prompt, or nothing once input runs out.
+/// At a terminal the line can be edited, Up and Down step through the
+/// history, and Ctrl-R searches back through it.
+pub fn read_line(prompt: &str, history: &[String]) -> Result<Option<String>, std::io::Error> {
+    let mut stdout = stdout();
+    #[cfg(not(target_arch = "wasm32"))]
+    if stdin().is_terminal() && supports_ansi() {
+        crossterm::terminal::enable_raw_mode()?;
+        let line = Editor::new(prompt, history).run(&mut stdout);
+        crossterm::terminal::disable_raw_mode()?;
+        writeln!(stdout)?;
+        return line;
+    }
+    write!(stdout, "{prompt}")?;
+    stdout.flush()?;
+    let mut line = String::new();
+    if stdin().read_line(&mut line)? == 0 {
+        writeln!(stdout)?;
+        return Ok(None);
+    }
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+/// The line being typed at the REPL, in raw mode
+#[cfg(not(target_arch = "wasm32"))]
+struct Editor<'a> {
+    prompt: &'a str,
+    history: &'a [String],
+    line: Vec<char>,
+    cursor: usize,
+    /// Which line of the history Up and Down have got to, and what had been
+    /// typed before going there
+    browsing: Option<(usize, Vec<char>)>,
+    /// What's being searched for with Ctrl-R, and the line it was found on
+    search: Option<(String, Option<usize>)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> Editor<'a> {
+    fn new(prompt: &'a str, history: &'a [String]) -> Self {
+        Editor {
+            prompt,
+            history,
+            line: Vec::new(),
+            cursor: 0,
+            browsing: None,
+            search: None,
+        }
+    }
+
+    fn run(mut self, out: &mut impl Write) -> Result<Option<String>, std::io::Error> {
+        use crossterm::cursor::MoveToColumn;
+        use crossterm::terminal::{Clear, ClearType};
+
+        loop {
+            let (shown, column) = match &self.search {
+                Some((query, found)) => {
+                    let found = found.map_or("", |i| self.history[i].as_str());
+                    let shown = format!("(reverse-i-search)'{query}': {found}");
+                    let column = shown.chars().count();
+                    (shown, column)
+                }
+                None => (
+                    format!("{}{}", self.prompt, self.line.iter().collect::<String>()),
+                    self.prompt.chars().count() + self.cursor,
+                ),
+            };
+            out.queue(MoveToColumn(0))?
+                .queue(Clear(ClearType::CurrentLine))?
+                .queue(Print(shown))?
+                .queue(MoveToColumn(column as u16))?;
+            out.flush()?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
             }
-            '+' | '-' | '\u{00D7}' | '=' | '>' => {
-                style.set_color(&mut stdout, Color::DarkYellow)?;
-                style.set_attribute(&mut stdout, Attribute::Reset)?;
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            if self.search.is_some() && self.searching(key.code, control) {
+                continue;
             }
-            'k' => {
-                if let Some('0'..='9' | 'l') = chars.peek() {
-                    style.set_color(&mut stdout, Color::Magenta)?;
-                    style.set_attribute(&mut stdout, Attribute::Reset)?;
+            match key.code {
+                KeyCode::Enter => return Ok(Some(self.line.iter().collect())),
+                // what Enter typed ahead of the prompt looks like in raw mode
+                KeyCode::Char('j') if control => return Ok(Some(self.line.iter().collect())),
+                KeyCode::Char('d') if control && self.line.is_empty() => return Ok(None),
+                KeyCode::Char('c') if control => return Ok(Some(String::new())),
+                KeyCode::Char('r') if control => self.search = Some((String::new(), None)),
+                KeyCode::Char('a') if control => self.cursor = 0,
+                KeyCode::Char('e') if control => self.cursor = self.line.len(),
+                KeyCode::Char('u') if control => {
+                    self.line.drain(..self.cursor);
+                    self.cursor = 0;
                 }
-            }
-            'd' | 'l' => {
-                if let Some('0'..='9') = chars.peek() {
-                    style.set_color(&mut stdout, Color::Magenta)?;
-                    style.set_attribute(&mut stdout, Attribute::Reset)?;
+                KeyCode::Char(c) if !control => {
+                    self.line.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+                KeyCode::Backspace if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.line.remove(self.cursor);
+                }
+                KeyCode::Delete if self.cursor < self.line.len() => {
+                    self.line.remove(self.cursor);
                 }
+                KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+                KeyCode::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+                KeyCode::Home => self.cursor = 0,
+                KeyCode::End => self.cursor = self.line.len(),
+                KeyCode::Up => self.browse(-1),
+                KeyCode::Down => self.browse(1),
+                _ => {}
             }
-            'a'..='z' | 'A'..='Z' => {
-                style.set_color(&mut stdout, Color::Green)?;
-                style.set_attribute(&mut stdout, Attribute::Bold)?;
+        }
+    }
+
+    /// Handles a key while searching, returning whether that was all there
+    /// was to it. Any other key stops the search, and is then handled as
+    /// usual on the line that was found.
+    fn searching(&mut self, code: KeyCode, control: bool) -> bool {
+        let Some((query, _)) = &mut self.search else {
+            return false;
+        };
+        match code {
+            KeyCode::Char('r') if control => self.search_back(true),
+            KeyCode::Char('c' | 'g') if control => self.search = None,
+            KeyCode::Esc => self.search = None,
+            KeyCode::Char(c) if !control => {
+                query.push(c);
+                self.search_back(false);
             }
-            VERTICAL_PIPE | HORIZONTAL_PIPE | RIGHT_FORK => {
-                style.set_color(&mut stdout, Color::Reset)?;
-                style.set_attribute(&mut stdout, Attribute::Reset)?;
+            KeyCode::Backspace => {
+                query.pop();
+                self.search_back(false);
             }
             _ => {
-                style.set_color(&mut stdout, Color::Reset)?;
-                style.set_attribute(&mut stdout, Attribute::Reset)?;
+                self.take_found();
+                return false;
             }
         }
-        stdout.queue(Print(c))?;
+        true
+    }
+
+    /// Moves through the history, coming back to whatever was being typed
+    /// after going past the newest line
+    fn browse(&mut self, step: isize) {
+        let (at, draft) = match self.browsing.take() {
+            Some(browsing) => browsing,
+            None if step < 0 => (self.history.len(), self.line.clone()),
+            None => return,
+        };
+        let at = at.saturating_add_signed(step);
+        match self.history.get(at) {
+            Some(line) => {
+                self.line = line.chars().collect();
+                self.browsing = Some((at, draft));
+            }
+            None => self.line = draft,
+        }
+        self.cursor = self.line.len();
+    }
+
+    /// Finds the newest line with the query in it, older than the one already
+    /// found if `older` is set
+    fn search_back(&mut self, older: bool) {
+        let Some((query, found)) = &mut self.search else {
+            return;
+        };
+        let before = match (*found, older) {
+            (Some(i), true) => i,
+            (Some(i), false) => i + 1,
+            (None, _) => self.history.len(),
+        };
+        if let Some(i) = self.history[..before]
+            .iter()
+            .rposition(|line| line.contains(query.as_str()))
+        {
+            *found = Some(i);
+        }
+    }
+
+    /// Stops searching, leaving the line that was found to be edited
+    fn take_found(&mut self) {
+        if let Some((_, Some(i))) = self.search.take() {
+            self.line = self.history[i].chars().collect();
+            self.cursor = self.line.len();
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn painted(out: &mut impl Write, value: &Value, options: &RenderOptions) -> Result<(), Error> {
+    render::write(out, value, options)?;
+    Ok(out.flush()?)
+}
+
+/// Draws the tree into `out`, coloring each line as it's drawn
+#[cfg(not(target_arch = "wasm32"))]
+fn painted(out: &mut impl Write, value: &Value, options: &RenderOptions) -> Result<(), Error> {
+    let dropped_first = render::dropped_first(value);
+    let mut painter = Painter::new(out, &dropped_first)?;
+    render::write(&mut painter, value, options)?;
+    Ok(painter.finish()?)
+}
+
+/// Colors a drawn tree a line at a time, holding on to nothing but the line
+/// it's in the middle of. `dropped_first` says, for each of the tree's lists
+/// of dice in turn, whether the dropped ones are the ones before the `|`.
+#[cfg(not(target_arch = "wasm32"))]
+struct Painter<'a, W: Write> {
+    out: W,
+    palette: Palette,
+    style: Style,
+    lists: std::slice::Iter<'a, bool>,
+    line: Vec<u8>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a, W: Write> Painter<'a, W> {
+    fn new(mut out: W, dropped_first: &'a [bool]) -> Result<Self, std::io::Error> {
+        out.queue(SetAttribute(Attribute::Bold))?;
+        Ok(Painter {
+            out,
+            palette: THEME.get().copied().unwrap_or_default().palette(),
+            style: Style::default(),
+            lists: dropped_first.iter(),
+            line: Vec::new(),
+        })
+    }
+
+    /// Paints whatever's left of the last line, if it didn't end in one
+    fn finish(mut self) -> Result<(), std::io::Error> {
+        let line = std::mem::take(&mut self.line);
+        self.paint(&String::from_utf8_lossy(&line))?;
+        self.out.flush()
+    }
+
+    fn paint(&mut self, input: &str) -> Result<(), std::io::Error> {
+        let Painter {
+            out,
+            palette,
+            style,
+            lists,
+            ..
+        } = self;
+        // whether we're among a roll's dice, and then whether the ones on
+        // this side of its '|' are the ones it dropped
+        let mut in_dice = false;
+        let mut before_pipe = false;
+        let mut dropped = false;
+        let mut previous = None;
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '0'..='9' => {
+                    // a number takes the color of the mark after it, if any
+                    let color = match previous {
+                        Some('0'..='9') => style.color,
+                        _ if dropped => palette.dropped,
+                        _ => chars
+                            .clone()
+                            .find(|c| !c.is_ascii_digit())
+                            .and_then(|c| palette.mark(c, in_dice))
+                            .unwrap_or(palette.number),
+                    };
+                    style.set_color(out, color)?;
+                    style.set_attribute(out, Attribute::Reset)?;
+                }
+                c if palette.mark(c, in_dice).is_some() => {
+                    let color = match dropped {
+                        true => palette.dropped,
+                        false => palette.mark(c, in_dice).expect("c is a mark"),
+                    };
+                    style.set_color(out, color)?;
+                    style.set_attribute(out, Attribute::Bold)?;
+                }
+                '+' | '-' | '\u{00D7}' | '=' | '>' => {
+                    style.set_color(out, palette.operator)?;
+                    style.set_attribute(out, Attribute::Reset)?;
+                }
+                'k' => {
+                    if let Some('0'..='9' | 'l') = chars.peek() {
+                        style.set_color(out, palette.number)?;
+                        style.set_attribute(out, Attribute::Reset)?;
+                    }
+                }
+                'd' | 'l' => {
+                    if let Some('0'..='9') = chars.peek() {
+                        style.set_color(out, palette.number)?;
+                        style.set_attribute(out, Attribute::Reset)?;
+                    }
+                }
+                'a'..='z' | 'A'..='Z' => {
+                    style.set_color(out, palette.word)?;
+                    style.set_attribute(out, Attribute::Bold)?;
+                }
+                VERTICAL_PIPE | HORIZONTAL_PIPE | RIGHT_FORK => {
+                    style.set_color(out, Color::Reset)?;
+                    style.set_attribute(out, Attribute::Reset)?;
+                }
+                _ => {
+                    match c {
+                        '[' => {
+                            before_pipe = lists.next().copied().unwrap_or(false);
+                            (in_dice, dropped) = (true, before_pipe);
+                        }
+                        '|' if in_dice => dropped = !before_pipe,
+                        ']' | '\n' => (in_dice, dropped) = (false, false),
+                        _ => {}
+                    }
+                    style.set_color(out, Color::Reset)?;
+                    style.set_attribute(out, Attribute::Reset)?;
+                }
+            }
+            out.queue(Print(c))?;
+            previous = Some(c);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<W: Write> Write for Painter<'_, W> {
+    /// Paints every line that's been finished, keeping back the rest
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        self.line.extend_from_slice(buf);
+        while let Some(end) = self.line.iter().position(|&byte| byte == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=end).collect();
+            self.paint(&String::from_utf8_lossy(&line))?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.out.flush()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use crate::console::*;
+    use crate::parse::parse;
+    use crate::render::{no_color, RenderOptions};
+    use crossterm::Command;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// The two sides of the first list of dice, each as painted
+    fn sides(input: &str) -> (String, String) {
+        let value = parse(input)
+            .unwrap()
+            .evaluate(&mut StdRng::seed_from_u64(1));
+        let mut out = Vec::new();
+        painted(&mut out, &value, &RenderOptions::default()).unwrap();
+        let painted = String::from_utf8(out).unwrap();
+        let dice = &painted[painted.find('[').unwrap()..painted.find(']').unwrap()];
+        let (before, after) = dice.split_once('|').unwrap();
+        (before.to_string(), after.to_string())
+    }
+
+    #[test]
+    fn dropped_dice() {
+        let mut dropped = String::new();
+        SetForegroundColor(Theme::default().palette().dropped)
+            .write_ansi(&mut dropped)
+            .unwrap();
+        // the highest dice are always listed first, whichever ones were kept
+        for (input, dropped_first) in [
+            ("4d6k2", false),
+            ("4d6dl1", false),
+            ("4d6kl1", true),
+            ("4d6dh1", true),
+        ] {
+            let (before, after) = sides(input);
+            assert_eq!(dropped_first, before.contains(&dropped), "{input}");
+            assert_eq!(!dropped_first, after.contains(&dropped), "{input}");
+        }
+    }
+
+    /// Everything written to it, a write at a time
+    #[derive(Default)]
+    struct Writes(Vec<String>);
+
+    impl Write for Writes {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+            self.0.push(String::from_utf8(buf.to_vec()).unwrap());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<(), std::io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn painted_a_line_at_a_time() {
+        let value = parse("(2d4)d6k2 + 3d8")
+            .unwrap()
+            .evaluate(&mut StdRng::seed_from_u64(1));
+        let options = RenderOptions::default();
+        let mut writes = Writes::default();
+        painted(&mut writes, &value, &options).unwrap();
+        let drawn = no_color(&value, &options).unwrap();
+        // each line is passed along as soon as it's drawn, never all at once
+        assert!(writes.0.len() > 1);
+        assert!(writes
+            .0
+            .iter()
+            .all(|write| write.matches('\n').count() <= 1));
+        let lines: Vec<_> = writes
+            .0
+            .iter()
+            .filter(|write| write.contains('\n'))
+            .collect();
+        assert_eq!(drawn.lines().count(), lines.len());
+        // and with the colors taken out, it's the same as the plain tree
+        let mut plain = String::new();
+        let mut escape = false;
+        for c in writes.0.concat().chars() {
+            match c {
+                '\u{1B}' => escape = true,
+                'm' if escape => escape = false,
+                c if !escape => plain.push(c),
+                _ => {}
+            }
+        }
+        assert_eq!(drawn, plain);
     }
-    stdout.flush()?;
-    Ok(())
 }
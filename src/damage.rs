@@ -0,0 +1,256 @@
+//! Damage types. A term of an expression can be tagged with the type of
+//! damage it deals, like `2d6[fire] + 1d4[cold] + 3`, and each type's total
+//! is then halved, zeroed, or doubled by the target's resistances,
+//! immunities, and vulnerabilities.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::eval::Int;
+
+/// One term of the expression, with the sign it was added with
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Component {
+    pub expression: String,
+    pub negative: bool,
+    /// Untyped damage is never resisted
+    pub kind: Option<String>,
+}
+
+/// Whether any term of the input is tagged with a damage type
+pub fn tagged(input: &str) -> bool {
+    input.contains('[')
+}
+
+/// Splits the input into its terms (everything between a `+` or `-` outside
+/// of any parentheses), each taking the type it's tagged with. A tag anywhere
+/// inside a term, even within parentheses, applies to all of it.
+pub fn components(input: &str) -> Result<Vec<Component>, String> {
+    let mut components = Vec::new();
+    let mut current = Component {
+        expression: String::new(),
+        negative: false,
+        kind: None,
+    };
+    let mut depth = 0;
+    let mut chars = input.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let (mut kind, mut closed) = (String::new(), false);
+                for c in chars.by_ref() {
+                    closed = c == ']';
+                    if closed {
+                        break;
+                    }
+                    kind.push(c);
+                }
+                if !closed {
+                    return Err("A damage type is missing its closing ']'".to_string());
+                }
+                let kind = kind.trim().to_lowercase();
+                if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphabetic()) {
+                    return Err(format!("'{kind}' is not a damage type like [fire]"));
+                }
+                match &current.kind {
+                    Some(other) if *other != kind => {
+                        return Err(format!(
+                            "'{}' is tagged as both {other} and {kind} damage",
+                            current.expression.trim()
+                        ))
+                    }
+                    _ => current.kind = Some(kind),
+                }
+            }
+            ']' => return Err("A damage type is missing its opening '['".to_string()),
+            // a sign at the very start of a term belongs to the term itself
+            '+' | '-' if depth == 0 && !current.expression.trim().is_empty() => {
+                let next = Component {
+                    expression: String::new(),
+                    negative: c == '-',
+                    kind: None,
+                };
+                components.push(std::mem::replace(&mut current, next));
+            }
+            _ => {
+                match c {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                current.expression.push(c);
+            }
+        }
+    }
+    if current.expression.trim().is_empty() {
+        return Err("The expression ends without a term to deal damage".to_string());
+    }
+    components.push(current);
+    for component in &mut components {
+        component.expression = component.expression.trim().to_string();
+    }
+    Ok(components)
+}
+
+/// How a target takes each type of damage
+#[derive(Debug, Default)]
+pub struct Defenses {
+    pub resistant: Vec<String>,
+    pub immune: Vec<String>,
+    pub vulnerable: Vec<String>,
+}
+
+impl Defenses {
+    /// The damage actually taken from a type's total. Immunity wins outright;
+    /// otherwise resistance halves it (rounding down) before vulnerability
+    /// doubles it.
+    pub fn apply(&self, kind: Option<&str>, total: Int) -> Int {
+        let Some(kind) = kind else {
+            return total;
+        };
+        let has = |list: &[String]| list.iter().any(|k| k.eq_ignore_ascii_case(kind));
+        if has(&self.immune) {
+            return 0;
+        }
+        let mut total = total;
+        if has(&self.resistant) {
+            total = total.div_euclid(2);
+        }
+        if has(&self.vulnerable) {
+            total *= 2;
+        }
+        total
+    }
+
+    /// What happened to a type's damage, e.g. `resisted`
+    fn describe(&self, kind: &str) -> Vec<&'static str> {
+        let has = |list: &[String]| list.iter().any(|k| k.eq_ignore_ascii_case(kind));
+        [
+            (&self.immune, "immune"),
+            (&self.resistant, "resisted"),
+            (&self.vulnerable, "vulnerable"),
+        ]
+        .into_iter()
+        .filter(|(list, _)| has(list))
+        .map(|(_, label)| label)
+        .collect()
+    }
+}
+
+/// Adds up each type's damage from the rolled components' values, in the
+/// order the types first came up, with untyped damage first
+pub fn totals(components: &[Component], values: &[Int]) -> Vec<(Option<String>, Int)> {
+    let mut order = Vec::new();
+    let mut totals = BTreeMap::new();
+    for (component, value) in components.iter().zip(values) {
+        let signed = if component.negative { -value } else { *value };
+        if !totals.contains_key(&component.kind) {
+            order.push(component.kind.clone());
+        }
+        *totals.entry(component.kind.clone()).or_insert(0) += signed;
+    }
+    order.sort_by_key(Option::is_some);
+    order
+        .into_iter()
+        .map(|kind| {
+            let total = totals[&kind];
+            (kind, total)
+        })
+        .collect()
+}
+
+/// Each type's total, what the defenses made of it, and the damage taken
+pub fn breakdown(totals: &[(Option<String>, Int)], defenses: &Defenses) -> (String, Int) {
+    let mut output = String::new();
+    let mut taken = 0;
+    for (kind, total) in totals {
+        let applied = defenses.apply(kind.as_deref(), *total);
+        taken += applied;
+        let name = kind.as_deref().unwrap_or("untyped");
+        let notes = kind
+            .as_deref()
+            .map(|kind| defenses.describe(kind))
+            .unwrap_or_default();
+        match notes.is_empty() {
+            true => writeln!(output, "{name}: {total}").unwrap(),
+            false => writeln!(
+                output,
+                "{name}: {total} \u{2192} {applied} ({})",
+                notes.join(", ")
+            )
+            .unwrap(),
+        }
+    }
+    writeln!(output, "total damage: {taken}").unwrap();
+    (output, taken)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::damage::*;
+
+    fn kinds(input: &str) -> Vec<(String, bool, Option<String>)> {
+        components(input)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.expression, c.negative, c.kind))
+            .collect()
+    }
+
+    #[test]
+    fn tagged_terms() {
+        let fire = Some("fire".to_string());
+        assert_eq!(
+            vec![
+                ("2d6".to_string(), false, fire.clone()),
+                ("1d4".to_string(), false, Some("cold".to_string())),
+                ("3".to_string(), true, None),
+            ],
+            kinds("2d6[fire] + 1d4[Cold] - 3")
+        );
+        assert_eq!(
+            vec![
+                ("-1".to_string(), false, None),
+                ("(2d6 - 1)".to_string(), false, fire)
+            ],
+            kinds("-1 + (2d6[fire] - 1)")
+        );
+        assert!(components("2d6[fire][cold]").is_err());
+        assert!(components("2d6[fire] + 1d4[cold").is_err());
+        assert!(components("2d6 +").is_err());
+    }
+
+    #[test]
+    fn defenses() {
+        let defenses = Defenses {
+            resistant: vec!["fire".to_string()],
+            immune: vec!["cold".to_string()],
+            vulnerable: vec!["fire".to_string(), "radiant".to_string()],
+        };
+        assert_eq!(6, defenses.apply(Some("fire"), 7));
+        assert_eq!(0, defenses.apply(Some("cold"), 7));
+        assert_eq!(14, defenses.apply(Some("radiant"), 7));
+        assert_eq!(7, defenses.apply(None, 7));
+    }
+
+    #[test]
+    fn per_type_totals() {
+        let components = components("2d6[fire] + 3 + 1d4[fire] + 1d8[cold]").unwrap();
+        let totals = totals(&components, &[7, 3, 2, 5]);
+        assert_eq!(
+            vec![
+                (None, 3),
+                (Some("fire".to_string()), 9),
+                (Some("cold".to_string()), 5)
+            ],
+            totals
+        );
+        let defenses = Defenses {
+            resistant: vec!["fire".to_string()],
+            ..Default::default()
+        };
+        let (output, taken) = breakdown(&totals, &defenses);
+        assert_eq!(12, taken);
+        assert!(output.contains("fire: 9 \u{2192} 4 (resisted)"));
+    }
+}
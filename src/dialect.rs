@@ -0,0 +1,460 @@
+//! Other dice rollers write expressions a little differently. These let a
+//! macro from a virtual tabletop be read in, and let any expression be written
+//! back out for one.
+
+use std::collections::VecDeque;
+use std::iter::Peekable;
+
+use crate::error::Error;
+use crate::eval::{
+    vec_deque, Comparison, Condition, Exp, Explode, Explosion, Int, Keep, Operation, Pick,
+};
+use crate::messages::Message;
+use crate::parse::parse;
+
+pub const DIALECTS: &[&str] = &["rdr", "roll20", "foundry"];
+
+/// The others can't say how many times to repeat something, so a group roll
+/// spells out every repetition. Past this many, counting every repetition of
+/// every group, an expression isn't written for them at all.
+pub const MAX_GROUPED: u64 = 100;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Dialect {
+    Rdr,
+    Roll20,
+    Foundry,
+}
+
+impl Dialect {
+    pub fn named(name: &str) -> Result<Dialect, Error> {
+        match name {
+            "rdr" => Ok(Dialect::Rdr),
+            "roll20" => Ok(Dialect::Roll20),
+            "foundry" => Ok(Dialect::Foundry),
+            other => Err(Error::Input(
+                format!(
+                    "'{other}' is not a dialect we know; try one of {}",
+                    DIALECTS.join(", ")
+                )
+                .into(),
+            )),
+        }
+    }
+}
+
+/// Parses an expression written in the given dialect
+pub fn read(input: &str, dialect: Dialect) -> Result<Exp, Error> {
+    parse(&rewrite(input, dialect)?)
+}
+
+/// Rewrites an expression from the given dialect into ours without parsing
+/// it, for input that's still going to be picked apart some other way first
+pub fn rewrite(input: &str, dialect: Dialect) -> Result<String, Error> {
+    match dialect {
+        Dialect::Rdr => Ok(input.to_string()),
+        Dialect::Roll20 | Dialect::Foundry => {
+            normalize(input, dialect).map_err(|e| Error::Input(e.into()))
+        }
+    }
+}
+
+/// Writes an expression the way the given dialect expects it, unless it's
+/// repeated more than [`MAX_GROUPED`] times and the dialect would have to
+/// write out every repetition
+pub fn write(exp: &Exp, dialect: Dialect) -> Result<String, Error> {
+    if dialect != Dialect::Rdr && repetitions(exp) > MAX_GROUPED {
+        return Err(Error::Limit(Message::TooManyRepetitions(
+            MAX_GROUPED as u32,
+        )));
+    }
+    Ok(written(exp, dialect))
+}
+
+/// How many times the expressions inside repeats would be written out in
+/// all, if every repetition had to be
+fn repetitions(exp: &Exp) -> u64 {
+    match exp {
+        Exp::Const(_) => 0,
+        Exp::Op(op) => op
+            .arguments
+            .borrow()
+            .iter()
+            .map(repetitions)
+            .fold(0, u64::saturating_add),
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            let explode = roll
+                .explode
+                .as_ref()
+                .and_then(|explosion| explosion.on.as_ref());
+            [Some(&roll.dice), Some(&roll.sides), roll.keep.count()]
+                .into_iter()
+                .chain([explode, roll.reroll.as_ref()].map(|on| on.map(|on| &on.against)))
+                .flatten()
+                .map(repetitions)
+                .fold(0, u64::saturating_add)
+        }
+        Exp::Repeat(repeat) => {
+            (repeat.times as u64).saturating_mul(repetitions(&repeat.exp).saturating_add(1))
+        }
+    }
+}
+
+/// [`write`], without checking how many repetitions it writes out
+fn written(exp: &Exp, dialect: Dialect) -> String {
+    match exp {
+        Exp::Const(c) => c.to_string(),
+        Exp::Op(op) => {
+            let operator = match op.operation {
+                Operation::Add => " + ",
+                Operation::Sub => " - ",
+                Operation::Mul => " * ",
+            };
+            let precedence = op.operation.precedence();
+            op.arguments
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(i, argument)| {
+                    let written = written(argument, dialect);
+                    match argument {
+                        // the first operand can't be affected by the
+                        // grouping, but `a - (b - c)` needs its parentheses
+                        Exp::Op(inner)
+                            if inner.operation.precedence() < precedence
+                                || (i > 0 && inner.operation.precedence() == precedence) =>
+                        {
+                            format!("({written})")
+                        }
+                        _ => written,
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(operator)
+        }
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            let dice = operand(&roll.dice, dialect);
+            let sides = operand(&roll.sides, dialect);
+            let keep = match (&roll.keep, dialect) {
+                (Keep::All, _) => String::new(),
+                (Keep::Highest(count), Dialect::Rdr) => format!("k{}", operand(count, dialect)),
+                (Keep::Highest(count), _) => format!("kh{}", operand(count, dialect)),
+                (Keep::Lowest(count), _) => format!("kl{}", operand(count, dialect)),
+                (Keep::HighestShare(percent), Dialect::Rdr) => format!("k{percent}%"),
+                (Keep::HighestShare(percent), _) => format!("kh{percent}%"),
+                (Keep::LowestShare(percent), _) => format!("kl{percent}%"),
+                (Keep::DropLowest(count), _) => format!("dl{}", operand(count, dialect)),
+                (Keep::DropHighest(count), _) => format!("dh{}", operand(count, dialect)),
+            };
+            let reroll = roll.reroll.as_ref().map(|on| rerolling(on, dialect));
+            let explode = roll
+                .explode
+                .as_ref()
+                .map(|explosion| exploding(explosion, dialect));
+            format!(
+                "{dice}d{sides}{}{}{keep}",
+                reroll.unwrap_or_default(),
+                explode.unwrap_or_default()
+            )
+        }
+        // the others have no word for it, but a group roll keeps the best
+        // (or worst) of its parts
+        Exp::Repeat(repeat) => {
+            let written = written(&repeat.exp, dialect);
+            match (repeat.pick, dialect) {
+                (pick, Dialect::Rdr) => format!("{pick} of {}x({written})", repeat.times),
+                (Pick::Best(keep), _) => format!("{{{}}}kh{keep}", group(&written, repeat.times)),
+                (Pick::Worst(keep), _) => format!("{{{}}}kl{keep}", group(&written, repeat.times)),
+            }
+        }
+    }
+}
+
+/// An explosion, like `!>=8`. Foundry has no compounding, so it gets Roll20's
+/// notation for that.
+fn exploding(explosion: &Explosion, dialect: Dialect) -> String {
+    let explode = match (explosion.explode, dialect) {
+        (Explode::Extra, Dialect::Foundry) => "x".to_string(),
+        (explode, _) => explode.to_string(),
+    };
+    match &explosion.on {
+        Some(on) => format!("{explode}{}", compared(on, dialect)),
+        None => explode,
+    }
+}
+
+/// A reroll, like `r<3`. Foundry's `r` only rerolls once, and it's `rr` that
+/// keeps going.
+fn rerolling(on: &Condition<Exp>, dialect: Dialect) -> String {
+    let reroll = match dialect {
+        Dialect::Foundry => "rr",
+        _ => "r",
+    };
+    match on.comparison {
+        Comparison::Equal => format!("{reroll}{}", operand(&on.against, dialect)),
+        _ => format!("{reroll}{}", compared(on, dialect)),
+    }
+}
+
+/// What a die is compared to, like the `>=8` of `!>=8`. Roll20's comparisons
+/// always include the number they compare to, so strict ones are moved over
+/// by one, and it only needs the number to compare for equality.
+fn compared(on: &Condition<Exp>, dialect: Dialect) -> String {
+    let nudged = |by: Int| match &on.against {
+        Exp::Const(c) => Exp::Const(c.saturating_add(by)),
+        against if by < 0 => Exp::sub(vec_deque![against.clone(), Exp::Const(-by)]),
+        against => Exp::add(vec_deque![against.clone(), Exp::Const(by)]),
+    };
+    let (comparison, against) = match (on.comparison, dialect) {
+        (Comparison::Equal, Dialect::Roll20) => (String::new(), on.against.clone()),
+        (Comparison::Greater, Dialect::Roll20) => (">".to_string(), nudged(1)),
+        (Comparison::AtLeast, Dialect::Roll20) => (">".to_string(), on.against.clone()),
+        (Comparison::Less, Dialect::Roll20) => ("<".to_string(), nudged(-1)),
+        (Comparison::AtMost, Dialect::Roll20) => ("<".to_string(), on.against.clone()),
+        (comparison, _) => (comparison.to_string(), on.against.clone()),
+    };
+    format!("{comparison}{}", operand(&against, dialect))
+}
+
+/// The same expression over and over, separated by commas
+fn group(written: &str, times: u32) -> String {
+    vec![written; times as usize].join(", ")
+}
+
+/// Rewrites an expression from one dialect into another. Converting into
+/// [`Dialect::Rdr`] gives the canonical form, the same one whatever way the
+/// expression was originally written:
+///
+/// ```
+/// use recursive_dice_roller::{convert, Dialect};
+///
+/// let canonical = convert("/r 4d6kh3 + 2[str]", Dialect::Roll20, Dialect::Rdr).unwrap();
+/// assert_eq!("4d6k3 + 2", canonical);
+/// ```
+pub fn convert(input: &str, from: Dialect, to: Dialect) -> Result<String, Error> {
+    write(&read(input, from)?, to)
+}
+
+/// A number of dice, number of sides, or number to keep or drop. Roll20 only
+/// allows computed values there as nested inline rolls.
+fn operand(exp: &Exp, dialect: Dialect) -> String {
+    match (exp, dialect) {
+        (Exp::Const(c), _) if *c >= 0 => c.to_string(),
+        (_, Dialect::Roll20) => format!("[[{}]]", written(exp, dialect)),
+        _ => format!("({})", written(exp, dialect)),
+    }
+}
+
+/// Rewrites a Roll20 or Foundry expression into one of ours: the command and
+/// inline roll brackets come off, labels like `[fire]` are dropped, a keep
+/// or a drop without a count keeps or drops one die.
+/// Exploding dice are written the same way in ours, except that Roll20's
+/// comparisons include the number they compare to, so `!>8` is our `!>=8`,
+/// and Foundry explodes with an `x`. Rerolls are the same, except that only
+/// rerolling until the die stops qualifying has an equivalent, which is `r`
+/// in Roll20 and `rr` in Foundry.
+/// Character attributes, like Foundry's `@abilities.str.mod` or Roll20's
+/// `@{str_mod}`, become variables like `@abilities_str_mod`.
+fn normalize(input: &str, dialect: Dialect) -> Result<String, String> {
+    let mut input = input.trim();
+    for command in ["/roll ", "/r "] {
+        input = input.strip_prefix(command).unwrap_or(input);
+    }
+    let input = input.replace("[[", "(").replace("]]", ")");
+
+    let mut output = String::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                chars
+                    .find(|c| *c == ']')
+                    .ok_or("A label is missing its closing ']'")?;
+            }
+            '@' => {
+                output.push(c);
+                let braced = chars.next_if_eq(&'{').is_some();
+                let in_name = |c: &char| {
+                    c.is_ascii_alphanumeric() || *c == '_' || *c == '.' || (braced && *c == '-')
+                };
+                while let Some(c) = chars.next_if(in_name) {
+                    output.push(if c.is_ascii_alphanumeric() { c } else { '_' });
+                }
+                if braced && chars.next() != Some('}') {
+                    return Err("An attribute is missing its closing '}'".to_string());
+                }
+            }
+            'd' if matches!(chars.peek(), Some('l' | 'h')) => {
+                output.push(c);
+                output.extend(chars.next());
+                if let Some(dropped) = count(&mut chars)? {
+                    output.push_str(&dropped.to_string());
+                } else if chars.peek() != Some(&'(') {
+                    output.push('1');
+                }
+            }
+            'k' => {
+                output.push(c);
+                if let Some(rule) = chars.next_if(|c| matches!(c, 'h' | 'l')) {
+                    output.push(rule);
+                }
+                if let Some(kept) = count(&mut chars)? {
+                    output.push_str(&kept.to_string());
+                } else if chars.peek() != Some(&'(') {
+                    output.push('1');
+                }
+            }
+            '!' => {
+                output.push(c);
+                if chars.next_if_eq(&'!').is_some() {
+                    output.push(c);
+                }
+                inclusive(&mut chars, &mut output);
+            }
+            'x' if chars.next_if_eq(&'o').is_some() => {
+                return Err("Exploding only once has no equivalent here".to_string())
+            }
+            'x' => {
+                output.push('!');
+                while let Some(comparison) = chars.next_if(|c| matches!(c, '<' | '>' | '=')) {
+                    output.push(comparison);
+                }
+            }
+            '/' => return Err("Division has no equivalent here".to_string()),
+            '<' | '>' | '=' => return Err("Counting successes has no equivalent here".to_string()),
+            'r' if dialect == Dialect::Roll20 => {
+                if chars.next_if_eq(&'o').is_some() {
+                    return Err("Rerolling only once has no equivalent here".to_string());
+                }
+                output.push(c);
+                inclusive(&mut chars, &mut output);
+            }
+            'r' if chars.next_if_eq(&'r').is_some() => {
+                output.push(c);
+                while let Some(comparison) = chars.next_if(|c| matches!(c, '<' | '>' | '=')) {
+                    output.push(comparison);
+                }
+            }
+            'r' => return Err("Rerolling only once has no equivalent here".to_string()),
+            _ => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+/// Reads a Roll20 comparison, like the `>8` of `!>8`, which includes the
+/// number it compares to. A bare number is the one face it's true of.
+fn inclusive(chars: &mut Peekable<impl Iterator<Item = char>>, output: &mut String) {
+    match chars.next_if(|c| matches!(c, '<' | '>')) {
+        Some(comparison) => {
+            output.push(comparison);
+            output.push('=');
+        }
+        None if chars.peek().is_some_and(char::is_ascii_digit) => output.push('='),
+        None => {}
+    }
+}
+
+/// Reads the digits immediately following, if there are any
+fn count(chars: &mut Peekable<impl Iterator<Item = char>>) -> Result<Option<i32>, String> {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(char::is_ascii_digit) {
+        digits.push(c);
+    }
+    match digits.is_empty() {
+        true => Ok(None),
+        false => digits
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("{digits} is too large")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dialect::*;
+
+    #[test]
+    fn from_roll20() {
+        let converted = |input| convert(input, Dialect::Roll20, Dialect::Rdr).unwrap();
+        assert_eq!("2d20k1 + 5", converted("/r 2d20kh1 + 5"));
+        assert_eq!("4d6dl1", converted("[[4d6dl1]]"));
+        assert_eq!("(1d4 + 2)d6dl1", converted("[[(1d4+2)d6dl]]"));
+        assert_eq!("1d8 + 3", converted("1d8[slashing] + 3[str]"));
+        assert_eq!("10d10!>=8", converted("10d10!>8"));
+        assert_eq!("3d6!!=5 + 1", converted("3d6!!5 + 1"));
+        assert!(convert("3d6>4", Dialect::Roll20, Dialect::Rdr).is_err());
+    }
+
+    #[test]
+    fn from_foundry() {
+        let converted = |input| convert(input, Dialect::Foundry, Dialect::Rdr).unwrap();
+        assert_eq!("2d20kl1 - 1", converted("2d20kl - 1"));
+        assert_eq!("4d6dh1", converted("4d6dh"));
+        assert_eq!("5d10!>8", converted("5d10x>8"));
+        assert!(convert("5d10xo", Dialect::Foundry, Dialect::Rdr).is_err());
+        let rewritten = |input| rewrite(input, Dialect::Foundry).unwrap();
+        assert_eq!(
+            "1d20 + @abilities_str_mod + @prof",
+            rewritten("1d20 + @abilities.str.mod + @prof")
+        );
+        assert_eq!("d20 + @str_mod", rewritten("/r d20 + @{str-mod}"));
+    }
+
+    #[test]
+    fn to_other_dialects() {
+        let input = "(1d4)d6 + 2d20k1 - (3 - 1)";
+        assert_eq!(
+            "[[1d4]]d6 + 2d20kh1 - (3 - 1)",
+            convert(input, Dialect::Rdr, Dialect::Roll20).unwrap()
+        );
+        assert_eq!(
+            "(1d4)d6 + 2d20kh1 - (3 - 1)",
+            convert(input, Dialect::Rdr, Dialect::Foundry).unwrap()
+        );
+        let input = "best 1 of 3x(4d6k3) + 1";
+        assert_eq!(input, convert(input, Dialect::Rdr, Dialect::Rdr).unwrap());
+        assert_eq!(
+            "{2d20kh1, 2d20kh1}kl1",
+            convert("worst 1 of 2 x 2d20k1", Dialect::Rdr, Dialect::Roll20).unwrap()
+        );
+        // every repetition is written out, so there can't be too many
+        let nested = "best 1 of 20x(best 1 of 20x(d6))";
+        assert!(matches!(
+            convert(nested, Dialect::Rdr, Dialect::Foundry),
+            Err(Error::Limit(Message::TooManyRepetitions(_)))
+        ));
+        assert!(convert(nested, Dialect::Rdr, Dialect::Rdr).is_ok());
+    }
+
+    #[test]
+    fn explosions_in_other_dialects() {
+        let roll20 = |input| convert(input, Dialect::Rdr, Dialect::Roll20).unwrap();
+        assert_eq!("10d10!>8", roll20("10d10!>=8"));
+        assert_eq!("10d10!>9", roll20("10d10!>8"));
+        assert_eq!("4d6!![[1d3 + 1]]", roll20("4d6!!=(1d3+1)"));
+        assert_eq!("4d6!<[[1d3 - 1]]kh3", roll20("4d6!<(1d3)k3"));
+        let foundry = |input| convert(input, Dialect::Rdr, Dialect::Foundry).unwrap();
+        assert_eq!("10d10x>=8", foundry("10d10!>=8"));
+        assert_eq!("5d6!!", foundry("5d6!!"));
+    }
+
+    #[test]
+    fn rerolls_in_other_dialects() {
+        let from = |input, dialect| convert(input, dialect, Dialect::Rdr);
+        assert_eq!("4d6r<=2k3", from("4d6r<2kh3", Dialect::Roll20).unwrap());
+        assert_eq!("2d10r1!", from("2d10r1!", Dialect::Roll20).unwrap());
+        assert_eq!("4d6r<3", from("4d6rr<3", Dialect::Foundry).unwrap());
+        assert!(from("4d6ro1", Dialect::Roll20).is_err());
+        assert!(from("4d6r1", Dialect::Foundry).is_err());
+
+        let roll20 = |input| convert(input, Dialect::Rdr, Dialect::Roll20).unwrap();
+        assert_eq!("4d6r<2kh3", roll20("4d6r<3k3"));
+        assert_eq!("2d10r1!>10", roll20("2d10r1!>=10"));
+        let foundry = |input| convert(input, Dialect::Rdr, Dialect::Foundry).unwrap();
+        assert_eq!("4d6rr1", foundry("4d6r1"));
+        assert_eq!("4d6rr<=2x", foundry("4d6r<=2!"));
+    }
+}
@@ -0,0 +1,170 @@
+//! What changed between two rolls of the same expression: which dice came up
+//! differently, and how far the total moved. Dice are matched up by their
+//! [ids](crate::eval::Value::ids), which a reroll keeps and which two rolls of
+//! the same expression share, so this works as well for checking a reroll
+//! against the original as for auditing a replay.
+//!
+//! ```
+//! use rand::{rngs::StdRng, SeedableRng};
+//! use recursive_dice_roller::parse;
+//!
+//! let exp = parse("3d6 + 2").unwrap();
+//! let first = exp.evaluate(&mut StdRng::seed_from_u64(1));
+//! let mut second = first.clone();
+//! second.reroll(&[0], &mut StdRng::seed_from_u64(2));
+//! let diff = first.diff(&second);
+//! assert!(diff.changes.iter().all(|change| change.id == 0));
+//! assert_eq!(second.value() - first.value(), diff.moved());
+//! ```
+
+use serde::Serialize;
+use std::fmt::{self, Display};
+
+use crate::eval::{Die, Int, Value};
+
+/// How two rolls differ
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Diff {
+    pub before: Int,
+    pub after: Int,
+    /// Every die that isn't the same in both, in order of id
+    pub changes: Vec<Change>,
+}
+
+/// One die that came up differently, was kept or dropped differently, or
+/// was only rolled in one of the two
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+pub struct Change {
+    pub id: usize,
+    pub sides: u32,
+    /// How the die landed the first time, if it was rolled at all
+    pub before: Option<Face>,
+    pub after: Option<Face>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub struct Face {
+    pub value: i32,
+    pub kept: bool,
+}
+
+impl Value {
+    /// What's different about `other`, which is usually the same expression
+    /// rolled again or with some of its dice rerolled
+    pub fn diff(&self, other: &Value) -> Diff {
+        let before: Vec<Die> = self.dice().collect();
+        let after: Vec<Die> = other.dice().collect();
+        let mut ids: Vec<usize> = before.iter().chain(&after).map(|die| die.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        let find = |dice: &[Die], id| dice.iter().find(|die| die.id == id).copied();
+        let face = |die: Die| Face {
+            value: die.value,
+            kept: die.kept,
+        };
+        let changes = ids
+            .into_iter()
+            .filter_map(|id| {
+                let (before, after) = (find(&before, id), find(&after, id));
+                let sides = after.or(before).map_or(0, |die| die.sides);
+                let (before, after) = (before.map(face), after.map(face));
+                (before != after).then_some(Change {
+                    id,
+                    sides,
+                    before,
+                    after,
+                })
+            })
+            .collect();
+        Diff {
+            before: self.value(),
+            after: other.value(),
+            changes,
+        }
+    }
+}
+
+impl Diff {
+    /// How far the total moved, up or down
+    pub fn moved(&self) -> Int {
+        self.after - self.before
+    }
+}
+
+impl Display for Face {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kept {
+            true => write!(f, "{}", self.value),
+            false => write!(f, "{} (dropped)", self.value),
+        }
+    }
+}
+
+/// A line per changed die, then the totals
+impl Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let face = |face: Option<Face>| face.map_or("not rolled".to_string(), |f| f.to_string());
+        for change in &self.changes {
+            writeln!(
+                f,
+                "die {} (d{}): {} -> {}",
+                change.id,
+                change.sides,
+                face(change.before),
+                face(change.after)
+            )?;
+        }
+        write!(
+            f,
+            "total: {} -> {} ({:+})",
+            self.before,
+            self.after,
+            self.moved()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::*;
+    use crate::parse::parse;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn rerolls_and_replays() {
+        let exp = parse("3d6 + 2").unwrap();
+        let first = exp.evaluate(&mut StdRng::seed_from_u64(3));
+        assert_eq!(Vec::<Change>::new(), first.diff(&first).changes);
+
+        let mut rerolled = first.clone();
+        let mut rng = StdRng::seed_from_u64(4);
+        while rerolled.faces()[1] == first.faces()[1] {
+            rerolled.reroll(&[1], &mut rng);
+        }
+        let diff = first.diff(&rerolled);
+        assert_eq!(1, diff.changes.len());
+        assert_eq!(1, diff.changes[0].id);
+        assert_eq!(6, diff.changes[0].sides);
+        assert_eq!(rerolled.value() - first.value(), diff.moved());
+
+        // a whole different number of dice is dice only one of them rolled
+        let fewer = parse("2d6 + 2").unwrap().evaluate(&mut rng);
+        let diff = first.diff(&fewer);
+        assert_eq!(None, diff.changes.last().unwrap().after);
+        assert!(diff.to_string().ends_with(&format!("({:+})", diff.moved())));
+    }
+
+    #[test]
+    fn dropped_dice() {
+        let exp = parse("2d20k1").unwrap();
+        let first = exp.evaluate(&mut StdRng::seed_from_u64(5));
+        let mut second = first.clone();
+        let mut rng = StdRng::seed_from_u64(6);
+        while second.value() == first.value() {
+            second.reroll(&[0, 1], &mut rng);
+        }
+        let diff = first.diff(&second);
+        assert!(!diff.changes.is_empty());
+        assert!(diff.changes.iter().all(|change| change.sides == 20));
+    }
+}
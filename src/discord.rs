@@ -0,0 +1,165 @@
+//! A Discord bot that answers `/roll <expression>` with the Discord-flavored
+//! renderer. Only built with the `discord` feature, since it needs an async
+//! runtime and a TLS stack that nothing else here has any use for.
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serenity::all::{
+    Client, Command, CommandInteraction, CommandOptionType, Context, CreateCommand,
+    CreateCommandOption, CreateInteractionResponse, CreateInteractionResponseMessage, EventHandler,
+    GatewayIntents, Interaction, Ready,
+};
+use serenity::async_trait;
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::eval::Limits;
+use crate::formats;
+use crate::parse::parse;
+use crate::quota::Quota;
+use crate::render::RenderOptions;
+
+/// Discord refuses messages longer than this many characters
+const MAX_MESSAGE: usize = 2000;
+
+/// Anyone in the server can invoke the bot, so rolls are capped well below
+/// what the command line allows
+const LIMITS: Limits = Limits {
+    max_dice: 1000,
+    max_depth: 50,
+    timeout: Some(Duration::from_secs(1)),
+    summarize_over: 100,
+    max_explosions: 20,
+};
+
+/// Each user can save up this many dice, which come back at the rate below
+const QUOTA: u64 = 3000;
+const QUOTA_PER_SECOND: u64 = 50;
+
+struct Handler {
+    quota: Mutex<Quota>,
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, ready: Ready) {
+        let command = CreateCommand::new("roll")
+            .description("Roll a dice expression")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "expression",
+                    "Something like 2d20kh1+5",
+                )
+                .required(true),
+            );
+        match Command::create_global_command(&ctx.http, command).await {
+            Ok(_) => eprintln!("connected as {}", ready.user.name),
+            Err(e) => eprintln!("could not register /roll: {e}"),
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let Interaction::Command(command) = interaction else {
+            return;
+        };
+        if command.data.name != "roll" {
+            return;
+        }
+        let user = command.user.id.to_string();
+        let message = CreateInteractionResponseMessage::new().content(reply(
+            expression(&command),
+            &user,
+            &self.quota,
+        ));
+        if let Err(e) = command
+            .create_response(&ctx.http, CreateInteractionResponse::Message(message))
+            .await
+        {
+            eprintln!("could not reply to /roll: {e}");
+        }
+    }
+}
+
+fn expression(command: &CommandInteraction) -> &str {
+    command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "expression")
+        .and_then(|option| option.value.as_str())
+        .unwrap_or_default()
+}
+
+/// The message to send back for an expression, whether it rolled or not
+fn reply(expression: &str, user: &str, quota: &Mutex<Quota>) -> String {
+    let parsed = match parse(expression) {
+        Ok(parsed) => parsed,
+        Err(message) => return format!("```\n{message}\n```"),
+    };
+    let charged = quota
+        .lock()
+        .expect("no thread panics while holding the quota")
+        .charge(user, &parsed, &LIMITS);
+    if let Err(message) = charged {
+        return message;
+    }
+    // a seed of its own, shown with the result, lets anyone check the roll
+    // with `rdr --seed`
+    let seed = rand::random();
+    let evaluated = match parsed.evaluate_within(&mut StdRng::seed_from_u64(seed), &LIMITS) {
+        Ok(evaluated) => evaluated,
+        Err(error) => return error.to_string(),
+    };
+    let options = RenderOptions {
+        max_output: Some(MAX_MESSAGE),
+        seed: Some(seed),
+        ..Default::default()
+    };
+    let renderer = formats::renderer("discord").expect("the discord renderer is registered");
+    match (renderer.render)(&evaluated, &options) {
+        Ok(rendered) => rendered,
+        Err(e) => e.to_string(),
+    }
+}
+
+/// Connects to Discord and answers commands until the process is killed
+pub fn run(token: &str) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Could not start the bot: {e}"))?;
+    runtime.block_on(async {
+        let mut client = Client::builder(token, GatewayIntents::empty())
+            .event_handler(Handler {
+                quota: Mutex::new(Quota::new(QUOTA, QUOTA_PER_SECOND)),
+            })
+            .await
+            .map_err(|e| format!("Could not create the bot: {e}"))?;
+        client
+            .start()
+            .await
+            .map_err(|e| format!("The bot stopped: {e}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::discord::*;
+
+    #[test]
+    fn replies() {
+        let quota = Mutex::new(Quota::new(QUOTA, QUOTA_PER_SECOND));
+        let reply = |expression: &str| reply(expression, "someone", &quota);
+        assert!(reply("2d20kh1+5").starts_with("`2d20k1 + 5` \u{2192} **"));
+        assert!(reply("d20").contains("\n-# seed "));
+        assert!(reply("1000d6 + 1d6").contains("more than 1000 dice"));
+        assert!(reply("2d").starts_with("```"));
+        assert!(reply("999999d999999").contains("more than the 3000 allowed"));
+        let huge = "2147483647 * 2147483647 * 2147483647 * 2147483647 * 2147483647";
+        assert!(reply(huge).contains("too big"));
+        assert!(reply(&"9".repeat(40)).contains("too big"));
+        assert!(reply("1000d6 + 1000d6").starts_with("Slow down!"));
+    }
+}
@@ -0,0 +1,313 @@
+//! A structured snapshot of an evaluated expression, for consumers that want
+//! to do their own presentation rather than read a pre-rendered tree
+
+use std::collections::BTreeMap;
+
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::eval::{Explode, Int, KeptRule, Operation, Pick, Value};
+use crate::modifier::Summary;
+use crate::parse::{SourceMap, Span};
+
+#[derive(Debug, Serialize)]
+pub struct Document {
+    pub expression: String,
+    pub total: Int,
+    pub breakdown: Node,
+    /// The seed the dice were rolled with, when there was one to tell
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// The [integrity hash](crate::integrity) of the roll, which comes with
+    /// the seed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+}
+
+impl Document {
+    pub fn new(value: &Value) -> Self {
+        Document {
+            expression: value.to_string(),
+            total: value.value(),
+            breakdown: Node::new(value),
+            seed: None,
+            hash: None,
+        }
+    }
+
+    /// A document whose nodes each say where they were written, given the
+    /// source map that came with the expression the value was rolled from
+    pub fn mapped(value: &Value, map: &SourceMap) -> Self {
+        Document {
+            expression: value.to_string(),
+            total: value.value(),
+            breakdown: Node::numbered(value, Some(map), &mut 0),
+            seed: None,
+            hash: None,
+        }
+    }
+}
+
+/// The documents of several named results, which serialize as one object
+/// keyed by name, in the order the results were written
+#[derive(Debug)]
+pub struct Named {
+    pub results: Vec<(String, Document)>,
+}
+
+impl Serialize for Named {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.results.len()))?;
+        for (name, document) in &self.results {
+            map.serialize_entry(name, document)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Node {
+    Const {
+        value: Int,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        span: Option<Span>,
+    },
+    Roll {
+        /// Numbers the roll nodes in the order their dice were rolled
+        id: usize,
+        expression: String,
+        result: Int,
+        dice: Box<Node>,
+        sides: Box<Node>,
+        keep: KeepRule,
+        kept: Vec<i32>,
+        dropped: Vec<i32>,
+        /// The stable id of each die in `kept`, and then in `dropped`, which
+        /// also comes with every die reported while rolling
+        kept_ids: Vec<usize>,
+        dropped_ids: Vec<usize>,
+        /// How many dice showed each face, instead of `kept` and `dropped`,
+        /// for a roll too big to list every die
+        #[serde(skip_serializing_if = "Option::is_none")]
+        summary: Option<Summary>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        explode: Option<Box<Explosions>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reroll: Option<Box<Rerolls>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        span: Option<Span>,
+    },
+    Op {
+        expression: String,
+        result: Int,
+        operation: &'static str,
+        terms: Vec<Node>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        span: Option<Span>,
+    },
+    /// An expression rolled several times over, with every time it was
+    /// rolled in `repetitions`
+    Repeat {
+        expression: String,
+        result: Int,
+        /// `best` or `worst`
+        pick: &'static str,
+        keep: u32,
+        repetitions: Vec<Node>,
+        /// Which of the repetitions count towards the result
+        counted: Vec<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        span: Option<Span>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct KeepRule {
+    pub rule: &'static str,
+    pub count: Box<Node>,
+    /// The percentage of the pool that's kept, for rules like `kh50%`,
+    /// which `count` is worked out from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share: Option<u32>,
+}
+
+/// How a roll's dice exploded
+#[derive(Debug, Serialize)]
+pub struct Explosions {
+    /// `extra` or `compound`
+    pub rule: &'static str,
+    /// How the faces that explode compare to the threshold, like `>=`, if
+    /// it isn't only the highest face that explodes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comparison: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<Node>,
+    /// Every face each die that exploded landed on, keyed by the die's id
+    pub chains: BTreeMap<usize, Vec<i32>>,
+    /// Whether a die stopped exploding only because it hit the limit
+    pub capped: bool,
+}
+
+/// How a roll's dice were rerolled
+#[derive(Debug, Serialize)]
+pub struct Rerolls {
+    /// How the faces that are rerolled compare to the threshold, like `<`
+    pub comparison: String,
+    pub threshold: Node,
+    /// The faces each rerolled die landed on before the one it shows, keyed
+    /// by the die's id
+    pub discarded: BTreeMap<usize, Vec<i32>>,
+    /// Whether a die stopped being rerolled only because it hit the limit
+    pub capped: bool,
+}
+
+impl Node {
+    pub fn new(value: &Value) -> Self {
+        Node::numbered(value, None, &mut 0)
+    }
+
+    /// Builds the node, numbering the rolls in the same order that evaluation
+    /// rolls them: sides, then dice, then what the dice explode on, then what
+    /// they're rerolled on, then the roll itself, then its keep count
+    fn numbered(value: &Value, map: Option<&SourceMap>, next_id: &mut usize) -> Self {
+        let span = map.map(|map| map.span);
+        let child = |i: usize| map.and_then(|map| map.children.get(i));
+        match value {
+            Value::Const(c) => Node::Const { value: *c, span },
+            Value::Rolled(rolled) => {
+                let sides = Node::numbered(&rolled.sides, child(1), next_id);
+                let dice = Node::numbered(&rolled.dice, child(0), next_id);
+                let on = rolled
+                    .exploded
+                    .as_ref()
+                    .and_then(|exploded| exploded.on.as_ref());
+                let threshold = on.map(|on| Node::numbered(&on.against, child(3), next_id));
+                let reroll = rolled.rerolled.as_ref().map(|rerolled| {
+                    Box::new(Rerolls {
+                        comparison: rerolled.on.comparison.to_string(),
+                        threshold: Node::numbered(&rerolled.on.against, child(4), next_id),
+                        discarded: rerolled.discarded.clone(),
+                        capped: rerolled.capped,
+                    })
+                });
+                let id = *next_id;
+                *next_id += 1;
+                let kept = rolled.kept.kept().len().min(rolled.kept.ids.len());
+                let (kept_ids, dropped_ids) = rolled.kept.ids.split_at(kept);
+                Node::Roll {
+                    id,
+                    expression: value.to_string(),
+                    result: rolled.val(),
+                    dice: Box::new(dice),
+                    sides: Box::new(sides),
+                    keep: KeepRule {
+                        rule: match rolled.kept.keep {
+                            KeptRule::All => "all",
+                            KeptRule::Lowest(_) | KeptRule::LowestShare(..) => "lowest",
+                            KeptRule::Highest(_) | KeptRule::HighestShare(..) => "highest",
+                            KeptRule::DropLowest(_) => "drop_lowest",
+                            KeptRule::DropHighest(_) => "drop_highest",
+                        },
+                        count: Box::new(Node::numbered(&rolled.kept.retained, child(2), next_id)),
+                        share: match rolled.kept.keep {
+                            KeptRule::LowestShare(percent, _)
+                            | KeptRule::HighestShare(percent, _) => Some(percent),
+                            _ => None,
+                        },
+                    },
+                    kept: rolled.kept.kept().to_vec(),
+                    dropped: rolled.kept.dropped().to_vec(),
+                    kept_ids: kept_ids.to_vec(),
+                    dropped_ids: dropped_ids.to_vec(),
+                    summary: rolled.kept.summary.clone(),
+                    explode: rolled.exploded.as_ref().map(|exploded| {
+                        Box::new(Explosions {
+                            rule: match exploded.explode {
+                                Explode::Extra => "extra",
+                                Explode::Compound => "compound",
+                            },
+                            comparison: on.map(|on| on.comparison.to_string()),
+                            threshold,
+                            chains: exploded.chains.clone(),
+                            capped: exploded.capped,
+                        })
+                    }),
+                    reroll,
+                    span,
+                }
+            }
+            Value::Op { op, values } => Node::Op {
+                expression: value.to_string(),
+                result: value.value(),
+                operation: match op {
+                    Operation::Add => "add",
+                    Operation::Sub => "sub",
+                    Operation::Mul => "mul",
+                },
+                terms: values
+                    .iter()
+                    .enumerate()
+                    .map(|(i, value)| Node::numbered(value, child(i), next_id))
+                    .collect(),
+                span,
+            },
+            Value::Repeated { pick, values } => {
+                let (name, keep) = match *pick {
+                    Pick::Best(keep) => ("best", keep),
+                    Pick::Worst(keep) => ("worst", keep),
+                };
+                Node::Repeat {
+                    expression: value.to_string(),
+                    result: value.value(),
+                    pick: name,
+                    keep,
+                    // every repetition was written in the same place
+                    repetitions: values
+                        .iter()
+                        .map(|value| Node::numbered(value, child(0), next_id))
+                        .collect(),
+                    counted: pick.counted(values),
+                    span,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::document::*;
+    use crate::parse::{parse_mapped, parse_named};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn nodes_know_where_they_were_written() {
+        let (exp, map) = parse_mapped("2d20k1 + 5").unwrap();
+        let value = exp.evaluate(&mut StdRng::seed_from_u64(0));
+        let json = serde_json::to_value(Document::mapped(&value, &map)).unwrap();
+        let breakdown = &json["breakdown"];
+        assert_eq!(0, breakdown["span"]["start"]);
+        assert_eq!(10, breakdown["span"]["end"]);
+        assert_eq!(6, breakdown["terms"][0]["span"]["end"]);
+        assert_eq!(5, breakdown["terms"][0]["keep"]["count"]["span"]["start"]);
+        assert_eq!(9, breakdown["terms"][1]["span"]["start"]);
+        // without a map, there's nothing to say
+        let json = serde_json::to_value(Document::new(&value)).unwrap();
+        assert!(json["breakdown"].get("span").is_none());
+    }
+
+    #[test]
+    fn named_results_keep_their_order() {
+        let results = parse_named("zeta = 1; alpha = 2")
+            .unwrap()
+            .into_iter()
+            .map(|(name, exp)| {
+                let value = exp.evaluate(&mut StdRng::seed_from_u64(0));
+                (name, Document::new(&value))
+            })
+            .collect();
+        let json = serde_json::to_string(&Named { results }).unwrap();
+        assert!(json.starts_with("{\"zeta\":{"));
+        assert!(json.contains("},\"alpha\":{"));
+    }
+}
@@ -0,0 +1,101 @@
+//! The one error type every public function here returns
+
+use std::fmt::{self, Display};
+
+use crate::messages::Message;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The input isn't a valid expression. The position (in characters) is
+    /// where the problem was noticed, which is the end of the input when
+    /// something is missing.
+    Syntax { message: Message, position: usize },
+    /// Rolling went past one of the [`Limits`](crate::eval::Limits)
+    Limit(Message),
+    /// The request made no sense, like translating to an unknown dialect
+    Input(Message),
+    /// The result couldn't be written out
+    Render(String),
+}
+
+impl Error {
+    /// A short name for the kind of error, for anyone outside of Rust who
+    /// wants to tell them apart
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Syntax { .. } => "syntax",
+            Error::Limit(_) => "limit",
+            Error::Input(_) => "input",
+            Error::Render(_) => "render",
+        }
+    }
+
+    /// Where in the input a syntax error is
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            Error::Syntax { position, .. } => Some(*position),
+            _ => None,
+        }
+    }
+
+    /// Which message from the [catalog](crate::messages) this is, if it's
+    /// one of them
+    pub fn code(&self) -> Option<&'static str> {
+        match self {
+            Error::Syntax { message, .. } | Error::Limit(message) | Error::Input(message) => {
+                Some(message.code())
+            }
+            Error::Render(_) => None,
+        }
+    }
+}
+
+/// In the current [locale](crate::messages::locale)
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax { message, .. } | Error::Limit(message) | Error::Input(message) => {
+                write!(f, "{message}")
+            }
+            Error::Render(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Render(error.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(error: serde_json::Error) -> Self {
+        Error::Render(error.to_string())
+    }
+}
+
+/// The command line reports every error as a message, so this lets `?` work
+/// there without any ceremony
+impl From<Error> for String {
+    fn from(error: Error) -> Self {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::messages::Message;
+    use crate::parse::parse;
+
+    #[test]
+    fn syntax_errors_say_where() {
+        let error = parse("d20 + x").unwrap_err();
+        assert_eq!("syntax", error.kind());
+        assert_eq!(Some(6), error.position());
+        assert_eq!(Some("unexpected_symbol"), error.code());
+        assert_eq!(None, Error::Limit(Message::TooManyDice(10)).position());
+    }
+}
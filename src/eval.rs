@@ -1,4 +1,13 @@
-use std::{cell::RefCell, collections::VecDeque, fmt::Display, rc::Rc};
+//! The expression tree ([`Exp`]) and what it becomes once rolled ([`Value`]).
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, VecDeque},
+    fmt::Display,
+    rc::Rc,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
 use itertools::Itertools;
 
@@ -18,8 +27,22 @@ macro_rules! vec_deque {
 }
 
 use rand::Rng;
+use smallvec::SmallVec;
+
+use crate::error::Error;
+use crate::messages::Message;
+use crate::modifier::{self, Argument, Histogram, Modified, RollModifier, Summary};
+#[allow(unused_imports)]
 pub(crate) use vec_deque;
 
+/// The numbers expressions work in. Dice only ever show an `i32`, but with
+/// the `wide` feature, constants and totals are `i128`, so that huge
+/// multiplications come out exact rather than overflowing.
+#[cfg(not(feature = "wide"))]
+pub type Int = i32;
+#[cfg(feature = "wide")]
+pub type Int = i128;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Operation {
     Add,
@@ -44,6 +67,39 @@ impl Operation {
             Operation::Mul => 2,
         }
     }
+
+    /// `lhs` and `rhs` put together, unless that's too big for an [`Int`]
+    pub fn checked(&self, lhs: Int, rhs: Int) -> Option<Int> {
+        match self {
+            Operation::Add => lhs.checked_add(rhs),
+            Operation::Sub => lhs.checked_sub(rhs),
+            Operation::Mul => lhs.checked_mul(rhs),
+        }
+    }
+
+    /// `lhs` and `rhs` put together, stopping at the most (or least) an
+    /// [`Int`] holds
+    pub fn saturating(&self, lhs: Int, rhs: Int) -> Int {
+        match self {
+            Operation::Add => lhs.saturating_add(rhs),
+            Operation::Sub => lhs.saturating_sub(rhs),
+            Operation::Mul => lhs.saturating_mul(rhs),
+        }
+    }
+
+    /// The totals put together from left to right, or [`Message::Overflow`]
+    /// if that, or any step along the way, is too big for an [`Int`]
+    fn combined(
+        &self,
+        mut totals: impl Iterator<Item = Result<Int, Message>>,
+    ) -> Result<Int, Message> {
+        let first = totals
+            .next()
+            .expect("values is guaranteed to have at least one element")?;
+        totals.try_fold(first, |acc, total| {
+            self.checked(acc, total?).ok_or(Message::Overflow)
+        })
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -61,25 +117,99 @@ impl Op {
         self.arguments.borrow_mut().push_back(exp);
     }
 
-    fn value(&self, rng: &mut impl Rng) -> Value {
+    fn value(&self, rng: &mut impl Rng, guard: &mut Guard) -> Result<Value, Message> {
         let values = self
             .arguments
             .borrow()
             .iter()
-            .map(|subexpression| subexpression.evaluate(rng))
-            .collect();
-        Value::Op {
+            .map(|subexpression| subexpression.evaluate_guarded(rng, guard))
+            .collect::<Result<Vec<Value>, _>>()?;
+        // everything it's made of already fits, so only this total can't
+        self.operation
+            .combined(values.iter().map(|value| Ok(value.value())))?;
+        Ok(Value::Op {
             op: self.operation.clone(),
             values,
+        })
+    }
+}
+
+/// Which of a [`Repeat`]'s totals count, and how many of them
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Pick {
+    Best(u32),
+    Worst(u32),
+}
+
+impl Pick {
+    /// How many of `times` totals are kept
+    pub fn kept(&self, times: u32) -> u32 {
+        match *self {
+            Pick::Best(keep) | Pick::Worst(keep) => keep.min(times),
+        }
+    }
+
+    /// Whether each of the values counts towards the total, in the order they
+    /// were rolled. Of several that tie, the first to be rolled is kept.
+    pub fn counted(&self, values: &[Value]) -> Vec<bool> {
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        match self {
+            Pick::Best(_) => order.sort_by_key(|&i| std::cmp::Reverse(values[i].value())),
+            Pick::Worst(_) => order.sort_by_key(|&i| values[i].value()),
+        }
+        let mut counted = vec![false; values.len()];
+        for &i in order.iter().take(self.kept(values.len() as u32) as usize) {
+            counted[i] = true;
+        }
+        counted
+    }
+}
+
+impl Display for Pick {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pick::Best(keep) => write!(f, "best {keep}"),
+            Pick::Worst(keep) => write!(f, "worst {keep}"),
         }
     }
 }
 
+/// A whole expression rolled several times over, keeping only the best (or
+/// worst) of its totals, like `best 1 of 3x(4d6k3)`
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Repeat {
+    pub exp: Box<Exp>,
+    pub times: u32,
+    pub pick: Pick,
+}
+
+impl Repeat {
+    fn value(&self, rng: &mut impl Rng, guard: &mut Guard) -> Result<Value, Message> {
+        guard.repeat(self.times)?;
+        let values: Vec<Value> = (0..self.times)
+            .map(|_| self.exp.evaluate_guarded(rng, guard))
+            .collect::<Result<_, _>>()?;
+        // every repetition already fits, so only the totals that count, put
+        // together, can't
+        values
+            .iter()
+            .zip(self.pick.counted(&values))
+            .filter(|(_, counted)| *counted)
+            .try_fold(0 as Int, |acc, (value, _)| acc.checked_add(value.value()))
+            .ok_or(Message::Overflow)?;
+        Ok(Value::Repeated {
+            pick: self.pick,
+            values,
+        })
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Exp {
-    Const(i32),
+    Const(Int),
     Roll(Rc<RefCell<Roll>>),
     Op(Op),
+    Repeat(Repeat),
 }
 
 impl Exp {
@@ -108,12 +238,58 @@ impl Exp {
         })
     }
 
+    /// Evaluates the expression without any limits. It panics if a total is
+    /// too big for an [`Int`], so expressions from anyone else should go
+    /// through [`Exp::evaluate_within`].
     pub fn evaluate(&self, rng: &mut impl Rng) -> Value {
-        match self {
-            Exp::Const(value) => Value::Const(*value),
-            Exp::Roll(roll) => Value::Rolled(roll.borrow().val(rng)),
-            Exp::Op(op) => op.value(rng),
-        }
+        self.evaluate_within(rng, &Limits::default())
+            .expect("evaluation without limits only fails when a total overflows")
+    }
+
+    /// Evaluates the expression, giving up as soon as it goes past any of the
+    /// limits
+    pub fn evaluate_within(&self, rng: &mut impl Rng, limits: &Limits) -> Result<Value, Error> {
+        self.evaluate_guarded(rng, &mut Guard::new(limits, None))
+            .map_err(Error::Limit)
+    }
+
+    /// Like [`Exp::evaluate_within`], but tells the observer about every die
+    /// as soon as it's rolled, for front-ends that want to show the dice
+    /// landing one at a time
+    pub fn evaluate_observed(
+        &self,
+        rng: &mut impl Rng,
+        limits: &Limits,
+        observer: &mut dyn FnMut(DieRoll),
+    ) -> Result<Value, Error> {
+        self.evaluate_guarded(rng, &mut Guard::new(limits, Some(observer)))
+            .map_err(Error::Limit)
+    }
+
+    /// Evaluates the expression as though every die landed the way `assume`
+    /// says, rather than rolling them. The rng is only there for modifiers
+    /// that need one.
+    pub fn evaluate_assuming(
+        &self,
+        assume: Assume,
+        rng: &mut impl Rng,
+        limits: &Limits,
+    ) -> Result<Value, Error> {
+        let mut guard = Guard::new(limits, None);
+        guard.assume = Some(assume);
+        self.evaluate_guarded(rng, &mut guard).map_err(Error::Limit)
+    }
+
+    fn evaluate_guarded(&self, rng: &mut impl Rng, guard: &mut Guard) -> Result<Value, Message> {
+        guard.descend()?;
+        let value = match self {
+            Exp::Const(value) => Ok(Value::Const(*value)),
+            Exp::Roll(roll) => roll.borrow().val(rng, guard).map(Value::Rolled),
+            Exp::Op(op) => op.value(rng, guard),
+            Exp::Repeat(repeat) => repeat.value(rng, guard),
+        };
+        guard.depth -= 1;
+        value
     }
 }
 
@@ -123,67 +299,480 @@ impl Default for Exp {
     }
 }
 
+/// Bounds on how much work evaluating a single expression may do. The
+/// default is no bounds at all, except that a die only explodes (or is
+/// rerolled) [`MAX_EXPLOSIONS`] times.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// The most dice that may be rolled, counting every roll in the expression
+    pub max_dice: u64,
+    /// How deeply rolls and operations may be nested inside one another
+    pub max_depth: usize,
+    pub timeout: Option<Duration>,
+    /// Rolls of more dice than this keep only a [`Summary`] of them, rather
+    /// than every die
+    pub summarize_over: usize,
+    /// How many times a single die may explode, or be rerolled, before it's
+    /// left as it is. Without it, a die that always explodes, like `d1!!`,
+    /// never stops, and neither does one that's always rerolled, like `d6r<7`.
+    pub max_explosions: usize,
+}
+
+/// How many times a die explodes (or is rerolled) at most, unless the
+/// [`Limits`] say otherwise
+pub const MAX_EXPLOSIONS: usize = 100;
+
+/// How many times expressions may be repeated, like the `3x` of
+/// `best 1 of 3x(4d6k3)`, counting every repetition of every repeat in an
+/// expression. Repeating costs no dice of its own, so [`Limits::max_dice`]
+/// doesn't stop `best 1 of 100000000x(1)`.
+pub const MAX_REPETITIONS: u32 = 10_000;
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_dice: u64::MAX,
+            max_depth: usize::MAX,
+            timeout: None,
+            summarize_over: usize::MAX,
+            max_explosions: MAX_EXPLOSIONS,
+        }
+    }
+}
+
+/// A single die, as reported to the observer passed to
+/// [`Exp::evaluate_observed`]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DieRoll {
+    /// Which roll node the die belongs to. Nodes are numbered from zero in
+    /// the order their dice are rolled, which is the same numbering as the
+    /// `id` of roll nodes in a [`Document`](crate::document::Document).
+    pub node: usize,
+    /// The die's own id, numbering every die in the order it was rolled.
+    /// It's the same id the die has in [`Kept::ids`].
+    pub id: usize,
+    pub sides: u32,
+    pub value: i32,
+}
+
+/// One die of an evaluated [`Value`], as [`Value::dice`] lists them
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Die {
+    /// The same id as in [`Kept::ids`]
+    pub id: usize,
+    pub sides: u32,
+    pub value: i32,
+    /// Whether the die counts towards the total, rather than being dropped
+    pub kept: bool,
+}
+
+/// What every die shows when an expression is evaluated without rolling
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Assume {
+    /// Its highest face, for effects that maximize damage
+    Max,
+    /// Its lowest face
+    Min,
+    /// Its average, the way stat blocks work out average damage: when that
+    /// falls between two faces, a pool's dice alternate between rounding down
+    /// and up, starting with down, so the pool as a whole comes to its
+    /// average rounded down (`2d6` is 7, and `3d6` is 10)
+    Avg,
+    /// The middle face, or the lower of the two middle ones
+    Median,
+    /// The same face every time, brought within the die's faces if it's
+    /// outside them, so an expression's structure can be checked without any
+    /// randomness getting in the way
+    Face(i32),
+}
+
+impl FromStr for Assume {
+    type Err = String;
+
+    /// `max`, `min`, `avg`, `median`, or the number of a face
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(Assume::Max),
+            "min" => Ok(Assume::Min),
+            "avg" => Ok(Assume::Avg),
+            "median" => Ok(Assume::Median),
+            face => face.parse().map(Assume::Face).map_err(|_| {
+                format!("'{face}' is not max, min, avg, median, or the number of a face")
+            }),
+        }
+    }
+}
+
+impl Assume {
+    /// What the `i`th die of a pool shows
+    fn face(self, sides: u32, i: usize) -> i32 {
+        match self {
+            Assume::Max => sides as i32,
+            Assume::Min => sides.min(1) as i32,
+            Assume::Avg => {
+                // the pool's running total after this die, less the total
+                // before it
+                let total = |dice: u64| (dice * (sides as u64 + 1) / 2) as i32;
+                total(i as u64 + 1) - total(i as u64)
+            }
+            Assume::Median => sides.div_ceil(2) as i32,
+            Assume::Face(face) => face.clamp(sides.min(1) as i32, sides as i32),
+        }
+    }
+}
+
+/// Keeps track of how close an evaluation is to its limits
+struct Guard<'a> {
+    limits: &'a Limits,
+    dice: u64,
+    depth: usize,
+    deadline: Option<Instant>,
+    /// How many roll nodes have rolled their dice so far
+    nodes: usize,
+    /// How many times repeated expressions have been rolled so far
+    repetitions: u32,
+    /// The id for the next die to be rolled
+    next_die: usize,
+    observer: Option<&'a mut dyn FnMut(DieRoll)>,
+    /// Set when the dice aren't rolled at all
+    assume: Option<Assume>,
+}
+
+impl<'a> Guard<'a> {
+    fn new(limits: &'a Limits, observer: Option<&'a mut dyn FnMut(DieRoll)>) -> Self {
+        Guard {
+            limits,
+            dice: 0,
+            depth: 0,
+            // the clock isn't available on every platform (wasm, notably), so
+            // it's only consulted when there's a timeout to enforce
+            deadline: limits.timeout.map(|timeout| Instant::now() + timeout),
+            nodes: 0,
+            repetitions: 0,
+            next_die: 0,
+            observer,
+            assume: None,
+        }
+    }
+
+    fn descend(&mut self) -> Result<(), Message> {
+        self.depth += 1;
+        if self.depth > self.limits.max_depth {
+            return Err(Message::TooDeep(self.limits.max_depth));
+        }
+        self.check_deadline()
+    }
+
+    fn roll(&mut self, dice: u64) -> Result<(), Message> {
+        self.dice = self.dice.saturating_add(dice);
+        if self.dice > self.limits.max_dice {
+            return Err(Message::TooManyDice(self.limits.max_dice));
+        }
+        self.check_deadline()
+    }
+
+    fn repeat(&mut self, times: u32) -> Result<(), Message> {
+        self.repetitions = self.repetitions.saturating_add(times);
+        if self.repetitions > MAX_REPETITIONS {
+            return Err(Message::TooManyRepetitions(MAX_REPETITIONS));
+        }
+        self.check_deadline()
+    }
+
+    fn check_deadline(&self) -> Result<(), Message> {
+        match (self.deadline, self.limits.timeout) {
+            (Some(deadline), Some(timeout)) if Instant::now() > deadline => {
+                Err(Message::TimedOut(timeout.as_millis()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The dice of a single roll while it's being made. Most rolls have only a
+/// handful, which fit without a trip to the allocator.
+pub(crate) type Dice = SmallVec<[i32; 16]>;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Keep {
     Lowest(Exp),
     Highest(Exp),
+    /// A percentage of the pool, like `kl50%`, however many dice it has
+    LowestShare(u32),
+    HighestShare(u32),
+    /// Every die but the lowest few, like `dl1`
+    DropLowest(Exp),
+    DropHighest(Exp),
     All,
 }
 
+/// What happens to a die that explodes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Explode {
+    /// `!`, where every die rolled again is an extra die in the pool, and
+    /// can be kept or dropped like any other
+    Extra,
+    /// `!!`, where every die rolled again is added into the one that
+    /// exploded, so a d6 that rolls `6, 6, 3` is a single die showing 15
+    Compound,
+}
+
+impl Explode {
+    /// The name of the [`RollModifier`] that explodes dice this way
+    pub fn name(self) -> &'static str {
+        match self {
+            Explode::Extra => "!",
+            Explode::Compound => "!!",
+        }
+    }
+}
+
+impl Display for Explode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// How a die's face is held up against a number, as in the `>=` of `!>=8`
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Comparison {
+    Equal,
+    Less,
+    AtMost,
+    Greater,
+    AtLeast,
+}
+
+impl Comparison {
+    pub fn holds<T: PartialOrd>(self, face: T, against: T) -> bool {
+        match self {
+            Comparison::Equal => face == against,
+            Comparison::Less => face < against,
+            Comparison::AtMost => face <= against,
+            Comparison::Greater => face > against,
+            Comparison::AtLeast => face >= against,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Comparison::Equal => write!(f, "="),
+            Comparison::Less => write!(f, "<"),
+            Comparison::AtMost => write!(f, "<="),
+            Comparison::Greater => write!(f, ">"),
+            Comparison::AtLeast => write!(f, ">="),
+        }
+    }
+}
+
+/// A test for each die, like the `>=8` in `10d10!>=8`. Before it's rolled,
+/// what the dice are held up against is an [`Exp`], and after, a [`Value`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Condition<T> {
+    pub comparison: Comparison,
+    pub against: T,
+}
+
+/// Dice that are rolled again when they land on certain faces
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Explosion {
+    pub explode: Explode,
+    /// The faces that explode, or just the highest one if there's no
+    /// condition
+    pub on: Option<Condition<Exp>>,
+}
+
+impl Explosion {
+    pub fn new(explode: Explode) -> Self {
+        Explosion { explode, on: None }
+    }
+}
+
+/// A modifier that rolls dice again, like an explosion or a reroll, with the
+/// faces it rolls them again on worked out, ready to apply to one die at a
+/// time
+#[derive(Clone, Copy)]
+struct Again {
+    modifier: &'static dyn RollModifier,
+    argument: Argument,
+}
+
+impl Again {
+    fn new(name: &str, sides: u32, on: Option<(Comparison, Int)>, most: usize) -> Self {
+        Again {
+            modifier: registered(name),
+            argument: Argument {
+                sides,
+                on,
+                most,
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Applies the modifier to a die that's just landed on `face`, which is
+    /// the die at position 0 of what comes back
+    fn settle(&self, face: i32, rng: &mut impl Rng) -> Modified {
+        self.modifier.apply(&[face], self.argument, rng)
+    }
+}
+
+/// The modifier with this name, which every rule that can be written has
+fn registered(name: &str) -> &'static dyn RollModifier {
+    modifier::modifier(name).expect("every rule's modifier is registered")
+}
+
+/// How many of a pool of `dice` a percentage of it comes to, rounding up, so
+/// that half of five dice is three
+pub(crate) fn share(percent: u32, dice: usize) -> usize {
+    dice.saturating_mul(percent as usize).div_ceil(100)
+}
+
 impl Keep {
-    fn retain(&self, elements: &[i32], rng: &mut impl Rng) -> Kept {
-        // get the number of elements to retain
-        // let retained = self.retain.evaluate(rng);
+    /// How many dice are kept, or dropped, when that's an expression
+    pub fn count(&self) -> Option<&Exp> {
+        match self {
+            Keep::Lowest(exp)
+            | Keep::Highest(exp)
+            | Keep::DropLowest(exp)
+            | Keep::DropHighest(exp) => Some(exp),
+            _ => None,
+        }
+    }
+
+    fn retain(
+        &self,
+        elements: Dice,
+        ids: Vec<usize>,
+        rng: &mut impl Rng,
+        guard: &mut Guard,
+    ) -> Result<Kept, Message> {
+        // get the number of elements to retain. Keeping everything is by far
+        // the most common case, and the dice can go straight into the result
         let retained = match self {
-            Keep::Lowest(exp) => exp.evaluate(rng),
-            Keep::Highest(exp) => exp.evaluate(rng),
+            Keep::Lowest(exp)
+            | Keep::Highest(exp)
+            | Keep::DropLowest(exp)
+            | Keep::DropHighest(exp) => exp.evaluate_guarded(rng, guard)?,
+            Keep::LowestShare(percent) | Keep::HighestShare(percent) => {
+                Value::Const(share(*percent, elements.len()) as Int)
+            }
             Keep::All => {
-                // scramble the results if we keep all
-
-                return Kept {
+                return Ok(Kept {
                     keep: KeptRule::All,
-                    retained: Value::Const(elements.len() as i32),
+                    retained: Value::Const(elements.len() as Int),
                     lowest: Vec::new(),
-                    highest: elements.to_vec(),
-                };
+                    highest: elements.into_vec(),
+                    ids,
+                    summary: None,
+                })
             }
         };
 
         // make sure that we are keeping a legal number of elements. The number
         // must be between zero (inclusive) and the total number of elements
         // available
-        let n = (retained.value().max(0) as usize).min(elements.len());
+        let n = Value::Const((retained.value().max(0) as usize).min(elements.len()) as Int);
+        let keep = self.rule(n);
+        Ok(Kept::new(keep, retained, elements, &ids, rng))
+    }
 
-        // calculate the index at which to split the slice
-        let index = match &self {
-            Keep::Lowest(_) => n,
-            Keep::Highest(_) => elements.len() - n,
-            Keep::All => unreachable!("variant was handled earlier"),
+    /// The same as [`Keep::retain`], for a pool that's only a histogram
+    fn summarize(
+        &self,
+        faces: Histogram,
+        rng: &mut impl Rng,
+        guard: &mut Guard,
+    ) -> Result<Kept, Message> {
+        let count = faces.values().sum::<u64>() as usize;
+        let retained = match self {
+            Keep::Lowest(exp)
+            | Keep::Highest(exp)
+            | Keep::DropLowest(exp)
+            | Keep::DropHighest(exp) => exp.evaluate_guarded(rng, guard)?,
+            Keep::LowestShare(percent) | Keep::HighestShare(percent) => {
+                Value::Const(share(*percent, count) as Int)
+            }
+            Keep::All => {
+                let retained = Value::Const(count as Int);
+                return Ok(Kept::summarized(KeptRule::All, retained, faces, rng));
+            }
         };
+        let n = Value::Const((retained.value().max(0) as usize).min(count) as Int);
+        Ok(Kept::summarized(self.rule(n), retained, faces, rng))
+    }
 
-        // split the slice
-        let (lowest, highest) = elements.split_at(index);
-
-        // return all of this nonsense
-        let n = Value::Const(n as i32);
-        Kept {
-            keep: match &self {
-                Keep::Lowest(_) => KeptRule::Lowest(n),
-                Keep::Highest(_) => KeptRule::Highest(n),
-                Keep::All => unreachable!("variant was handled earlier"),
-            },
-            retained,
-            lowest: lowest.to_vec(),
-            highest: highest.to_vec(),
+    /// The rule that keeps (or drops) `n` dice
+    fn rule(&self, n: Value) -> KeptRule {
+        match self {
+            Keep::Lowest(_) => KeptRule::Lowest(n),
+            Keep::Highest(_) => KeptRule::Highest(n),
+            Keep::LowestShare(percent) => KeptRule::LowestShare(*percent, n),
+            Keep::HighestShare(percent) => KeptRule::HighestShare(*percent, n),
+            Keep::DropLowest(_) => KeptRule::DropLowest(n),
+            Keep::DropHighest(_) => KeptRule::DropHighest(n),
+            Keep::All => KeptRule::All,
         }
     }
 }
 
+/// Pools at least this big are rolled with [`roll_dice`] rather than one die
+/// at a time
+const BULK: usize = 4096;
+
+/// How many sides dice have when the expression for their sides comes to
+/// this. Past what a `u32` holds, they have as many as a die can.
+#[allow(clippy::useless_conversion)]
+fn die_sides(value: Int) -> u32 {
+    u32::try_from(value.unsigned_abs()).unwrap_or(u32::MAX)
+}
+
+/// Rolls a single die
+pub(crate) fn roll_die(sides: u32, rng: &mut (impl Rng + ?Sized)) -> i32 {
+    face(rng.next_u32(), sides)
+}
+
+/// The face a die lands on for a random number
+fn face(random: u32, sides: u32) -> i32 {
+    // zero-sided die means a value of zero because I get to make the rules
+    if sides == 0 {
+        return 0;
+    }
+    // wrap zeros around to the max value because dice are 1-indexed. This is
+    // a weird way to do it but it makes testing easier
+    let mut result = random % sides;
+    if result == 0 {
+        result = sides;
+    }
+    result as i32
+}
+
+/// Rolls a whole pool at once, filling a buffer with random numbers a block
+/// at a time and turning them all into faces in one pass, which the compiler
+/// is free to vectorize. The block generators `rand` uses hand out the same
+/// numbers either way, so a seed rolls the same dice as [`roll_die`] would.
+fn roll_dice(sides: u32, count: usize, rng: &mut impl Rng, mut add: impl FnMut(i32)) {
+    let mut buffer = [0u32; BULK];
+    let mut left = count;
+    while left > 0 {
+        let chunk = &mut buffer[..left.min(BULK)];
+        rng.fill(chunk);
+        chunk.iter().for_each(|&random| add(face(random, sides)));
+        left -= chunk.len();
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Roll {
     pub dice: Exp,
     pub sides: Exp,
     pub keep: Keep,
+    pub explode: Option<Explosion>,
+    /// The faces that are rolled again until the die lands on another, as
+    /// in the `r<3` of `4d6r<3`
+    pub reroll: Option<Condition<Exp>>,
 }
 
 impl Roll {
@@ -192,6 +781,8 @@ impl Roll {
             dice,
             sides,
             keep: Keep::All,
+            explode: None,
+            reroll: None,
         }
     }
 
@@ -202,6 +793,8 @@ impl Roll {
             dice,
             sides,
             keep: Keep::Highest(highest),
+            explode: None,
+            reroll: None,
         }
     }
 
@@ -212,51 +805,191 @@ impl Roll {
             dice,
             sides,
             keep: Keep::Lowest(lowest),
+            explode: None,
+            reroll: None,
         }
     }
 
-    fn val(&self, rng: &mut impl Rng) -> Rolled {
+    fn val(&self, rng: &mut impl Rng, guard: &mut Guard) -> Result<Rolled, Message> {
         // first we need to evaluate how many sides the die has
-        let sides = self.sides.evaluate(rng);
-        let _sides = sides.value().unsigned_abs();
+        let sides = self.sides.evaluate_guarded(rng, guard)?;
+        let _sides = die_sides(sides.value());
 
         // then we need to determine the number of dice
-        let dice = self.dice.evaluate(rng);
+        let dice = self.dice.evaluate_guarded(rng, guard)?;
+        guard.roll(dice.value().max(0) as u64)?;
+
+        // and which faces explode, if any do
+        let on = match self
+            .explode
+            .as_ref()
+            .and_then(|explosion| explosion.on.as_ref())
+        {
+            Some(on) => Some(Condition {
+                comparison: on.comparison,
+                against: Box::new(on.against.evaluate_guarded(rng, guard)?),
+            }),
+            None => None,
+        };
+        // and which are rerolled, if any are
+        let rerolling = match &self.reroll {
+            Some(on) => Some(Condition {
+                comparison: on.comparison,
+                against: Box::new(on.against.evaluate_guarded(rng, guard)?),
+            }),
+            None => None,
+        };
 
         // once we have both of these, we can begin to actually "roll" the dice
         // and start accumulating values
-        let mut rolled = Vec::new();
-
-        // if the number of dice is somehow negative, we don't do any rolls
-        for _ in 0..dice.value().max(0) {
-            // zero-sided die means a value of zero because I get to make the
-            // rules
-            if _sides == 0 {
-                rolled.push(0);
-                continue;
+        let count = dice.value().max(0) as usize;
+        let node = guard.nodes;
+        guard.nodes += 1;
+        let first = guard.next_die;
+        guard.next_die += count;
+
+        // nobody is watching the dice of a huge pool one by one, so they can
+        // all be rolled together. If the number of dice is somehow negative,
+        // we don't do any rolls
+        let bulk = count >= BULK
+            && guard.assume.is_none()
+            && guard.observer.is_none()
+            && self.explode.is_none()
+            && self.reroll.is_none();
+        // dice that are only assumed to show a face don't explode (or get
+        // rerolled), or they'd all show the most they ever could
+        let most = guard.limits.max_explosions;
+        let reroller = rerolling
+            .as_ref()
+            .filter(|_| guard.assume.is_none())
+            .map(|on| Again::new("r", _sides, Some((on.comparison, on.against.value())), most));
+        let explosion = self.explode.as_ref().filter(|_| guard.assume.is_none());
+        let exploder = explosion.map(|explosion| {
+            let on = on.as_ref().map(|on| (on.comparison, on.against.value()));
+            let exploder = Again::new(explosion.explode.name(), _sides, on, most);
+            (explosion.explode, exploder)
+        });
+        let summarized = count > guard.limits.summarize_over;
+        let mut chains = BTreeMap::new();
+        let mut capped = false;
+        let mut discarded = BTreeMap::new();
+        let mut stopped_rerolling = false;
+        // the extra dice of explosions like `!`, which join the pool after
+        // the dice it started with
+        let mut extra = Vec::new();
+        let mut roll_one = |i: usize| -> Result<i32, Message> {
+            let mut value = match guard.assume {
+                Some(assume) => assume.face(_sides, i),
+                None => roll_die(_sides, rng),
+            };
+            // only the die as it's first rolled is rerolled, not the faces it
+            // explodes into
+            if let Some(reroller) = reroller {
+                let mut rerolled = reroller.settle(value, rng);
+                stopped_rerolling |= rerolled.capped;
+                value = rerolled.faces[0];
+                if let Some(mut faces) = rerolled.chains.remove(&0) {
+                    guard.roll(faces.len() as u64 - 1)?;
+                    // all but the face it ended up showing were left behind
+                    faces.pop();
+                    discarded.insert(first + i, faces);
+                }
             }
-            // wrap zeros around to the max value because dice are 1-indexed.
-            // This is a weird way to do it but it makes testing easier
-            let mut result = rng.next_u32() % _sides;
-            if result == 0 {
-                result = _sides;
+            let mut observed = vec![(first + i, value)];
+            if let Some((explode, exploder)) = exploder {
+                let mut exploded = exploder.settle(value, rng);
+                capped |= exploded.capped;
+                if let Some(chain) = exploded.chains.remove(&0) {
+                    guard.roll(chain.len() as u64 - 1)?;
+                    // the dice that `!` rolls are all in the pool already
+                    if explode == Explode::Compound {
+                        chains.insert(first + i, chain);
+                    }
+                }
+                value = exploded.faces[0];
+                observed[0].1 = value;
+                for &face in &exploded.faces[1..] {
+                    observed.push((guard.next_die, face));
+                    extra.push((guard.next_die, face));
+                    guard.next_die += 1;
+                }
             }
-            rolled.push(result as i32);
-        }
-
-        // we can now sort the accumulated, actual values into the "lowest" and
-        // "highest" buckets; the first step is to sort the list
-        rolled.sort_unstable();
+            if let Some(observer) = guard.observer.as_mut() {
+                for (id, value) in observed {
+                    observer(DieRoll {
+                        node,
+                        id,
+                        sides: _sides,
+                        value,
+                    });
+                }
+            }
+            Ok(value)
+        };
 
-        // now we split at the appropriate index
-        let kept = self.keep.retain(&rolled, rng);
+        // a pool too big to keep one by one only keeps count of its faces
+        let kept = if summarized {
+            let mut faces = Histogram::new();
+            match bulk {
+                true => roll_dice(_sides, count, rng, |die| {
+                    *faces.entry(die).or_default() += 1
+                }),
+                false => {
+                    for i in 0..count {
+                        *faces.entry(roll_one(i)?).or_default() += 1;
+                    }
+                    for (_, face) in extra.drain(..) {
+                        *faces.entry(face).or_default() += 1;
+                    }
+                }
+            }
+            self.keep.summarize(faces, rng, guard)?
+        } else {
+            let mut rolled = Dice::with_capacity(count);
+            match bulk {
+                true => roll_dice(_sides, count, rng, |die| rolled.push(die)),
+                false => {
+                    for i in 0..count {
+                        rolled.push(roll_one(i)?);
+                    }
+                }
+            }
+            // we can now sort the accumulated, actual values into the "lowest"
+            // and "highest" buckets, keeping them in the order they were rolled
+            let mut ids: Vec<usize> = (first..first + count).collect();
+            for (id, face) in extra {
+                ids.push(id);
+                rolled.push(face);
+            }
+            self.keep.retain(rolled, ids, rng, guard)?
+        };
 
-        // bundle up all of our calculated values
-        Rolled {
+        // bundle up all of our calculated values. A summarized pool has no
+        // dice to follow, so there's no telling which of them exploded, or
+        // which were rerolled
+        if kept.summary.is_some() {
+            chains.clear();
+            discarded.clear();
+        }
+        kept.total()?;
+        Ok(Rolled {
             sides: Box::new(sides),
             dice: Box::new(dice),
             kept: Box::new(kept),
-        }
+            exploded: exploder.map(|(explode, _)| Exploded {
+                explode,
+                on,
+                chains,
+                capped,
+                most,
+            }),
+            rerolled: rerolling.map(|on| Rerolled {
+                on,
+                discarded,
+                capped: stopped_rerolling,
+                most,
+            }),
+        })
     }
 }
 
@@ -265,12 +998,293 @@ pub struct Rolled {
     pub dice: Box<Value>,
     pub sides: Box<Value>,
     pub kept: Box<Kept>,
+    /// How the dice exploded, for a roll with an explosion like `5d6!!`
+    pub exploded: Option<Exploded>,
+    /// Which dice were rerolled, for a roll like `4d6r1`
+    pub rerolled: Option<Rerolled>,
+}
+
+/// The explosions of a roll's dice
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Exploded {
+    pub explode: Explode,
+    /// The faces that exploded, as they were rolled, if it wasn't only the
+    /// highest face
+    pub on: Option<Condition<Box<Value>>>,
+    /// Every face each compounding die that exploded landed on, by its id in
+    /// [`Kept::ids`], like `[6, 6, 3]` for a d6 showing 15. Dice that didn't
+    /// explode aren't listed, and neither are dice that exploded with `!`,
+    /// since every die they rolled is in the pool already.
+    pub chains: BTreeMap<usize, Vec<i32>>,
+    /// Whether any die was still exploding when it reached
+    /// [`Limits::max_explosions`]
+    pub capped: bool,
+    /// The [`Limits::max_explosions`] the dice were rolled under, which
+    /// holds for dice rolled again later too
+    pub most: usize,
+}
+
+/// The rerolls of a roll's dice
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Rerolled {
+    /// The faces that were rerolled, as they were rolled
+    pub on: Condition<Box<Value>>,
+    /// Every face each rerolled die landed on before the one it shows, by its
+    /// id in [`Kept::ids`]. Dice that weren't rerolled aren't listed.
+    pub discarded: BTreeMap<usize, Vec<i32>>,
+    /// Whether any die would still have been rerolled when it reached
+    /// [`Limits::max_explosions`]
+    pub capped: bool,
+    /// The [`Limits::max_explosions`] the dice were rolled under, which
+    /// holds for dice rolled again later too
+    pub most: usize,
 }
 
 impl Rolled {
-    pub fn val(&self) -> i32 {
+    pub fn val(&self) -> Int {
         self.kept.val()
     }
+
+    /// How many sides the dice have
+    pub fn sides(&self) -> u32 {
+        die_sides(self.sides.value())
+    }
+
+    /// Every face the die with this id landed on, if it exploded
+    pub fn chain(&self, id: usize) -> Option<&[i32]> {
+        self.exploded.as_ref()?.chains.get(&id).map(Vec::as_slice)
+    }
+
+    /// The faces the die with this id landed on and was rerolled from, if it
+    /// was
+    pub fn discarded(&self, id: usize) -> Option<&[i32]> {
+        self.rerolled
+            .as_ref()?
+            .discarded
+            .get(&id)
+            .map(Vec::as_slice)
+    }
+
+    /// What the roll was made from: how many dice, how many sides, what the
+    /// dice explode on (if it's more than their highest face), what they're
+    /// rerolled on, and how many were kept, in the order their dice come in
+    /// [`Value::faces`]
+    pub fn parts(&self) -> Vec<&Value> {
+        let mut parts = vec![self.dice.as_ref(), self.sides.as_ref()];
+        parts.extend(self.threshold());
+        parts.extend(
+            self.rerolled
+                .as_ref()
+                .map(|rerolled| rerolled.on.against.as_ref()),
+        );
+        parts.push(&self.kept.retained);
+        parts
+    }
+
+    /// The roll's own dice: the kept ones, then the dropped ones
+    fn own_faces(&self) -> Vec<i32> {
+        let mut faces = self.kept.kept().to_vec();
+        faces.extend_from_slice(self.kept.dropped());
+        faces
+    }
+
+    /// What the dice are held up against to see if they explode, if they
+    /// don't only explode on their highest face
+    fn threshold(&self) -> Option<&Value> {
+        let on = self.exploded.as_ref()?.on.as_ref()?;
+        Some(&on.against)
+    }
+
+    /// What the dice are rerolled on, if they're rerolled at all
+    fn rerolled_on(&self) -> Option<&Value> {
+        Some(&self.rerolled.as_ref()?.on.against)
+    }
+
+    fn reroll(
+        &mut self,
+        chosen: &[usize],
+        next: &mut usize,
+        fresh: &mut usize,
+        rng: &mut impl Rng,
+    ) {
+        let decided = |rolled: &Rolled| {
+            (
+                rolled.dice.value(),
+                rolled.sides.value(),
+                rolled.threshold().map(Value::value),
+                rolled.rerolled_on().map(Value::value),
+            )
+        };
+        let before = decided(self);
+        self.dice.reroll_numbered(chosen, next, fresh, rng);
+        self.sides.reroll_numbered(chosen, next, fresh, rng);
+        if let Some(on) = self
+            .exploded
+            .as_mut()
+            .and_then(|exploded| exploded.on.as_mut())
+        {
+            on.against.reroll_numbered(chosen, next, fresh, rng);
+        }
+        if let Some(rerolled) = &mut self.rerolled {
+            rerolled
+                .on
+                .against
+                .reroll_numbered(chosen, next, fresh, rng);
+        }
+        self.kept.retained.reroll_numbered(chosen, next, fresh, rng);
+
+        let sides = self.sides();
+        let changed = before != decided(self);
+        if changed {
+            // none of the dice are the dice from before, so nothing that
+            // happened to them still holds
+            if let Some(exploded) = &mut self.exploded {
+                exploded.chains.clear();
+                exploded.capped = false;
+            }
+            if let Some(rerolled) = &mut self.rerolled {
+                rerolled.discarded.clear();
+                rerolled.capped = false;
+            }
+        }
+        // a summarized pool has no dice to pick out, so it's only rolled again
+        // when there's a different number of them
+        if self.kept.summary.is_some() {
+            if changed {
+                let count = self.dice.value().max(0) as usize;
+                let mut faces = Histogram::new();
+                match (&self.exploded, &self.rerolled) {
+                    (None, None) => {
+                        roll_dice(sides, count, rng, |die| *faces.entry(die).or_default() += 1)
+                    }
+                    _ => {
+                        for _ in 0..count {
+                            let first = roll_die(sides, rng);
+                            let (face, extra) = self.settle(None, first, sides, rng);
+                            for face in std::iter::once(face).chain(extra) {
+                                *faces.entry(face).or_default() += 1;
+                            }
+                        }
+                    }
+                }
+                let retained = std::mem::replace(&mut self.kept.retained, Value::Const(0));
+                let (keep, retained) = self.kept.keep.clamped(retained, count);
+                *self.kept = Kept::summarized(keep, retained, faces, rng);
+            }
+            return;
+        }
+        let mut faces = self.own_faces();
+        let mut ids = self.kept.ids.clone();
+        if changed {
+            // the dice themselves are different now, so every one of them has
+            // to be rolled again
+            faces = (0..self.dice.value().max(0))
+                .map(|_| roll_die(sides, rng))
+                .collect();
+            ids = (0..faces.len())
+                .map(|_| {
+                    *fresh += 1;
+                    *fresh - 1
+                })
+                .collect();
+        }
+        // the extra dice that rerolled dice explode into, which are new dice
+        // of their own
+        let mut extra = Vec::new();
+        for i in 0..faces.len() {
+            if !changed && !chosen.contains(&(*next + i)) {
+                continue;
+            }
+            let first = match changed {
+                true => faces[i],
+                false => roll_die(sides, rng),
+            };
+            // a die rolled again is rerolled and explodes all over again
+            let (face, exploded) = self.settle(Some(ids[i]), first, sides, rng);
+            faces[i] = face;
+            for face in exploded {
+                extra.push((*fresh, face));
+                *fresh += 1;
+            }
+        }
+        *next += faces.len();
+        // back into the order they were rolled in, which their ids follow
+        let mut dice: Vec<(usize, i32)> = ids.into_iter().zip(faces).collect();
+        dice.extend(extra);
+        dice.sort_unstable_by_key(|(id, _)| *id);
+        let (ids, faces): (Vec<usize>, Dice) = dice.into_iter().unzip();
+
+        let retained = std::mem::replace(&mut self.kept.retained, Value::Const(0));
+        let (keep, retained) = self.kept.keep.clamped(retained, faces.len());
+        *self.kept = Kept::new(keep, retained, faces, &ids, rng);
+    }
+
+    /// Rerolls and explodes a die that's just been rolled, the way the roll's
+    /// dice are, and keeps track of what happened to it by its id (dice in
+    /// a summarized pool have none). What it shows comes back along with any
+    /// extra dice it exploded into.
+    fn settle(
+        &mut self,
+        id: Option<usize>,
+        first: i32,
+        sides: u32,
+        rng: &mut impl Rng,
+    ) -> (i32, Vec<i32>) {
+        let mut face = first;
+        if let Some(rerolled) = &mut self.rerolled {
+            let mut settled = rerolled.reroller(sides).settle(face, rng);
+            rerolled.capped |= settled.capped;
+            face = settled.faces[0];
+            let discarded = settled.chains.remove(&0).map(|mut faces| {
+                faces.pop();
+                faces
+            });
+            match (id, discarded) {
+                (Some(id), None) => rerolled.discarded.remove(&id),
+                (Some(id), Some(discarded)) => rerolled.discarded.insert(id, discarded),
+                (None, _) => None,
+            };
+        }
+        let mut extra = Vec::new();
+        if let Some(exploded) = &mut self.exploded {
+            let mut settled = exploded.exploder(sides).settle(face, rng);
+            exploded.capped |= settled.capped;
+            face = settled.faces[0];
+            extra.extend_from_slice(&settled.faces[1..]);
+            // only compounding dice keep track of what they landed on
+            let chain = settled
+                .chains
+                .remove(&0)
+                .filter(|_| exploded.explode == Explode::Compound);
+            match (id, chain) {
+                (Some(id), None) => exploded.chains.remove(&id),
+                (Some(id), Some(chain)) => exploded.chains.insert(id, chain),
+                (None, _) => None,
+            };
+        }
+        (face, extra)
+    }
+}
+
+impl Rerolled {
+    /// Rerolls dice with this many sides the same way again, for dice that
+    /// are rolled again
+    fn reroller(&self, sides: u32) -> Again {
+        let on = (self.on.comparison, self.on.against.value());
+        Again::new("r", sides, Some(on), self.most)
+    }
+}
+
+impl Exploded {
+    /// Explodes dice with this many sides the same way again, for dice that
+    /// are rolled again
+    fn exploder(&self, sides: u32) -> Again {
+        let on = self
+            .on
+            .as_ref()
+            .map(|on| (on.comparison, on.against.value()));
+        Again::new(self.explode.name(), sides, on, self.most)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -278,6 +1292,58 @@ pub enum KeptRule {
     All,
     Lowest(Value),
     Highest(Value),
+    /// A percentage of the pool, like `kl50%`, along with how many dice that
+    /// came to
+    LowestShare(u32, Value),
+    HighestShare(u32, Value),
+    DropLowest(Value),
+    DropHighest(Value),
+}
+
+impl KeptRule {
+    /// The same rule for a pool of `dice`, keeping as many as `retained`
+    /// says, but no more than there are. A share of the pool is worked out
+    /// all over again, and comes back as what's retained in its place.
+    fn clamped(&self, retained: Value, dice: usize) -> (KeptRule, Value) {
+        let n = Value::Const((retained.value().max(0) as usize).min(dice) as Int);
+        let rule = match self {
+            KeptRule::All => KeptRule::All,
+            KeptRule::Lowest(_) => KeptRule::Lowest(n),
+            KeptRule::Highest(_) => KeptRule::Highest(n),
+            KeptRule::LowestShare(percent, _) | KeptRule::HighestShare(percent, _) => {
+                let n = Value::Const(share(*percent, dice) as Int);
+                let rule = match self.keeps_lowest() {
+                    true => KeptRule::LowestShare(*percent, n.clone()),
+                    false => KeptRule::HighestShare(*percent, n.clone()),
+                };
+                return (rule, n);
+            }
+            KeptRule::DropLowest(_) => KeptRule::DropLowest(n),
+            KeptRule::DropHighest(_) => KeptRule::DropHighest(n),
+        };
+        (rule, retained)
+    }
+
+    /// The modifier that carries out the rule, along with its argument, or
+    /// nothing if every die is kept
+    pub fn modifier(&self) -> Option<(&'static dyn RollModifier, &Value)> {
+        let (name, n) = match self {
+            KeptRule::All => return None,
+            KeptRule::Lowest(n) | KeptRule::LowestShare(_, n) => ("kl", n),
+            KeptRule::Highest(n) | KeptRule::HighestShare(_, n) => ("kh", n),
+            KeptRule::DropLowest(n) => ("dl", n),
+            KeptRule::DropHighest(n) => ("dh", n),
+        };
+        Some((registered(name), n))
+    }
+
+    /// Whether the dice that count are the lowest ones
+    pub fn keeps_lowest(&self) -> bool {
+        matches!(
+            self,
+            KeptRule::Lowest(_) | KeptRule::LowestShare(..) | KeptRule::DropHighest(_)
+        )
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -286,45 +1352,297 @@ pub struct Kept {
     pub retained: Value,
     pub lowest: Vec<i32>,
     pub highest: Vec<i32>,
+    /// A stable id for every die: the kept ones first, then the dropped ones,
+    /// in the same order as [`Kept::kept`] and [`Kept::dropped`]. A die keeps
+    /// its id when it's rerolled, so front-ends can follow it around.
+    pub ids: Vec<usize>,
+    /// For a roll of more dice than [`Limits::summarize_over`], how many of
+    /// them showed each face. There's no list of dice at all then, so
+    /// `lowest`, `highest`, and `ids` are all empty.
+    pub summary: Option<Summary>,
 }
 
 impl Kept {
-    pub fn val(&self) -> i32 {
-        let to_sum = match &self.keep {
-            KeptRule::Lowest(_) => &self.lowest,
-            _ => &self.highest,
+    /// Applies the rule's modifier to the faces, which have the given ids and
+    /// are in the order they were rolled. The ones it keeps end up on the
+    /// side of the rule (`lowest` when keeping the lowest, and so on).
+    fn new(
+        keep: KeptRule,
+        retained: Value,
+        faces: Dice,
+        ids: &[usize],
+        rng: &mut impl Rng,
+    ) -> Kept {
+        let Some((modifier, n)) = keep.modifier() else {
+            return Kept {
+                keep,
+                retained,
+                lowest: Vec::new(),
+                highest: faces.into_vec(),
+                ids: ids.to_vec(),
+                summary: None,
+            };
+        };
+        let argument = Argument::count(n.value().max(0) as usize);
+        let Modified {
+            faces,
+            kept,
+            dropped,
+            ..
+        } = modifier.apply(&faces, argument, rng);
+        // the modifier hands back positions, so every face keeps its die's id
+        let ids = kept.iter().chain(&dropped).map(|&i| ids[i]).collect();
+        let kept = kept.into_iter().map(|i| faces[i]).collect();
+        let dropped = dropped.into_iter().map(|i| faces[i]).collect();
+        let (lowest, highest) = match keep.keeps_lowest() {
+            true => (kept, dropped),
+            false => (dropped, kept),
+        };
+        Kept {
+            keep,
+            retained,
+            lowest,
+            highest,
+            ids,
+            summary: None,
+        }
+    }
+
+    /// Like [`Kept::new`], for a pool that's only a histogram of its faces
+    fn summarized(keep: KeptRule, retained: Value, faces: Histogram, rng: &mut impl Rng) -> Kept {
+        let summary = match keep.modifier() {
+            Some((modifier, n)) => {
+                let argument = Argument::count(n.value().max(0) as usize);
+                modifier.apply_summarized(&faces, argument, rng)
+            }
+            None => Summary {
+                kept: faces,
+                dropped: Histogram::new(),
+            },
         };
-        to_sum.iter().sum()
+        Kept {
+            keep,
+            retained,
+            lowest: Vec::new(),
+            highest: Vec::new(),
+            ids: Vec::new(),
+            summary: Some(summary),
+        }
+    }
+
+    /// The total of the dice that count, or [`Message::Overflow`] if it's
+    /// too big for an [`Int`]
+    #[allow(clippy::useless_conversion)]
+    pub fn total(&self) -> Result<Int, Message> {
+        match &self.summary {
+            Some(summary) => Int::try_from(summary.sum()).map_err(|_| Message::Overflow),
+            None => self
+                .kept()
+                .iter()
+                .try_fold(0 as Int, |total, &face| total.checked_add(face as Int))
+                .ok_or(Message::Overflow),
+        }
+    }
+
+    /// Like [`Kept::total`], but stopping at the most (or least) an [`Int`]
+    /// holds. Rolling fails rather than come to a total that doesn't fit, so
+    /// only dice rerolled afterwards can get this far.
+    #[allow(clippy::useless_conversion)]
+    pub fn val(&self) -> Int {
+        match &self.summary {
+            Some(summary) => summary.sum().clamp(Int::MIN.into(), Int::MAX.into()) as Int,
+            None => self
+                .kept()
+                .iter()
+                .fold(0 as Int, |total, &face| total.saturating_add(face as Int)),
+        }
+    }
+
+    /// The dice that count towards the total
+    pub fn kept(&self) -> &[i32] {
+        match self.keep.keeps_lowest() {
+            true => &self.lowest,
+            false => &self.highest,
+        }
+    }
+
+    /// The dice that were rolled but set aside by the keep rule
+    pub fn dropped(&self) -> &[i32] {
+        match self.keep.keeps_lowest() {
+            true => &self.highest,
+            false => &self.lowest,
+        }
+    }
+
+    /// The ids of the dice in `highest`, then of the ones in `lowest`
+    pub fn ids_by_side(&self) -> (&[usize], &[usize]) {
+        let (kept, dropped) = self.ids.split_at(self.kept().len().min(self.ids.len()));
+        match self.keep.keeps_lowest() {
+            true => (dropped, kept),
+            false => (kept, dropped),
+        }
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Value {
-    Const(i32),
+    Const(Int),
     Rolled(Rolled),
-    Op { op: Operation, values: Vec<Value> },
+    Op {
+        op: Operation,
+        values: Vec<Value>,
+    },
+    /// Every time a [`Repeat`] was rolled, whether its total counts or not
+    Repeated {
+        pick: Pick,
+        values: Vec<Value>,
+    },
 }
 
 impl Value {
-    pub fn value(&self) -> i32 {
+    /// Every die in the tree. A die's position in this list is how it's
+    /// picked out for [`Value::reroll`]; the dice that decide how many dice
+    /// (or sides) a roll has come before the dice of that roll.
+    pub fn faces(&self) -> Vec<i32> {
         match self {
-            Value::Const(val) => *val,
-            Value::Rolled(rolled) => rolled.val(),
-            Value::Op { op, values } => match op {
-                Operation::Add => values.iter().map(Value::value).sum(),
-                Operation::Sub => {
-                    let mut values = values.iter();
-                    let mut acc = values
-                        .next()
-                        .expect("values is guaranteed to have at least one element")
-                        .value();
-                    for value in values {
-                        acc -= value.value();
+            Value::Const(_) => Vec::new(),
+            Value::Rolled(rolled) => {
+                let mut faces: Vec<i32> =
+                    rolled.parts().into_iter().flat_map(Value::faces).collect();
+                faces.extend(rolled.own_faces());
+                faces
+            }
+            Value::Op { values, .. } | Value::Repeated { values, .. } => {
+                values.iter().flat_map(Value::faces).collect()
+            }
+        }
+    }
+
+    /// Rolls the chosen dice (by their position in [`Value::faces`]) again,
+    /// leaving everything else as it was. If a rerolled die changes how many
+    /// dice another roll has, that whole roll is made again too.
+    pub fn reroll(&mut self, chosen: &[usize], rng: &mut impl Rng) {
+        let mut fresh = self.ids().into_iter().max().map_or(0, |id| id + 1);
+        self.reroll_numbered(chosen, &mut 0, &mut fresh, rng);
+    }
+
+    fn reroll_numbered(
+        &mut self,
+        chosen: &[usize],
+        next: &mut usize,
+        fresh: &mut usize,
+        rng: &mut impl Rng,
+    ) {
+        match self {
+            Value::Const(_) => {}
+            Value::Rolled(rolled) => rolled.reroll(chosen, next, fresh, rng),
+            Value::Op { values, .. } | Value::Repeated { values, .. } => {
+                for value in values {
+                    value.reroll_numbered(chosen, next, fresh, rng);
+                }
+            }
+        }
+    }
+
+    /// The id of every die in the tree, in the same order as
+    /// [`Value::faces`]
+    pub fn ids(&self) -> Vec<usize> {
+        match self {
+            Value::Const(_) => Vec::new(),
+            Value::Rolled(rolled) => {
+                let mut ids: Vec<usize> = rolled.parts().into_iter().flat_map(Value::ids).collect();
+                ids.extend(&rolled.kept.ids);
+                ids
+            }
+            Value::Op { values, .. } | Value::Repeated { values, .. } => {
+                values.iter().flat_map(Value::ids).collect()
+            }
+        }
+    }
+
+    /// Every die in the tree, in the same order as [`Value::faces`], along
+    /// with how many sides it has and whether it was kept
+    pub fn dice(&self) -> impl Iterator<Item = Die> {
+        let mut dice = Vec::new();
+        self.collect_dice(&mut dice);
+        dice.into_iter()
+    }
+
+    fn collect_dice(&self, dice: &mut Vec<Die>) {
+        match self {
+            Value::Const(_) => {}
+            Value::Rolled(rolled) => {
+                for part in rolled.parts() {
+                    part.collect_dice(dice);
+                }
+                let sides = rolled.sides();
+                let kept = rolled.kept.kept().len();
+                let faces = rolled.kept.kept().iter().chain(rolled.kept.dropped());
+                for (i, (&id, &value)) in rolled.kept.ids.iter().zip(faces).enumerate() {
+                    dice.push(Die {
+                        id,
+                        sides,
+                        value,
+                        kept: i < kept,
+                    });
+                }
+            }
+            Value::Op { values, .. } => {
+                for value in values {
+                    value.collect_dice(dice);
+                }
+            }
+            // the dice of a total that was dropped don't count either
+            Value::Repeated { pick, values } => {
+                for (value, counted) in values.iter().zip(pick.counted(values)) {
+                    let start = dice.len();
+                    value.collect_dice(dice);
+                    if !counted {
+                        dice[start..].iter_mut().for_each(|die| die.kept = false);
                     }
-                    acc
                 }
-                Operation::Mul => values.iter().map(Value::value).product(),
-            },
+            }
+        }
+    }
+
+    /// The total, or [`Message::Overflow`] if it, or any total it's made of,
+    /// is too big for an [`Int`]
+    pub fn total(&self) -> Result<Int, Message> {
+        match self {
+            Value::Const(val) => Ok(*val),
+            Value::Rolled(rolled) => rolled.kept.total(),
+            Value::Op { op, values } => op.combined(values.iter().map(Value::total)),
+            Value::Repeated { pick, values } => values
+                .iter()
+                .zip(pick.counted(values))
+                .filter(|(_, counted)| *counted)
+                .try_fold(0 as Int, |acc, (value, _)| {
+                    acc.checked_add(value.total()?).ok_or(Message::Overflow)
+                }),
+        }
+    }
+
+    /// Like [`Value::total`], but stopping at the most (or least) an [`Int`]
+    /// holds. Evaluating fails rather than come to a total that doesn't fit,
+    /// so only values rerolled afterwards, or put together by hand, get this
+    /// far.
+    pub fn value(&self) -> Int {
+        match self {
+            Value::Const(val) => *val,
+            Value::Rolled(rolled) => rolled.val(),
+            Value::Op { op, values } => {
+                let mut values = values.iter();
+                let first = values
+                    .next()
+                    .expect("values is guaranteed to have at least one element")
+                    .value();
+                values.fold(first, |acc, value| op.saturating(acc, value.value()))
+            }
+            Value::Repeated { pick, values } => values
+                .iter()
+                .zip(pick.counted(values))
+                .filter(|(_, counted)| *counted)
+                .fold(0, |acc, (value, _)| acc.saturating_add(value.value())),
         }
     }
 
@@ -345,30 +1663,96 @@ impl Value {
 
     pub fn roll_fmt(&self) -> String {
         match self {
-            Value::Op { .. } | Value::Rolled(_) => format!("({self})"),
+            Value::Op { .. } | Value::Rolled(_) | Value::Repeated { .. } => format!("({self})"),
             _ => self.to_string(),
         }
     }
+
+    /// Reconstructs the expression that this value was evaluated from, which
+    /// lets us ask statistical questions about any node in the tree
+    pub fn expression(&self) -> Exp {
+        match self {
+            Value::Const(c) => Exp::Const(*c),
+            Value::Rolled(Rolled {
+                dice,
+                sides,
+                kept,
+                exploded,
+                rerolled,
+            }) => Exp::roll(Roll {
+                dice: dice.expression(),
+                sides: sides.expression(),
+                keep: match &kept.keep {
+                    KeptRule::All => Keep::All,
+                    KeptRule::Lowest(_) => Keep::Lowest(kept.retained.expression()),
+                    KeptRule::Highest(_) => Keep::Highest(kept.retained.expression()),
+                    KeptRule::LowestShare(percent, _) => Keep::LowestShare(*percent),
+                    KeptRule::HighestShare(percent, _) => Keep::HighestShare(*percent),
+                    KeptRule::DropLowest(_) => Keep::DropLowest(kept.retained.expression()),
+                    KeptRule::DropHighest(_) => Keep::DropHighest(kept.retained.expression()),
+                },
+                explode: exploded.as_ref().map(|exploded| Explosion {
+                    explode: exploded.explode,
+                    on: exploded.on.as_ref().map(|on| Condition {
+                        comparison: on.comparison,
+                        against: on.against.expression(),
+                    }),
+                }),
+                reroll: rerolled.as_ref().map(|rerolled| Condition {
+                    comparison: rerolled.on.comparison,
+                    against: rerolled.on.against.expression(),
+                }),
+            }),
+            Value::Op { op, values } => Exp::Op(Op {
+                operation: op.clone(),
+                arguments: Rc::new(RefCell::new(values.iter().map(Value::expression).collect())),
+            }),
+            Value::Repeated { pick, values } => Exp::Repeat(Repeat {
+                exp: Box::new(values.first().map_or_else(Exp::default, Value::expression)),
+                times: values.len() as u32,
+                pick: *pick,
+            }),
+        }
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
             Value::Const(c) => write!(f, "{c}"),
-            Value::Rolled(Rolled { dice, sides, kept }) => {
+            Value::Rolled(Rolled {
+                dice,
+                sides,
+                kept,
+                exploded,
+                rerolled,
+            }) => {
                 let dice = dice.roll_fmt();
                 let sides = sides.roll_fmt();
-                match &kept.keep {
-                    KeptRule::All => {
-                        write!(f, "{dice}d{sides}")
-                    }
-                    KeptRule::Lowest(_) => {
-                        write!(f, "{dice}d{sides}kl{}", kept.retained.roll_fmt())
+                write!(f, "{dice}d{sides}")?;
+                if let Some(rerolled) = rerolled {
+                    // rerolling a single face, like `r1`, needs no comparison
+                    match rerolled.on.comparison {
+                        Comparison::Equal => write!(f, "r")?,
+                        comparison => write!(f, "r{comparison}")?,
                     }
-                    KeptRule::Highest(_) => {
-                        write!(f, "{dice}d{sides}k{}", kept.retained.roll_fmt())
+                    write!(f, "{}", rerolled.on.against.roll_fmt())?;
+                }
+                if let Some(exploded) = exploded {
+                    write!(f, "{}", exploded.explode)?;
+                    if let Some(on) = &exploded.on {
+                        write!(f, "{}{}", on.comparison, on.against.roll_fmt())?;
                     }
                 }
+                match &kept.keep {
+                    KeptRule::All => Ok(()),
+                    KeptRule::Lowest(_) => write!(f, "kl{}", kept.retained.roll_fmt()),
+                    KeptRule::Highest(_) => write!(f, "k{}", kept.retained.roll_fmt()),
+                    KeptRule::LowestShare(percent, _) => write!(f, "kl{percent}%"),
+                    KeptRule::HighestShare(percent, _) => write!(f, "k{percent}%"),
+                    KeptRule::DropLowest(_) => write!(f, "dl{}", kept.retained.roll_fmt()),
+                    KeptRule::DropHighest(_) => write!(f, "dh{}", kept.retained.roll_fmt()),
+                }
             }
             Value::Op { op, values } => {
                 let operator = match op {
@@ -387,6 +1771,10 @@ impl Display for Value {
                     .collect();
                 write!(f, "{value}")
             }
+            Value::Repeated { pick, values } => match values.first() {
+                Some(value) => write!(f, "{pick} of {}x({value})", values.len()),
+                None => write!(f, "{pick} of 0x()"),
+            },
         }
     }
 }
@@ -421,13 +1809,7 @@ mod tests {
             MockRng(vec![0].into_iter().cycle())
         };
         [ $( $x:expr ),* ] => {
-            {
-                let mut temp_vec = Vec::new();
-                $(
-                    temp_vec.push($x);
-                )*
-                MockRng(temp_vec.into_iter())
-            }
+            MockRng(vec![$($x),*].into_iter())
         };
     }
 
@@ -444,6 +1826,8 @@ mod tests {
             dice: Exp::Const(1),
             sides: Exp::Const(6),
             keep: Keep::All,
+            explode: None,
+            reroll: None,
         };
         let expression = Exp::Roll(Rc::new(RefCell::new(roll)));
         let expected = Value::Rolled(Rolled {
@@ -454,7 +1838,11 @@ mod tests {
                 retained: Value::Const(1),
                 lowest: vec![],
                 highest: vec![3],
+                ids: vec![0],
+                summary: None,
             }),
+            exploded: None,
+            rerolled: None,
         });
         assert_eq!(expected, expression.evaluate(&mut rng))
     }
@@ -467,9 +1855,13 @@ mod tests {
                 dice: Exp::Const(1),
                 sides: Exp::Const(6),
                 keep: Keep::All,
+                explode: None,
+                reroll: None,
             }),
             sides: Exp::Const(6),
             keep: Keep::All,
+            explode: None,
+            reroll: None,
         };
         let expression = Exp::roll(roll);
         let expected = Value::Rolled(Rolled {
@@ -481,7 +1873,11 @@ mod tests {
                     retained: Value::Const(1),
                     lowest: vec![],
                     highest: vec![2],
+                    ids: vec![0],
+                    summary: None,
                 }),
+                exploded: None,
+                rerolled: None,
             })),
             sides: Box::new(Value::Const(6)),
             kept: Box::new(Kept {
@@ -489,14 +1885,572 @@ mod tests {
                 retained: Value::Const(2),
                 lowest: vec![],
                 highest: vec![3, 4],
+                ids: vec![1, 2],
+                summary: None,
             }),
+            exploded: None,
+            rerolled: None,
         });
         assert_eq!(expected, expression.evaluate(&mut rng))
     }
 
+    #[test]
+    fn keep_preserves_roll_order() {
+        let mut rng = mock_rng![5, 2, 6, 1];
+        let roll = Roll::keep_highest(Exp::Const(4), Exp::Const(6), Exp::Const(2));
+        let expected = Kept {
+            keep: KeptRule::Highest(Value::Const(2)),
+            retained: Value::Const(2),
+            lowest: vec![2, 1],
+            highest: vec![5, 6],
+            // the kept 5 and 6 were the first and third dice rolled
+            ids: vec![0, 2, 1, 3],
+            summary: None,
+        };
+        match Exp::roll(roll).evaluate(&mut rng) {
+            Value::Rolled(rolled) => assert_eq!(expected, *rolled.kept),
+            other => panic!("expected a roll, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn dice_know_their_sides() {
+        let mut rng = mock_rng![3, 1, 4];
+        let roll = Roll::keep_lowest(Exp::Const(2), Exp::Const(4), Exp::Const(1));
+        let exp = Exp::add(vec_deque![
+            Exp::roll(roll),
+            Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(6)))
+        ]);
+        let dice: Vec<_> = exp.evaluate(&mut rng).dice().collect();
+        let die = |id, sides, value, kept| Die {
+            id,
+            sides,
+            value,
+            kept,
+        };
+        assert_eq!(
+            vec![die(1, 4, 1, true), die(0, 4, 3, false), die(2, 6, 4, true)],
+            dice
+        );
+    }
+
+    #[test]
+    fn bulk_rolls_match_single_rolls() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut bulk = Dice::new();
+        roll_dice(6, BULK + 10, &mut StdRng::seed_from_u64(9), |die| {
+            bulk.push(die)
+        });
+        let mut rng = StdRng::seed_from_u64(9);
+        let single: Dice = (0..BULK + 10).map(|_| roll_die(6, &mut rng)).collect();
+        assert_eq!(single, bulk);
+    }
+
+    #[cfg(feature = "wide")]
+    #[test]
+    fn wide_totals() {
+        let exp = Exp::mul(vec_deque![
+            Exp::Const(1_000_000),
+            Exp::Const(1_000_000),
+            Exp::add(vec_deque![Exp::Const(10_000_000_000), Exp::Const(3)])
+        ]);
+        let total = exp.evaluate(&mut mock_rng![]).value();
+        assert_eq!(10_000_000_003_000_000_000_000, total);
+    }
+
+    #[test]
+    fn summarized_pools() {
+        use crate::modifier::histogram;
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let exp = Exp::roll(Roll::keep_highest(
+            Exp::Const(50),
+            Exp::Const(6),
+            Exp::Const(3),
+        ));
+        let limits = Limits {
+            summarize_over: 10,
+            ..Default::default()
+        };
+        let full = exp.evaluate(&mut StdRng::seed_from_u64(2));
+        let summarized = exp
+            .evaluate_within(&mut StdRng::seed_from_u64(2), &limits)
+            .unwrap();
+        assert_eq!(full.value(), summarized.value());
+        assert!(summarized.faces().is_empty());
+        let (Value::Rolled(full), Value::Rolled(summarized)) = (full, summarized) else {
+            panic!("expected rolls");
+        };
+        let summary = summarized.kept.summary.unwrap();
+        assert_eq!(histogram(full.kept.kept()), summary.kept);
+        assert_eq!(histogram(full.kept.dropped()), summary.dropped);
+    }
+
+    #[test]
+    fn totals_that_overflow() {
+        let limits = Limits::default();
+        let huge = Exp::mul(vec_deque![Exp::Const(Int::MAX), Exp::Const(2)]);
+        assert!(matches!(
+            huge.evaluate_within(&mut mock_rng![], &limits),
+            Err(Error::Limit(Message::Overflow))
+        ));
+        let repeated = Exp::Repeat(Repeat {
+            exp: Box::new(Exp::Const(Int::MAX)),
+            times: 2,
+            pick: Pick::Best(2),
+        });
+        assert!(matches!(
+            repeated.evaluate_within(&mut mock_rng![], &limits),
+            Err(Error::Limit(Message::Overflow))
+        ));
+        // a value put together by hand stops at the most an Int holds
+        let value = Value::Op {
+            op: Operation::Sub,
+            values: vec![Value::Const(Int::MIN), Value::Const(1)],
+        };
+        assert_eq!(Err(Message::Overflow), value.total());
+        assert_eq!(Int::MIN, value.value());
+    }
+
+    #[cfg(not(feature = "wide"))]
+    #[test]
+    fn totals_too_big_to_count() {
+        let exp = Exp::roll(Roll::simple(Exp::Const(3), Exp::Const(i32::MAX)));
+        // whether the dice are kept one by one or only as a histogram
+        for summarize_over in [1, usize::MAX] {
+            let limits = Limits {
+                summarize_over,
+                ..Default::default()
+            };
+            assert!(matches!(
+                exp.evaluate_assuming(Assume::Max, &mut mock_rng![], &limits),
+                Err(Error::Limit(Message::Overflow))
+            ));
+        }
+    }
+
+    #[test]
+    fn keeping_a_share() {
+        let roll = |keep| {
+            Exp::roll(Roll {
+                dice: Exp::Const(5),
+                sides: Exp::Const(6),
+                keep,
+                explode: None,
+                reroll: None,
+            })
+        };
+        let value = roll(Keep::HighestShare(50)).evaluate(&mut mock_rng![2, 6, 1, 5, 3]);
+        assert_eq!(14, value.value());
+        // it's still a share of the pool once it's rolled, not however many
+        // dice that came to
+        assert_eq!("5d6k50%", value.to_string());
+        assert_eq!(roll(Keep::HighestShare(50)), value.expression());
+        let value = roll(Keep::LowestShare(20)).evaluate(&mut mock_rng![2, 6, 1, 5, 3]);
+        assert_eq!(1, value.value());
+
+        let limits = Limits {
+            summarize_over: 2,
+            ..Default::default()
+        };
+        let summarized = roll(Keep::HighestShare(50))
+            .evaluate_within(&mut mock_rng![2, 6, 1, 5, 3], &limits)
+            .unwrap();
+        assert_eq!(14, summarized.value());
+    }
+
+    #[test]
+    fn rerolled_pools_keep_their_share() {
+        let d4 = Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(4)));
+        let roll = Exp::roll(Roll {
+            keep: Keep::HighestShare(50),
+            ..Roll::simple(d4, Exp::Const(6))
+        });
+        let mut value = roll.evaluate(&mut mock_rng![2, 6, 1]);
+        assert_eq!(6, value.value());
+        // rolling four dice instead of two keeps the highest two of them
+        value.reroll(&[0], &mut mock_rng![4, 2, 6, 1, 5]);
+        assert_eq!(11, value.value());
+        assert_eq!("(1d4)d6k50%", value.to_string());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert_eq!(
+            KeptRule::HighestShare(50, Value::Const(2)),
+            rolled.kept.keep
+        );
+        assert_eq!(Value::Const(2), rolled.kept.retained);
+    }
+
+    #[test]
+    fn dropping_dice() {
+        let roll = |keep| {
+            Exp::roll(Roll {
+                dice: Exp::Const(4),
+                sides: Exp::Const(6),
+                keep,
+                explode: None,
+                reroll: None,
+            })
+        };
+        let lowest = roll(Keep::DropLowest(Exp::Const(1)));
+        let value = lowest.evaluate(&mut mock_rng![2, 6, 1, 5]);
+        assert_eq!(13, value.value());
+        assert_eq!("4d6dl1", value.to_string());
+        assert_eq!(lowest, value.expression());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert_eq!(&[2, 6, 5], rolled.kept.kept());
+        assert_eq!(&[1], rolled.kept.dropped());
+
+        let value = roll(Keep::DropHighest(Exp::Const(3))).evaluate(&mut mock_rng![2, 6, 1, 5]);
+        assert_eq!(1, value.value());
+        assert_eq!("4d6dh3", value.to_string());
+        // dropping more dice than there are drops all of them
+        let value = roll(Keep::DropLowest(Exp::Const(9))).evaluate(&mut mock_rng![2, 6, 1, 5]);
+        assert_eq!(0, value.value());
+    }
+
+    #[test]
+    fn best_of_several() {
+        let repeat = |pick| {
+            Exp::Repeat(Repeat {
+                exp: Box::new(Exp::roll(Roll::simple(Exp::Const(2), Exp::Const(6)))),
+                times: 3,
+                pick,
+            })
+        };
+        let value = repeat(Pick::Best(1)).evaluate(&mut mock_rng![1, 2, 6, 5, 3, 4]);
+        assert_eq!(11, value.value());
+        assert_eq!("best 1 of 3x(2d6)", value.to_string());
+        let Value::Repeated { pick, values } = &value else {
+            panic!("a repeated expression is rolled every time");
+        };
+        assert_eq!(vec![false, true, false], pick.counted(values));
+        // the dice of the totals that were dropped aren't kept either
+        let kept: Vec<bool> = value.dice().map(|die| die.kept).collect();
+        assert_eq!(vec![false, false, true, true, false, false], kept);
+        assert_eq!(value.expression(), repeat(Pick::Best(1)));
+
+        // ties go to the first rolled
+        let value = repeat(Pick::Worst(2)).evaluate(&mut mock_rng![3, 4, 2, 5, 6, 1]);
+        assert_eq!(14, value.value());
+        let Value::Repeated { pick, values } = &value else {
+            panic!("a repeated expression is rolled every time");
+        };
+        assert_eq!(vec![true, true, false], pick.counted(values));
+
+        // repeats inside repeats are counted all together
+        let nested = Exp::Repeat(Repeat {
+            exp: Box::new(Exp::Repeat(Repeat {
+                exp: Box::new(Exp::Const(1)),
+                times: MAX_REPETITIONS,
+                pick: Pick::Best(1),
+            })),
+            times: 2,
+            pick: Pick::Best(1),
+        });
+        assert!(matches!(
+            nested.evaluate_within(&mut mock_rng![], &Limits::default()),
+            Err(Error::Limit(Message::TooManyRepetitions(_)))
+        ));
+    }
+
     #[test]
     fn one_plus_one() {
         let exp = Exp::add(vec_deque![Exp::Const(1), Exp::Const(1)]);
         assert_eq!(2, exp.evaluate(&mut mock_rng![]).value())
     }
+
+    #[test]
+    fn reroll_chosen_dice() {
+        let roll = Roll::keep_highest(Exp::Const(3), Exp::Const(6), Exp::Const(2));
+        let mut value =
+            Exp::add(vec_deque![Exp::roll(roll), Exp::Const(1)]).evaluate(&mut mock_rng![5, 2, 6]);
+        assert_eq!(vec![5, 6, 2], value.faces());
+        assert_eq!(vec![0, 2, 1], value.ids());
+        assert_eq!(12, value.value());
+        // swap the 5 for a 1, which gets dropped in favor of the 2. The dice
+        // stay in the order they were first rolled, and keep their ids.
+        value.reroll(&[0], &mut mock_rng![1]);
+        assert_eq!(vec![2, 6, 1], value.faces());
+        assert_eq!(vec![1, 2, 0], value.ids());
+        assert_eq!(9, value.value());
+    }
+
+    #[test]
+    fn reroll_dice_count() {
+        let inner = Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(4)));
+        let mut value =
+            Exp::roll(Roll::simple(inner, Exp::Const(6))).evaluate(&mut mock_rng![2, 3, 3]);
+        assert_eq!(vec![2, 3, 3], value.faces());
+        // two dice become four, all of which are rolled fresh
+        value.reroll(&[0], &mut mock_rng![4, 1, 1, 1, 1]);
+        assert_eq!(vec![4, 1, 1, 1, 1], value.faces());
+        assert_eq!(vec![0, 3, 4, 5, 6], value.ids());
+    }
+
+    #[test]
+    fn dice_limit() {
+        // (2d2)d6 rolls two dice, then up to four more
+        let inner = Exp::roll(Roll::simple(Exp::Const(2), Exp::Const(2)));
+        let exp = Exp::roll(Roll::simple(inner, Exp::Const(6)));
+        let limits = Limits {
+            max_dice: 5,
+            ..Default::default()
+        };
+        assert!(exp.evaluate_within(&mut mock_rng![1, 1], &limits).is_ok());
+        assert!(exp.evaluate_within(&mut mock_rng![2, 2], &limits).is_err());
+    }
+
+    #[test]
+    fn compounding_dice() {
+        let roll = Roll {
+            explode: Some(Explosion::new(Explode::Compound)),
+            ..Roll::simple(Exp::Const(3), Exp::Const(6))
+        };
+        let value = Exp::roll(roll.clone()).evaluate(&mut mock_rng![6, 6, 3, 2, 6, 1]);
+        assert_eq!(vec![15, 2, 7], value.faces());
+        assert_eq!(24, value.value());
+        assert_eq!("3d6!!", value.to_string());
+        assert_eq!(Exp::roll(roll), value.expression());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert_eq!(Some(&[6, 6, 3][..]), rolled.chain(0));
+        assert_eq!(None, rolled.chain(1));
+        assert!(!rolled.exploded.as_ref().unwrap().capped);
+
+        // every die rolled while exploding counts against the limit
+        let d6 = Exp::roll(Roll {
+            explode: Some(Explosion::new(Explode::Compound)),
+            ..Roll::simple(Exp::Const(1), Exp::Const(6))
+        });
+        let limits = Limits {
+            max_dice: 2,
+            ..Default::default()
+        };
+        assert!(d6
+            .evaluate_within(&mut mock_rng![6, 6, 6], &limits)
+            .is_err());
+    }
+
+    #[test]
+    fn explosions_stop_at_the_limit() {
+        let d1 = Exp::roll(Roll {
+            explode: Some(Explosion::new(Explode::Compound)),
+            ..Roll::simple(Exp::Const(1), Exp::Const(1))
+        });
+        let limits = Limits {
+            max_explosions: 3,
+            ..Default::default()
+        };
+        let mut value = d1.evaluate_within(&mut mock_rng![], &limits).unwrap();
+        assert_eq!(4, value.value());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert!(rolled.exploded.as_ref().unwrap().capped);
+        // the die is held to the same limit when it's rerolled
+        value.reroll(&[0], &mut mock_rng![]);
+        assert_eq!(4, value.value());
+        assert_eq!(101, d1.evaluate(&mut mock_rng![]).value());
+    }
+
+    #[test]
+    fn extra_dice_join_the_pool() {
+        let roll = Roll {
+            explode: Some(Explosion {
+                explode: Explode::Extra,
+                on: Some(Condition {
+                    comparison: Comparison::AtLeast,
+                    against: Exp::Const(5),
+                }),
+            }),
+            ..Roll::keep_highest(Exp::Const(3), Exp::Const(6), Exp::Const(2))
+        };
+        let mut value = Exp::roll(roll.clone()).evaluate(&mut mock_rng![5, 2, 1, 6, 3]);
+        assert_eq!(11, value.value());
+        assert_eq!(5, value.dice().count());
+        assert_eq!(
+            vec![0, 1, 2, 3, 4],
+            value.dice().map(|die| die.id).sorted().collect_vec()
+        );
+        assert_eq!("3d6!>=5k2", value.to_string());
+        assert_eq!(Exp::roll(roll), value.expression());
+
+        // a rerolled die explodes all over again, into dice of its own
+        let one = value.faces().iter().position(|&face| face == 1).unwrap();
+        value.reroll(&[one], &mut mock_rng![6, 4]);
+        assert_eq!(12, value.value());
+        assert_eq!(6, value.dice().count());
+    }
+
+    #[test]
+    fn rerolled_dice_keep_what_they_left_behind() {
+        let roll = Roll {
+            reroll: Some(Condition {
+                comparison: Comparison::Equal,
+                against: Exp::Const(1),
+            }),
+            ..Roll::simple(Exp::Const(2), Exp::Const(6))
+        };
+        let mut value = Exp::roll(roll.clone()).evaluate(&mut mock_rng![1, 1, 4, 3]);
+        assert_eq!(7, value.value());
+        assert_eq!("2d6r1", value.to_string());
+        assert_eq!(Exp::roll(roll), value.expression());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert_eq!(Some(&[1, 1][..]), rolled.discarded(0));
+        assert_eq!(None, rolled.discarded(1));
+
+        // a die rerolled by hand is rerolled on the same faces
+        value.reroll(&[1], &mut mock_rng![1, 5]);
+        assert_eq!(9, value.value());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert_eq!(Some(&[1][..]), rolled.discarded(1));
+    }
+
+    #[test]
+    fn rerolls_stop_at_the_limit() {
+        let d1 = Exp::roll(Roll {
+            reroll: Some(Condition {
+                comparison: Comparison::AtMost,
+                against: Exp::Const(1),
+            }),
+            ..Roll::simple(Exp::Const(1), Exp::Const(1))
+        });
+        let limits = Limits {
+            max_explosions: 3,
+            ..Default::default()
+        };
+        let value = d1.evaluate_within(&mut mock_rng![], &limits).unwrap();
+        assert_eq!(1, value.value());
+        let Value::Rolled(rolled) = &value else {
+            panic!("a roll comes out rolled");
+        };
+        assert!(rolled.rerolled.as_ref().unwrap().capped);
+        assert_eq!(Some(&[1, 1, 1][..]), rolled.discarded(0));
+        assert_eq!("1d1r<=1", value.to_string());
+    }
+
+    #[test]
+    fn explosions_and_rerolls_stop_alike() {
+        let roll = |explode, reroll| {
+            Exp::roll(Roll {
+                explode,
+                reroll,
+                ..Roll::simple(Exp::Const(1), Exp::Const(1))
+            })
+        };
+        let always = || Condition {
+            comparison: Comparison::AtMost,
+            against: Exp::Const(1),
+        };
+        let compound = roll(Some(Explosion::new(Explode::Compound)), None);
+        let extra = roll(Some(Explosion::new(Explode::Extra)), None);
+        let rerolled = roll(None, Some(always()));
+        for most in [0, 1, 3] {
+            let limits = Limits {
+                max_explosions: most,
+                ..Default::default()
+            };
+            let rolls = |exp: &Exp| exp.evaluate_within(&mut mock_rng![], &limits).unwrap();
+            // a die is rolled `most` more times after the first, whichever
+            // way it's rolled again
+            assert_eq!(most as Int + 1, rolls(&compound).value());
+            assert_eq!(most + 1, rolls(&extra).dice().count());
+            let Value::Rolled(rolled) = rolls(&rerolled) else {
+                panic!("a roll comes out rolled");
+            };
+            assert_eq!(most, rolled.discarded(0).unwrap_or_default().len());
+            assert!(rolled.rerolled.unwrap().capped);
+        }
+    }
+
+    #[test]
+    fn explosion_thresholds_are_rolled() {
+        let d4 = Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(4)));
+        let roll = Roll {
+            explode: Some(Explosion {
+                explode: Explode::Extra,
+                on: Some(Condition {
+                    comparison: Comparison::Greater,
+                    against: d4,
+                }),
+            }),
+            ..Roll::simple(Exp::Const(1), Exp::Const(6))
+        };
+        let value = Exp::roll(roll).evaluate(&mut mock_rng![2, 3, 1]);
+        assert_eq!(4, value.value());
+        // the threshold's die comes before the dice it decides on
+        assert_eq!(vec![2, 3, 1], value.faces());
+        assert_eq!("1d6!>(1d4)", value.to_string());
+    }
+
+    #[test]
+    fn depth_limit() {
+        let exp = Exp::add(vec_deque![
+            Exp::Const(1),
+            Exp::mul(vec_deque![Exp::Const(2), Exp::Const(3)])
+        ]);
+        let limits = |max_depth| Limits {
+            max_depth,
+            ..Default::default()
+        };
+        assert!(exp.evaluate_within(&mut mock_rng![], &limits(3)).is_ok());
+        assert!(exp.evaluate_within(&mut mock_rng![], &limits(2)).is_err());
+    }
+
+    #[test]
+    fn assumed_dice() {
+        // (1d2)d6k1 + 3, with every die at one end or the other
+        let inner = Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(2)));
+        let roll = Exp::roll(Roll::keep_highest(inner, Exp::Const(6), Exp::Const(1)));
+        let exp = Exp::add(vec_deque![roll, Exp::Const(3)]);
+        let assuming = |assume| {
+            exp.evaluate_assuming(assume, &mut mock_rng![], &Limits::default())
+                .unwrap()
+        };
+        let max = assuming(Assume::Max);
+        assert_eq!(9, max.value());
+        assert_eq!(vec![2, 6, 6], max.faces());
+        assert_eq!(4, assuming(Assume::Min).value());
+        // a d2 averages 1.5, rounded down to 1, and then the lone d6 to 3
+        let avg = assuming(Assume::Avg);
+        assert_eq!(vec![1, 3], avg.faces());
+        let pool = |input: Int| {
+            Exp::roll(Roll::simple(Exp::Const(input), Exp::Const(6)))
+                .evaluate_assuming(Assume::Avg, &mut mock_rng![], &Limits::default())
+                .unwrap()
+        };
+        assert_eq!(vec![3, 4, 3, 4], pool(4).faces());
+        assert_eq!(10, pool(3).value());
+        assert_eq!(vec![1, 3], assuming(Assume::Median).faces());
+        assert_eq!(vec![2, 5, 5], assuming("5".parse().unwrap()).faces());
+        assert!("mean".parse::<Assume>().is_err());
+        let zero = Exp::roll(Roll::simple(Exp::Const(2), Exp::Const(0)));
+        let zero = zero.evaluate_assuming(Assume::Min, &mut mock_rng![], &Limits::default());
+        assert_eq!(0, zero.unwrap().value());
+    }
+
+    #[test]
+    fn observed_dice() {
+        // (1d2)d6: the inner roll is node 0, the outer one node 1
+        let inner = Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(2)));
+        let exp = Exp::roll(Roll::simple(inner, Exp::Const(6)));
+        let mut seen = Vec::new();
+        let value = exp
+            .evaluate_observed(&mut mock_rng![2, 5, 3], &Limits::default(), &mut |die| {
+                seen.push((die.node, die.sides, die.value))
+            })
+            .unwrap();
+        assert_eq!(8, value.value());
+        assert_eq!(vec![(0, 2, 2), (1, 6, 5), (1, 6, 3)], seen);
+    }
 }
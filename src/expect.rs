@@ -0,0 +1,90 @@
+//! The range of results given with `--expect`, like `3..18`, which every
+//! roll has to land in for the command to succeed. It's meant for checking
+//! saved rolls and homebrew in scripts, where a roll that comes out wrong
+//! should fail the run.
+
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use recursive_dice_roller::eval::Int;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Expected {
+    pub lowest: Option<Int>,
+    pub highest: Option<Int>,
+}
+
+impl FromStr for Expected {
+    type Err = String;
+
+    /// `3..18`, with both ends included, or `3..` or `..18` for only one of
+    /// them, or a single number that has to be rolled exactly
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bound = |end: &str| match end.trim() {
+            "" => Ok(None),
+            end => end.parse().map(Some),
+        };
+        let expected = match s.split_once("..") {
+            Some((lowest, highest)) => bound(lowest)
+                .and_then(|lowest| Ok((lowest, bound(highest)?)))
+                .map(|(lowest, highest)| Expected { lowest, highest }),
+            None => bound(s).map(|exactly| Expected {
+                lowest: exactly,
+                highest: exactly,
+            }),
+        };
+        match expected {
+            Ok(expected) if expected.lowest.is_none() && expected.highest.is_none() => {
+                Err("the range needs at least one end, like 3..18 or 3..".to_string())
+            }
+            Ok(expected) if expected.lowest > expected.highest && expected.highest.is_some() => {
+                Err(format!("nothing is in {s}, since it ends before it starts"))
+            }
+            Ok(expected) => Ok(expected),
+            Err(_) => Err(format!("'{s}' is not a range of results like 3..18")),
+        }
+    }
+}
+
+impl Expected {
+    pub fn contains(&self, total: Int) -> bool {
+        self.lowest.is_none_or(|lowest| total >= lowest)
+            && self.highest.is_none_or(|highest| total <= highest)
+    }
+}
+
+impl Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(lowest) = self.lowest {
+            write!(f, "{lowest}")?;
+        }
+        write!(f, "..")?;
+        if let Some(highest) = self.highest {
+            write!(f, "{highest}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::expect::*;
+
+    #[test]
+    fn ranges() {
+        let expected: Expected = "3..18".parse().unwrap();
+        assert!(expected.contains(3) && expected.contains(18));
+        assert!(!expected.contains(2) && !expected.contains(19));
+        assert_eq!("3..18", expected.to_string());
+
+        let at_least: Expected = "-2..".parse().unwrap();
+        assert!(at_least.contains(1000) && !at_least.contains(-3));
+        let exactly: Expected = "7".parse().unwrap();
+        assert!(exactly.contains(7) && !exactly.contains(8));
+        assert_eq!("7..7", exactly.to_string());
+
+        assert!("..".parse::<Expected>().is_err());
+        assert!("18..3".parse::<Expected>().is_err());
+        assert!("3..eighteen".parse::<Expected>().is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! A C interface, built with the `ffi` feature, for embedding the roller in
+//! apps written in C, C++, Swift, and anything else that can call C. The
+//! declarations are in `include/rdr.h`, which cbindgen can regenerate.
+//!
+//! Every result from [`rdr_evaluate`] has to be handed back to
+//! [`rdr_free_result`], since the strings in it were allocated by Rust.
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::document::Document;
+use crate::error::Error;
+use crate::eval::Limits;
+use crate::parse::parse;
+use crate::render::{self, RenderOptions};
+
+/// The outcome of rolling an expression. When `ok` is false, `total` is zero,
+/// `tree` holds the error message, and `json` is null.
+#[repr(C)]
+pub struct RdrResult {
+    pub ok: bool,
+    pub total: i32,
+    /// The same tree the command line draws, without colors
+    pub tree: *mut c_char,
+    /// The result document, as JSON
+    pub json: *mut c_char,
+}
+
+/// Parses and rolls a nul-terminated UTF-8 expression. Returns null only if
+/// `input` is null.
+///
+/// # Safety
+///
+/// `input` must be null or point to a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn rdr_evaluate(input: *const c_char) -> *mut RdrResult {
+    if input.is_null() {
+        return ptr::null_mut();
+    }
+    let input = CStr::from_ptr(input).to_string_lossy();
+    let result = match evaluate(&input) {
+        Ok((total, tree, json)) => RdrResult {
+            ok: true,
+            total,
+            tree: c_string(tree),
+            json: c_string(json),
+        },
+        Err(error) => RdrResult {
+            ok: false,
+            total: 0,
+            tree: c_string(error.to_string()),
+            json: ptr::null_mut(),
+        },
+    };
+    Box::into_raw(Box::new(result))
+}
+
+/// Frees a result from [`rdr_evaluate`], along with its strings
+///
+/// # Safety
+///
+/// `result` must be null or have come from [`rdr_evaluate`], and must not be
+/// used (or freed) again afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn rdr_free_result(result: *mut RdrResult) {
+    if result.is_null() {
+        return;
+    }
+    let result = Box::from_raw(result);
+    for string in [result.tree, result.json] {
+        if !string.is_null() {
+            drop(CString::from_raw(string));
+        }
+    }
+}
+
+// the total is only ever too big for C with the `wide` feature
+#[allow(clippy::useless_conversion)]
+fn evaluate(input: &str) -> Result<(i32, String, String), Error> {
+    let evaluated = parse(input)?.evaluate_within(&mut rand::thread_rng(), &Limits::default())?;
+    let tree = render::no_color(&evaluated, &RenderOptions::default())?;
+    let json = serde_json::to_string(&Document::new(&evaluated))?;
+    let total = i32::try_from(evaluated.value())
+        .map_err(|_| Error::Render(format!("{} is too large for a C total", evaluated.value())))?;
+    Ok((total, tree, json))
+}
+
+/// Strings headed to C can't have nuls in the middle, though nothing we write
+/// out should ever contain one
+fn c_string(string: String) -> *mut c_char {
+    CString::new(string.replace('\0', ""))
+        .expect("nuls were removed")
+        .into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffi::*;
+
+    #[test]
+    fn round_trip() {
+        let input = CString::new("2d6 + 3").unwrap();
+        unsafe {
+            let result = rdr_evaluate(input.as_ptr());
+            assert!((*result).ok);
+            assert!((5..=15).contains(&(*result).total));
+            let json = CStr::from_ptr((*result).json).to_str().unwrap();
+            assert!(json.contains("\"expression\":\"2d6 + 3\""));
+            rdr_free_result(result);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let input = CString::new("2d").unwrap();
+        unsafe {
+            let result = rdr_evaluate(input.as_ptr());
+            assert!(!(*result).ok);
+            assert!((*result).json.is_null());
+            rdr_free_result(result);
+            assert!(rdr_evaluate(ptr::null()).is_null());
+        }
+    }
+}
@@ -0,0 +1,595 @@
+//! Every way we know how to present an evaluated expression. The tree is the
+//! original (and default) output; the rest are for piping into other tools or
+//! pasting into chat. Renderers are looked up by name from [`RENDERERS`].
+
+use itertools::Itertools;
+use std::fmt::Write;
+
+use crate::document::Document;
+use crate::error::Error;
+use crate::eval::{Int, Operation, Rolled, Value};
+use crate::integrity;
+use crate::modifier;
+use crate::render::{self, RenderOptions};
+use crate::stats;
+
+type RenderFn = fn(&Value, &RenderOptions) -> Result<String, Error>;
+
+pub struct Renderer {
+    pub name: &'static str,
+    pub render: RenderFn,
+}
+
+pub const RENDERERS: &[Renderer] = &[
+    Renderer {
+        name: "tree",
+        render: render::no_color,
+    },
+    Renderer {
+        name: "inline",
+        render: inline,
+    },
+    Renderer {
+        name: "json",
+        render: json,
+    },
+    Renderer {
+        name: "markdown",
+        render: markdown,
+    },
+    Renderer {
+        name: "html",
+        render: html,
+    },
+    Renderer {
+        name: "discord",
+        render: discord,
+    },
+    Renderer {
+        name: "csv",
+        render: csv,
+    },
+    Renderer {
+        name: "tsv",
+        render: tsv,
+    },
+    Renderer {
+        name: "dot",
+        render: dot,
+    },
+];
+
+pub fn renderer(name: &str) -> Option<&'static Renderer> {
+    RENDERERS.iter().find(|renderer| renderer.name == name)
+}
+
+fn capped(output: String, options: &RenderOptions) -> String {
+    match options.max_output {
+        Some(max) => render::truncate(output, max),
+        None => output,
+    }
+}
+
+/// The dice of a roll as a comma-separated list, with the dropped dice struck
+/// through using markdown
+fn struck_faces(rolled: &Rolled, options: &RenderOptions) -> String {
+    if let Some(summary) = &rolled.kept.summary {
+        return summary.to_string();
+    }
+    let sides = rolled.sides();
+    let face = |(die, id): (&i32, Option<&usize>)| {
+        let face =
+            render::exploded_face(rolled, id, render::die_face(*die, sides, options), options);
+        render::rerolled_face(rolled, id, face, options, true)
+    };
+    let kept = rolled.kept.kept();
+    let (kept_ids, dropped_ids) = rolled
+        .kept
+        .ids
+        .split_at(kept.len().min(rolled.kept.ids.len()));
+    let dropped = render::with_ids(rolled.kept.dropped(), dropped_ids)
+        .map(|die| format!("~~{}~~", face(die)));
+    render::with_ids(kept, kept_ids)
+        .map(face)
+        .chain(dropped)
+        .join(", ")
+}
+
+/// A single line with each roll's dice written next to it, e.g.
+/// `4d6k3 [5, 2, 6 | 1] + 2 = 15`
+fn inline(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let expression = render::expression_with(value, &mut |v, formatted| match v {
+        Value::Rolled(rolled) => {
+            if let Some(summary) = &rolled.kept.summary {
+                return format!("{formatted} [{summary}]");
+            }
+            let sides = rolled.sides();
+            let face = |(die, id): (&i32, Option<&usize>)| {
+                let face = render::die_face(*die, sides, options);
+                let face = render::exploded_face(rolled, id, face, options);
+                render::rerolled_face(rolled, id, face, options, false)
+            };
+            let (highest_ids, lowest_ids) = rolled.kept.ids_by_side();
+            let kept = render::with_ids(&rolled.kept.highest, highest_ids)
+                .map(face)
+                .join(", ");
+            match rolled.kept.lowest.is_empty() {
+                true => format!("{formatted} [{kept}]"),
+                false => {
+                    let dropped = render::with_ids(&rolled.kept.lowest, lowest_ids)
+                        .map(face)
+                        .join(", ");
+                    format!("{formatted} [{kept} | {dropped}]")
+                }
+            }
+        }
+        _ => formatted,
+    });
+    Ok(capped(
+        format!("{expression} = {}\n", value.value()),
+        options,
+    ))
+}
+
+fn json(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    // truncating JSON would only produce something unparseable, so the output
+    // cap doesn't apply here
+    let document = Document {
+        seed: options.seed,
+        hash: options.seed.map(|seed| integrity::hash(value, seed)),
+        ..Document::new(value)
+    };
+    let mut output = serde_json::to_string_pretty(&document)?;
+    output.push('\n');
+    Ok(output)
+}
+
+/// A nested bullet list, one bullet per roll or operation
+fn markdown(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let mut output = String::new();
+    markdown_node(&mut output, value, None, 0, options);
+    if output.is_empty() {
+        writeln!(output, "**{}**", value.value()).unwrap();
+    }
+    Ok(capped(output, options))
+}
+
+fn markdown_node(
+    output: &mut String,
+    value: &Value,
+    parent_op: Option<&Operation>,
+    depth: usize,
+    options: &RenderOptions,
+) {
+    let indent = "  ".repeat(depth);
+    match value {
+        Value::Const(c) => {
+            if let Some(op) = parent_op {
+                writeln!(output, "{indent}- `{}{c}`", operator(op)).unwrap();
+            }
+        }
+        Value::Rolled(rolled) => {
+            writeln!(
+                output,
+                "{indent}- Rolling `{value}`: {} = **{}**",
+                struck_faces(rolled, options),
+                rolled.val()
+            )
+            .unwrap();
+            for component in children(value) {
+                markdown_node(output, component, None, depth + 1, options);
+            }
+        }
+        Value::Op { op, values } => {
+            writeln!(
+                output,
+                "{indent}- Evaluating `{value}` = **{}**",
+                value.value()
+            )
+            .unwrap();
+            for v in values {
+                markdown_node(output, v, Some(op), depth + 1, options);
+            }
+        }
+        Value::Repeated { pick, values } => {
+            let (kept, dropped) = render::totals(pick, values);
+            let dropped = dropped.iter().map(|total| format!("~~{total}~~"));
+            writeln!(
+                output,
+                "{indent}- Repeating `{value}`: {} = **{}**",
+                kept.iter().map(Int::to_string).chain(dropped).join(", "),
+                value.value()
+            )
+            .unwrap();
+            for v in values {
+                markdown_node(output, v, None, depth + 1, options);
+            }
+        }
+    }
+}
+
+/// Nested lists with a class on everything worth styling: `rdr-op`,
+/// `rdr-roll`, and `rdr-repeat` items, `rdr-die` faces marked `rdr-dropped`,
+/// `rdr-max`, or `rdr-min` as appropriate, and `rdr-repetition` totals marked
+/// `rdr-dropped` when they don't count
+fn html(value: &Value, _: &RenderOptions) -> Result<String, Error> {
+    // as with JSON, cutting the markup short would leave it unbalanced, so
+    // the output cap doesn't apply
+    let mut output = String::from("<div class=\"rdr-result\">\n");
+    writeln!(
+        output,
+        "  <p class=\"rdr-summary\"><code>{value}</code> = <strong class=\"rdr-total\">{}</strong></p>",
+        value.value()
+    )
+    .unwrap();
+    output.push_str("  <ul class=\"rdr-tree\">\n");
+    html_node(&mut output, value, None, 2);
+    output.push_str("  </ul>\n</div>\n");
+    Ok(output)
+}
+
+fn html_node(output: &mut String, value: &Value, parent_op: Option<&Operation>, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let (class, heading) = match value {
+        Value::Const(c) => {
+            if let Some(op) = parent_op {
+                writeln!(
+                    output,
+                    "{indent}<li class=\"rdr-const\">{}{c}</li>",
+                    operator(op)
+                )
+                .unwrap();
+            }
+            return;
+        }
+        Value::Rolled(rolled) => {
+            let sides = rolled.sides();
+            let face = |die: &i32, dropped: bool| {
+                let mut classes = String::from("rdr-die");
+                if dropped {
+                    classes.push_str(" rdr-dropped");
+                }
+                match *die {
+                    die if die as u32 == sides => classes.push_str(" rdr-max"),
+                    1 => classes.push_str(" rdr-min"),
+                    _ => {}
+                }
+                format!("<span class=\"{classes}\">{die}</span>")
+            };
+            let faces = match &rolled.kept.summary {
+                Some(summary) => format!("<span class=\"rdr-pool\">{summary}</span>"),
+                None => rolled
+                    .kept
+                    .kept()
+                    .iter()
+                    .map(|die| face(die, false))
+                    .chain(rolled.kept.dropped().iter().map(|die| face(die, true)))
+                    .join(""),
+            };
+            let heading = format!(
+                "<code>{value}</code> <span class=\"rdr-faces\">{faces}</span> = <strong>{}</strong>",
+                rolled.val()
+            );
+            ("rdr-roll", heading)
+        }
+        Value::Op { .. } => (
+            "rdr-op",
+            format!("<code>{value}</code> = <strong>{}</strong>", value.value()),
+        ),
+        Value::Repeated { pick, values } => {
+            let (kept, dropped) = render::totals(pick, values);
+            let total = |total: &Int, class| format!("<span class=\"{class}\">{total}</span>");
+            let totals = kept
+                .iter()
+                .map(|t| total(t, "rdr-repetition"))
+                .chain(
+                    dropped
+                        .iter()
+                        .map(|t| total(t, "rdr-repetition rdr-dropped")),
+                )
+                .join("");
+            let heading = format!(
+                "<code>{value}</code> <span class=\"rdr-totals\">{totals}</span> = <strong>{}</strong>",
+                value.value()
+            );
+            ("rdr-repeat", heading)
+        }
+    };
+    let children = children(value);
+    if children.is_empty() {
+        writeln!(output, "{indent}<li class=\"{class}\">{heading}</li>").unwrap();
+        return;
+    }
+    writeln!(output, "{indent}<li class=\"{class}\">{heading}").unwrap();
+    writeln!(output, "{indent}  <ul>").unwrap();
+    let op = match value {
+        Value::Op { op, .. } => Some(op),
+        _ => None,
+    };
+    for child in children {
+        html_node(output, child, op, depth + 2);
+    }
+    writeln!(output, "{indent}  </ul>").unwrap();
+    writeln!(output, "{indent}</li>").unwrap();
+}
+
+/// A compact, chat-sized summary: the total up top, then one quoted line per
+/// roll with the dropped dice struck through, and the seed and the start of
+/// its hash at the bottom
+fn discord(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let mut output = String::new();
+    writeln!(output, "`{value}` \u{2192} **{}**", value.value()).unwrap();
+    let mut rolls = Vec::new();
+    collect_rolls(value, &mut rolls);
+    for roll in rolls {
+        if let Value::Rolled(rolled) = roll {
+            let faces = struck_faces(rolled, options);
+            writeln!(output, "> `{roll}` [{faces}] = {}", rolled.val()).unwrap();
+        }
+    }
+    // in small print, for anyone who wants to check the roll
+    if let Some(seed) = options.seed {
+        let hash = integrity::hash(value, seed);
+        writeln!(
+            output,
+            "-# seed {seed} \u{b7} {}",
+            &hash[..integrity::SHORT]
+        )
+        .unwrap();
+    }
+    Ok(capped(output, options))
+}
+
+/// Every roll in the tree, in the order they appear in the expression
+fn collect_rolls<'a>(value: &'a Value, rolls: &mut Vec<&'a Value>) {
+    for child in children(value) {
+        collect_rolls(child, rolls);
+    }
+    if let Value::Rolled(_) = value {
+        rolls.push(value);
+    }
+}
+
+/// The nodes worth showing beneath this one. The constant parts of a roll are
+/// already spelled out in its expression, so they're left out.
+fn children(value: &Value) -> Vec<&Value> {
+    match value {
+        Value::Const(_) => vec![],
+        Value::Rolled(rolled) => rolled
+            .parts()
+            .into_iter()
+            .filter(|v| !matches!(v, Value::Const(_)))
+            .collect(),
+        Value::Op { values, .. } | Value::Repeated { values, .. } => values.iter().collect(),
+    }
+}
+
+/// One row per node of the tree. The first row is always the whole expression,
+/// so its result is the total.
+fn csv(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let mut output = String::from("depth,kind,expression,kept,dropped,result\n");
+    csv_rows(&mut output, value, 0);
+    Ok(capped(output, options))
+}
+
+fn csv_rows(output: &mut String, value: &Value, depth: usize) {
+    let (kind, kept, dropped) = match value {
+        Value::Const(_) => ("const", String::new(), String::new()),
+        Value::Rolled(rolled) => match &rolled.kept.summary {
+            Some(summary) => (
+                "roll",
+                csv_field(&modifier::describe(&summary.kept)),
+                csv_field(&modifier::describe(&summary.dropped)),
+            ),
+            None => (
+                "roll",
+                rolled.kept.kept().iter().join(" "),
+                rolled.kept.dropped().iter().join(" "),
+            ),
+        },
+        Value::Op { .. } => ("op", String::new(), String::new()),
+        Value::Repeated { pick, values } => {
+            let (kept, dropped) = render::totals(pick, values);
+            ("repeat", kept.iter().join(" "), dropped.iter().join(" "))
+        }
+    };
+    writeln!(
+        output,
+        "{depth},{kind},{},{kept},{dropped},{}",
+        csv_field(&value.to_string()),
+        value.value()
+    )
+    .unwrap();
+    for child in children(value) {
+        csv_rows(output, child, depth + 1);
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// One tab-separated line per expression, for scripts: the expression, its
+/// total, the lowest and highest totals it could have come to, and the seed
+/// (left empty when there isn't one that rolls it again). These columns, in
+/// this order, are a promise; anything new goes on the end.
+fn tsv(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let range = stats::range(&value.expression());
+    let seed = options
+        .seed
+        .map(|seed| seed.to_string())
+        .unwrap_or_default();
+    Ok(format!(
+        "{value}\t{}\t{}\t{}\t{seed}\n",
+        value.value(),
+        range.min,
+        range.max
+    ))
+}
+
+/// A graphviz digraph of the tree, for `dot -Tsvg`
+fn dot(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let mut output = String::from("digraph roll {\n    node [shape=box];\n");
+    dot_node(&mut output, value, &mut 0);
+    output.push_str("}\n");
+    Ok(capped(output, options))
+}
+
+/// Writes a node and its children, returning the id assigned to the node
+fn dot_node(output: &mut String, value: &Value, next_id: &mut usize) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    let label = match value {
+        Value::Const(c) => c.to_string(),
+        Value::Rolled(rolled) => format!(
+            "{value}\\n[{}]\\n= {}",
+            match &rolled.kept.summary {
+                Some(summary) => modifier::describe(&summary.kept),
+                None => rolled.kept.kept().iter().join(", "),
+            },
+            rolled.val()
+        ),
+        Value::Op { .. } | Value::Repeated { .. } => format!("{value}\\n= {}", value.value()),
+    };
+    writeln!(
+        output,
+        "    n{id} [label=\"{}\"];",
+        label.replace('"', "\\\"")
+    )
+    .unwrap();
+    for child in children(value) {
+        let child_id = dot_node(output, child, next_id);
+        writeln!(output, "    n{id} -> n{child_id};").unwrap();
+    }
+    id
+}
+
+fn operator(op: &Operation) -> char {
+    match op {
+        Operation::Add => '+',
+        Operation::Sub => '-',
+        Operation::Mul => '\u{00D7}',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{Kept, KeptRule, Operation, Rolled, Value};
+    use crate::formats::*;
+
+    fn attack() -> Value {
+        Value::Op {
+            op: Operation::Add,
+            values: vec![
+                Value::Rolled(Rolled {
+                    dice: Box::new(Value::Const(2)),
+                    sides: Box::new(Value::Const(20)),
+                    kept: Box::new(Kept {
+                        keep: KeptRule::Highest(Value::Const(1)),
+                        retained: Value::Const(1),
+                        lowest: vec![4],
+                        highest: vec![17],
+                        ids: vec![0, 1],
+                        summary: None,
+                    }),
+                    exploded: None,
+                    rerolled: None,
+                }),
+                Value::Const(5),
+            ],
+        }
+    }
+
+    fn rendered(name: &str) -> String {
+        let renderer = renderer(name).unwrap();
+        (renderer.render)(&attack(), &RenderOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn every_renderer_is_registered() {
+        for name in [
+            "tree", "inline", "json", "markdown", "html", "discord", "csv", "tsv", "dot",
+        ] {
+            assert!(renderer(name).is_some(), "{name} is missing");
+        }
+        assert!(renderer("nonsense").is_none());
+    }
+
+    #[test]
+    fn inline_format() {
+        assert_eq!("2d20k1 [17 | 4] + 5 = 22\n", rendered("inline"));
+    }
+
+    #[test]
+    fn discord_format() {
+        assert_eq!(
+            "`2d20k1 + 5` \u{2192} **22**\n> `2d20k1` [17, ~~4~~] = 17\n",
+            rendered("discord")
+        );
+        let options = RenderOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        let seeded = discord(&attack(), &options).unwrap();
+        let hash = &integrity::hash(&attack(), 42)[..integrity::SHORT];
+        assert!(seeded.ends_with(&format!("= 17\n-# seed 42 \u{b7} {hash}\n")));
+    }
+
+    #[test]
+    fn json_format() {
+        let json: serde_json::Value = serde_json::from_str(&rendered("json")).unwrap();
+        assert_eq!(22, json["total"]);
+        assert_eq!("op", json["breakdown"]["kind"]);
+        assert_eq!(17, json["breakdown"]["terms"][0]["kept"][0]);
+        assert_eq!(4, json["breakdown"]["terms"][0]["dropped"][0]);
+        assert_eq!(0, json["breakdown"]["terms"][0]["id"]);
+        assert!(json.get("seed").is_none());
+        let options = RenderOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        let seeded = super::json(&attack(), &options).unwrap();
+        let seeded: serde_json::Value = serde_json::from_str(&seeded).unwrap();
+        assert_eq!(42, seeded["seed"]);
+        assert_eq!(integrity::hash(&attack(), 42), seeded["hash"]);
+    }
+
+    #[test]
+    fn html_format() {
+        let html = rendered("html");
+        assert!(html.contains("<strong class=\"rdr-total\">22</strong>"));
+        assert!(html.contains(
+            "<span class=\"rdr-die\">17</span><span class=\"rdr-die rdr-dropped\">4</span>"
+        ));
+        assert!(html.contains("<li class=\"rdr-const\">+5</li>"));
+    }
+
+    #[test]
+    fn tsv_format() {
+        assert_eq!("2d20k1 + 5\t22\t6\t25\t\n", rendered("tsv"));
+        let options = RenderOptions {
+            seed: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(
+            "2d20k1 + 5\t22\t6\t25\t42\n",
+            tsv(&attack(), &options).unwrap()
+        );
+    }
+
+    #[test]
+    fn csv_format() {
+        let csv = rendered("csv");
+        let mut lines = csv.lines();
+        assert_eq!(
+            Some("depth,kind,expression,kept,dropped,result"),
+            lines.next()
+        );
+        assert_eq!(Some("0,op,2d20k1 + 5,,,22"), lines.next());
+        assert_eq!(Some("1,roll,2d20k1,17,4,17"), lines.next());
+    }
+}
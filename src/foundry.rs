@@ -0,0 +1,204 @@
+//! `rdr alias import`: bringing macros and roll tables exported from Foundry
+//! VTT over as aliases. An export is one document, an array of them, or a
+//! compendium's worth with one per line. Chat macros give up their roll
+//! commands and inline `[[rolls]]`, script macros any `new Roll("...")`, and
+//! roll tables their formula. Whatever can't be converted is reported, with
+//! the reason, rather than saved.
+
+use serde_json::Value;
+
+use crate::dialect::{self, Dialect};
+
+/// The chat commands that roll their formula
+const COMMANDS: &[&str] = &[
+    "/roll",
+    "/r",
+    "/gmroll",
+    "/gmr",
+    "/blindroll",
+    "/broll",
+    "/br",
+    "/selfroll",
+    "/sr",
+    "/publicroll",
+    "/pr",
+];
+
+/// One document from the export, converted (or not)
+pub struct Converted {
+    /// What it was called in Foundry
+    pub source: String,
+    /// The alias name and its expressions, or why there aren't any
+    pub alias: Result<(String, String), String>,
+}
+
+pub fn import(contents: &str) -> Result<Vec<Converted>, String> {
+    let documents = match serde_json::from_str::<Value>(contents) {
+        Ok(Value::Array(documents)) => documents,
+        Ok(document) => vec![document],
+        Err(e) => contents
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("This isn't a Foundry export: {e}"))?,
+    };
+    Ok(documents.iter().map(convert).collect())
+}
+
+fn convert(document: &Value) -> Converted {
+    let source = document
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("(unnamed)")
+        .to_string();
+    let alias = formulas(document).and_then(|formulas| {
+        let expressions = formulas
+            .iter()
+            .map(|formula| dialect::rewrite(formula, Dialect::Foundry))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        Ok((alias_name(&source)?, expressions.join("; ")))
+    });
+    Converted { source, alias }
+}
+
+/// The formulas a macro or roll table rolls
+fn formulas(document: &Value) -> Result<Vec<String>, String> {
+    let text = |key| document.get(key).and_then(Value::as_str);
+    let formulas = match (text("command"), text("type")) {
+        (Some(command), Some("script")) => scripted(command),
+        (Some(command), _) => chat(command),
+        (None, _) => match text("formula") {
+            Some(formula) if document.get("results").is_some() => vec![formula.to_string()],
+            _ => return Err("it's neither a macro nor a roll table".to_string()),
+        },
+    };
+    let formulas: Vec<String> = formulas
+        .into_iter()
+        .map(|formula| formula.trim().to_string())
+        .filter(|formula| !formula.is_empty())
+        .collect();
+    match formulas.is_empty() {
+        true => Err("it doesn't roll anything we can find".to_string()),
+        false => Ok(formulas),
+    }
+}
+
+/// The roll commands of a chat macro, and the inline rolls in its messages
+fn chat(command: &str) -> Vec<String> {
+    let mut formulas = Vec::new();
+    for line in command.lines().map(str::trim) {
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+        if COMMANDS.contains(&word) {
+            // anything after a '#' is the roll's flavor text
+            formulas.push(rest.split('#').next().unwrap_or_default().to_string());
+            continue;
+        }
+        let mut rest = line;
+        while let Some((_, inline)) = rest.split_once("[[") {
+            let Some(end) = closing(inline) else {
+                break;
+            };
+            let formula = inline[..end].trim();
+            let formula = COMMANDS
+                .iter()
+                .find_map(|command| formula.strip_prefix(&format!("{command} ")))
+                .unwrap_or(formula);
+            formulas.push(formula.to_string());
+            rest = &inline[end + 2..];
+        }
+    }
+    formulas
+}
+
+/// Where the `]]` closing an inline roll is, past any `[labels]` inside it
+fn closing(inline: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in inline.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth > 0 => depth -= 1,
+            ']' if inline[i..].starts_with("]]") => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The first argument of every `new Roll(...)` in a script macro, when it's
+/// a plain string
+fn scripted(script: &str) -> Vec<String> {
+    script
+        .split("new Roll(")
+        .skip(1)
+        .filter_map(|call| {
+            let call = call.trim_start();
+            let quote = call.chars().next().filter(|c| matches!(c, '"' | '\''))?;
+            let formula = call[1..].split(quote).next()?;
+            Some(formula.to_string())
+        })
+        .collect()
+}
+
+/// Turns a name like "Sneak Attack (3rd)" into `sneak_attack_3rd`
+fn alias_name(source: &str) -> Result<String, String> {
+    let mut name = String::new();
+    for c in source.chars() {
+        match c.is_ascii_alphanumeric() {
+            true => name.push(c.to_ascii_lowercase()),
+            false if !name.is_empty() && !name.ends_with('_') => name.push('_'),
+            false => {}
+        }
+    }
+    let name = name.trim_end_matches('_');
+    match name.starts_with(|c: char| c.is_ascii_digit()) {
+        true => Ok(format!("_{name}")),
+        false if name.is_empty() => Err("its name has no letters or digits".to_string()),
+        false => Ok(name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::foundry::*;
+
+    fn aliases(contents: &str) -> Vec<Result<(String, String), String>> {
+        import(contents)
+            .unwrap()
+            .into_iter()
+            .map(|converted| converted.alias)
+            .collect()
+    }
+
+    #[test]
+    fn macros_and_tables() {
+        let export = r#"[
+            {"name": "Sneak Attack", "type": "chat", "command": "/r 3d6 # sneaky\n/gmr 1d20 + @abilities.dex.mod"},
+            {"name": "Fireball!", "type": "chat", "command": "Burn! [[8d6[fire]]] then [[/r 1d4]]"},
+            {"name": "Smite", "type": "script", "command": "let r = new Roll(\"2d8 + 2\"); r.toMessage();"},
+            {"name": "Wild Magic", "formula": "1d100", "results": []},
+            {"name": "Hello", "type": "chat", "command": "Hello there"},
+            {"name": "Scene", "navigation": true}
+        ]"#;
+        let aliases = aliases(export);
+        let ok = |name: &str, expression: &str| Ok((name.to_string(), expression.to_string()));
+        assert_eq!(
+            ok("sneak_attack", "3d6; 1d20 + @abilities_dex_mod"),
+            aliases[0]
+        );
+        assert_eq!(ok("fireball", "8d6; 1d4"), aliases[1]);
+        assert_eq!(ok("smite", "2d8 + 2"), aliases[2]);
+        assert_eq!(ok("wild_magic", "1d100"), aliases[3]);
+        assert!(aliases[4].is_err());
+        assert!(aliases[5].is_err());
+    }
+
+    #[test]
+    fn compendiums() {
+        let compendium = "{\"name\": \"A\", \"command\": \"/r d4\"}\n{\"name\": \"2 B\", \"command\": \"/r d6\"}\n";
+        let aliases = aliases(compendium);
+        assert_eq!(Ok(("_2_b".to_string(), "d6".to_string())), aliases[1]);
+        assert!(import("not json").is_err());
+    }
+}
@@ -0,0 +1,168 @@
+//! Random expressions, for property tests and fuzzing. With the `arbitrary`
+//! feature on, [`Exp`] implements [`Arbitrary`], so a fuzz target can take
+//! one as its input:
+//!
+//! ```ignore
+//! fuzz_target!(|exp: Exp| {
+//!     let _ = exp.evaluate_within(&mut rand::thread_rng(), &Limits::default());
+//! });
+//! ```
+//!
+//! The trees are kept small enough that no total in them can overflow an
+//! `i32`, but a roll's number of dice can still come out in the millions, so
+//! anything rolling them should do it within some [`Limits`](crate::Limits).
+
+use std::collections::VecDeque;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::dialect::{self, Dialect};
+use crate::eval::{Exp, Int, Keep, Roll};
+
+/// The most constants a generated tree holds. Every operation and roll
+/// multiplies its arguments at worst, so with constants no bigger than
+/// [`MAX_CONST`] the result stays well inside an `i32`.
+const MAX_LEAVES: usize = 8;
+
+const MAX_CONST: Int = 12;
+
+/// The most arguments a single operation gets
+const MAX_ARGUMENTS: usize = 4;
+
+impl<'a> Arbitrary<'a> for Exp {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let leaves = u.int_in_range(1..=MAX_LEAVES)?;
+        expression(u, leaves)
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (1, None)
+    }
+}
+
+/// An expression along with how it's written, which parses back into an
+/// expression that rolls the same way
+#[derive(Debug, Clone)]
+pub struct Notated {
+    pub exp: Exp,
+    pub notation: String,
+}
+
+impl<'a> Arbitrary<'a> for Notated {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let exp = Exp::arbitrary(u)?;
+        let notation =
+            dialect::write(&exp, Dialect::Rdr).expect("our own dialect can write anything");
+        Ok(Notated { exp, notation })
+    }
+
+    fn size_hint(depth: usize) -> (usize, Option<usize>) {
+        Exp::size_hint(depth)
+    }
+}
+
+/// A tree holding exactly `leaves` constants
+fn expression(u: &mut Unstructured, leaves: usize) -> Result<Exp> {
+    if leaves == 1 {
+        return Ok(Exp::Const(u.int_in_range(-MAX_CONST..=MAX_CONST)?));
+    }
+    if u.arbitrary()? {
+        // a keep rule needs a leaf of its own
+        let rule = match leaves {
+            2 => 0,
+            _ => u.int_in_range(0..=4)?,
+        };
+        let parts = split(u, leaves, if rule == 0 { 2 } else { 3 })?;
+        let dice = expression(u, parts[0])?;
+        let sides = expression(u, parts[1])?;
+        let keep = match rule {
+            0 => Keep::All,
+            1 => Keep::Highest(expression(u, parts[2])?),
+            2 => Keep::Lowest(expression(u, parts[2])?),
+            3 => Keep::DropLowest(expression(u, parts[2])?),
+            _ => Keep::DropHighest(expression(u, parts[2])?),
+        };
+        return Ok(Exp::roll(Roll {
+            dice,
+            sides,
+            keep,
+            explode: None,
+            reroll: None,
+        }));
+    }
+    let operation = u.int_in_range(0..=2)?;
+    let count = u.int_in_range(2..=leaves.min(MAX_ARGUMENTS))?;
+    let arguments = split(u, leaves, count)?
+        .into_iter()
+        .map(|part| expression(u, part))
+        .collect::<Result<VecDeque<_>>>()?;
+    Ok(match operation {
+        0 => Exp::add(arguments),
+        1 => Exp::sub(arguments),
+        _ => Exp::mul(arguments),
+    })
+}
+
+/// Divides `leaves` among `parts` arguments, each getting at least one
+fn split(u: &mut Unstructured, leaves: usize, parts: usize) -> Result<Vec<usize>> {
+    let mut sizes = vec![1; parts];
+    for _ in parts..leaves {
+        let part = u.choose_index(parts)?;
+        sizes[part] += 1;
+    }
+    Ok(sizes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::Limits;
+    use crate::generate::*;
+    use crate::parse::parse;
+    use crate::render::{self, RenderOptions};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    /// Runs the property against a few hundred generated expressions
+    fn check(property: impl Fn(Notated)) {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..500 {
+            let bytes: Vec<u8> = (0..64).map(|_| rng.gen()).collect();
+            let notated = Notated::arbitrary(&mut Unstructured::new(&bytes)).unwrap();
+            property(notated);
+        }
+    }
+
+    #[test]
+    fn notation_parses() {
+        check(|Notated { notation, .. }| {
+            let reparsed = parse(&notation).unwrap_or_else(|e| panic!("{notation}: {e}"));
+            // the parser folds `1 + (2 + 3)` into one sum, so the text can
+            // change once, but no further
+            let written = dialect::write(&reparsed, Dialect::Rdr).unwrap();
+            assert_eq!(
+                written,
+                dialect::write(&parse(&written).unwrap(), Dialect::Rdr).unwrap()
+            );
+        });
+    }
+
+    #[test]
+    fn notation_rolls_the_same() {
+        let limits = Limits {
+            max_dice: 10_000,
+            ..Default::default()
+        };
+        check(|Notated { exp, notation }| {
+            let reparsed = parse(&notation).unwrap();
+            let rolled = exp.evaluate_within(&mut StdRng::seed_from_u64(1), &limits);
+            let again = reparsed.evaluate_within(&mut StdRng::seed_from_u64(1), &limits);
+            match (rolled, again) {
+                (Ok(rolled), Ok(again)) => {
+                    assert_eq!(rolled.value(), again.value(), "{notation}");
+                    render::no_color(&rolled, &RenderOptions::default()).unwrap();
+                }
+                (Err(_), Err(_)) => {}
+                (rolled, again) => panic!("{notation}: {rolled:?} vs {again:?}"),
+            }
+        });
+    }
+}
@@ -0,0 +1,131 @@
+//! Everything typed at the REPL, kept between sessions in the config
+//! directory like a shell's history. A line starting with `!` brings back an
+//! earlier one: `!!` is the last line, `!3` the third in `.history`, `!-2`
+//! the one before last, and `!4d6` the latest that started with `4d6`.
+//! Whatever follows is tacked on, so `!! + 2` is the last roll plus two.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How many lines are kept in the file; older ones are forgotten
+const KEEP: usize = 1000;
+
+#[derive(Debug, Default)]
+pub struct History {
+    /// Where the history is saved, if anywhere
+    path: Option<PathBuf>,
+    pub lines: Vec<String>,
+}
+
+impl History {
+    /// Reads the history at the path, which is started afresh if it doesn't
+    /// exist yet
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let lines = match path.exists() {
+            true => fs::read_to_string(path)
+                .map_err(|e| format!("Could not read {}: {e}", path.display()))?
+                .lines()
+                .map(str::to_string)
+                .collect(),
+            false => Vec::new(),
+        };
+        Ok(History {
+            path: Some(path.to_path_buf()),
+            lines,
+        })
+    }
+
+    /// Remembers a line, unless it's blank or the same as the last one
+    pub fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.lines.last().is_some_and(|last| last == line) {
+            return;
+        }
+        self.lines.push(line.to_string());
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {e}", parent.display()))?;
+        }
+        let start = self.lines.len().saturating_sub(KEEP);
+        let mut contents = self.lines[start..].join("\n");
+        contents.push('\n');
+        fs::write(path, contents).map_err(|e| format!("Could not write {}: {e}", path.display()))
+    }
+
+    /// The line with any `!` recall at its start replaced by the line it
+    /// refers to
+    pub fn recall(&self, line: &str) -> Result<String, String> {
+        let Some(designator) = line.strip_prefix('!') else {
+            return Ok(line.to_string());
+        };
+        let (designator, rest) = match designator.strip_prefix('!') {
+            Some(rest) => ("!", rest),
+            None => {
+                let end = designator
+                    .find(char::is_whitespace)
+                    .unwrap_or(designator.len());
+                designator.split_at(end)
+            }
+        };
+        let recalled = match designator {
+            "!" => self.lines.last(),
+            _ => match designator.parse::<isize>() {
+                Ok(n) if n < 0 => self
+                    .lines
+                    .len()
+                    .checked_sub(n.unsigned_abs())
+                    .and_then(|i| self.lines.get(i)),
+                Ok(n) => (n as usize).checked_sub(1).and_then(|i| self.lines.get(i)),
+                Err(_) => self
+                    .lines
+                    .iter()
+                    .rev()
+                    .find(|line| line.starts_with(designator)),
+            },
+        };
+        match recalled {
+            Some(recalled) => Ok(format!("{recalled}{rest}")),
+            None => Err(format!("Nothing in the history for !{designator}")),
+        }
+    }
+
+    /// The numbered list `.history` shows, the last `count` lines of it
+    pub fn report(&self, count: usize) -> String {
+        let start = self.lines.len().saturating_sub(count);
+        let width = self.lines.len().to_string().len();
+        self.lines[start..]
+            .iter()
+            .enumerate()
+            .map(|(i, line)| format!("{:>width$}  {line}\n", start + i + 1))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::history::*;
+
+    #[test]
+    fn recalling() {
+        let mut history = History::default();
+        for line in ["4d6k3", "d20 + 5", "d20 + 5", "2d8"] {
+            history.push(line);
+        }
+        assert_eq!(3, history.lines.len());
+        assert_eq!(Ok("2d8".to_string()), history.recall("!!"));
+        assert_eq!(Ok("2d8 + 2".to_string()), history.recall("!! + 2"));
+        assert_eq!(Ok("4d6k3".to_string()), history.recall("!1"));
+        assert_eq!(Ok("d20 + 5 - 1".to_string()), history.recall("!-2 - 1"));
+        assert_eq!(Ok("4d6k3".to_string()), history.recall("!4d"));
+        assert_eq!(Ok("d20".to_string()), history.recall("d20"));
+        assert!(history.recall("!9").is_err());
+        assert!(history.recall("!x").is_err());
+        assert_eq!("2  d20 + 5\n3  2d8\n", history.report(2));
+    }
+}
@@ -0,0 +1,69 @@
+//! A fingerprint of a roll, taken over its expression, its seed, and every
+//! die it rolled. Shared alongside the seed, it lets anyone holding a
+//! screenshot or a chat message roll the expression again with `rdr verify`
+//! and see whether the dice shown are the dice that seed really rolls.
+
+use sha2::{Digest, Sha256};
+
+use crate::eval::Value;
+
+/// How much of the hash chat messages show, which is still far too much to
+/// hit on by changing the dice until it matches
+pub const SHORT: usize = 12;
+
+/// The shortest prefix of a hash that [`matches`] will accept
+const SHORTEST: usize = 8;
+
+/// The SHA-256 of the value's expression, the seed, and each die in the
+/// order it was rolled, as lowercase hex
+pub fn hash(value: &Value, seed: u64) -> String {
+    let mut dice: Vec<_> = value.dice().collect();
+    dice.sort_by_key(|die| die.id);
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{value}\n{seed}\n"));
+    for die in dice {
+        hasher.update(format!("d{}={}\n", die.sides, die.value));
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Whether a claimed hash is the actual one, or enough of the start of it,
+/// like the [`SHORT`] hash in a chat message
+pub fn matches(actual: &str, claimed: &str) -> bool {
+    let claimed = claimed.trim().to_ascii_lowercase();
+    claimed.len() >= SHORTEST && actual.starts_with(&claimed)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::integrity::*;
+    use crate::parse::parse;
+
+    #[test]
+    fn same_roll_same_hash() {
+        let roll = |expression, seed| {
+            let value = parse(expression)
+                .unwrap()
+                .evaluate(&mut StdRng::seed_from_u64(seed));
+            hash(&value, seed)
+        };
+        let actual = roll("4d6k3 + 2", 7);
+        assert_eq!(64, actual.len());
+        // however it was written, it's the same expression
+        assert_eq!(actual, roll("4d6kh3+2", 7));
+        assert_ne!(actual, roll("4d6k3 + 2", 8));
+        assert_ne!(actual, roll("4d6k3 + 3", 7));
+
+        assert!(matches(&actual, &actual));
+        assert!(matches(&actual, &actual[..SHORT].to_uppercase()));
+        assert!(!matches(&actual, &actual[..4]));
+        assert!(!matches(&actual, "0123456789ab"));
+    }
+}
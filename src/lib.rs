@@ -1,24 +1,51 @@
 #![allow(clippy::needless_return, clippy::neg_multiply)]
+//! Parses and rolls dice expressions like `(3d2 + 1)d(2d4)kl(2 * 1d4)`, where
+//! anywhere a number can go, another roll can go instead.
+//!
+//! ```
+//! use recursive_dice_roller::{parse, RenderOptions};
+//!
+//! let exp = parse("4d6k3 + 2").unwrap();
+//! let value = exp.evaluate(&mut rand::thread_rng());
+//! assert!((5..=20).contains(&value.value()));
+//!
+//! // the same tree the command line draws, minus the colors
+//! let tree = recursive_dice_roller::render::no_color(&value, &RenderOptions::default()).unwrap();
+//! assert!(tree.contains("4d6k3"));
+//! ```
+//!
+//! The command line tool is built with the `cli` feature (on by default), the
+//! web page's bindings with `wasm`, a C interface with `ffi`, and a Python
+//! module with `python`. The `arbitrary` feature makes random expressions for
+//! property tests and fuzzing. Without any of them, the only dependencies are
+//! `rand`, `serde`, and friends.
 
-use rand::rngs::ThreadRng;
-use wasm_bindgen::prelude::*;
+pub mod collection;
+pub mod dialect;
+pub mod diff;
+pub mod document;
+pub mod error;
+pub mod eval;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod formats;
+#[cfg(any(test, feature = "arbitrary"))]
+pub mod generate;
+pub mod integrity;
+pub mod messages;
+pub mod modifier;
+pub mod parse;
+#[cfg(feature = "python")]
+mod python;
+pub mod render;
+pub mod simulate;
+pub mod stats;
+pub mod tokenize;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-mod eval;
-mod parse;
-mod render;
-mod tokenize;
-
-use parse::parse;
-
-#[wasm_bindgen]
-pub fn evaluate_and_draw(input: &str) -> String {
-    let parsed = match parse(input) {
-        Ok(ast) => ast,
-        Err(message) => return message,
-    };
-    let evaluated = parsed.evaluate(&mut ThreadRng::default());
-    match render::no_color(&evaluated) {
-        Ok(rendered) => rendered,
-        Err(e) => return e.to_string(),
-    }
-}
+pub use dialect::{convert, Dialect};
+pub use error::Error;
+pub use eval::{Exp, Limits, Value};
+pub use parse::{parse, parse_all, split};
+pub use render::{RenderOptions, Verbosity};
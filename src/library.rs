@@ -0,0 +1,105 @@
+//! Bundled macro libraries, loaded with `--library`. A library is a set of
+//! aliases whose names have a colon in them, so they can't collide with
+//! anyone's own. The numbers that differ from character to character come in
+//! as variables: `rdr roll check:athletics --var str=5`.
+
+use crate::alias::Aliases;
+
+pub const LIBRARIES: &[&str] = &["5e"];
+
+const ABILITIES: &[&str] = &["str", "dex", "con", "int", "wis", "cha"];
+
+/// Each skill and the ability it's checked with
+const SKILLS: &[(&str, &str)] = &[
+    ("acrobatics", "dex"),
+    ("animal_handling", "wis"),
+    ("arcana", "int"),
+    ("athletics", "str"),
+    ("deception", "cha"),
+    ("history", "int"),
+    ("insight", "wis"),
+    ("intimidation", "cha"),
+    ("investigation", "int"),
+    ("medicine", "wis"),
+    ("nature", "int"),
+    ("perception", "wis"),
+    ("performance", "cha"),
+    ("persuasion", "cha"),
+    ("religion", "int"),
+    ("sleight_of_hand", "dex"),
+    ("stealth", "dex"),
+    ("survival", "wis"),
+];
+
+/// The d20 for a straight roll, then with advantage and disadvantage
+const D20S: &[(&str, &str)] = &[("", "d20"), (":adv", "2d20k1"), (":dis", "2d20kl1")];
+
+/// Adds the named library's macros to the aliases
+pub fn load(name: &str, aliases: &mut Aliases) -> Result<(), String> {
+    match name {
+        "5e" => {
+            fifth_edition(aliases);
+            Ok(())
+        }
+        other => Err(format!(
+            "'{other}' is not a library we have; try one of {}",
+            LIBRARIES.join(", ")
+        )),
+    }
+}
+
+/// Ability and skill checks, saving throws, and attacks, each with `:adv`
+/// and `:dis` versions, plus death saves and hit dice. The variables hold a
+/// character's whole bonus, so a proficient skill's includes proficiency.
+fn fifth_edition(aliases: &mut Aliases) {
+    for (suffix, d20) in D20S {
+        for ability in ABILITIES {
+            aliases.define(
+                &format!("check:{ability}{suffix}"),
+                &format!("{d20} + @{ability}"),
+            );
+            aliases.define(
+                &format!("save:{ability}{suffix}"),
+                &format!("{d20} + @{ability}"),
+            );
+        }
+        for (skill, ability) in SKILLS {
+            aliases.define(
+                &format!("check:{skill}{suffix}"),
+                &format!("{d20} + @{ability}"),
+            );
+        }
+        aliases.define(
+            &format!("attack:melee{suffix}"),
+            &format!("{d20} + @str + @prof"),
+        );
+        aliases.define(
+            &format!("attack:ranged{suffix}"),
+            &format!("{d20} + @dex + @prof"),
+        );
+        aliases.define(&format!("save:death{suffix}"), d20);
+    }
+    for sides in [6, 8, 10, 12] {
+        aliases.define(&format!("hitdie:d{sides}"), &format!("d{sides} + @con"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alias::Aliases;
+    use crate::library::*;
+
+    #[test]
+    fn fifth_edition_macros() {
+        let mut aliases = Aliases::default();
+        load("5e", &mut aliases).unwrap();
+        assert_eq!("d20 + @str", aliases.expand("check:athletics").unwrap());
+        assert_eq!(
+            "2d20k1 + @dex",
+            aliases.expand("check:stealth:adv").unwrap()
+        );
+        assert_eq!("2d20kl1", aliases.expand("save:death:dis").unwrap());
+        assert_eq!("d10 + @con", aliases.expand("hitdie:d10").unwrap());
+        assert!(load("pf2e", &mut aliases).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+//! Statistics for everything ever rolled, kept in the config directory for
+//! `rdr mystats`. Nothing is recorded until it's switched on with
+//! `rdr mystats on`, and `rdr mystats off` stops recording without forgetting
+//! what's been tallied so far.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dialect::{self, Dialect};
+use crate::eval::{Int, Value};
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Lifetime {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub enabled: bool,
+    /// By expression, written the way `rdr translate` writes it
+    #[serde(default)]
+    pub expressions: BTreeMap<String, Tally>,
+    /// By number of sides
+    #[serde(default)]
+    pub dice: BTreeMap<u32, Faces>,
+}
+
+/// The results of rolling one expression, however many times
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tally {
+    pub rolls: u64,
+    pub total: i64,
+    pub highest: Int,
+    pub lowest: Int,
+}
+
+/// Every die of one size that was rolled
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Faces {
+    /// Every one, dropped or not, so the average says whether the dice are
+    /// fair
+    pub rolled: u64,
+    pub sum: i64,
+    /// Only the dice that counted, so the low die of a roll with advantage
+    /// isn't a natural 1, as in `.stats`
+    pub maxed: u64,
+    pub ones: u64,
+}
+
+impl Lifetime {
+    /// Reads the statistics at the path, starting afresh (and switched off)
+    /// if there aren't any yet
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut lifetime = match path.exists() {
+            true => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| format!("Could not parse {}: {e}", path.display()))?
+            }
+            false => Lifetime::default(),
+        };
+        lifetime.path = path.to_path_buf();
+        Ok(lifetime)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Could not create {}: {e}", parent.display()))?;
+        }
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, contents + "\n")
+            .map_err(|e| format!("Could not write {}: {e}", self.path.display()))
+    }
+
+    /// Saves the statistics, but only if they're being kept at all
+    pub fn save_if_enabled(&self) -> Result<(), String> {
+        match self.enabled {
+            true => self.save(),
+            false => Ok(()),
+        }
+    }
+
+    /// Tallies a roll of the expression, if statistics are switched on
+    pub fn record(&mut self, expression: &str, value: &Value) {
+        if !self.enabled {
+            return;
+        }
+        let expression = dialect::convert(expression, Dialect::Rdr, Dialect::Rdr)
+            .unwrap_or_else(|_| expression.trim().to_string());
+        let result = value.value();
+        let tally = self.expressions.entry(expression).or_default();
+        if tally.rolls == 0 {
+            (tally.highest, tally.lowest) = (result, result);
+        }
+        tally.rolls += 1;
+        tally.total += result as i64;
+        tally.highest = tally.highest.max(result);
+        tally.lowest = tally.lowest.min(result);
+
+        for die in value.dice() {
+            let faces = self.dice.entry(die.sides).or_default();
+            faces.rolled += 1;
+            faces.sum += die.value as i64;
+            if die.kept && die.sides > 1 {
+                match die.value {
+                    1 => faces.ones += 1,
+                    value if value == die.sides as i32 => faces.maxed += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.expressions.clear();
+        self.dice.clear();
+    }
+
+    /// Every size of die, then the `top` expressions rolled most often
+    pub fn report(&self, top: usize) -> String {
+        let mut output = String::new();
+        if !self.enabled {
+            writeln!(output, "(not recording; `rdr mystats on` starts)").unwrap();
+        }
+        if self.dice.is_empty() {
+            writeln!(output, "nothing rolled yet").unwrap();
+            return output;
+        }
+        for (sides, faces) in &self.dice {
+            writeln!(
+                output,
+                "d{sides}: {} rolled (average {:.2}), {} natural {sides}s, {} natural 1s",
+                faces.rolled,
+                faces.sum as f64 / faces.rolled as f64,
+                faces.maxed,
+                faces.ones
+            )
+            .unwrap();
+        }
+        let mut expressions: Vec<_> = self.expressions.iter().collect();
+        expressions.sort_by(|a, b| b.1.rolls.cmp(&a.1.rolls).then(a.0.cmp(b.0)));
+        writeln!(output).unwrap();
+        for (expression, tally) in expressions.into_iter().take(top) {
+            writeln!(
+                output,
+                "{expression}: {} rolls (average {:.2}, highest {}, lowest {})",
+                tally.rolls,
+                tally.total as f64 / tally.rolls as f64,
+                tally.highest,
+                tally.lowest
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{Int, Kept, KeptRule, Rolled, Value};
+    use crate::lifetime::*;
+
+    fn d20s(highest: Vec<i32>, lowest: Vec<i32>) -> Value {
+        Value::Rolled(Rolled {
+            dice: Box::new(Value::Const((highest.len() + lowest.len()) as Int)),
+            sides: Box::new(Value::Const(20)),
+            kept: Box::new(Kept {
+                keep: KeptRule::Highest(Value::Const(highest.len() as Int)),
+                retained: Value::Const(highest.len() as Int),
+                ids: (0..highest.len() + lowest.len()).collect(),
+                summary: None,
+                lowest,
+                highest,
+            }),
+            exploded: None,
+            rerolled: None,
+        })
+    }
+
+    #[test]
+    fn only_when_enabled() {
+        let mut lifetime = Lifetime::default();
+        lifetime.record("2d20k1", &d20s(vec![20], vec![1]));
+        assert!(lifetime.dice.is_empty());
+
+        lifetime.enabled = true;
+        lifetime.record("2d20k1", &d20s(vec![20], vec![1]));
+        lifetime.record("2d20k1", &d20s(vec![7], vec![3]));
+        let faces = &lifetime.dice[&20];
+        assert_eq!(4, faces.rolled);
+        assert_eq!(31, faces.sum);
+        assert_eq!(1, faces.maxed);
+        // the 1 was dropped, so it doesn't count
+        assert_eq!(0, faces.ones);
+        let tally = &lifetime.expressions["2d20k1"];
+        assert_eq!((2, 20, 7), (tally.rolls, tally.highest, tally.lowest));
+        assert!(lifetime.report(5).contains("d20: 4 rolled (average 7.75)"));
+    }
+}
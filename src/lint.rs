@@ -0,0 +1,240 @@
+//! Warnings about expressions that are valid but probably not what was meant,
+//! like keeping more dice than were rolled
+
+use crate::eval::{Comparison, Exp, Keep, Pick, Roll, MAX_EXPLOSIONS};
+use crate::stats::{self, Range};
+
+pub fn lint(exp: &Exp) -> Vec<String> {
+    let mut warnings = Vec::new();
+    lint_nested(exp, &mut warnings);
+    warnings
+}
+
+fn lint_nested(exp: &Exp, warnings: &mut Vec<String>) {
+    match exp {
+        Exp::Const(_) => {}
+        Exp::Op(op) => {
+            for argument in op.arguments.borrow().iter() {
+                lint_nested(argument, warnings);
+            }
+        }
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            lint_roll(&roll, warnings);
+            lint_nested(&roll.dice, warnings);
+            lint_nested(&roll.sides, warnings);
+            if let Some(on) = roll
+                .explode
+                .as_ref()
+                .and_then(|explosion| explosion.on.as_ref())
+            {
+                lint_nested(&on.against, warnings);
+            }
+            if let Some(on) = &roll.reroll {
+                lint_nested(&on.against, warnings);
+            }
+            if let Some(keep) = roll.keep.count() {
+                lint_nested(keep, warnings);
+            }
+        }
+        Exp::Repeat(repeat) => {
+            let name = format!("{} of {}x", repeat.pick, repeat.times);
+            match repeat.pick {
+                Pick::Best(0) | Pick::Worst(0) => {
+                    warnings.push(format!("{name} never keeps any of the totals"))
+                }
+                Pick::Best(keep) | Pick::Worst(keep) if keep >= repeat.times => warnings.push(
+                    format!("{name} always keeps every total, so it's only a sum"),
+                ),
+                _ => {}
+            }
+            lint_nested(&repeat.exp, warnings);
+        }
+    }
+}
+
+fn lint_roll(roll: &Roll, warnings: &mut Vec<String>) {
+    let name = describe(roll);
+    let dice = stats::range(&roll.dice);
+    let sides = stats::range(&roll.sides);
+    if dice.max <= 0 {
+        warnings.push(format!("{name} never rolls any dice"));
+        return;
+    }
+    if sides == (Range { min: 1, max: 1 }) && roll.explode.is_none() {
+        warnings.push(format!("{name} rolls one-sided dice, which always show 1"));
+    }
+    if let Some(explosion) = &roll.explode {
+        // only dice whose sides, and what they're compared to, are known
+        // ahead of time can be told to always or never explode
+        let threshold = match &explosion.on {
+            Some(on) => stats::range(&on.against),
+            None => sides,
+        };
+        if sides.min == sides.max && threshold.min == threshold.max && sides.max != 0 {
+            let faces = 1..=sides.max.abs();
+            let explodes = |face| match &explosion.on {
+                Some(on) => on.comparison.holds(face, threshold.max),
+                None => face == threshold.max.abs(),
+            };
+            if faces.clone().all(explodes) {
+                warnings.push(format!(
+                    "{name} explodes on every face, so its dice explode {MAX_EXPLOSIONS} times \
+                     over unless the limit is lower"
+                ));
+            } else if !faces.into_iter().any(explodes) {
+                warnings.push(format!("{name} never explodes"));
+            }
+        }
+    }
+    if let Some(on) = &roll.reroll {
+        let threshold = stats::range(&on.against);
+        if sides.min == sides.max && threshold.min == threshold.max && sides.max != 0 {
+            let mut faces = 1..=sides.max.abs();
+            let rerolls = |face| on.comparison.holds(face, threshold.max);
+            if faces.clone().all(rerolls) {
+                warnings.push(format!(
+                    "{name} rerolls every face, so its dice are rerolled {MAX_EXPLOSIONS} times \
+                     over unless the limit is lower"
+                ));
+            } else if !faces.any(rerolls) {
+                warnings.push(format!("{name} never rerolls"));
+            }
+        }
+    }
+    if let Keep::HighestShare(percent) | Keep::LowestShare(percent) = roll.keep {
+        match percent {
+            0 => warnings.push(format!("{name} never keeps any dice")),
+            100.. => warnings.push(format!(
+                "{name} always keeps every die, so the keep does nothing"
+            )),
+            _ => {}
+        }
+    }
+    if let Keep::Highest(keep) | Keep::Lowest(keep) = &roll.keep {
+        let keep = stats::range(keep);
+        if keep.max <= 0 {
+            warnings.push(format!("{name} never keeps any dice"));
+        } else if keep.min >= dice.max {
+            warnings.push(format!(
+                "{name} always keeps every die, so the keep does nothing"
+            ));
+        }
+    }
+    if let Keep::DropLowest(drop) | Keep::DropHighest(drop) = &roll.keep {
+        let drop = stats::range(drop);
+        if drop.max <= 0 {
+            warnings.push(format!(
+                "{name} never drops any dice, so the drop does nothing"
+            ));
+        } else if drop.min >= dice.max {
+            warnings.push(format!("{name} always drops every die"));
+        }
+    }
+}
+
+/// The roll as it was written, if it's simple enough to write down, e.g. `4d6`
+fn describe(roll: &Roll) -> String {
+    let written = || {
+        let (Exp::Const(dice), Exp::Const(sides)) = (&roll.dice, &roll.sides) else {
+            return None;
+        };
+        let reroll = match &roll.reroll {
+            None => String::new(),
+            Some(on) => match (&on.against, on.comparison) {
+                (Exp::Const(against), Comparison::Equal) => format!("r{against}"),
+                (Exp::Const(against), comparison) => format!("r{comparison}{against}"),
+                _ => return None,
+            },
+        };
+        let explode = match &roll.explode {
+            None => String::new(),
+            Some(explosion) => match &explosion.on {
+                None => explosion.explode.to_string(),
+                Some(on) => match &on.against {
+                    Exp::Const(against) => {
+                        format!("{}{}{against}", explosion.explode, on.comparison)
+                    }
+                    _ => return None,
+                },
+            },
+        };
+        Some(format!("{dice}d{sides}{reroll}{explode}"))
+    };
+    written().unwrap_or("a roll".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lint::lint;
+    use crate::parse::parse;
+
+    fn warnings(input: &str) -> Vec<String> {
+        lint(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn nothing_to_warn_about() {
+        assert!(warnings("4d6k3 + 2d20kl1 + (d4)d6").is_empty());
+    }
+
+    #[test]
+    fn pointless_keep() {
+        assert_eq!(
+            vec!["4d6 always keeps every die, so the keep does nothing"],
+            warnings("4d6k4")
+        );
+        assert_eq!(vec!["2d6 never keeps any dice"], warnings("2d6k0"));
+        assert_eq!(vec!["2d6 never keeps any dice"], warnings("2d6kl0%"));
+        assert!(warnings("(d4)d6khhalf").is_empty());
+        assert_eq!(
+            vec!["best 2 of 2x always keeps every total, so it's only a sum"],
+            warnings("best 2 of 2x(d20)")
+        );
+        assert!(warnings("worst 1 of 2x(4d6k3)").is_empty());
+    }
+
+    #[test]
+    fn degenerate_dice() {
+        assert_eq!(vec!["0d6 never rolls any dice"], warnings("1 + 0d6"));
+        assert_eq!(
+            vec!["3d1 rolls one-sided dice, which always show 1"],
+            warnings("3d1")
+        );
+    }
+
+    #[test]
+    fn explosions_that_always_or_never_happen() {
+        assert_eq!(
+            vec!["1d1!! explodes on every face, so its dice explode 100 times over unless the limit is lower"],
+            warnings("d1!!")
+        );
+        assert_eq!(
+            vec!["4d6!>=1 explodes on every face, so its dice explode 100 times over unless the limit is lower"],
+            warnings("4d6!>=1")
+        );
+        assert_eq!(vec!["4d6!>6 never explodes"], warnings("4d6!>6"));
+        assert!(warnings("10d10!>=8 + 4d6!>(d4)").is_empty());
+    }
+
+    #[test]
+    fn rerolls_that_always_or_never_happen() {
+        assert_eq!(
+            vec!["4d6r<7 rerolls every face, so its dice are rerolled 100 times over unless the limit is lower"],
+            warnings("4d6r<7")
+        );
+        assert_eq!(vec!["4d6r0 never rerolls"], warnings("4d6r0"));
+        assert_eq!(vec!["2d6r>6! never rerolls"], warnings("2d6r>6!"));
+        assert!(warnings("4d6r1k3 + 2d6r(d2)").is_empty());
+    }
+
+    #[test]
+    fn pointless_drops() {
+        assert_eq!(
+            vec!["4d6 never drops any dice, so the drop does nothing"],
+            warnings("4d6dl0")
+        );
+        assert_eq!(vec!["4d6 always drops every die"], warnings("4d6dh4"));
+        assert!(warnings("4d6dl1 + (1d4)d6dh(1d2)").is_empty());
+    }
+}
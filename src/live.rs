@@ -0,0 +1,188 @@
+//! `rdr serve --websocket`: live dice for streamed games. Anyone connected can
+//! send an expression (as plain text, or `{"expression": "..."}`), and every
+//! connection is sent the roll as it happens, one message per event:
+//!
+//! ```text
+//! {"event":"roll","roll":3,"expression":"2d20k1 + 5"}
+//! {"event":"die","roll":3,"node":0,"id":0,"sides":20,"value":17}
+//! {"event":"die","roll":3,"node":0,"id":1,"sides":20,"value":4}
+//! {"event":"result","roll":3,"document":{...}}
+//! ```
+//!
+//! so an overlay can animate each die before showing the total. Mistakes only
+//! go back to whoever made them, as `{"event":"error",...}`. Only built with
+//! the `websocket` feature.
+
+use rand::rngs::StdRng;
+use serde_json::{json, Value};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::collection::Collection;
+use crate::document::Document;
+use crate::eval::Limits;
+use crate::parse::parse;
+use crate::quota::Quota;
+
+/// Each address can save up this many dice, which come back at the rate below
+const QUOTA: u64 = 3000;
+const QUOTA_PER_SECOND: u64 = 50;
+
+/// Everything the connections share
+struct Table {
+    limits: Limits,
+    rng: Mutex<StdRng>,
+    /// Rolls from `--rolls`, usable by name
+    saved: Collection,
+    /// A writing end for every open connection
+    viewers: Mutex<Vec<WebSocket<TcpStream>>>,
+    /// How many rolls have been made, for numbering them
+    rolls: Mutex<usize>,
+    /// Rationed by IP address, so opening more connections doesn't help
+    quota: Mutex<Quota>,
+}
+
+/// Listens on the address until the process is killed
+pub fn run(address: &str, limits: Limits, rng: StdRng, saved: Collection) -> Result<(), String> {
+    let listener =
+        TcpListener::bind(address).map_err(|e| format!("Could not listen on {address}: {e}"))?;
+    eprintln!("streaming rolls on ws://{address}");
+    let table = Arc::new(Table {
+        limits,
+        rng: Mutex::new(rng),
+        saved,
+        viewers: Mutex::new(Vec::new()),
+        rolls: Mutex::new(0),
+        quota: Mutex::new(Quota::new(QUOTA, QUOTA_PER_SECOND)),
+    });
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let table = table.clone();
+        thread::spawn(move || {
+            if let Err(e) = table.connect(stream) {
+                eprintln!("connection closed: {e}");
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Table {
+    fn connect(&self, stream: TcpStream) -> Result<(), String> {
+        // the socket is read on this thread but written from whichever one
+        // made the roll, so it gets a second handle for writing
+        let writer = stream.try_clone().map_err(|e| e.to_string())?;
+        let client = stream.peer_addr().map_err(|e| e.to_string())?;
+        let mut socket = tungstenite::accept(stream).map_err(|e| e.to_string())?;
+        self.viewers
+            .lock()
+            .expect("no thread panics while holding the viewers")
+            .push(WebSocket::from_raw_socket(writer, Role::Server, None));
+        loop {
+            let text = match socket.read() {
+                Ok(Message::Text(text)) => text.to_string(),
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            match self.roll(&expression(&text), client) {
+                Ok(events) => self.broadcast(&events),
+                Err(error) => {
+                    let error = json!({ "event": "error", "message": error });
+                    let sent = socket.send(Message::text(error.to_string()));
+                    sent.map_err(|e| e.to_string())?;
+                }
+            }
+        }
+    }
+
+    /// Rolls the expression, giving back every event in the order they
+    /// should be shown
+    fn roll(&self, expression: &str, client: SocketAddr) -> Result<Vec<Value>, String> {
+        let parsed = parse(&self.saved.expand(expression)?)?;
+        self.quota
+            .lock()
+            .expect("no thread panics while holding the quota")
+            .charge(&client.ip().to_string(), &parsed, &self.limits)?;
+        let roll = {
+            let mut rolls = self.rolls.lock().expect("no thread panics while counting");
+            *rolls += 1;
+            *rolls
+        };
+        let mut events = vec![json!({ "event": "roll", "roll": roll, "expression": expression })];
+        let mut rng = self.rng.lock().expect("no thread panics while rolling");
+        let evaluated = parsed.evaluate_observed(&mut *rng, &self.limits, &mut |die| {
+            events.push(json!({
+                "event": "die",
+                "roll": roll,
+                "node": die.node,
+                "id": die.id,
+                "sides": die.sides,
+                "value": die.value,
+            }))
+        })?;
+        events.push(
+            json!({ "event": "result", "roll": roll, "document": Document::new(&evaluated) }),
+        );
+        Ok(events)
+    }
+
+    /// Sends the events to every connection, forgetting any that have gone
+    fn broadcast(&self, events: &[Value]) {
+        let mut viewers = self
+            .viewers
+            .lock()
+            .expect("no thread panics while holding the viewers");
+        viewers.retain_mut(|viewer| {
+            events
+                .iter()
+                .all(|event| viewer.send(Message::text(event.to_string())).is_ok())
+        });
+    }
+}
+
+/// Messages can be a bare expression or a JSON object with one in it
+fn expression(text: &str) -> String {
+    let request = serde_json::from_str::<Value>(text).unwrap_or_default();
+    match request.get("expression").and_then(Value::as_str) {
+        Some(expression) => expression.trim().to_string(),
+        None => text.trim().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::live::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn events() {
+        let table = Table {
+            limits: Limits::default(),
+            rng: Mutex::new(StdRng::seed_from_u64(0)),
+            saved: Collection::default(),
+            viewers: Mutex::new(Vec::new()),
+            rolls: Mutex::new(0),
+            quota: Mutex::new(Quota::new(QUOTA, QUOTA_PER_SECOND)),
+        };
+        let client = SocketAddr::from(([127, 0, 0, 1], 9000));
+        let events = table
+            .roll(&expression(r#"{"expression": "2d6 + 1"}"#), client)
+            .unwrap();
+        let kinds: Vec<_> = events.iter().map(|event| event["event"].clone()).collect();
+        assert_eq!(vec!["roll", "die", "die", "result"], kinds);
+        assert_eq!(6, events[1]["sides"]);
+        assert_eq!(1, events[2]["id"]);
+        assert!(table.roll("2d", client).is_err());
+        assert_eq!(2, table.roll("d4", client).unwrap()[0]["roll"]);
+        assert!(table.roll("999999d999999", client).is_err());
+        let huge = "2147483647 * 2147483647 * 2147483647 * 2147483647 * 2147483647";
+        assert!(table.roll(huge, client).is_err());
+        assert!(table.roll(&"9".repeat(40), client).is_err());
+    }
+}
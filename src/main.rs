@@ -1,17 +1,84 @@
 #![allow(clippy::needless_return, clippy::neg_multiply)]
 
+mod alias;
+mod check;
+mod clipboard;
+mod combat;
+mod config;
 mod console;
-mod eval;
-mod parse;
-mod render;
-mod tokenize;
+mod damage;
+#[cfg(feature = "discord")]
+mod discord;
+mod expect;
+mod foundry;
+mod history;
+mod library;
+mod lifetime;
+mod lint;
+#[cfg(feature = "websocket")]
+mod live;
+#[cfg(feature = "scripting")]
+mod mechanics;
+mod preset;
+#[cfg(any(feature = "discord", feature = "websocket"))]
+mod quota;
+mod repl;
+mod resources;
+mod results;
+mod script;
+mod server;
+mod session;
+mod sheet;
+mod statgen;
+mod template;
+mod transcript;
+mod vars;
 
-use clap::{Arg, ArgAction, Command};
-use parse::parse;
-use rand::rngs::ThreadRng;
+use recursive_dice_roller::parse::{self, parse, parse_all, split};
+use recursive_dice_roller::{
+    collection, dialect, document, error, eval, formats, integrity, messages, render, simulate,
+    stats, tokenize,
+};
 
-fn main() -> Result<(), String> {
-    let matches = Command::new("rdr")
+use alias::Aliases;
+use check::Checker;
+use clap::{
+    builder::PossibleValuesParser, parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches,
+    Command,
+};
+use collection::Collection;
+use combat::{Outcome, Save};
+use console::Theme;
+use damage::Defenses;
+use dialect::Dialect;
+use eval::{Assume, Exp, Int, Limits, Value};
+use expect::Expected;
+use history::History;
+use itertools::Itertools;
+use lifetime::Lifetime;
+use mechanics::Mechanics;
+use messages::Locale;
+use preset::{Preset, Success};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use render::{RenderOptions, Verbosity};
+use repl::Repl;
+use resources::Resources;
+use results::Results;
+use script::Statement;
+use server::Server;
+use session::Session;
+use sheet::Sheet;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use transcript::Transcript;
+use vars::Variables;
+
+fn cli() -> Command {
+    Command::new("rdr")
         .version("0.1.0")
         .author("Kyle Silver")
         .about("Roll dice expressions with support for recursive statements")
@@ -22,30 +89,1963 @@ fn main() -> Result<(), String> {
             parenthesis. Anywhere you can put a number, you can substitute a dice roll,\n\
             such as (3d2 + 1)d(2d4)kl(2 * 1d4). The recursion can go arbitrarily deep.",
         )
-        .arg(Arg::new("expression").help("A dice expression"))
+        .arg(Arg::new("expression").help("A dice expression or the name of an alias"))
+        .subcommand(
+            Command::new("roll")
+                .about("Roll a dice expression or alias")
+                .arg(
+                    Arg::new("expression")
+                        .help("A dice expression or the name of an alias")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("alias")
+                .about("Manage named rolls")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Save an expression under a name")
+                        .arg(Arg::new("name").required(true))
+                        .arg(
+                            Arg::new("expression")
+                                .help("One or more expressions, separated by semicolons")
+                                .required(true),
+                        ),
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Forget a named roll")
+                        .arg(Arg::new("name").required(true)),
+                )
+                .subcommand(Command::new("list").about("Show every named roll"))
+                .subcommand(
+                    Command::new("import")
+                        .about("Save the rolls of macros and roll tables exported from Foundry VTT")
+                        .arg(
+                            Arg::new("file")
+                                .help("A Foundry export or compendium file")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("overwrite")
+                                .long("overwrite")
+                                .help("Replace aliases that already have an imported name")
+                                .action(ArgAction::SetTrue),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("attack")
+                .about("Roll to hit against an AC, then roll damage if it lands")
+                .arg(
+                    Arg::new("to-hit")
+                        .help("The attack roll, such as d20+7")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("ac")
+                        .long("ac")
+                        .help("The target's armor class")
+                        .value_parser(value_parser!(i32))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("damage")
+                        .long("damage")
+                        .value_name("EXPRESSION")
+                        .help("The damage roll, with its dice doubled on a critical hit")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("crit-on")
+                        .long("crit-on")
+                        .value_name("N")
+                        .help("The lowest natural d20 that counts as a critical hit")
+                        .value_parser(value_parser!(i32).range(2..=20))
+                        .default_value("20"),
+                ),
+        )
+        .subcommand(
+            Command::new("saves")
+                .about("Roll a saving throw for each target of an effect, given its --dc")
+                .arg(
+                    Arg::new("save")
+                        .help("Each target's saving throw, such as d20+3")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("count")
+                        .short('n')
+                        .long("count")
+                        .help("How many targets are making the save")
+                        .value_parser(value_parser!(u32).range(1..))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("damage")
+                        .long("damage")
+                        .value_name("EXPRESSION")
+                        .help("The damage roll, rolled once and dealt to every target that fails")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("half-on-save")
+                        .long("half-on-save")
+                        .help("Targets that save still take half damage")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Check expressions, dice scripts, and .dice files for mistakes without rolling")
+                .arg(
+                    Arg::new("expressions")
+                        .help("Dice expressions to check, which may use aliases")
+                        .num_args(1..)
+                        .required_unless_present("file"),
+                )
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .value_name("FILE")
+                        .help("A dice script, or a collection of rolls if it ends in .dice")
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("strict")
+                        .long("strict")
+                        .help("Fail on warnings too, not only errors")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("compare")
+                .about("Compare the exact distributions of two expressions")
+                .arg(
+                    Arg::new("first")
+                        .help("A dice expression or the name of an alias")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("second")
+                        .help("The expression to compare it against")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("odds")
+                .about("Print the chance of succeeding against a range of DCs, worked out exactly")
+                .arg(
+                    Arg::new("expression")
+                        .help("A dice expression or the name of an alias")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("lowest")
+                        .long("lowest")
+                        .value_name("DC")
+                        .help("The first DC in the table")
+                        .value_parser(value_parser!(i64))
+                        .default_value("5"),
+                )
+                .arg(
+                    Arg::new("highest")
+                        .long("highest")
+                        .value_name("DC")
+                        .help("The last DC in the table")
+                        .value_parser(value_parser!(i64))
+                        .default_value("30"),
+                ),
+        )
+        .subcommand(
+            Command::new("diff")
+                .about("Show which dice changed between two rolls, by seed or from a transcript")
+                .arg(
+                    Arg::new("first")
+                        .help("A seed, or with --from, a roll in the transcript numbered from 1")
+                        .value_parser(value_parser!(u64))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("second")
+                        .help("The seed or roll to compare it against")
+                        .value_parser(value_parser!(u64))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("expression")
+                        .help("The expression to roll with each seed")
+                        .required_unless_present("from"),
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("FILE")
+                        .help("Compare two rolls in a transcript written with --transcript")
+                        .conflicts_with("expression"),
+                ),
+        )
+        .subcommand(
+            Command::new("translate")
+                .about("Rewrite an expression from one dice roller's notation into another's")
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_parser(PossibleValuesParser::new(dialect::DIALECTS))
+                        .default_value("rdr"),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_parser(PossibleValuesParser::new(dialect::DIALECTS))
+                        .default_value("rdr"),
+                )
+                .arg(
+                    Arg::new("expression")
+                        .help("The expression to rewrite")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("replay")
+                .about("Roll everything in a transcript again, with the same dice as before")
+                .arg(
+                    Arg::new("file")
+                        .help("A transcript written with --transcript")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("mystats")
+                .about("Show statistics for everything ever rolled, once they're switched on")
+                .arg(
+                    Arg::new("action")
+                        .help("Start or stop recording, or forget everything recorded")
+                        .value_parser(PossibleValuesParser::new(["on", "off", "reset"])),
+                )
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .value_name("N")
+                        .help("How many of the most rolled expressions to show")
+                        .value_parser(value_parser!(usize))
+                        .default_value("10"),
+                ),
+        )
+        .subcommand(Command::new("repl").about("Roll expressions one after another at a prompt"))
+        .subcommand(
+            Command::new("run")
+                .about("Roll every expression in a dice script, top to bottom")
+                .arg(
+                    Arg::new("file")
+                        .help("A file of labelled rolls, aliases, and @variables")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            Command::new("serve").about(
+                "Answer JSON-RPC requests on stdin, one per line, for editors and assistants",
+            ),
+        )
+        .subcommand(
+            Command::new("statgen")
+                .about("Generate an array of ability scores")
+                .arg(
+                    Arg::new("method")
+                        .short('m')
+                        .long("method")
+                        .help("4d6 drop lowest (sorted), 3d6 in order, or check a point-buy array")
+                        .value_parser(PossibleValuesParser::new(statgen::METHODS))
+                        .default_value("4d6"),
+                )
+                .arg(
+                    Arg::new("scores")
+                        .help("The six scores to check, for point buy")
+                        .num_args(1..)
+                        .value_parser(value_parser!(i32)),
+                ),
+        )
+        .subcommand(
+            Command::new("sheet")
+                .about("List the fields of a character sheet, each usable as an @variable")
+                .arg(
+                    Arg::new("file")
+                        .help("The sheet to read, if not the one given with --character"),
+                ),
+        )
+        .subcommand(
+            Command::new("table")
+                .about("Print the chance of every outcome of an expression, worked out exactly")
+                .arg(
+                    Arg::new("expression")
+                        .help("A dice expression or the name of an alias")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("csv")
+                        .long("csv")
+                        .help("Write the table as CSV, with probabilities from 0 to 1")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("at-least")
+                        .long("at-least")
+                        .help("Chart the chance of rolling at least each outcome")
+                        .conflicts_with("csv")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Check a shared roll against the dice its seed really rolls")
+                .arg(
+                    Arg::new("expression")
+                        .help("The expression, as the roll shows it")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .help("The seed the roll shows")
+                        .value_parser(value_parser!(u64))
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .help("The hash the roll shows, or at least its first 8 characters")
+                        .required(true),
+                ),
+        )
         .arg(
             Arg::new("quiet")
+                .global(true)
                 .short('q')
                 .long("quiet")
-                .help("Only output the final result")
+                .help("Only output the final result, and with --dc, whether it succeeded")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("verbose"),
+        )
+        .arg(
+            Arg::new("verbose")
+                .global(true)
+                .short('v')
+                .long("verbose")
+                .help("Explain each roll and print the seed; twice adds timing and warnings")
+                .action(ArgAction::Count),
+        )
+        .arg(
+            Arg::new("expected")
+                .global(true)
+                .short('e')
+                .long("expected")
+                .help("Show the expected value alongside each result")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("emoji")
+                .global(true)
+                .long("emoji")
+                .help("Draw dice results as emoji die faces")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-output")
+                .global(true)
+                .long("max-output")
+                .value_name("BYTES")
+                .help("Summarize anything past this many bytes of output")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("ascii")
+                .global(true)
+                .long("ascii")
+                .help("Draw the tree with plain ASCII characters")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("theme")
+                .global(true)
+                .long("theme")
+                .help(
+                    "Paint the output for a dark or light background, or in a palette that's \
+                     easier to tell apart with deuteranopia, protanopia, or tritanopia (these \
+                     also turn on --marks). Without one, RDR_THEME is used if it's set, and \
+                     otherwise the terminal's background is guessed from COLORFGBG",
+                )
+                .value_parser(PossibleValuesParser::new(console::THEMES))
+                .default_value("auto"),
+        )
+        .arg(
+            Arg::new("marks")
+                .global(true)
+                .long("marks")
+                .help("Mark each die on its highest face with \u{25B2} and on its lowest with \u{25BC}")
                 .action(ArgAction::SetTrue),
         )
-        .get_matches();
+        .arg(
+            Arg::new("no-pager")
+                .global(true)
+                .long("no-pager")
+                .help("Never send output that's too tall for the terminal to a pager")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("resolve")
+                .global(true)
+                .short('r')
+                .long("resolve")
+                .help("Show how the expression collapsed into its result")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("subtotals")
+                .global(true)
+                .long("subtotals")
+                .help("Show a running total after each term")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("seed")
+                .global(true)
+                .long("seed")
+                .value_name("SEED")
+                .help("Seed the random number generator to make rolls reproducible")
+                .value_parser(value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("min")
+                .global(true)
+                .long("min")
+                .help("Print the smallest possible result instead of rolling")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max")
+                .global(true)
+                .long("max")
+                .help("Print the largest possible result instead of rolling")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("avg")
+                .global(true)
+                .long("avg")
+                .help("Print the expected result instead of rolling")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("assume")
+                .global(true)
+                .long("assume")
+                .value_name("FACE")
+                .help(
+                    "Instead of rolling, take every die to show its highest face (max), \
+                     its lowest (min), its average (avg, rounded down across each pool like a \
+                     stat block), its middle face (median), or a face of your choosing, like 3",
+                )
+                .value_parser(|face: &str| face.parse::<Assume>())
+                .conflicts_with_all(["stats", "transcript", "reroll"]),
+        )
+        .arg(
+            Arg::new("crits")
+                .global(true)
+                .long("crits")
+                .help(
+                    "Print the chance that a kept die lands on its highest face, \
+                     and on its lowest, instead of rolling",
+                )
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("percentile")
+                .global(true)
+                .long("percentile")
+                .value_name("P")
+                .help(
+                    "Print the result at each of these percentiles, like 5,50,95, \
+                     instead of rolling",
+                )
+                .value_delimiter(',')
+                .value_parser(value_parser!(f64)),
+        )
+        .arg(
+            Arg::new("stats")
+                .global(true)
+                .long("stats")
+                .value_name("N")
+                .help("Roll the expression N times and summarize the results")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("10000")
+                .value_parser(value_parser!(usize)),
+        )
+        .arg(
+            Arg::new("export")
+                .global(true)
+                .long("export")
+                .value_name("FILE")
+                .requires("stats")
+                .help(
+                    "Save how often each --stats result came up to this file, \
+                     as JSON if it ends in .json and CSV otherwise",
+                ),
+        )
+        .arg(
+            Arg::new("raw")
+                .global(true)
+                .long("raw")
+                .value_name("FILE")
+                .requires("stats")
+                .help(
+                    "Write each of the --stats samples to this file as it's rolled: \
+                     four little-endian bytes apiece if it ends in .bin, one per line otherwise",
+                ),
+        )
+        .arg(
+            Arg::new("var")
+                .global(true)
+                .long("var")
+                .value_name("NAME=VALUE")
+                .help("Give a value to @NAME wherever it appears in the expression")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("character")
+                .global(true)
+                .long("character")
+                .value_name("FILE")
+                .help(
+                    "Load a TOML or JSON character sheet, whose fields become @variables \
+                     (anything given with --var wins)",
+                ),
+        )
+        .arg(
+            Arg::new("rolls")
+                .global(true)
+                .long("rolls")
+                .value_name("FILE")
+                .help(
+                    "Load saved rolls and macros from a .dice file (by default rolls.dice in \
+                     the config directory, if there is one), keeping those tagged for --system",
+                ),
+        )
+        .arg(
+            Arg::new("max-dice")
+                .global(true)
+                .long("max-dice")
+                .value_name("N")
+                .help("Give up on any expression that rolls more than N dice")
+                .value_parser(value_parser!(u64))
+                .default_value("1000000"),
+        )
+        .arg(
+            Arg::new("summarize-over")
+                .global(true)
+                .long("summarize-over")
+                .value_name("N")
+                .help("Show how many of each face came up, rather than every die, past N dice")
+                .value_parser(value_parser!(u64))
+                .default_value("10000"),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .global(true)
+                .long("max-depth")
+                .value_name("N")
+                .help("Give up on any expression nested more than N levels deep")
+                .value_parser(value_parser!(usize))
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("max-explosions")
+                .global(true)
+                .long("max-explosions")
+                .value_name("N")
+                .help("Stop any one die from exploding (or being rerolled) more than N times")
+                .value_parser(value_parser!(usize))
+                .default_value("100"),
+        )
+        .arg(
+            Arg::new("timeout-ms")
+                .global(true)
+                .long("timeout-ms")
+                .value_name("MS")
+                .help("Give up on any expression that takes longer than this to roll")
+                .value_parser(value_parser!(u64))
+                .default_value("5000"),
+        )
+        .arg(
+            Arg::new("reroll")
+                .global(true)
+                .long("reroll")
+                .help("After each roll, offer to roll some of the dice again")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("quiet"),
+        )
+        .arg(
+            Arg::new("interactive")
+                .global(true)
+                .short('i')
+                .long("interactive")
+                .visible_alias("watch")
+                .help("After each roll, press Enter or r to roll it again, or q to quit")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["quiet", "copy"]),
+        )
+        .arg(
+            Arg::new("dc")
+                .global(true)
+                .long("dc")
+                .value_name("N")
+                .help("Succeed (exit code 0) only if every result meets or beats N (or under --system coc, doesn't go over it)")
+                .value_parser(value_parser!(i32))
+                .conflicts_with_all(["min", "max", "avg", "stats"]),
+        )
+        .arg(
+            Arg::new("expect")
+                .global(true)
+                .long("expect")
+                .value_name("RANGE")
+                .help("Fail (exit code 1) if any result falls outside RANGE, like 3..18 or 10..")
+                .value_parser(|range: &str| range.parse::<Expected>())
+                .conflicts_with_all(["min", "max", "avg", "stats"]),
+        )
+        .arg(
+            Arg::new("resist")
+                .global(true)
+                .long("resist")
+                .value_name("TYPE")
+                .help("Halve damage of this type, tagged like 2d6[fire]")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("immune")
+                .global(true)
+                .long("immune")
+                .value_name("TYPE")
+                .help("Take no damage of this type")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("vulnerable")
+                .global(true)
+                .long("vulnerable")
+                .value_name("TYPE")
+                .help("Double damage of this type")
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("library")
+                .global(true)
+                .long("library")
+                .value_name("NAME")
+                .help("Make a bundled set of macros available, like check:athletics from 5e")
+                .value_parser(PossibleValuesParser::new(library::LIBRARIES))
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("system")
+                .global(true)
+                .long("system")
+                .help("Set the notation, default die, and what counts as success for a game system")
+                .value_parser(PossibleValuesParser::new(preset::SYSTEMS)),
+        )
+        .arg(
+            Arg::new("template")
+                .global(true)
+                .long("template")
+                .value_name("TEMPLATE")
+                .help(
+                    "Print one line per roll, filling in {expr}, {total}, {min}, {max}, {avg}, \
+                    {dice}, {seed}, and {timestamp}",
+                )
+                .conflicts_with_all(["quiet", "format", "reroll"]),
+        )
+        .arg(
+            Arg::new("copy")
+                .global(true)
+                .long("copy")
+                .value_name("WHAT")
+                .help("Copy the total (or with --copy=full, the whole output) to the clipboard")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("total")
+                .value_parser(PossibleValuesParser::new(["total", "full"])),
+        )
+        .arg(
+            Arg::new("transcript")
+                .global(true)
+                .long("transcript")
+                .value_name("FILE")
+                .help(
+                    "Write every roll down in this file, so it can be replayed with `rdr replay`",
+                ),
+        )
+        .arg(
+            Arg::new("format")
+                .global(true)
+                .short('f')
+                .long("format")
+                .help("How to present the results")
+                .value_parser(PossibleValuesParser::new(
+                    formats::RENDERERS.iter().map(|renderer| renderer.name),
+                ))
+                .default_value("tree"),
+        )
+}
+
+/// The bot is only around when the `discord` feature is enabled
+#[cfg(feature = "discord")]
+fn with_discord(command: Command) -> Command {
+    command.subcommand(
+        Command::new("discord")
+            .about("Run a Discord bot that answers /roll")
+            .arg(
+                Arg::new("token")
+                    .long("token")
+                    .help("The bot's token; defaults to the DISCORD_TOKEN environment variable"),
+            ),
+    )
+}
+
+#[cfg(not(feature = "discord"))]
+fn with_discord(command: Command) -> Command {
+    command
+}
 
-    let quiet = matches.get_flag("quiet");
+/// Streaming rolls over a WebSocket is only around when the `websocket`
+/// feature is enabled
+#[cfg(feature = "websocket")]
+fn with_websocket(command: Command) -> Command {
+    command.mut_subcommand("serve", |serve| {
+        serve.arg(
+            Arg::new("websocket")
+                .long("websocket")
+                .value_name("ADDRESS")
+                .help("Stream every roll, die by die, to WebSocket clients on this address"),
+        )
+    })
+}
+
+#[cfg(not(feature = "websocket"))]
+fn with_websocket(command: Command) -> Command {
+    command
+}
+
+/// Custom mechanics are only around when the `scripting` feature is enabled
+#[cfg(feature = "scripting")]
+fn with_scripting(command: Command) -> Command {
+    command.arg(
+        Arg::new("mechanics")
+            .global(true)
+            .long("mechanics")
+            .value_name("FILE")
+            .help("A Rhai script of functions that can wrap a roll, like brutal(2d12 + 5)"),
+    )
+}
+
+#[cfg(not(feature = "scripting"))]
+fn with_scripting(command: Command) -> Command {
+    command
+}
 
-    let expression: &str = matches
+/// Without the `scripting` feature there are never any mechanics to apply
+#[cfg(not(feature = "scripting"))]
+mod mechanics {
+    use crate::eval::{Int, Value};
+    use rand::rngs::StdRng;
+
+    pub enum Mechanics {}
+
+    impl Mechanics {
+        pub fn call<'a>(&self, _: &'a str) -> Option<(&'a str, &'a str)> {
+            match *self {}
+        }
+
+        pub fn apply(&self, _: &str, _: &Value, _: &mut StdRng) -> Result<Int, String> {
+            match *self {}
+        }
+    }
+}
+
+fn main() -> Result<ExitCode, String> {
+    messages::set_locale(Locale::from_env());
+    let matches = with_websocket(with_scripting(with_discord(cli()))).get_matches();
+    match matches.subcommand() {
+        #[cfg(feature = "discord")]
+        Some(("discord", matches)) => {
+            let token = match matches.get_one::<String>("token") {
+                Some(token) => token.clone(),
+                None => std::env::var("DISCORD_TOKEN")
+                    .map_err(|_| "No token given and DISCORD_TOKEN is not set".to_string())?,
+            };
+            discord::run(&token).map(|_| ExitCode::SUCCESS)
+        }
+        Some(("alias", matches)) => alias(matches).map(|_| ExitCode::SUCCESS),
+        Some(("attack", matches)) => attack(matches).map(|_| ExitCode::SUCCESS),
+        Some(("check", matches)) => check(matches),
+        Some(("compare", matches)) => compare(matches).map(|_| ExitCode::SUCCESS),
+        Some(("diff", matches)) => diff(matches).map(|_| ExitCode::SUCCESS),
+        Some(("odds", matches)) => odds(matches).map(|_| ExitCode::SUCCESS),
+        Some(("roll", matches)) => roll(matches),
+        Some(("saves", matches)) => saves(matches).map(|_| ExitCode::SUCCESS),
+        Some(("translate", matches)) => {
+            let argument = |name| {
+                matches
+                    .get_one::<String>(name)
+                    .expect("translate arguments are required or have defaults")
+            };
+            let from = Dialect::named(argument("from"))?;
+            let to = Dialect::named(argument("to"))?;
+            println!("{}", dialect::convert(argument("expression"), from, to)?);
+            Ok(ExitCode::SUCCESS)
+        }
+        Some(("mystats", matches)) => mystats(matches).map(|_| ExitCode::SUCCESS),
+        Some(("replay", matches)) => replay(matches),
+        Some(("repl", matches)) => repl(matches).map(|_| ExitCode::SUCCESS),
+        Some(("run", matches)) => run(matches).map(|_| ExitCode::SUCCESS),
+        Some(("serve", matches)) => serve(matches).map(|_| ExitCode::SUCCESS),
+        Some(("statgen", matches)) => statgen(matches).map(|_| ExitCode::SUCCESS),
+        Some(("sheet", matches)) => sheet(matches).map(|_| ExitCode::SUCCESS),
+        Some(("table", matches)) => table(matches).map(|_| ExitCode::SUCCESS),
+        Some(("verify", matches)) => verify(matches),
+        _ => roll(&matches),
+    }
+}
+
+fn alias(matches: &ArgMatches) -> Result<(), String> {
+    let path = config::config_file("aliases.toml")?;
+    let mut aliases = Aliases::load(&path)?;
+    match matches.subcommand() {
+        Some(("add", matches)) => {
+            let name = matches.get_one::<String>("name").expect("name is required");
+            let expression = matches
+                .get_one::<String>("expression")
+                .expect("expression is required");
+            // make sure the alias is usable before we save it
+            let expanded = Variables::placeholders(&aliases.expand(expression)?)?;
+            parse_all(&Results::placeholders(&expanded)?)?;
+            aliases.add(name, expression)?;
+            aliases.save(&path)
+        }
+        Some(("remove", matches)) => {
+            let name = matches.get_one::<String>("name").expect("name is required");
+            aliases.remove(name)?;
+            aliases.save(&path)
+        }
+        Some(("list", _)) => {
+            for (name, expression) in aliases.iter() {
+                println!("{name} = {expression}");
+            }
+            Ok(())
+        }
+        Some(("import", matches)) => {
+            let file = matches.get_one::<String>("file").expect("file is required");
+            let contents =
+                fs::read_to_string(file).map_err(|e| format!("Could not read {file}: {e}"))?;
+            let overwrite = matches.get_flag("overwrite");
+            for converted in foundry::import(&contents)? {
+                let imported = converted.alias.and_then(|(name, expression)| {
+                    if !overwrite && aliases.iter().any(|(saved, _)| saved == name) {
+                        return Err(format!("there's already an alias named '{name}'"));
+                    }
+                    let expanded = Variables::placeholders(&aliases.expand(&expression)?)?;
+                    parse_all(&Results::placeholders(&expanded)?)?;
+                    aliases.add(&name, &expression)?;
+                    Ok(format!("{name} = {expression}"))
+                });
+                match imported {
+                    Ok(alias) => println!("imported {alias}"),
+                    Err(reason) => println!("skipped '{}': {reason}", converted.source),
+                }
+            }
+            aliases.save(&path)
+        }
+        _ => unreachable!("a subcommand is required"),
+    }
+}
+
+fn verbosity(matches: &ArgMatches) -> Verbosity {
+    if matches.get_flag("quiet") {
+        return Verbosity::Quiet;
+    }
+    match matches.get_count("verbose") {
+        // when the output is being piped somewhere, the total is usually all
+        // that's wanted, unless some other kind of output was asked for
+        0 if !io::stdout().is_terminal() && !output_chosen(matches) => Verbosity::Quiet,
+        0 => Verbosity::Normal,
+        1 => Verbosity::Verbose,
+        _ => Verbosity::Debug,
+    }
+}
+
+fn output_chosen(matches: &ArgMatches) -> bool {
+    matches.value_source("format") == Some(ValueSource::CommandLine)
+        || matches.contains_id("template")
+}
+
+/// How to draw the results. The `--theme` is picked here too, since the tree
+/// is painted in it.
+fn render_options(matches: &ArgMatches) -> RenderOptions {
+    // a bad RDR_THEME isn't worth failing over
+    let theme = Theme::named(
+        matches
+            .get_one::<String>("theme")
+            .expect("theme has a default value"),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{e}");
+        Theme::default()
+    });
+    console::set_theme(theme);
+    RenderOptions {
+        verbosity: verbosity(matches),
+        expected_values: matches.get_flag("expected"),
+        emoji_faces: matches.get_flag("emoji"),
+        max_output: matches.get_one::<usize>("max-output").copied(),
+        resolution: matches.get_flag("resolve"),
+        subtotals: matches.get_flag("subtotals"),
+        // consoles without escape sequences tend not to have box-drawing
+        // characters either
+        ascii: matches.get_flag("ascii") || !console::supports_ansi(),
+        marks: matches.get_flag("marks") || theme.marked(),
+        ..Default::default()
+    }
+}
+
+fn limits(matches: &ArgMatches) -> Limits {
+    let limit = |name| {
+        *matches
+            .get_one::<u64>(name)
+            .expect("limits have default values")
+    };
+    Limits {
+        max_dice: limit("max-dice"),
+        max_depth: *matches
+            .get_one::<usize>("max-depth")
+            .expect("limits have default values"),
+        timeout: Some(Duration::from_millis(limit("timeout-ms"))),
+        summarize_over: limit("summarize-over") as usize,
+        max_explosions: *matches
+            .get_one::<usize>("max-explosions")
+            .expect("limits have default values"),
+    }
+}
+
+/// The functions in the `--mechanics` script, if one was given
+#[cfg(feature = "scripting")]
+fn mechanics(matches: &ArgMatches) -> Result<Option<Mechanics>, String> {
+    let Some(path) = matches.get_one::<String>("mechanics") else {
+        return Ok(None);
+    };
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    Mechanics::compile(&source).map(Some)
+}
+
+#[cfg(not(feature = "scripting"))]
+fn mechanics(_: &ArgMatches) -> Result<Option<Mechanics>, String> {
+    Ok(None)
+}
+
+/// The `--mechanics` function that a piece is wrapped in, along with the
+/// expression inside it
+fn wrapper<'a>(mechanics: &Option<Mechanics>, piece: &'a str) -> Option<(&'a str, &'a str)> {
+    mechanics
+        .as_ref()
+        .and_then(|mechanics| mechanics.call(piece))
+}
+
+/// The `--system` preset, or the plain defaults without one
+fn preset(matches: &ArgMatches) -> Result<Preset, String> {
+    match matches.get_one::<String>("system") {
+        Some(system) => Preset::named(system),
+        None => Ok(Preset::default()),
+    }
+}
+
+/// Expands an expression from the command line: the preset's die goes in
+/// front of a lone modifier, aliases and variables are filled in, and the
+/// preset's notation is rewritten into ours
+fn expand(
+    expression: &str,
+    preset: &Preset,
+    aliases: &Aliases,
+    variables: &Variables,
+) -> Result<String, String> {
+    let expanded = variables.substitute(&aliases.expand(&preset.complete(expression))?)?;
+    Ok(dialect::rewrite(&expanded, preset.dialect)?)
+}
+
+/// The aliases saved in the config directory, along with the macros of any
+/// `--library`
+fn aliases(matches: &ArgMatches) -> Result<Aliases, String> {
+    let mut aliases =
+        Aliases::load(&config::config_file("aliases.toml")?)?.with_rolls(rolls(matches)?);
+    for name in matches.get_many::<String>("library").into_iter().flatten() {
+        library::load(name, &mut aliases)?;
+    }
+    Ok(aliases)
+}
+
+/// The `.dice` collection from `--rolls`, or the config directory's
+/// `rolls.dice`, narrowed down to the `--system`'s rolls
+fn rolls(matches: &ArgMatches) -> Result<Collection, String> {
+    let path = match matches.get_one::<String>("rolls") {
+        Some(path) => PathBuf::from(path),
+        None => match config::config_file("rolls.dice")? {
+            path if path.exists() => path,
+            _ => return Ok(Collection::default()),
+        },
+    };
+    let contents =
+        fs::read_to_string(&path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+    let rolls = Collection::parse(&contents)
+        .map_err(|e| format!("Could not parse {}: {e}", path.display()))?;
+    Ok(rolls.select(matches.get_one::<String>("system").map(String::as_str)))
+}
+
+/// The damage types given with `--resist`, `--immune`, and `--vulnerable`,
+/// if there were any
+fn defenses(matches: &ArgMatches) -> Option<Defenses> {
+    let types = |name| {
+        matches
+            .get_many::<String>(name)
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let defenses = Defenses {
+        resistant: types("resist"),
+        immune: types("immune"),
+        vulnerable: types("vulnerable"),
+    };
+    let any = [&defenses.resistant, &defenses.immune, &defenses.vulnerable]
+        .iter()
+        .any(|types| !types.is_empty());
+    any.then_some(defenses)
+}
+
+/// The variables given with `--var`
+fn variables(matches: &ArgMatches) -> Result<Variables, String> {
+    let mut variables = Variables::default();
+    for assignment in matches.get_many::<String>("var").into_iter().flatten() {
+        variables.assign(assignment)?;
+    }
+    if let Some(path) = matches.get_one::<String>("character") {
+        for (name, value) in Sheet::load(Path::new(path))?.fields() {
+            variables.assign_default(&format!("{name}={value}"))?;
+        }
+    }
+    Ok(variables)
+}
+
+fn sheet(matches: &ArgMatches) -> Result<(), String> {
+    let path = matches
+        .get_one::<String>("file")
+        .or(matches.get_one::<String>("character"))
+        .ok_or("Name the sheet to list, or give it with --character")?;
+    let sheet = Sheet::load(Path::new(path))?;
+    let width = sheet
+        .fields()
+        .map(|(name, _)| name.len() + 1)
+        .max()
+        .unwrap_or(0);
+    for (name, value) in sheet.fields() {
+        println!("{:<width$} = {value}", format!("@{name}"));
+    }
+    Ok(())
+}
+
+/// The random number generator for this run, along with the seed it started
+/// from. Even when no seed is given we pick one ourselves, so that it can be
+/// printed and any roll can be replayed later. (That pick comes from the OS,
+/// which under WASI means the runtime's `random_get`.)
+fn rng(matches: &ArgMatches) -> (u64, StdRng) {
+    let seed = matches
+        .get_one::<u64>("seed")
+        .copied()
+        .unwrap_or_else(rand::random);
+    (seed, StdRng::seed_from_u64(seed))
+}
+
+fn serve(matches: &ArgMatches) -> Result<(), String> {
+    let (_, rng) = rng(matches);
+    #[cfg(feature = "websocket")]
+    if let Some(address) = matches.get_one::<String>("websocket") {
+        return live::run(address, limits(matches), rng, rolls(matches)?);
+    }
+    Server {
+        limits: limits(matches),
+        rng,
+        rolls: rolls(matches)?,
+    }
+    .run()
+}
+
+fn statgen(matches: &ArgMatches) -> Result<(), String> {
+    let method = matches
+        .get_one::<String>("method")
+        .expect("method has a default value");
+    let scores = matches
+        .get_many::<i32>("scores")
+        .map(|scores| scores.copied().collect::<Vec<_>>());
+    match (method.as_str(), scores) {
+        ("point-buy", Some(scores)) => {
+            let cost = statgen::point_cost(&scores)?;
+            print!("{}", statgen::format(&scores, true));
+            println!("points: {cost}/{}", statgen::POINT_BUY_BUDGET);
+        }
+        ("point-buy", None) => return Err("Point buy needs the six scores to check".to_string()),
+        (_, Some(_)) => return Err("Scores can only be given for point buy".to_string()),
+        (method, None) => {
+            let (seed, mut rng) = rng(matches);
+            let scores = statgen::roll(method, &mut rng)?;
+            print!("{}", statgen::format(&scores, method == "3d6"));
+            println!("seed: {seed}");
+        }
+    }
+    Ok(())
+}
+
+fn table(matches: &ArgMatches) -> Result<(), String> {
+    let expression = matches
         .get_one::<String>("expression")
-        .ok_or("No dice roll expression was provided".to_string())?;
+        .expect("the expression is required");
+    let aliases = aliases(matches)?;
+    let input = expand(
+        expression,
+        &preset(matches)?,
+        &aliases,
+        &variables(matches)?,
+    )?;
+    for (i, exp) in parse_all(&input)?.iter().enumerate() {
+        let table = stats::table(exp)?;
+        if matches.get_flag("csv") {
+            print!("{}", table.csv());
+            continue;
+        }
+        if i > 0 {
+            println!();
+        }
+        match matches.get_flag("at-least") {
+            true => print!("{}", table.at_least()),
+            false => print!("{}", table.report()),
+        }
+    }
+    Ok(())
+}
 
-    let parsed = parse(expression)?;
-    let evaluated = parsed.evaluate(&mut ThreadRng::default());
+/// Rolls the expression again with the seed, and says whether the hash that
+/// came with it is the one those dice give
+fn verify(matches: &ArgMatches) -> Result<ExitCode, String> {
+    let argument = |name| {
+        matches
+            .get_one::<String>(name)
+            .expect("verify arguments are required")
+    };
+    let seed = *matches.get_one::<u64>("seed").expect("seed is required");
+    let evaluated = parse(argument("expression"))?
+        .evaluate_within(&mut StdRng::seed_from_u64(seed), &limits(matches))?;
+    let hash = integrity::hash(&evaluated, seed);
+    let verified = integrity::matches(&hash, argument("hash"));
+    if verbosity(matches) != Verbosity::Quiet {
+        console::colorful(&evaluated, &render_options(matches))?;
+        println!("hash: {hash}");
+    }
+    match verified {
+        true => {
+            let total = evaluated.value();
+            println!("verified: seed {seed} rolls {evaluated} = {total}");
+            Ok(ExitCode::SUCCESS)
+        }
+        false => {
+            println!("not verified: seed {seed} doesn't roll the dice that hash was taken from");
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
 
-    if quiet {
-        println!("{}", evaluated.value());
+fn check(matches: &ArgMatches) -> Result<ExitCode, String> {
+    let aliases = aliases(matches)?;
+    let mut checker = Checker::default();
+    for expression in matches
+        .get_many::<String>("expressions")
+        .into_iter()
+        .flatten()
+    {
+        checker.expression(&format!("'{expression}'"), expression, &aliases);
+    }
+    for path in matches.get_many::<String>("file").into_iter().flatten() {
+        let source = fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+        match Path::new(path).extension().is_some_and(|e| e == "dice") {
+            true => checker.collection(path, &source, &aliases),
+            false => checker.script(path, &source, &aliases),
+        }
+    }
+    // unlike a roll's tree, the findings are wanted in a CI log too
+    if !matches.get_flag("quiet") {
+        print!("{checker}");
+    }
+    let failed = checker.errors() > 0 || (matches.get_flag("strict") && checker.warnings() > 0);
+    match failed {
+        true => Ok(ExitCode::FAILURE),
+        false => Ok(ExitCode::SUCCESS),
+    }
+}
+
+fn compare(matches: &ArgMatches) -> Result<(), String> {
+    let argument = |name| {
+        matches
+            .get_one::<String>(name)
+            .expect("both expressions are required")
+    };
+    let aliases = aliases(matches)?;
+    let variables = variables(matches)?;
+    let preset = preset(matches)?;
+    let expand = |expression| expand(expression, &preset, &aliases, &variables);
+    let first = parse(&expand(argument("first"))?)?;
+    let second = parse(&expand(argument("second"))?)?;
+    let comparison = stats::compare(&first, &second)?;
+    if verbosity(matches) == Verbosity::Quiet {
+        println!("{:.4}", comparison.first_wins);
         return Ok(());
     }
-    let output = render::no_color(&evaluated).map_err(|_| "uh-oh".to_string())?;
-    console::colorful(&output).map_err(|_| "uh-oh".to_string())?;
+    print!(
+        "{}",
+        comparison.report(argument("first"), argument("second"))
+    );
     Ok(())
 }
+
+/// The chance of success against each DC, by the preset's idea of success
+fn odds(matches: &ArgMatches) -> Result<(), String> {
+    let expression = matches
+        .get_one::<String>("expression")
+        .expect("the expression is required");
+    let number = |name| {
+        *matches
+            .get_one::<i64>(name)
+            .expect("the range of DCs has defaults")
+    };
+    let preset = preset(matches)?;
+    let input = expand(
+        expression,
+        &preset,
+        &aliases(matches)?,
+        &variables(matches)?,
+    )?;
+    let under = preset.success == Success::AtMost;
+    let odds = stats::odds(&parse(&input)?, number("lowest"), number("highest"), under)?;
+    if verbosity(matches) == Verbosity::Quiet {
+        for (dc, chance) in &odds.rows {
+            println!("{dc} {chance:.4}");
+        }
+        return Ok(());
+    }
+    print!("{}", odds.report(preset.target));
+    Ok(())
+}
+
+/// Two rolls side by side: one expression rolled with two seeds, or two of
+/// the rolls in a transcript
+fn diff(matches: &ArgMatches) -> Result<(), String> {
+    let number = |name| {
+        *matches
+            .get_one::<u64>(name)
+            .expect("both rolls are required")
+    };
+    let limits = limits(matches);
+    let (first, second) = match matches.get_one::<String>("from") {
+        Some(path) => {
+            let transcript = Transcript::open(Path::new(path))?;
+            let entry = |name| {
+                let n = number(name);
+                transcript
+                    .rolls
+                    .get((n as usize).wrapping_sub(1))
+                    .ok_or(format!("{path} has no roll {n}"))
+            };
+            (
+                entry("first")?.replay(&limits)?,
+                entry("second")?.replay(&limits)?,
+            )
+        }
+        None => {
+            let expression = matches
+                .get_one::<String>("expression")
+                .expect("expression is required without --from");
+            let aliases = aliases(matches)?;
+            let variables = variables(matches)?;
+            let parsed = parse(&expand(
+                expression,
+                &preset(matches)?,
+                &aliases,
+                &variables,
+            )?)?;
+            let roll = |seed| parsed.evaluate_within(&mut StdRng::seed_from_u64(seed), &limits);
+            (roll(number("first"))?, roll(number("second"))?)
+        }
+    };
+    let diff = first.diff(&second);
+    if verbosity(matches) == Verbosity::Quiet {
+        println!("{:+}", diff.moved());
+    } else if matches.get_one::<String>("format").map(String::as_str) == Some("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&diff).expect("diffs always serialize")
+        );
+    } else {
+        println!("{diff}");
+    }
+    Ok(())
+}
+
+fn attack(matches: &ArgMatches) -> Result<(), String> {
+    let argument = |name| {
+        matches
+            .get_one::<String>(name)
+            .expect("attack arguments are required")
+    };
+    let number = |name| {
+        *matches
+            .get_one::<i32>(name)
+            .expect("attack arguments are required or have defaults")
+    };
+    let preset = preset(matches)?;
+    let crit_on = match (matches.value_source("crit-on"), preset.crit_on) {
+        (Some(ValueSource::DefaultValue), Some(crit_on)) => crit_on,
+        _ => number("crit-on"),
+    };
+    let ac = number("ac") as Int;
+    let aliases = aliases(matches)?;
+    let variables = variables(matches)?;
+    let expand = |expression| expand(expression, &preset, &aliases, &variables);
+    let to_hit = parse(&expand(argument("to-hit"))?)?;
+    let damage = parse(&expand(argument("damage"))?)?;
+
+    let verbosity = verbosity(matches);
+    let options = render_options(matches);
+    let limits = limits(matches);
+    let (seed, mut rng) = rng(matches);
+    let inline = |value: &Value| {
+        let renderer = formats::renderer("inline").expect("the inline renderer is registered");
+        (renderer.render)(value, &options).map_err(String::from)
+    };
+
+    let attack = to_hit.evaluate_within(&mut rng, &limits)?;
+    let outcome = Outcome::of(attack.value(), combat::natural_d20(&attack), ac, crit_on);
+    let damage = match outcome {
+        Outcome::Miss => None,
+        Outcome::Hit => Some(damage.evaluate_within(&mut rng, &limits)?),
+        Outcome::Critical => Some(combat::double_dice(&damage).evaluate_within(&mut rng, &limits)?),
+    };
+    if verbosity == Verbosity::Quiet {
+        println!("{}", damage.map_or(0, |damage| damage.value()));
+        return Ok(());
+    }
+    println!(
+        "to hit: {} vs AC {ac}: {}",
+        inline(&attack)?.trim_end(),
+        outcome.describe()
+    );
+    if let Some(damage) = damage {
+        print!("damage: {}", inline(&damage)?);
+    }
+    if verbosity >= Verbosity::Verbose {
+        println!("seed: {seed}");
+    }
+    Ok(())
+}
+
+fn saves(matches: &ArgMatches) -> Result<(), String> {
+    let dc = *matches
+        .get_one::<i32>("dc")
+        .ok_or("Saving throws need a --dc to beat")? as Int;
+    let count = *matches
+        .get_one::<u32>("count")
+        .expect("count has a default");
+    let half_on_save = matches.get_flag("half-on-save");
+    let preset = preset(matches)?;
+    let aliases = aliases(matches)?;
+    let variables = variables(matches)?;
+    let expand = |name| {
+        let expression = matches
+            .get_one::<String>(name)
+            .expect("saves arguments are required");
+        Ok::<_, String>(parse(&expand(expression, &preset, &aliases, &variables)?)?)
+    };
+    let (save, damage) = (expand("save")?, expand("damage")?);
+
+    let verbosity = verbosity(matches);
+    let options = render_options(matches);
+    let limits = limits(matches);
+    let (seed, mut rng) = rng(matches);
+
+    let damage = damage.evaluate_within(&mut rng, &limits)?;
+    let saves = (0..count)
+        .map(|_| {
+            let roll = save.evaluate_within(&mut rng, &limits)?.value();
+            Ok(Save::new(roll, dc, damage.value(), half_on_save))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if verbosity == Verbosity::Quiet {
+        println!("{}", saves.iter().map(|save| save.damage).sum::<Int>());
+        return Ok(());
+    }
+    let renderer = formats::renderer("inline").expect("the inline renderer is registered");
+    let rendered = (renderer.render)(&damage, &options)?;
+    print!("damage: {rendered}");
+    print!("{}", combat::table(&saves));
+    if verbosity >= Verbosity::Verbose {
+        println!("seed: {seed}");
+    }
+    Ok(())
+}
+
+fn repl(matches: &ArgMatches) -> Result<(), String> {
+    let (_, rng) = rng(matches);
+    let mut repl = Repl {
+        aliases: aliases(matches)?,
+        variables: variables(matches)?,
+        options: render_options(matches),
+        limits: limits(matches),
+        rng,
+        session: Session::default(),
+        modifiers: Vec::new(),
+        results: Results::default(),
+        transcript: transcript(matches)?,
+        lifetime: lifetime()?,
+        resources: Resources::default(),
+        // without a config directory, the history only lasts the session
+        history: match config::config_file("history") {
+            Ok(path) => History::open(&path)?,
+            Err(_) => History::default(),
+        },
+    };
+    repl.run()
+}
+
+/// The statistics `rdr mystats` shows, which only last the run if there's no
+/// config directory to keep them in
+fn lifetime() -> Result<Lifetime, String> {
+    match config::config_file("lifetime.json") {
+        Ok(path) => Lifetime::open(&path),
+        Err(_) => Ok(Lifetime::default()),
+    }
+}
+
+fn mystats(matches: &ArgMatches) -> Result<(), String> {
+    let mut lifetime = lifetime()?;
+    match matches.get_one::<String>("action").map(String::as_str) {
+        Some("on") => {
+            lifetime.enabled = true;
+            println!("recording every roll from now on");
+        }
+        Some("off") => {
+            lifetime.enabled = false;
+            println!("no longer recording rolls; `rdr mystats reset` forgets the old ones");
+        }
+        Some(_) => {
+            lifetime.reset();
+            println!("forgot everything recorded");
+        }
+        None => {
+            let top = *matches
+                .get_one::<usize>("top")
+                .expect("top has a default value");
+            print!("{}", lifetime.report(top));
+            return Ok(());
+        }
+    }
+    lifetime.save()
+}
+
+/// The transcript given with `--transcript`, if any
+fn transcript(matches: &ArgMatches) -> Result<Option<Transcript>, String> {
+    matches
+        .get_one::<String>("transcript")
+        .map(|path| Transcript::open(Path::new(path)))
+        .transpose()
+}
+
+fn replay(matches: &ArgMatches) -> Result<ExitCode, String> {
+    let path = matches.get_one::<String>("file").expect("file is required");
+    let transcript = Transcript::open(Path::new(path))?;
+    let options = render_options(matches);
+    let limits = limits(matches);
+    let mut faithful = true;
+    for (i, entry) in transcript.rolls.iter().enumerate() {
+        let evaluated = entry.replay(&limits)?;
+        if options.verbosity == Verbosity::Quiet {
+            println!("{}", evaluated.value());
+        } else {
+            if i > 0 {
+                println!();
+            }
+            console::colorful(&evaluated, &options)?;
+        }
+        // a different total means the file was edited, or the dice work
+        // differently than they did when it was written
+        if evaluated.value() != entry.total {
+            faithful = false;
+            eprintln!(
+                "warning: roll {} came to {}, but the transcript says {}",
+                i + 1,
+                evaluated.value(),
+                entry.total
+            );
+        }
+    }
+    match faithful {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::FAILURE),
+    }
+}
+
+fn run(matches: &ArgMatches) -> Result<(), String> {
+    let path = matches.get_one::<String>("file").expect("file is required");
+    let source = fs::read_to_string(path).map_err(|e| format!("Could not read {path}: {e}"))?;
+    let verbosity = verbosity(matches);
+    let options = render_options(matches);
+    let mut aliases = aliases(matches)?;
+    let mut variables = variables(matches)?;
+    let limits = limits(matches);
+    let (seed, mut rng) = rng(matches);
+    let mut results = Results::default();
+
+    for line in script::parse_script(&source)? {
+        let at_line = |e: String| format!("line {}: {e}", line.number);
+        match line.statement {
+            // values given on the command line win over the script's own
+            Statement::Variable { assignment } => {
+                variables.assign_default(&assignment).map_err(at_line)?
+            }
+            Statement::Alias { name, expression } => {
+                aliases.add(&name, &expression).map_err(at_line)?
+            }
+            Statement::Roll { label, expression } => {
+                let expanded = aliases
+                    .expand(&expression)
+                    .and_then(|expanded| variables.substitute(&expanded))
+                    .map_err(at_line)?;
+                for expression in split(&expanded).map_err(|e| at_line(e.into()))? {
+                    let evaluated = results
+                        .substitute(expression)
+                        .and_then(|substituted| Ok(parse(&substituted)?))
+                        .and_then(|parsed| Ok(parsed.evaluate_within(&mut rng, &limits)?))
+                        .map_err(at_line)?;
+                    results.push(evaluated.value());
+                    if verbosity == Verbosity::Quiet {
+                        println!("{}", evaluated.value());
+                        continue;
+                    }
+                    println!("{label}: {}", evaluated.value());
+                    if verbosity >= Verbosity::Verbose {
+                        console::colorful(&evaluated, &options)?;
+                    }
+                }
+            }
+        }
+    }
+    if verbosity >= Verbosity::Verbose {
+        println!("seed: {seed}");
+    }
+    Ok(())
+}
+
+/// Rolls each term of the input on its own, then adds up each damage type
+/// and applies the target's defenses to it
+fn damage(
+    input: &str,
+    preset: &Preset,
+    defenses: &Defenses,
+    matches: &ArgMatches,
+) -> Result<ExitCode, String> {
+    let verbosity = verbosity(matches);
+    let options = render_options(matches);
+    let limits = limits(matches);
+    let (seed, mut rng) = rng(matches);
+    let renderer = formats::renderer("inline").expect("the inline renderer is registered");
+
+    for (i, piece) in split(input)?.into_iter().enumerate() {
+        let components = damage::components(piece)?;
+        let mut values = Vec::new();
+        let mut rolled = String::new();
+        for component in &components {
+            let exp = parse(&dialect::rewrite(&component.expression, preset.dialect)?)?;
+            let value = exp.evaluate_within(&mut rng, &limits)?;
+            values.push(value.value());
+            let kind = component.kind.as_deref().unwrap_or("untyped");
+            let sign = if component.negative { "- " } else { "" };
+            let inline = (renderer.render)(&value, &options)?;
+            rolled.push_str(&format!("{kind}: {sign}{}\n", inline.trim_end()));
+        }
+        let totals = damage::totals(&components, &values);
+        let (breakdown, taken) = damage::breakdown(&totals, defenses);
+        if verbosity == Verbosity::Quiet {
+            println!("{taken}");
+            continue;
+        }
+        if i > 0 {
+            println!();
+        }
+        print!("{rolled}{breakdown}");
+    }
+    if verbosity >= Verbosity::Verbose {
+        println!("seed: {seed}");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints the requested statistics about each expression without rolling it
+fn analyze(expressions: &[Exp], matches: &ArgMatches) -> Result<(), String> {
+    let quiet = verbosity(matches) == Verbosity::Quiet;
+    let limits = limits(matches);
+    for exp in expressions {
+        let range = stats::range_within(exp, &limits);
+        let mut results = Vec::new();
+        if matches.get_flag("min") {
+            results.push(("min".to_string(), range.min.to_string()));
+        }
+        if matches.get_flag("max") {
+            results.push(("max".to_string(), range.max.to_string()));
+        }
+        if matches.get_flag("avg") {
+            let average = stats::expected_value(exp)
+                .ok_or("The expression is too complex to compute an average for")?;
+            results.push(("avg".to_string(), render::format_average(average)));
+        }
+        if matches.get_flag("crits") {
+            let crits = stats::crits(exp)?;
+            for (label, chance) in [
+                ("highest face", crits.highest),
+                ("lowest face", crits.lowest),
+            ] {
+                results.push((label.to_string(), format!("{:.2}%", 100.0 * chance)));
+            }
+        }
+        for &p in matches.get_many::<f64>("percentile").into_iter().flatten() {
+            if !(0.0..=100.0).contains(&p) {
+                return Err(format!("{p} is not a percentile between 0 and 100"));
+            }
+            let outcome = stats::quantile(exp, p / 100.0)?;
+            results.push((format!("p{p}"), outcome.to_string()));
+        }
+        for (label, result) in results {
+            match quiet {
+                true => println!("{result}"),
+                false => println!("{label}: {result}"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Asks which dice to roll again, and keeps asking until none are picked
+fn offer_rerolls(
+    evaluated: &mut Value,
+    rng: &mut StdRng,
+    options: &RenderOptions,
+    mut transcript: Option<&mut Transcript>,
+) -> Result<(), String> {
+    loop {
+        let faces = evaluated.faces();
+        if faces.is_empty() {
+            return Ok(());
+        }
+        let listing = faces
+            .iter()
+            .enumerate()
+            .map(|(i, face)| format!("[{}] {face}", i + 1))
+            .join("  ");
+        println!("dice: {listing}");
+        print!("reroll which dice? (blank to keep them) ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let mut line = String::new();
+        if io::stdin()
+            .read_line(&mut line)
+            .map_err(|e| e.to_string())?
+            == 0
+        {
+            return Ok(());
+        }
+        let chosen = line
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|word| !word.is_empty())
+            .map(|word| match word.parse::<usize>() {
+                Ok(n) if (1..=faces.len()).contains(&n) => Ok(n - 1),
+                _ => Err(format!("'{word}' is not one of the dice")),
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let chosen = match chosen {
+            Ok(chosen) if chosen.is_empty() => return Ok(()),
+            Ok(chosen) => chosen,
+            Err(message) => {
+                eprintln!("{message}");
+                continue;
+            }
+        };
+
+        let total = evaluated.value();
+        match &mut transcript {
+            Some(transcript) => transcript.reroll(evaluated, &chosen, rng),
+            None => evaluated.reroll(&chosen, rng),
+        }
+        let rerolled = evaluated.faces();
+        for i in chosen {
+            match rerolled.get(i) {
+                Some(face) => println!("die {}: {} \u{2192} {face}", i + 1, faces[i]),
+                None => println!("die {}: {} \u{2192} gone", i + 1, faces[i]),
+            }
+        }
+        println!("total: {total} \u{2192} {}", evaluated.value());
+        console::colorful(evaluated, options)?;
+    }
+}
+
+/// Runs the simulation while writing every sample's total to the file
+fn record(
+    exp: &Exp,
+    samples: usize,
+    path: &str,
+    rng: &mut StdRng,
+    limits: &Limits,
+) -> Result<simulate::Summary, String> {
+    let file = fs::File::create(path).map_err(|e| format!("Could not create {path}: {e}"))?;
+    let format = match Path::new(path).extension().is_some_and(|e| e == "bin") {
+        true => simulate::Record::Binary,
+        false => simulate::Record::Csv,
+    };
+    let mut simulation = simulate::Simulation::new(exp.clone(), samples)
+        .recording(Box::new(io::BufWriter::new(file)), format)
+        .map_err(|e| format!("Could not write to {path}: {e}"))?;
+    simulation.run(samples, rng, limits)?;
+    Ok(simulation.summary()?)
+}
+
+fn roll(matches: &ArgMatches) -> Result<ExitCode, String> {
+    let verbosity = verbosity(matches);
+    let quiet = verbosity == Verbosity::Quiet;
+    let options = render_options(matches);
+
+    let preset = preset(matches)?;
+    let expression = match matches.get_one::<String>("expression") {
+        Some(expression) => expression.as_str(),
+        // a system with a die of its own rolls it when given nothing else
+        None if preset.die.is_some() => "",
+        None => return Err("No dice roll expression was provided".to_string()),
+    };
+    let aliases = aliases(matches)?;
+    let variables = variables(matches)?;
+    // damage types look just like the labels other dialects drop, so they're
+    // picked out before anything gets rewritten
+    let unwritten = variables.substitute(&aliases.expand(&preset.complete(expression))?)?;
+    let defenses = defenses(matches);
+    if defenses.is_some() || (preset.dialect == Dialect::Rdr && damage::tagged(&unwritten)) {
+        return damage(&unwritten, &preset, &defenses.unwrap_or_default(), matches);
+    }
+    let input = dialect::rewrite(&unwritten, preset.dialect)?;
+    // results can be named, like `hit = d20 + 7; dmg = 1d8 + 4`
+    let (names, pieces): (Vec<_>, Vec<_>) = split(&input)?.into_iter().map(parse::named).unzip();
+    let mechanics = mechanics(matches)?;
+    let expressions = pieces
+        .iter()
+        .map(|piece| {
+            let expression = wrapper(&mechanics, piece).map_or(*piece, |(_, inner)| inner);
+            Ok::<_, String>(parse(&Results::placeholders(expression)?)?)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let analytic = ["min", "max", "avg", "crits"]
+        .iter()
+        .any(|flag| matches.get_flag(flag))
+        || matches.contains_id("percentile");
+    let referenced = pieces.iter().any(|piece| Results::referenced(piece));
+    if referenced && (analytic || matches.contains_id("stats")) {
+        return Err("Earlier results like $1 can only be used when rolling".to_string());
+    }
+    if pieces
+        .iter()
+        .any(|piece| wrapper(&mechanics, piece).is_some())
+    {
+        let tree = matches.get_one::<String>("format").map(String::as_str) == Some("tree");
+        if analytic || matches.contains_id("stats") || matches.contains_id("template") || !tree {
+            return Err(
+                "Mechanics can only be used when rolling, with the tree format".to_string(),
+            );
+        }
+    }
+    if analytic {
+        return analyze(&expressions, matches).map(|_| ExitCode::SUCCESS);
+    }
+
+    let limits = limits(matches);
+    let (seed, mut rng) = rng(matches);
+    let assume = matches.get_one::<Assume>("assume").copied();
+
+    if let Some(&samples) = matches.get_one::<usize>("stats") {
+        let raw = matches.get_one::<String>("raw");
+        let export = matches.get_one::<String>("export");
+        if (raw.is_some() || export.is_some()) && expressions.len() > 1 {
+            return Err("--raw and --export save one expression's samples at a time".to_string());
+        }
+        for (i, parsed) in expressions.iter().enumerate() {
+            let summary = match raw {
+                Some(path) => record(parsed, samples, path, &mut rng, &limits)?,
+                None => simulate::simulate(parsed, samples, &mut rng, &limits)?,
+            };
+            if let Some(path) = export {
+                let contents = match Path::new(path).extension().is_some_and(|e| e == "json") {
+                    true => serde_json::to_string_pretty(&summary.rows())
+                        .expect("rows always serialize"),
+                    false => summary.csv(),
+                };
+                fs::write(path, contents).map_err(|e| format!("Could not write {path}: {e}"))?;
+            }
+            if quiet {
+                println!("{:.2}", summary.mean);
+                continue;
+            }
+            if i > 0 {
+                println!();
+            }
+            print!("{}", summary.report());
+        }
+        if verbosity >= Verbosity::Verbose {
+            println!("seed: {seed}");
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let format = matches
+        .get_one::<String>("format")
+        .expect("format has a default value");
+    let renderer = formats::renderer(format).expect("format is one of the possible values");
+
+    let dc = matches.get_one::<i32>("dc").map(|&dc| dc as Int);
+    let expected = matches.get_one::<Expected>("expect");
+    let passes = |total: Int| {
+        if let Some(expected) = expected.filter(|expected| !expected.contains(total)) {
+            eprintln!("error: expected a result in {expected}, but rolled {total}");
+            return false;
+        }
+        dc.is_none_or(|dc| preset.passes(total, dc))
+    };
+    let mut succeeded = true;
+
+    let copy = matches.get_one::<String>("copy");
+    let mut copied = Vec::new();
+    let mut transcript = transcript(matches)?;
+    let interactive = matches.get_flag("interactive");
+    let mut lifetime = lifetime()?;
+    // the seed rolls the same dice again (and so verifies) only for the first
+    // expression rolled, and only if that expression rolled straight from it
+    // rather than from no dice at all or from a transcript's seed of its own
+    let mut seeded = assume.is_none() && transcript.is_none();
+
+    // interactively, the same expressions get rolled over and over (with
+    // fresh dice each time) until the user has had enough
+    loop {
+        let mut results = Results::default();
+
+        for (i, piece) in pieces.iter().enumerate() {
+            let substituted = results.substitute(piece)?;
+            let wrapper = wrapper(&mechanics, &substituted);
+            let expression = wrapper.map_or(substituted.as_str(), |(_, inner)| inner);
+            let parsed = parse(expression)?;
+            if verbosity >= Verbosity::Debug {
+                for warning in lint::lint(&parsed) {
+                    eprintln!("warning: {warning}");
+                }
+            }
+            let started = Instant::now();
+            let mut evaluated = match (assume, transcript.as_mut()) {
+                (Some(assume), _) => parsed.evaluate_assuming(assume, &mut rng, &limits)?,
+                (None, Some(transcript)) => transcript.roll(&parsed, &mut rng, &limits)?,
+                (None, None) => parsed.evaluate_within(&mut rng, &limits)?,
+            };
+            let mut total = match (&mechanics, wrapper) {
+                (Some(mechanics), Some((name, _))) => {
+                    mechanics.apply(name, &evaluated, &mut rng)?
+                }
+                _ => evaluated.value(),
+            };
+            let options = RenderOptions {
+                seed: std::mem::take(&mut seeded).then_some(seed),
+                ..options.clone()
+            };
+            if quiet {
+                succeeded &= passes(total);
+                println!("{total}");
+                // without it, only the exit code would say how the roll went
+                if let Some(dc) = dc {
+                    println!("{}", preset.verdict(total, dc));
+                }
+            } else if let Some(template) = matches.get_one::<String>("template") {
+                succeeded &= passes(evaluated.value());
+                println!("{}", template::fill(template, &evaluated, seed)?);
+            } else if renderer.name != "tree" {
+                // only the tree is meant for human eyes; everything else gets
+                // printed verbatim so it can be piped into other tools
+                succeeded &= passes(evaluated.value());
+                print!("{}", (renderer.render)(&evaluated, &options)?);
+            } else {
+                if i > 0 {
+                    println!();
+                }
+                if let Some(name) = names[i] {
+                    println!("{name}:");
+                }
+                match matches.get_flag("no-pager") || matches.get_flag("reroll") || interactive {
+                    true => console::colorful(&evaluated, &options),
+                    false => console::paged(&evaluated, &options),
+                }?;
+                if matches.get_flag("reroll") {
+                    offer_rerolls(&mut evaluated, &mut rng, &options, transcript.as_mut())?;
+                    total = evaluated.value();
+                }
+                if let (Some(mechanics), Some((name, _))) = (&mechanics, wrapper) {
+                    total = mechanics.apply(name, &evaluated, &mut rng)?;
+                    println!("{name} \u{2192} {total}");
+                }
+                succeeded &= passes(total);
+                if let Some(dc) = dc {
+                    println!("{}", preset.verdict(total, dc));
+                }
+                if verbosity >= Verbosity::Debug {
+                    println!("time: {:.2?}", started.elapsed());
+                }
+            }
+
+            // only now that any dice have been rerolled is the roll settled
+            results.push(total);
+            if assume.is_none() {
+                lifetime.record(expression, &evaluated);
+            }
+            match copy.map(String::as_str) {
+                Some("full") => copied.push((renderer.render)(&evaluated, &options)?),
+                Some(_) => copied.push(format!("{total}\n")),
+                None => {}
+            }
+        }
+        if !interactive || !console::roll_again().map_err(|e| e.to_string())? {
+            break;
+        }
+    }
+    if verbosity >= Verbosity::Verbose && renderer.name == "tree" {
+        println!("seed: {seed}");
+    }
+    if copy.is_some() {
+        clipboard::copy(copied.concat().trim_end())?;
+    }
+    if let Some(transcript) = transcript {
+        transcript.save()?;
+    }
+    lifetime.save_if_enabled()?;
+    match succeeded {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::FAILURE),
+    }
+}
@@ -0,0 +1,170 @@
+//! Custom mechanics, written as Rhai functions and loaded with `--mechanics`.
+//! Each function takes a finished roll and gives back a new total, and can be
+//! used by wrapping an expression in it:
+//!
+//! ```text
+//! // mechanics.rhai
+//! fn brutal(r) {
+//!     if r.total >= 20 { r.total + roll(1, 6) } else { r.total }
+//! }
+//! ```
+//!
+//! after which `rdr --mechanics mechanics.rhai "brutal(2d12 + 5)"` works. Only
+//! built with the `scripting` feature.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Map, Scope, AST, INT};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::eval::{Int, Value};
+
+/// Rolling more than this many extra dice in one go is almost certainly a
+/// mistake, and the operation limit doesn't see inside `roll`
+const MAX_EXTRA_DICE: INT = 1000;
+
+pub struct Mechanics {
+    engine: Engine,
+    ast: AST,
+    /// Shared with the engine's `roll` function. It's reseeded from the
+    /// caller's generator before every call, so that `--seed` still makes
+    /// scripted rolls repeatable.
+    rng: Rc<RefCell<StdRng>>,
+}
+
+impl Mechanics {
+    pub fn compile(source: &str) -> Result<Self, String> {
+        let rng = Rc::new(RefCell::new(StdRng::seed_from_u64(0)));
+        let mut engine = Engine::new();
+        // the script is sandboxed: no printing, no eval, and a budget on how
+        // much work it may do
+        engine
+            .on_print(|_| {})
+            .on_debug(|_, _, _| {})
+            .disable_symbol("eval")
+            .set_max_operations(100_000)
+            .set_max_call_levels(32)
+            .set_max_string_size(10_000)
+            .set_max_array_size(10_000)
+            .set_max_map_size(1000);
+        let shared = rng.clone();
+        engine.register_fn(
+            "roll",
+            move |dice: INT, sides: INT| -> Result<INT, Box<EvalAltResult>> {
+                if dice > MAX_EXTRA_DICE {
+                    return Err(format!("roll() can roll at most {MAX_EXTRA_DICE} dice").into());
+                }
+                let mut rng = shared.borrow_mut();
+                Ok((0..dice.max(0))
+                    .map(|_| match sides > 0 {
+                        true => rng.gen_range(1..=sides),
+                        false => 0,
+                    })
+                    .sum())
+            },
+        );
+        let ast = engine
+            .compile(source)
+            .map_err(|e| format!("Could not compile the mechanics: {e}"))?;
+        Ok(Mechanics { engine, ast, rng })
+    }
+
+    /// Splits an expression like `brutal(2d12 + 5)` into the name of the
+    /// function it's wrapped in and the expression inside. Anything that
+    /// isn't wrapped in one of the script's functions is left alone.
+    pub fn call<'a>(&self, input: &'a str) -> Option<(&'a str, &'a str)> {
+        let (name, rest) = input.trim().split_once('(')?;
+        let name = name.trim();
+        let inner = rest.strip_suffix(')')?;
+        // the parentheses around the argument have to be the outermost ones,
+        // which rules out things like `brutal(d6) + (2)`
+        let mut depth = 0;
+        for c in inner.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return None,
+                ')' => depth -= 1,
+                _ => {}
+            }
+        }
+        let defined = self
+            .ast
+            .iter_functions()
+            .any(|function| function.name == name && function.params.len() == 1);
+        defined.then_some((name, inner))
+    }
+
+    /// Passes the roll to the named function, giving back the new total. The
+    /// function sees the roll as `total`, `dice` (every face, in order), and
+    /// `expression`.
+    // with the `wide` feature, every total the script can give back fits
+    #[allow(clippy::unnecessary_fallible_conversions)]
+    pub fn apply(&self, name: &str, value: &Value, rng: &mut StdRng) -> Result<Int, String> {
+        *self.rng.borrow_mut() = StdRng::seed_from_u64(rng.gen());
+        let mut roll = Map::new();
+        roll.insert("total".into(), Dynamic::from(value.value() as INT));
+        let dice: Array = value
+            .faces()
+            .into_iter()
+            .map(|face| Dynamic::from(face as INT))
+            .collect();
+        roll.insert("dice".into(), Dynamic::from(dice));
+        roll.insert("expression".into(), Dynamic::from(value.to_string()));
+        let total = self
+            .engine
+            .call_fn::<INT>(&mut Scope::new(), &self.ast, name, (roll,))
+            .map_err(|e| format!("{name}: {e}"))?;
+        Int::try_from(total).map_err(|_| format!("{name}: {total} is too large to be a total"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mechanics::*;
+    use crate::parse::parse;
+
+    const SOURCE: &str = "
+        fn brutal(r) { if r.total >= 20 { r.total + roll(1, 6) } else { r.total } }
+        fn highest(r) { r.dice.reduce(|a, b| if a > b { a } else { b }, 0) }
+        fn forever(r) { loop {} }
+    ";
+
+    #[test]
+    fn calls() {
+        let mechanics = Mechanics::compile(SOURCE).unwrap();
+        assert_eq!(
+            Some(("brutal", "2d12 + 5")),
+            mechanics.call(" brutal(2d12 + 5) ")
+        );
+        assert_eq!(Some(("brutal", "(d6)")), mechanics.call("brutal((d6))"));
+        assert_eq!(None, mechanics.call("brutal(d6) + (2)"));
+        assert_eq!(None, mechanics.call("unknown(d6)"));
+        assert_eq!(None, mechanics.call("2d6"));
+    }
+
+    #[test]
+    fn applying() {
+        let mechanics = Mechanics::compile(SOURCE).unwrap();
+        let mut rng = StdRng::seed_from_u64(0);
+        let value = parse("3d6").unwrap().evaluate(&mut rng);
+        let highest = *value.faces().iter().max().unwrap() as Int;
+        assert_eq!(Ok(highest), mechanics.apply("highest", &value, &mut rng));
+        let low = parse("10").unwrap().evaluate(&mut rng);
+        assert_eq!(Ok(10), mechanics.apply("brutal", &low, &mut rng));
+        let high = parse("20").unwrap().evaluate(&mut rng);
+        let total = mechanics.apply("brutal", &high, &mut rng).unwrap();
+        assert!((21..=26).contains(&total));
+    }
+
+    #[test]
+    fn sandboxed() {
+        let mechanics = Mechanics::compile(SOURCE).unwrap();
+        let value = parse("1").unwrap().evaluate(&mut StdRng::seed_from_u64(0));
+        let error = mechanics
+            .apply("forever", &value, &mut StdRng::seed_from_u64(0))
+            .unwrap_err();
+        assert!(error.starts_with("forever:"), "{error}");
+        assert!(Mechanics::compile("fn oops(r) { eval(\"1\") }").is_err());
+    }
+}
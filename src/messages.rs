@@ -0,0 +1,258 @@
+//! What errors say. Everything that can go wrong reading or rolling an
+//! expression is one of these messages, which has a stable [code] for
+//! front-ends that would rather word it themselves, and is otherwise worded
+//! in whichever [`Locale`] was picked with [`set_locale`].
+//!
+//! [code]: Message::code
+//!
+//! ```
+//! use recursive_dice_roller::messages::{set_locale, Locale};
+//! use recursive_dice_roller::parse::parse;
+//!
+//! let error = parse("d20 + x").unwrap_err();
+//! assert_eq!(Some("unexpected_symbol"), error.code());
+//! set_locale(Locale::German);
+//! assert_eq!("Unerwartetes Zeichen 'x' in der Eingabe", error.to_string());
+//! # set_locale(Locale::English);
+//! ```
+
+use std::fmt::{self, Display};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    UnexpectedSymbol(char),
+    /// The input stopped partway through something, like `2d20k`
+    Incomplete,
+    /// Every symbol was fine, but they don't add up to an expression
+    Unparsable,
+    NoExpression,
+    DuplicateName(String),
+    TooDeep(usize),
+    TooManyDice(u64),
+    /// Expressions are rolled over again more than this many times in all
+    TooManyRepetitions(u32),
+    /// Rolling took longer than this many milliseconds
+    TimedOut(u128),
+    /// A total came to more than can be counted
+    Overflow,
+    /// A number, as written, is more than can be counted
+    NumberTooBig(String),
+    /// Anything not in the catalog, which is only ever in English
+    Other(String),
+}
+
+pub const LOCALES: &[&str] = &["en", "es", "fr", "de"];
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    English,
+    Spanish,
+    French,
+    German,
+}
+
+impl Locale {
+    /// The locale for a language tag like `es`, `fr-CA`, or `de_DE.UTF-8`,
+    /// going by the language alone
+    pub fn named(tag: &str) -> Option<Locale> {
+        let language = tag.split(['-', '_', '.']).next()?.to_ascii_lowercase();
+        match language.as_str() {
+            "en" | "c" | "posix" => Some(Locale::English),
+            "es" => Some(Locale::Spanish),
+            "fr" => Some(Locale::French),
+            "de" => Some(Locale::German),
+            _ => None,
+        }
+    }
+
+    /// The locale from `RDR_LANG`, or else the usual `LC_ALL`,
+    /// `LC_MESSAGES`, and `LANG`, taking the first that's set to anything
+    pub fn from_env() -> Locale {
+        ["RDR_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|tag| !tag.is_empty())
+            .and_then(|tag| Locale::named(&tag))
+            .unwrap_or_default()
+    }
+}
+
+static LOCALE: AtomicU8 = AtomicU8::new(0);
+
+/// Picks the language every message is worded in from now on
+pub fn set_locale(locale: Locale) {
+    LOCALE.store(locale as u8, Ordering::Relaxed);
+}
+
+pub fn locale() -> Locale {
+    match LOCALE.load(Ordering::Relaxed) {
+        1 => Locale::Spanish,
+        2 => Locale::French,
+        3 => Locale::German,
+        _ => Locale::English,
+    }
+}
+
+impl Message {
+    /// A name for the message that stays the same in every language
+    pub fn code(&self) -> &'static str {
+        match self {
+            Message::UnexpectedSymbol(_) => "unexpected_symbol",
+            Message::Incomplete => "incomplete",
+            Message::Unparsable => "unparsable",
+            Message::NoExpression => "no_expression",
+            Message::DuplicateName(_) => "duplicate_name",
+            Message::TooDeep(_) => "too_deep",
+            Message::TooManyDice(_) => "too_many_dice",
+            Message::TooManyRepetitions(_) => "too_many_repetitions",
+            Message::TimedOut(_) => "timed_out",
+            Message::Overflow => "overflow",
+            Message::NumberTooBig(_) => "number_too_big",
+            Message::Other(_) => "other",
+        }
+    }
+
+    pub fn worded(&self, locale: Locale) -> String {
+        use Locale::*;
+        use Message::*;
+        match (self, locale) {
+            (UnexpectedSymbol(c), English) => {
+                format!("Encountered unexpected symbol '{c}' while tokenizing input")
+            }
+            (UnexpectedSymbol(c), Spanish) => format!("Símbolo inesperado '{c}' en la entrada"),
+            (UnexpectedSymbol(c), French) => format!("Symbole inattendu '{c}' dans l'entrée"),
+            (UnexpectedSymbol(c), German) => format!("Unerwartetes Zeichen '{c}' in der Eingabe"),
+
+            (Incomplete, English) => {
+                "Character stream completed before token was fully assembled".to_string()
+            }
+            (Incomplete, Spanish) => "La entrada termina a mitad de un símbolo".to_string(),
+            (Incomplete, French) => "L'entrée s'arrête au milieu d'un symbole".to_string(),
+            (Incomplete, German) => "Die Eingabe endet mitten in einem Zeichen".to_string(),
+
+            (Unparsable, English) => "tokenized expression could not be parsed".to_string(),
+            (Unparsable, Spanish) => "No se pudo interpretar la expresión".to_string(),
+            (Unparsable, French) => "Impossible d'analyser l'expression".to_string(),
+            (Unparsable, German) => "Der Ausdruck konnte nicht gelesen werden".to_string(),
+
+            (NoExpression, English) => "No dice roll expression was provided".to_string(),
+            (NoExpression, Spanish) => "No se indicó ninguna tirada de dados".to_string(),
+            (NoExpression, French) => "Aucune expression de dés n'a été fournie".to_string(),
+            (NoExpression, German) => "Es wurde kein Würfelausdruck angegeben".to_string(),
+
+            (DuplicateName(name), English) => {
+                format!("There's more than one result named '{name}'")
+            }
+            (DuplicateName(name), Spanish) => {
+                format!("Hay más de un resultado llamado '{name}'")
+            }
+            (DuplicateName(name), French) => format!("Plusieurs résultats s'appellent '{name}'"),
+            (DuplicateName(name), German) => {
+                format!("Es gibt mehr als ein Ergebnis namens '{name}'")
+            }
+
+            (TooDeep(n), English) => format!("The expression is nested more than {n} levels deep"),
+            (TooDeep(n), Spanish) => {
+                format!("La expresión tiene más de {n} niveles de anidamiento")
+            }
+            (TooDeep(n), French) => format!("L'expression est imbriquée sur plus de {n} niveaux"),
+            (TooDeep(n), German) => {
+                format!("Der Ausdruck ist mehr als {n} Ebenen tief verschachtelt")
+            }
+
+            (TooManyDice(n), English) => format!("The expression rolls more than {n} dice"),
+            (TooManyDice(n), Spanish) => format!("La expresión tira más de {n} dados"),
+            (TooManyDice(n), French) => format!("L'expression lance plus de {n} dés"),
+            (TooManyDice(n), German) => format!("Der Ausdruck wirft mehr als {n} Würfel"),
+
+            (TooManyRepetitions(n), English) => {
+                format!("The expression is repeated more than {n} times")
+            }
+            (TooManyRepetitions(n), Spanish) => {
+                format!("La expresión se repite más de {n} veces")
+            }
+            (TooManyRepetitions(n), French) => {
+                format!("L'expression est répétée plus de {n} fois")
+            }
+            (TooManyRepetitions(n), German) => {
+                format!("Der Ausdruck wird mehr als {n}-mal wiederholt")
+            }
+
+            (TimedOut(ms), English) => format!("Gave up after {ms}ms of rolling"),
+            (TimedOut(ms), Spanish) => format!("Se abandonó tras {ms} ms de tiradas"),
+            (TimedOut(ms), French) => format!("Abandon après {ms} ms de lancers"),
+            (TimedOut(ms), German) => format!("Nach {ms} ms Würfeln abgebrochen"),
+
+            (Overflow, English) => "The total is too big to work out".to_string(),
+            (Overflow, Spanish) => "El total es demasiado grande para calcularlo".to_string(),
+            (Overflow, French) => "Le total est trop grand pour être calculé".to_string(),
+            (Overflow, German) => "Die Summe ist zu groß, um sie zu berechnen".to_string(),
+
+            (NumberTooBig(n), English) => format!("The number {n} is too big"),
+            (NumberTooBig(n), Spanish) => format!("El número {n} es demasiado grande"),
+            (NumberTooBig(n), French) => format!("Le nombre {n} est trop grand"),
+            (NumberTooBig(n), German) => format!("Die Zahl {n} ist zu groß"),
+
+            (Other(message), _) => message.clone(),
+        }
+    }
+}
+
+/// In the current [`locale`]
+impl Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.worded(locale()))
+    }
+}
+
+impl From<String> for Message {
+    fn from(message: String) -> Self {
+        Message::Other(message)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(message: &str) -> Self {
+        Message::Other(message.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::messages::*;
+
+    #[test]
+    fn every_message_in_every_locale() {
+        let messages = [
+            Message::UnexpectedSymbol('x'),
+            Message::Incomplete,
+            Message::Unparsable,
+            Message::NoExpression,
+            Message::DuplicateName("hit".to_string()),
+            Message::TooDeep(3),
+            Message::TooManyDice(10),
+            Message::TooManyRepetitions(10),
+            Message::TimedOut(50),
+        ];
+        for locale in LOCALES.iter().map(|tag| Locale::named(tag).unwrap()) {
+            for message in &messages {
+                let worded = message.worded(locale);
+                assert!(!worded.is_empty());
+                if locale != Locale::English {
+                    assert_ne!(message.worded(Locale::English), worded);
+                }
+            }
+        }
+        assert_eq!("other", Message::from("anything").code());
+    }
+
+    #[test]
+    fn locale_tags() {
+        assert_eq!(Some(Locale::French), Locale::named("fr-CA"));
+        assert_eq!(Some(Locale::German), Locale::named("de_DE.UTF-8"));
+        assert_eq!(Some(Locale::English), Locale::named("C.UTF-8"));
+        assert_eq!(None, Locale::named("tlh"));
+    }
+}
@@ -0,0 +1,557 @@
+//! Roll modifiers: the rules that change a roll's dice once they've landed,
+//! by rolling some of them again or by deciding which of them count towards
+//! its total. Each one works on the raw dice, in the order they were rolled,
+//! and is looked up by name in [`MODIFIERS`], so a new mechanic only has to
+//! implement [`RollModifier`] and be added to the list.
+
+use rand::RngCore;
+use serde::Serialize;
+use smallvec::SmallVec;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+use crate::eval::{roll_die, Comparison, Int};
+
+/// What a modifier has to go on besides the dice. Each only looks at what it
+/// needs: keeping and dropping go by `count`, and rolling dice again by the
+/// rest.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Argument {
+    /// The modifier's own number, like the 3 of `kh3`, already clamped to
+    /// the number of dice
+    pub count: usize,
+    /// How many sides the dice have
+    pub sides: u32,
+    /// The faces that are rolled again, like the `>=8` of `!>=8`, or only
+    /// the highest if there's no condition
+    pub on: Option<(Comparison, Int)>,
+    /// The most times a die is rolled again after the first
+    pub most: usize,
+}
+
+impl Argument {
+    /// The argument of a modifier that only goes by how many dice
+    pub fn count(count: usize) -> Self {
+        Argument {
+            count,
+            ..Default::default()
+        }
+    }
+}
+
+/// The dice of a single roll, once a modifier has been applied. The dice
+/// that count and the ones that don't are positions in `faces`, and both
+/// keep the order the dice were rolled in.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Modified {
+    /// What the dice show afterwards: the ones the modifier was given, then
+    /// any extra dice it added to the pool
+    pub faces: Vec<i32>,
+    pub kept: Vec<usize>,
+    pub dropped: Vec<usize>,
+    /// Every face each die the modifier rolled again landed on, in the order
+    /// they were rolled, by its position. Dice it left alone aren't listed.
+    pub chains: BTreeMap<usize, Vec<i32>>,
+    /// Whether any die would still have been rolled again when it reached
+    /// [`Argument::most`]
+    pub capped: bool,
+}
+
+impl Modified {
+    /// The dice just as they were, sorted into the ones that count and the
+    /// ones that don't
+    fn sorted(dice: &[i32], kept: Vec<usize>, dropped: Vec<usize>) -> Self {
+        Modified {
+            faces: dice.to_vec(),
+            kept,
+            dropped,
+            ..Default::default()
+        }
+    }
+}
+
+/// How many dice showed each face, for pools too big to keep one by one
+pub type Histogram = BTreeMap<i32, u64>;
+
+/// What's kept of a pool too big to keep one by one, once a modifier has
+/// been applied: how many of the dice that count, and of the ones that
+/// don't, showed each face
+#[derive(Debug, PartialEq, Eq, Clone, Default, Serialize)]
+pub struct Summary {
+    pub kept: Histogram,
+    pub dropped: Histogram,
+}
+
+pub trait RollModifier: Sync {
+    /// How the modifier is written after the dice, like the `kh` in `4d6kh3`
+    fn name(&self) -> &'static str;
+
+    /// Rolls dice again, or sorts them into the ones that count and the ones
+    /// that don't, or both
+    fn apply(&self, dice: &[i32], argument: Argument, rng: &mut dyn RngCore) -> Modified;
+
+    /// The same for a pool that's only a histogram of its faces. Unless the
+    /// modifier knows better, the dice are laid out from lowest to highest
+    /// and handed to [`RollModifier::apply`], which is fine for any modifier
+    /// that doesn't care what order they were rolled in.
+    fn apply_summarized(
+        &self,
+        faces: &Histogram,
+        argument: Argument,
+        rng: &mut dyn RngCore,
+    ) -> Summary {
+        let dice: Vec<i32> = faces
+            .iter()
+            .flat_map(|(&face, &count)| std::iter::repeat_n(face, count as usize))
+            .collect();
+        let Modified {
+            faces,
+            kept,
+            dropped,
+            ..
+        } = self.apply(&dice, argument, rng);
+        let faces = |positions: Vec<usize>| {
+            histogram(&positions.iter().map(|&i| faces[i]).collect::<Vec<_>>())
+        };
+        Summary {
+            kept: faces(kept),
+            dropped: faces(dropped),
+        }
+    }
+}
+
+pub struct KeepHighest;
+
+impl RollModifier for KeepHighest {
+    fn name(&self) -> &'static str {
+        "kh"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, _: &mut dyn RngCore) -> Modified {
+        let (dropped, kept) = split(dice, dice.len() - argument.count);
+        Modified::sorted(dice, kept, dropped)
+    }
+
+    fn apply_summarized(
+        &self,
+        faces: &Histogram,
+        argument: Argument,
+        _: &mut dyn RngCore,
+    ) -> Summary {
+        let count = faces.values().sum::<u64>() as usize;
+        let (dropped, kept) = split_summarized(faces, count - argument.count);
+        Summary { kept, dropped }
+    }
+}
+
+pub struct KeepLowest;
+
+impl RollModifier for KeepLowest {
+    fn name(&self) -> &'static str {
+        "kl"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, _: &mut dyn RngCore) -> Modified {
+        let (kept, dropped) = split(dice, argument.count);
+        Modified::sorted(dice, kept, dropped)
+    }
+
+    fn apply_summarized(
+        &self,
+        faces: &Histogram,
+        argument: Argument,
+        _: &mut dyn RngCore,
+    ) -> Summary {
+        let (kept, dropped) = split_summarized(faces, argument.count);
+        Summary { kept, dropped }
+    }
+}
+
+pub struct DropHighest;
+
+impl RollModifier for DropHighest {
+    fn name(&self) -> &'static str {
+        "dh"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, _: &mut dyn RngCore) -> Modified {
+        let (kept, dropped) = split(dice, dice.len() - argument.count);
+        Modified::sorted(dice, kept, dropped)
+    }
+
+    fn apply_summarized(
+        &self,
+        faces: &Histogram,
+        argument: Argument,
+        _: &mut dyn RngCore,
+    ) -> Summary {
+        let count = faces.values().sum::<u64>() as usize;
+        let (kept, dropped) = split_summarized(faces, count - argument.count);
+        Summary { kept, dropped }
+    }
+}
+
+pub struct DropLowest;
+
+impl RollModifier for DropLowest {
+    fn name(&self) -> &'static str {
+        "dl"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, _: &mut dyn RngCore) -> Modified {
+        let (dropped, kept) = split(dice, argument.count);
+        Modified::sorted(dice, kept, dropped)
+    }
+
+    fn apply_summarized(
+        &self,
+        faces: &Histogram,
+        argument: Argument,
+        _: &mut dyn RngCore,
+    ) -> Summary {
+        let (dropped, kept) = split_summarized(faces, argument.count);
+        Summary { kept, dropped }
+    }
+}
+
+/// `!`: every die that lands on one of the faces is rolled again, and every
+/// die it rolls is an extra die in the pool
+pub struct Explode;
+
+impl RollModifier for Explode {
+    fn name(&self) -> &'static str {
+        "!"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, rng: &mut dyn RngCore) -> Modified {
+        roll_each(dice, argument, rng, |modified, _, chain| {
+            modified.faces.extend_from_slice(&chain[1..])
+        })
+    }
+}
+
+/// `!!`: like [`Explode`], but every die rolled again is added into the one
+/// that exploded, so a d6 that rolls `6, 6, 3` is a single die showing 15
+pub struct Compound;
+
+impl RollModifier for Compound {
+    fn name(&self) -> &'static str {
+        "!!"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, rng: &mut dyn RngCore) -> Modified {
+        roll_each(dice, argument, rng, |modified, i, chain| {
+            modified.faces[i] = chain
+                .iter()
+                .fold(0i32, |total, &face| total.saturating_add(face))
+        })
+    }
+}
+
+/// `r`: every die that lands on one of the faces is rolled again, and shows
+/// the last face it lands on
+pub struct Reroll;
+
+impl RollModifier for Reroll {
+    fn name(&self) -> &'static str {
+        "r"
+    }
+
+    fn apply(&self, dice: &[i32], argument: Argument, rng: &mut dyn RngCore) -> Modified {
+        roll_each(dice, argument, rng, |modified, i, chain| {
+            modified.faces[i] = chain[chain.len() - 1]
+        })
+    }
+}
+
+/// Every modifier there is, each under its own name
+pub const MODIFIERS: &[&dyn RollModifier] = &[
+    &KeepHighest,
+    &KeepLowest,
+    &DropHighest,
+    &DropLowest,
+    &Explode,
+    &Compound,
+    &Reroll,
+];
+
+/// The modifier with this name, if there is one
+pub fn modifier(name: &str) -> Option<&'static dyn RollModifier> {
+    MODIFIERS
+        .iter()
+        .find(|modifier| modifier.name() == name)
+        .copied()
+}
+
+/// Rolls each die again for as long as it lands on one of the argument's
+/// faces, then lets `settle` say what became of the ones that were, from
+/// every face they landed on. Every die counts; it's up to a keep rule to
+/// drop any of them.
+fn roll_each(
+    dice: &[i32],
+    argument: Argument,
+    rng: &mut dyn RngCore,
+    settle: impl Fn(&mut Modified, usize, &[i32]),
+) -> Modified {
+    let mut modified = Modified {
+        faces: dice.to_vec(),
+        ..Default::default()
+    };
+    for (i, &first) in dice.iter().enumerate() {
+        let (chain, capped) = roll_again(first, argument, rng);
+        modified.capped |= capped;
+        if chain.len() > 1 {
+            settle(&mut modified, i, &chain);
+            modified.chains.insert(i, chain);
+        }
+    }
+    modified.kept = (0..modified.faces.len()).collect();
+    modified
+}
+
+/// Rolls a die again for as long as it lands on one of the argument's faces,
+/// but no more than [`Argument::most`] times after the first. Explosions and
+/// rerolls both stop this way, so a limit of 3 means a die is rolled 4 times
+/// at most either way. Every face it landed on comes back in the order they
+/// were rolled, along with whether it would have been rolled again.
+fn roll_again(first: i32, argument: Argument, rng: &mut dyn RngCore) -> (Vec<i32>, bool) {
+    let again = |face: i32| match argument.on {
+        // a zero-sided die shows zero, which isn't a face it can roll again
+        _ if argument.sides == 0 => false,
+        Some((comparison, against)) => comparison.holds(face as Int, against),
+        None => face == argument.sides as i32,
+    };
+    let mut faces = vec![first];
+    while again(faces[faces.len() - 1]) {
+        if faces.len() - 1 == argument.most {
+            return (faces, true);
+        }
+        faces.push(roll_die(argument.sides, rng));
+    }
+    (faces, false)
+}
+
+/// Separates the positions of the `index` lowest dice from the rest without
+/// disturbing the order in which they were rolled; the sort is stable, so
+/// ties are broken by whichever die came first
+fn split(elements: &[i32], index: usize) -> (Vec<usize>, Vec<usize>) {
+    // keeping (or dropping) every die needs no sorting at all
+    if index == 0 {
+        return (Vec::new(), (0..elements.len()).collect());
+    }
+    if index == elements.len() {
+        return ((0..elements.len()).collect(), Vec::new());
+    }
+    let mut order: SmallVec<[usize; 16]> = (0..elements.len()).collect();
+    order.sort_by_key(|&i| elements[i]);
+    let mut is_low: SmallVec<[bool; 16]> = SmallVec::from_elem(false, elements.len());
+    for &i in &order[..index] {
+        is_low[i] = true;
+    }
+    (0..elements.len()).partition(|&i| is_low[i])
+}
+
+/// Like [`split`], for a pool that's only a histogram: the `index` lowest
+/// dice, then the rest
+fn split_summarized(faces: &Histogram, index: usize) -> (Histogram, Histogram) {
+    let (mut lowest, mut highest) = (Histogram::new(), Histogram::new());
+    let mut left = index as u64;
+    for (&face, &count) in faces {
+        let low = count.min(left);
+        left -= low;
+        if low > 0 {
+            lowest.insert(face, low);
+        }
+        if count > low {
+            highest.insert(face, count - low);
+        }
+    }
+    (lowest, highest)
+}
+
+pub fn histogram(dice: &[i32]) -> Histogram {
+    let mut histogram = Histogram::new();
+    for &die in dice {
+        *histogram.entry(die).or_default() += 1;
+    }
+    histogram
+}
+
+/// Histograms with more faces than this are described by their range alone
+const MAX_FACES_SHOWN: usize = 20;
+
+impl Summary {
+    pub fn count(&self) -> u64 {
+        self.kept.values().chain(self.dropped.values()).sum()
+    }
+
+    /// The total of the dice that count, which can be more than an `i64`
+    /// holds when there are enough of them
+    pub fn sum(&self) -> i128 {
+        self.kept
+            .iter()
+            .map(|(&face, &count)| face as i128 * count as i128)
+            .sum()
+    }
+}
+
+/// Describes a histogram, e.g. `1000 dice, 1 to 6 (1: 170, 2: 161, ...)`
+pub fn describe(faces: &Histogram) -> String {
+    let count: u64 = faces.values().sum();
+    let (Some(min), Some(max)) = (faces.keys().next(), faces.keys().next_back()) else {
+        return "no dice".to_string();
+    };
+    let range = format!("{count} dice, {min} to {max}");
+    if faces.len() > MAX_FACES_SHOWN {
+        return range;
+    }
+    let faces: Vec<String> = faces
+        .iter()
+        .map(|(face, count)| format!("{face}: {count}"))
+        .collect();
+    format!("{range} ({})", faces.join(", "))
+}
+
+/// The kept dice, then the dropped ones if there are any
+impl Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", describe(&self.kept))?;
+        if !self.dropped.is_empty() {
+            write!(f, " | {}", describe(&self.dropped))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::Comparison;
+    use crate::modifier::*;
+    use rand::rngs::mock::StepRng;
+
+    /// Every die it rolls lands on `face`, if the dice have that many sides
+    fn landing_on(face: u64) -> StepRng {
+        StepRng::new(face, 0)
+    }
+
+    #[test]
+    fn keeping() {
+        let dice = [3, 6, 1, 6];
+        let rng = &mut landing_on(1);
+        let keep = |name, count, rng: &mut StepRng| {
+            modifier(name)
+                .unwrap()
+                .apply(&dice, Argument::count(count), rng)
+        };
+        let highest = keep("kh", 2, rng);
+        assert_eq!(vec![1, 3], highest.kept);
+        assert_eq!(vec![0, 2], highest.dropped);
+        assert_eq!(dice.to_vec(), highest.faces);
+        let lowest = keep("kl", 1, rng);
+        assert_eq!(vec![2], lowest.kept);
+        assert_eq!(vec![0, 1, 3], lowest.dropped);
+        let everything = keep("kh", 4, rng);
+        assert_eq!(vec![0, 1, 2, 3], everything.kept);
+        assert!(everything.dropped.is_empty());
+        let dropped = keep("dl", 1, rng);
+        assert_eq!(vec![0, 1, 3], dropped.kept);
+        assert_eq!(vec![2], dropped.dropped);
+        let dropped = keep("dh", 2, rng);
+        assert_eq!(vec![0, 2], dropped.kept);
+        assert!(modifier("x").is_none());
+    }
+
+    #[test]
+    fn ties_go_to_the_first_die() {
+        let dice = [4, 4, 4];
+        let rng = &mut landing_on(1);
+        assert_eq!(
+            vec![0],
+            KeepLowest.apply(&dice, Argument::count(1), rng).kept
+        );
+        assert_eq!(
+            vec![1, 2],
+            KeepHighest.apply(&dice, Argument::count(2), rng).kept
+        );
+    }
+
+    #[test]
+    fn keeping_summarized() {
+        let dice = [3, 6, 1, 6, 2];
+        let faces = histogram(&dice);
+        let rng = &mut landing_on(1);
+        for (name, count) in [
+            ("kh", 2),
+            ("kl", 3),
+            ("kh", 0),
+            ("kl", 5),
+            ("dl", 1),
+            ("dh", 2),
+        ] {
+            let modifier = modifier(name).unwrap();
+            let argument = Argument::count(count);
+            let Modified { kept, dropped, .. } = modifier.apply(&dice, argument, rng);
+            let summary = modifier.apply_summarized(&faces, argument, rng);
+            let faces =
+                |positions: Vec<usize>| positions.iter().map(|&i| dice[i]).collect::<Vec<_>>();
+            assert_eq!(histogram(&faces(kept)), summary.kept);
+            assert_eq!(histogram(&faces(dropped)), summary.dropped);
+        }
+        let summary = KeepHighest.apply_summarized(&faces, Argument::count(2), rng);
+        assert_eq!(5, summary.count());
+        assert_eq!(12, summary.sum());
+        assert_eq!(
+            "2 dice, 6 to 6 (6: 2) | 3 dice, 1 to 3 (1: 1, 2: 1, 3: 1)",
+            summary.to_string()
+        );
+    }
+
+    #[test]
+    fn names_are_unique() {
+        for (i, modifier) in MODIFIERS.iter().enumerate() {
+            let others = &MODIFIERS[i + 1..];
+            assert!(others.iter().all(|other| other.name() != modifier.name()));
+        }
+    }
+
+    #[test]
+    fn rolling_again() {
+        // every die rolled again lands on a 6, so a d6 explodes until it's
+        // stopped
+        let rng = &mut landing_on(6);
+        let argument = Argument {
+            sides: 6,
+            most: 2,
+            ..Default::default()
+        };
+        let dice = [6, 2];
+        let exploded = modifier("!").unwrap().apply(&dice, argument, rng);
+        assert_eq!(vec![6, 2, 6, 6], exploded.faces);
+        assert_eq!(vec![0, 1, 2, 3], exploded.kept);
+        assert_eq!(Some(&vec![6, 6, 6]), exploded.chains.get(&0));
+        assert!(exploded.capped);
+        let compounded = modifier("!!").unwrap().apply(&dice, argument, rng);
+        assert_eq!(vec![18, 2], compounded.faces);
+        assert_eq!(vec![0, 1], compounded.kept);
+        assert_eq!(exploded.chains, compounded.chains);
+
+        // a die showing 2 or less is rolled again, once it's landed on a 4
+        let argument = Argument {
+            on: Some((Comparison::AtMost, 2)),
+            ..argument
+        };
+        let rerolled = modifier("r")
+            .unwrap()
+            .apply(&dice, argument, &mut landing_on(4));
+        assert_eq!(vec![6, 4], rerolled.faces);
+        assert_eq!(Some(&vec![2, 4]), rerolled.chains.get(&1));
+        assert!(!rerolled.capped);
+        // and a d6 can't stop landing on a 1
+        let rerolled = modifier("r")
+            .unwrap()
+            .apply(&[1], argument, &mut landing_on(1));
+        assert_eq!(vec![1], rerolled.faces);
+        assert_eq!(Some(&vec![1, 1, 1]), rerolled.chains.get(&0));
+        assert!(rerolled.capped);
+    }
+}
@@ -1,34 +1,163 @@
+//! Turns a string like `2d20k1 + 5` into an [`Exp`].
+
+use serde::Serialize;
+
 use crate::{
-    eval::{self, Exp, Keep},
+    error::Error,
+    eval::{self, Exp, Keep, Pick},
+    messages::Message,
     tokenize::{Token, Tokenizer},
 };
 
+/// A stretch of the input, counted in characters, from `start` up to but not
+/// including `end`
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Where each node of an expression was written. It has the same shape as
+/// the [`Exp`] it came with, and so also as the [`Value`](eval::Value) that
+/// rolling it makes: an operation's children are its arguments, and a roll's
+/// are its dice, its sides, its keep count, what its dice explode on, and what
+/// they're rerolled on, each of the last three only if it has one. A roll
+/// written without a count, like `d20`, gets an empty span where the count
+/// would go, and so does anything else a roll leaves out before a part it
+/// does have.
+/// A repeated expression has only the one child, the expression it repeats,
+/// which is where every repetition of it was written.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SourceMap {
+    pub span: Span,
+    pub children: Vec<SourceMap>,
+}
+
+impl SourceMap {
+    fn leaf(span: Span) -> Self {
+        SourceMap {
+            span,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// How the source map of a reduced expression comes out of the maps of the
+/// tokens it was reduced from, which are numbered from the first of them
+#[derive(Debug, Clone, Copy)]
+enum Shape {
+    /// Nothing inside it to map, like a number
+    Leaf,
+    /// The same as one of the tokens, like the expression in parentheses
+    Inner(usize),
+    /// Only the one child, like the expression a repeat repeats
+    Wrapped(usize),
+    /// Two children, like the arguments of an operation
+    Pair(usize, usize),
+    /// The first token's children, and then the last token
+    Appended,
+    /// The first token, and then the last token's children
+    Prepended,
+    /// A roll written without a count, whose sides are the second token
+    Die,
+    /// The second token's children, with the first token as their count
+    Counted,
+    /// The first token's children, with the one at `from` put in at `at`
+    Placed { at: usize, from: usize },
+    /// The first token's children, over a wider span
+    Widened,
+}
+
+impl Shape {
+    fn map(self, mut maps: Vec<SourceMap>, whole: Span) -> SourceMap {
+        let mut take = |i: usize| std::mem::take(&mut maps[i]);
+        let children = match self {
+            Shape::Leaf => Vec::new(),
+            Shape::Inner(i) => return take(i),
+            Shape::Wrapped(i) => vec![take(i)],
+            Shape::Pair(a, b) => vec![take(a), take(b)],
+            Shape::Appended => {
+                let last = take(2);
+                let mut children = take(0).children;
+                children.push(last);
+                children
+            }
+            Shape::Prepended => {
+                let mut children = vec![take(0)];
+                children.append(&mut take(2).children);
+                children
+            }
+            Shape::Die => {
+                let count = SourceMap::leaf(Span {
+                    start: whole.start,
+                    end: whole.start,
+                });
+                vec![count, take(1)]
+            }
+            Shape::Counted => {
+                let count = take(0);
+                let mut children = take(1).children;
+                children[0] = count;
+                children
+            }
+            Shape::Placed { at, from } => {
+                let placed = take(from);
+                let mut children = take(0).children;
+                place(&mut children, at, placed);
+                children
+            }
+            Shape::Widened => take(0).children,
+        };
+        SourceMap {
+            span: whole,
+            children,
+        }
+    }
+}
+
+/// Alongside each token, the characters it covers (parentheses included) and,
+/// when asked for, where the nodes of its expression were written
 #[derive(Debug, Default)]
 struct ExpBuilder {
-    lookahead: Option<Token>,
+    lookahead: Option<(Token, Span)>,
     tokens: Vec<Token>,
+    extents: Vec<Span>,
+    /// Whether to keep track of the source maps at all
+    mapping: bool,
+    maps: Vec<SourceMap>,
+    /// Why the input can't be parsed, once it's known, when it's something
+    /// other than the tokens failing to add up to an expression
+    rejected: Option<Error>,
 }
 
 impl ExpBuilder {
-    fn reduced(&mut self, split: usize) -> Option<Exp> {
+    fn lookahead_precedence(&self) -> u32 {
+        self.lookahead
+            .as_ref()
+            .map_or(0, |(token, _)| token.precedence())
+    }
+
+    fn reduced(&mut self, split: usize) -> Option<(Exp, Shape)> {
         use Exp::*;
         use Token::*;
+        let precedence = self.lookahead_precedence();
+        let comparing = matches!(self.lookahead, Some((Token::Compare(_), _)));
         match &mut self.tokens[split..] {
             // the most basic thing we can do is convert a number literal into
             // constant expression
             [Number(n)] => {
-                return Some(Exp::Const(*n));
+                return Some((Exp::Const(*n), Shape::Leaf));
             }
             // parentheses supersede all operator precedence rules
             [OpenParen, Expression(exp), CloseParen] => {
-                return Some(exp.clone());
+                return Some((exp.clone(), Shape::Inner(1)));
             }
             // As an optimization, we try to collapse multiple contiguous
             // applications of the same operation into a single vector
             [Expression(Op(lhs)), Operation(op), Expression(rhs)] => {
                 // if the lookahead token has greater precedence than our
                 // current operator, we don't want to reduce the expression yet
-                if op.precedence() < self.lookahead.as_ref().map_or(0, Token::precedence) {
+                if op.precedence() < precedence {
                     return None;
                 }
                 // if the lhs operation is the same as the new operation we're
@@ -36,55 +165,135 @@ impl ExpBuilder {
                 // vector
                 if lhs.operation == *op {
                     lhs.push_back(rhs.clone());
-                    return Some(Exp::Op(lhs.clone()));
+                    return Some((Exp::Op(lhs.clone()), Shape::Appended));
                 }
                 // if we can't perform the optimization, just treat it as a
                 // normal case.
                 let expression = op.to_exp(Exp::Op(lhs.clone()), rhs.clone());
-                return Some(expression);
+                return Some((expression, Shape::Pair(0, 2)));
             }
-            // the optimization is symmetric, so we need to implement it for
-            // both the left-handed version as well as the right-handed version
+            // the optimization is symmetric for addition and multiplication,
+            // so we need to implement it for the right-handed version too.
+            // `a - (b - c)` isn't `a - b - c`, though.
             [Expression(lhs), Operation(op), Expression(Op(rhs))] => {
-                if op.precedence() < self.lookahead.as_ref().map_or(0, Token::precedence) {
+                if op.precedence() < precedence {
                     return None;
                 }
-                if *op == rhs.operation {
+                if *op == rhs.operation && *op != eval::Operation::Sub {
                     rhs.push_front(lhs.clone());
-                    return Some(Exp::Op(rhs.clone()));
+                    return Some((Exp::Op(rhs.clone()), Shape::Prepended));
                 }
                 let expression = op.to_exp(lhs.clone(), Exp::Op(rhs.clone()));
-                return Some(expression);
+                return Some((expression, Shape::Pair(0, 2)));
             }
             // for all other additions not eligible for the optimization; this
             // is the base case
             [Expression(a), Operation(op), Expression(b)] => {
-                if op.precedence() < self.lookahead.as_ref().map_or(0, Token::precedence) {
+                if op.precedence() < precedence {
                     return None;
                 }
                 let expression = op.to_exp(a.clone(), b.clone());
-                return Some(expression);
+                return Some((expression, Shape::Pair(0, 2)));
             }
             // basic dice roll, like d6 or d20
             [Die, Expression(sides)] => {
                 let expression = Exp::roll(eval::Roll::simple(Const(1), sides.clone()));
-                return Some(expression);
+                return Some((expression, Shape::Die));
             }
             // rolling multiple of the same die, e.g. 3d8
             [Expression(dice), Expression(Roll(roll))] => {
                 roll.borrow_mut().dice = dice.clone();
                 // roll.borrow_mut().keep.retain = dice.clone();
-                return Some(Roll(roll.clone()));
+                return Some((Roll(roll.clone()), Shape::Counted));
             }
             // keep highest
             [Expression(Roll(roll)), KeepHighest, Expression(exp)] => {
                 roll.borrow_mut().keep = Keep::Highest(exp.clone());
-                return Some(Roll(roll.clone()));
+                return Some((Roll(roll.clone()), Shape::Placed { at: 2, from: 2 }));
+            }
+            // keep a share of the pool, like the highest half
+            [Expression(Roll(roll)), keep @ (KeepHighest | KeepLowest), Share(percent)] => {
+                roll.borrow_mut().keep = match keep {
+                    KeepHighest => Keep::HighestShare(*percent),
+                    _ => Keep::LowestShare(*percent),
+                };
+                return Some((Roll(roll.clone()), Shape::Placed { at: 2, from: 2 }));
             }
             // keep lowest
             [Expression(Roll(roll)), KeepLowest, Expression(exp)] => {
                 roll.borrow_mut().keep = Keep::Lowest(exp.clone());
-                return Some(Roll(roll.clone()));
+                return Some((Roll(roll.clone()), Shape::Placed { at: 2, from: 2 }));
+            }
+            // drop lowest and drop highest
+            [Expression(Roll(roll)), drop @ (DropLowest | DropHighest), Expression(exp)] => {
+                roll.borrow_mut().keep = match drop {
+                    DropLowest => Keep::DropLowest(exp.clone()),
+                    _ => Keep::DropHighest(exp.clone()),
+                };
+                return Some((Roll(roll.clone()), Shape::Placed { at: 2, from: 2 }));
+            }
+            // exploding dice, like `5d6!!`, unless there's a comparison on
+            // the way that says which faces explode
+            [Expression(Roll(roll)), Explode(explode)] => {
+                if comparing {
+                    return None;
+                }
+                roll.borrow_mut().explode = Some(eval::Explosion::new(*explode));
+                return Some((Roll(roll.clone()), Shape::Widened));
+            }
+            // dice that explode on more than their highest face, like
+            // `10d10!>=8`
+            [Expression(Roll(roll)), Explode(explode), Compare(comparison), Expression(exp)] => {
+                roll.borrow_mut().explode = Some(eval::Explosion {
+                    explode: *explode,
+                    on: Some(eval::Condition {
+                        comparison: *comparison,
+                        against: exp.clone(),
+                    }),
+                });
+                return Some((Roll(roll.clone()), Shape::Placed { at: 3, from: 3 }));
+            }
+            // dice rerolled on a single face, like `4d6r1`
+            [Expression(Roll(roll)), Reroll, Expression(exp)] => {
+                roll.borrow_mut().reroll = Some(eval::Condition {
+                    comparison: eval::Comparison::Equal,
+                    against: exp.clone(),
+                });
+                return Some((Roll(roll.clone()), Shape::Placed { at: 4, from: 2 }));
+            }
+            // or on every face that compares to a number, like `4d6r<3`
+            [Expression(Roll(roll)), Reroll, Compare(comparison), Expression(exp)] => {
+                roll.borrow_mut().reroll = Some(eval::Condition {
+                    comparison: *comparison,
+                    against: exp.clone(),
+                });
+                return Some((Roll(roll.clone()), Shape::Placed { at: 4, from: 3 }));
+            }
+            // the whole of an expression rolled several times over, like
+            // `best 1 of 3x(4d6k3)`. It binds tighter than arithmetic, but
+            // not as tightly as the dice it repeats.
+            [pick @ (Best | Worst), Expression(Const(keep)), Of, Expression(Const(times)), Times, Expression(exp)] =>
+            {
+                if Times.precedence() < precedence {
+                    return None;
+                }
+                let keep = u32::try_from(*keep).ok()?;
+                let times = u32::try_from(*times).ok().filter(|&times| times > 0)?;
+                if times > eval::MAX_REPETITIONS {
+                    let message = Message::TooManyRepetitions(eval::MAX_REPETITIONS);
+                    self.rejected = Some(Error::Limit(message));
+                    return None;
+                }
+                let pick = match pick {
+                    Best => Pick::Best(keep),
+                    _ => Pick::Worst(keep),
+                };
+                let expression = Exp::Repeat(eval::Repeat {
+                    exp: Box::new(exp.clone()),
+                    times,
+                    pick,
+                });
+                return Some((expression, Shape::Wrapped(5)));
             }
             _ => None,
         }
@@ -92,48 +301,164 @@ impl ExpBuilder {
 
     fn reduce(&mut self) -> bool {
         for split_at in (0..self.tokens.len()).rev() {
-            if let Some(exp) = self.reduced(split_at) {
+            if let Some((exp, shape)) = self.reduced(split_at) {
+                let extent = covering(&self.extents[split_at..]);
                 self.tokens.drain(split_at..);
+                self.extents.drain(split_at..);
                 self.tokens.push(Token::Expression(exp));
+                self.extents.push(extent);
+                if self.mapping {
+                    let maps = self.maps.split_off(split_at);
+                    self.maps.push(shape.map(maps, extent));
+                }
                 return true;
             }
         }
         return false;
     }
 
-    fn push(&mut self, token: Token) {
-        if let Some(t) = &self.lookahead {
-            self.tokens.push(t.clone());
+    fn push(&mut self, token: Token, extent: Span) {
+        if let Some((t, extent)) = self.lookahead.take() {
+            self.tokens.push(t);
+            self.extents.push(extent);
+            if self.mapping {
+                self.maps.push(SourceMap::leaf(extent));
+            }
         }
-        self.lookahead = Some(token);
+        self.lookahead = Some((token, extent));
     }
 
-    fn build(&mut self) -> Result<Exp, String> {
+    fn build(&mut self) -> Result<(Exp, Option<SourceMap>), Message> {
         if self.tokens.len() != 1 {
-            return Err("tokenized expression could not be parsed".into());
+            return Err(Message::Unparsable);
         }
-        if let Some(Token::Expression(exp)) = self.tokens.pop() {
-            return Ok(exp);
-        } else {
-            return Err("Final item was not a token".into());
+        match self.tokens.pop() {
+            Some(Token::Expression(exp)) => Ok((exp, self.maps.pop())),
+            _ => Err(Message::Unparsable),
         }
     }
 }
 
-pub fn parse(input: &str) -> Result<Exp, String> {
-    let tokens = Tokenizer::new(input);
-    let mut exp_builder = ExpBuilder::default();
-    for token in tokens {
-        exp_builder.push(token?);
+/// Puts one of a roll's children in its place among the others. Any place
+/// before it that nothing was written for gets an empty span.
+fn place(children: &mut Vec<SourceMap>, i: usize, map: SourceMap) {
+    let start = map.span.start;
+    children.resize_with(children.len().max(i + 1), || {
+        SourceMap::leaf(Span { start, end: start })
+    });
+    children[i] = map;
+}
+
+/// Everything from the first of the extents to the last
+fn covering(extents: &[Span]) -> Span {
+    Span {
+        start: extents.first().map_or(0, |span| span.start),
+        end: extents.last().map_or(0, |span| span.end),
+    }
+}
+
+/// Parses an expression. Symbols that don't belong are pointed out exactly;
+/// anything else only shows up once the whole input has been read, so it's
+/// reported at the end.
+pub fn parse(input: &str) -> Result<Exp, Error> {
+    parsed(input, false).map(|(exp, _)| exp)
+}
+
+/// Like [`parse`], but also says where in the input each part of the
+/// expression was written, so a front-end can point from any node of a
+/// rolled result back to its characters
+pub fn parse_mapped(input: &str) -> Result<(Exp, SourceMap), Error> {
+    parsed(input, true).map(|(exp, map)| (exp, map.unwrap_or_default()))
+}
+
+/// Parses an expression, along with its source map if `mapping`
+fn parsed(input: &str, mapping: bool) -> Result<(Exp, Option<SourceMap>), Error> {
+    let mut tokens = Tokenizer::new(input);
+    let mut exp_builder = ExpBuilder {
+        mapping,
+        ..ExpBuilder::default()
+    };
+    while let Some(token) = tokens.next() {
+        match token {
+            Ok(token) => {
+                let end = tokens.position();
+                let start = tokens.start().min(end);
+                exp_builder.push(token, Span { start, end });
+            }
+            Err(message) => {
+                return Err(Error::Syntax {
+                    message,
+                    position: tokens.position().saturating_sub(1),
+                })
+            }
+        }
         while exp_builder.reduce() {}
+        if let Some(error) = exp_builder.rejected.take() {
+            return Err(error);
+        }
+    }
+    exp_builder.build().map_err(|message| Error::Syntax {
+        message,
+        position: input.chars().count(),
+    })
+}
+
+/// Separates several expressions written with semicolons between them, e.g.
+/// `d20+9; 3d8+2d6`
+pub fn split(input: &str) -> Result<Vec<&str>, Error> {
+    let expressions: Vec<&str> = input
+        .split(';')
+        .filter(|expression| !expression.trim().is_empty())
+        .collect();
+    if expressions.is_empty() {
+        return Err(Error::Input(Message::NoExpression));
+    }
+    Ok(expressions)
+}
+
+/// Parses several expressions separated by semicolons
+pub fn parse_all(input: &str) -> Result<Vec<Exp>, Error> {
+    split(input)?.into_iter().map(parse).collect()
+}
+
+/// Splits the name off an expression written like `hit = d20 + 7`. Names
+/// start with a letter and go on with letters, digits, and underscores.
+pub fn named(piece: &str) -> (Option<&str>, &str) {
+    match piece.split_once('=') {
+        Some((name, expression)) if is_name(name.trim()) => (Some(name.trim()), expression),
+        _ => (None, piece),
     }
-    return exp_builder.build();
+}
+
+fn is_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Parses several expressions separated by semicolons, each under its name
+/// (like `hit = d20 + 7; dmg = 1d8 + 4`), or if it has none, under its
+/// position counting from 1
+pub fn parse_named(input: &str) -> Result<Vec<(String, Exp)>, Error> {
+    let mut parsed: Vec<(String, Exp)> = Vec::new();
+    for (i, piece) in split(input)?.into_iter().enumerate() {
+        let (name, expression) = named(piece);
+        let name = name.map_or_else(|| (i + 1).to_string(), str::to_string);
+        if parsed.iter().any(|(other, _)| *other == name) {
+            return Err(Error::Input(Message::DuplicateName(name)));
+        }
+        parsed.push((name, parse(expression)?));
+    }
+    Ok(parsed)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::parse;
-    use crate::eval::{vec_deque, Exp, Keep, Roll};
+    use super::{parse, parse_all, parse_mapped, parse_named, SourceMap, Span};
+    use crate::error::Error;
+    use crate::eval::{
+        vec_deque, Comparison, Condition, Exp, Explode, Explosion, Keep, Pick, Repeat, Roll,
+    };
+    use crate::messages::Message;
     use rand::rngs::ThreadRng;
     use std::collections::VecDeque;
 
@@ -144,6 +469,75 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn subtraction_without_spaces() -> Result<(), String> {
+        let mut rng = ThreadRng::default();
+        assert_eq!(4, parse("5-1")?.evaluate(&mut rng).value());
+        assert_eq!(-6, parse("-5 -1")?.evaluate(&mut rng).value());
+        assert_eq!(2, parse("(3)-1")?.evaluate(&mut rng).value());
+        assert_eq!(-3, parse("2 * -1 - 1")?.evaluate(&mut rng).value());
+        Ok(())
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(Some(4), parse("d20 x 3").unwrap_err().position());
+        assert_eq!(Some(5), parse("2d20kx1").unwrap_err().position());
+        assert_eq!(Some(4), parse("2d20k").unwrap_err().position());
+        assert_eq!(Some(5), parse("1 + +").unwrap_err().position());
+    }
+
+    /// The text each node of the source map covers, depth first
+    fn covered(input: &str) -> Vec<String> {
+        fn walk(input: &str, map: &SourceMap, out: &mut Vec<String>) {
+            let text = input.chars().skip(map.span.start);
+            out.push(text.take(map.span.end - map.span.start).collect());
+            for child in &map.children {
+                walk(input, child, out);
+            }
+        }
+        let (_, map) = parse_mapped(input).unwrap();
+        let mut out = Vec::new();
+        walk(input, &map, &mut out);
+        out
+    }
+
+    #[test]
+    fn source_spans() {
+        assert_eq!(vec!["1 + 2", "1", "2"], covered(" 1 + 2"));
+        assert_eq!(
+            vec![
+                "(1 + 2) * 3 + 4",
+                "(1 + 2) * 3",
+                "1 + 2",
+                "1",
+                "2",
+                "3",
+                "4"
+            ],
+            covered("(1 + 2) * 3 + 4")
+        );
+        // the sum is flattened, and so is its map
+        assert_eq!(vec!["0 + (1 + 2)", "0", "1", "2"], covered("0 + (1 + 2)"));
+        assert_eq!(
+            vec!["2d20kl1", "2", "20", "1", "d(d4)", "", "d4", "", "4"],
+            covered("2d20kl1 + d(d4)")[1..]
+        );
+    }
+
+    #[test]
+    fn long_sums() {
+        let input = vec!["d6"; 20_000].join(" + ");
+        let (exp, map) = parse_mapped(&input).unwrap();
+        assert_eq!(parse(&input).unwrap(), exp);
+        assert_eq!(20_000, map.children.len());
+        let last = Span {
+            start: input.len() - 2,
+            end: input.len(),
+        };
+        assert_eq!(last, map.children[19_999].span);
+    }
+
     #[test]
     fn negative_number() -> Result<(), String> {
         let parsed = parse("-432")?;
@@ -252,6 +646,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn parenthesized_subtraction() -> Result<(), String> {
+        let parsed = parse("6 - (8 - 10)")?;
+        assert_eq!(
+            Exp::sub(vec_deque![
+                Exp::Const(6),
+                Exp::sub(vec_deque![Exp::Const(8), Exp::Const(10)])
+            ]),
+            parsed
+        );
+        assert_eq!(8, parsed.evaluate(&mut ThreadRng::default()).value());
+        Ok(())
+    }
+
+    #[test]
+    fn subtraction_chains() -> Result<(), String> {
+        let mut rng = ThreadRng::default();
+        assert_eq!(5, parse("10 - 3 - 2")?.evaluate(&mut rng).value());
+        assert_eq!(9, parse("10 - (3 - 2)")?.evaluate(&mut rng).value());
+        assert_eq!(5, parse("(10 - 3) - 2")?.evaluate(&mut rng).value());
+        Ok(())
+    }
+
     #[test]
     fn all_math_operations() -> Result<(), String> {
         let parsed = parse("1 + 2 * (3 - 4) - 5")?;
@@ -310,6 +727,8 @@ mod tests {
                 dice: Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(4))),
                 sides: Exp::roll(Roll::simple(Exp::Const(3), Exp::Const(6))),
                 keep: Keep::All,
+                explode: None,
+                reroll: None,
             }),
             parsed
         );
@@ -358,6 +777,249 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn drop_dice() -> Result<(), String> {
+        let dropping = |keep| {
+            Exp::roll(Roll {
+                keep,
+                ..Roll::simple(Exp::Const(4), Exp::Const(6))
+            })
+        };
+        assert_eq!(dropping(Keep::DropLowest(Exp::Const(1))), parse("4d6dl1")?);
+        assert_eq!(dropping(Keep::DropHighest(Exp::Const(2))), parse("4d6dh2")?);
+        // a die can still follow a drop
+        let dropped = Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(2)));
+        assert_eq!(dropping(Keep::DropLowest(dropped)), parse("4d6dl(1d2)")?);
+        assert_eq!(vec!["4d6dl1", "4", "6", "1"], covered("4d6dl1")[..4]);
+        assert!(parse("4d6dl").is_err());
+        assert!(parse("4dl1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn keep_a_share() -> Result<(), String> {
+        let half = |keep| {
+            Exp::roll(Roll {
+                dice: Exp::Const(8),
+                sides: Exp::Const(6),
+                keep,
+                explode: None,
+                reroll: None,
+            })
+        };
+        assert_eq!(half(Keep::HighestShare(50)), parse("8d6kh50%")?);
+        assert_eq!(half(Keep::HighestShare(50)), parse("8d6khhalf")?);
+        assert_eq!(half(Keep::LowestShare(50)), parse("8d6kl half")?);
+        assert!(parse("8d6kh50% + 2").is_ok());
+        assert!(parse("8d6khalf").is_err());
+        assert!(parse("50% + 2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compounding_dice() -> Result<(), String> {
+        let compounding = |roll| {
+            Exp::roll(Roll {
+                explode: Some(Explosion::new(Explode::Compound)),
+                ..roll
+            })
+        };
+        assert_eq!(
+            compounding(Roll::simple(Exp::Const(5), Exp::Const(6))),
+            parse("5d6!!")?
+        );
+        assert_eq!(
+            compounding(Roll::keep_highest(
+                Exp::Const(4),
+                Exp::Const(6),
+                Exp::Const(3)
+            )),
+            parse("4d6!!k3")?
+        );
+        // it's subtraction that follows, not a negative number
+        assert_eq!(
+            Exp::sub(vec_deque![
+                compounding(Roll::simple(Exp::Const(1), Exp::Const(6))),
+                Exp::Const(2)
+            ]),
+            parse("d6!!-2")?
+        );
+        assert!(parse("5!!").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn explosion_thresholds() -> Result<(), String> {
+        let exploding = |explode, on: Option<(Comparison, Exp)>, roll| {
+            Exp::roll(Roll {
+                explode: Some(Explosion {
+                    explode,
+                    on: on.map(|(comparison, against)| Condition {
+                        comparison,
+                        against,
+                    }),
+                }),
+                ..roll
+            })
+        };
+        let ten_d10 = Roll::simple(Exp::Const(10), Exp::Const(10));
+        assert_eq!(
+            exploding(Explode::Extra, None, ten_d10.clone()),
+            parse("10d10!")?
+        );
+        assert_eq!(
+            exploding(
+                Explode::Extra,
+                Some((Comparison::AtLeast, Exp::Const(8))),
+                ten_d10.clone()
+            ),
+            parse("10d10!>=8")?
+        );
+        assert_eq!(
+            exploding(
+                Explode::Compound,
+                Some((Comparison::Equal, Exp::Const(1))),
+                ten_d10
+            ),
+            parse("10d10!!=1")?
+        );
+        // the threshold can be anything in parentheses, and the keep comes
+        // after it
+        let threshold = Exp::add(vec_deque![
+            Exp::roll(Roll::simple(Exp::Const(1), Exp::Const(3))),
+            Exp::Const(3)
+        ]);
+        assert_eq!(
+            exploding(
+                Explode::Extra,
+                Some((Comparison::Greater, threshold)),
+                Roll::keep_highest(Exp::Const(4), Exp::Const(6), Exp::Const(3))
+            ),
+            parse("4d6!>(1d3+3)k3")?
+        );
+        assert_eq!(
+            vec!["4d6!<3", "4", "6", "", "3"],
+            covered("4d6!<3 + 1")[1..6]
+        );
+        assert!(parse("d6!>").is_err());
+        assert!(parse("d6>3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn rerolls() -> Result<(), String> {
+        let rerolled = |comparison, against, roll| {
+            Exp::roll(Roll {
+                reroll: Some(Condition {
+                    comparison,
+                    against: Exp::Const(against),
+                }),
+                ..roll
+            })
+        };
+        let four_d6 = Roll::simple(Exp::Const(4), Exp::Const(6));
+        assert_eq!(
+            rerolled(Comparison::Equal, 1, four_d6.clone()),
+            parse("4d6r1")?
+        );
+        assert_eq!(rerolled(Comparison::Less, 3, four_d6), parse("4d6r<3")?);
+        // the reroll comes before any explosion, and the keep after both
+        let roll = Roll {
+            explode: Some(Explosion::new(Explode::Extra)),
+            ..Roll::keep_highest(Exp::Const(4), Exp::Const(6), Exp::Const(3))
+        };
+        assert_eq!(rerolled(Comparison::Equal, 1, roll), parse("4d6r1!k3")?);
+        assert_eq!(
+            vec!["4d6r1", "4", "6", "", "", "1"],
+            covered("4d6r1 + 1")[1..7]
+        );
+        assert!(parse("d6r").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn best_of_repetitions() -> Result<(), String> {
+        let repeat = |pick, exp| {
+            Exp::Repeat(Repeat {
+                exp: Box::new(exp),
+                times: 3,
+                pick,
+            })
+        };
+        let stat = Exp::roll(Roll::keep_highest(
+            Exp::Const(4),
+            Exp::Const(6),
+            Exp::Const(3),
+        ));
+        assert_eq!(
+            repeat(Pick::Best(1), stat.clone()),
+            parse("best 1 of 3x(4d6k3)")?
+        );
+        assert_eq!(
+            repeat(Pick::Worst(2), stat.clone()),
+            parse("worst 2 of 3 x 4d6k3")?
+        );
+        // it binds tighter than arithmetic
+        let sum = Exp::add(vec_deque![repeat(Pick::Best(1), stat), Exp::Const(2)]);
+        assert_eq!(sum, parse("best 1 of 3x 4d6k3 + 2")?);
+        let (_, map) = parse_mapped("best 1 of 3x(d6)")?;
+        assert_eq!(1, map.children.len());
+        assert_eq!(
+            (13, 15),
+            (map.children[0].span.start, map.children[0].span.end)
+        );
+        assert!(parse("best 1 of 0x(d6)").is_err());
+        assert!(matches!(
+            parse("best 1 of 100000000x(d6)"),
+            Err(Error::Limit(Message::TooManyRepetitions(_)))
+        ));
+        assert!(parse("best d4 of 3x(d6)").is_err());
+        assert!(parse("bets 1 of 3x(d6)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn numbers_too_big() -> Result<(), String> {
+        let digits = "9".repeat(40);
+        assert!(matches!(
+            parse(&digits),
+            Err(Error::Syntax { message: Message::NumberTooBig(number), .. }) if number == digits
+        ));
+        // leading zeros don't count against it
+        let padded = format!("{}7", "0".repeat(40));
+        assert_eq!(
+            7,
+            parse(&padded)?.evaluate(&mut ThreadRng::default()).value()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn multiple_expressions() -> Result<(), String> {
+        let parsed = parse_all("d20 + 9; 3d8;")?;
+        assert_eq!(2, parsed.len());
+        assert_eq!(
+            Exp::roll(Roll::simple(Exp::Const(3), Exp::Const(8))),
+            parsed[1]
+        );
+        assert!(parse_all(" ; ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn named_expressions() -> Result<(), String> {
+        let parsed = parse_named("hit = d20 + 7; 1d8 + 4; crit_2=2")?;
+        let names = parsed
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(vec!["hit", "2", "crit_2"], names);
+        assert_eq!(Exp::Const(2), parsed[2].1);
+        assert!(parse_named("a = 1; a = 2").is_err());
+        assert!(parse_named("2 = 1").is_err());
+        Ok(())
+    }
+
     #[test]
     fn oh_god_why() -> Result<(), String> {
         let parsed = parse("1 + 2 + 3d(4d10 + 2)kl1 * 5 - 6 - 7")?;
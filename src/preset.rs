@@ -0,0 +1,143 @@
+//! Presets for the systems people play most. Each one is only a bundle of
+//! the other options: how expressions are written, which die gets rolled when
+//! only a modifier is given, and what counts as beating a `--dc`.
+
+use recursive_dice_roller::dialect::Dialect;
+use recursive_dice_roller::eval::Int;
+
+pub const SYSTEMS: &[&str] = &["5e", "coc", "sr", "fate"];
+
+/// Which side of the target number is a success
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Success {
+    /// Meet or beat it
+    AtLeast,
+    /// Roll under it (or on it)
+    AtMost,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Preset {
+    pub dialect: Dialect,
+    /// What gets rolled when the expression is only a modifier, like `+5`,
+    /// or is left off entirely
+    pub die: Option<&'static str>,
+    pub success: Success,
+    /// The lowest natural d20 that's a critical hit, for systems that roll
+    /// attacks on one
+    pub crit_on: Option<i32>,
+    pub succeeded: &'static str,
+    pub failed: &'static str,
+    /// What the number to beat is called
+    pub target: &'static str,
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Preset {
+            dialect: Dialect::Rdr,
+            die: None,
+            success: Success::AtLeast,
+            crit_on: None,
+            succeeded: "SUCCESS",
+            failed: "FAILURE",
+            target: "DC",
+        }
+    }
+}
+
+impl Preset {
+    pub fn named(name: &str) -> Result<Preset, String> {
+        let defaults = Preset::default();
+        match name {
+            "5e" => Ok(Preset {
+                dialect: Dialect::Roll20,
+                die: Some("d20"),
+                crit_on: Some(20),
+                ..defaults
+            }),
+            "coc" => Ok(Preset {
+                dialect: Dialect::Foundry,
+                die: Some("d100"),
+                success: Success::AtMost,
+                target: "skill",
+                ..defaults
+            }),
+            // a pool's hits can't be counted, but its sum can still be rolled
+            "sr" => Ok(Preset {
+                die: Some("d6"),
+                succeeded: "HIT",
+                failed: "MISS",
+                target: "threshold",
+                ..defaults
+            }),
+            // four Fate dice, each -1, 0, or +1
+            "fate" => Ok(Preset {
+                die: Some("4d3 - 8"),
+                succeeded: "SUCCEED",
+                failed: "FAIL",
+                target: "difficulty",
+                ..defaults
+            }),
+            other => Err(format!(
+                "'{other}' is not a system we know; try one of {}",
+                SYSTEMS.join(", ")
+            )),
+        }
+    }
+
+    /// Puts the system's die in front of an expression that's nothing but a
+    /// modifier
+    pub fn complete(&self, expression: &str) -> String {
+        let Some(die) = self.die else {
+            return expression.to_string();
+        };
+        let trimmed = expression.trim();
+        match trimmed.chars().next() {
+            None => die.to_string(),
+            Some('+' | '-') => format!("{die} {trimmed}"),
+            Some(_) => expression.to_string(),
+        }
+    }
+
+    pub fn passes(&self, total: Int, target: Int) -> bool {
+        match self.success {
+            Success::AtLeast => total >= target,
+            Success::AtMost => total <= target,
+        }
+    }
+
+    /// The line printed under a roll made against a target, e.g.
+    /// `SUCCESS (DC 15)`
+    pub fn verdict(&self, total: Int, target: Int) -> String {
+        let outcome = match self.passes(total, target) {
+            true => self.succeeded,
+            false => self.failed,
+        };
+        format!("{outcome} ({} {target})", self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::preset::*;
+
+    #[test]
+    fn modifiers_get_the_system_die() {
+        let five_e = Preset::named("5e").unwrap();
+        assert_eq!("d20 +5", five_e.complete(" +5"));
+        assert_eq!("d20", five_e.complete(""));
+        assert_eq!("2d6 + 3", five_e.complete("2d6 + 3"));
+        assert_eq!("+5", Preset::default().complete("+5"));
+        assert!(Preset::named("gurps").is_err());
+    }
+
+    #[test]
+    fn roll_under() {
+        let coc = Preset::named("coc").unwrap();
+        assert!(coc.passes(45, 45));
+        assert!(!coc.passes(46, 45));
+        assert_eq!("FAILURE (skill 45)", coc.verdict(46, 45));
+        assert_eq!("SUCCESS (DC 15)", Preset::default().verdict(15, 15));
+    }
+}
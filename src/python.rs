@@ -0,0 +1,198 @@
+//! A Python module, built with the `python` feature, for poking at dice
+//! expressions from a notebook:
+//!
+//! ```python
+//! import rdr
+//!
+//! rdr.evaluate("4d6k3").total
+//! rdr.simulate("2d20k1 + 5", 100_000).counts
+//! ```
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::collections::BTreeMap;
+
+use crate::dialect::{self, Dialect};
+use crate::document::Document;
+use crate::error::Error;
+use crate::eval::{Exp, Int, Limits};
+use crate::simulate::Summary;
+
+impl From<Error> for PyErr {
+    fn from(error: Error) -> Self {
+        PyValueError::new_err(error.to_string())
+    }
+}
+
+/// A parsed expression, which can be rolled any number of times. Expressions
+/// share parts of their trees internally, so they stay on the thread that
+/// made them.
+#[pyclass(unsendable, module = "rdr")]
+pub struct Expression {
+    exp: Exp,
+}
+
+#[pymethods]
+impl Expression {
+    fn evaluate(&self) -> PyResult<Roll> {
+        roll(&self.exp)
+    }
+
+    #[pyo3(signature = (trials=10_000))]
+    fn simulate(&self, trials: usize) -> PyResult<Simulation> {
+        simulation(&self.exp, trials)
+    }
+
+    fn __str__(&self) -> String {
+        dialect::write(&self.exp, Dialect::Rdr).expect("our own dialect can write anything")
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Expression('{}')", self.__str__())
+    }
+}
+
+/// One roll of an expression, in the same shape as the JSON output
+#[pyclass(module = "rdr")]
+pub struct Roll {
+    #[pyo3(get)]
+    expression: String,
+    #[pyo3(get)]
+    total: Int,
+    json: String,
+}
+
+#[pymethods]
+impl Roll {
+    /// Every node of the tree as nested dicts, with `kind` telling them apart
+    #[getter]
+    fn breakdown(&self, py: Python<'_>) -> PyResult<Py<PyAny>> {
+        let document: serde_json::Value =
+            serde_json::from_str(&self.json).expect("the document was serialized by us");
+        to_python(py, &document["breakdown"])
+    }
+
+    fn to_json(&self) -> String {
+        self.json.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Roll('{}', total={})", self.expression, self.total)
+    }
+}
+
+/// The results of rolling an expression many times
+#[pyclass(module = "rdr")]
+pub struct Simulation {
+    #[pyo3(get)]
+    samples: usize,
+    #[pyo3(get)]
+    mean: f64,
+    #[pyo3(get)]
+    std_dev: f64,
+    #[pyo3(get)]
+    median: Int,
+    /// Keyed by percentile
+    #[pyo3(get)]
+    percentiles: BTreeMap<u32, Int>,
+    /// How many times each result came up
+    #[pyo3(get)]
+    counts: BTreeMap<Int, usize>,
+}
+
+#[pymethods]
+impl Simulation {
+    fn __repr__(&self) -> String {
+        format!(
+            "Simulation(samples={}, mean={:.2}, std_dev={:.2})",
+            self.samples, self.mean, self.std_dev
+        )
+    }
+}
+
+#[pyfunction]
+fn parse(input: &str) -> PyResult<Expression> {
+    Ok(Expression {
+        exp: crate::parse::parse(input)?,
+    })
+}
+
+#[pyfunction]
+fn evaluate(input: &str) -> PyResult<Roll> {
+    roll(&crate::parse::parse(input)?)
+}
+
+#[pyfunction]
+#[pyo3(signature = (input, trials=10_000))]
+fn simulate(input: &str, trials: usize) -> PyResult<Simulation> {
+    simulation(&crate::parse::parse(input)?, trials)
+}
+
+fn roll(exp: &Exp) -> PyResult<Roll> {
+    let evaluated = exp.evaluate_within(&mut rand::thread_rng(), &Limits::default())?;
+    let document = Document::new(&evaluated);
+    Ok(Roll {
+        json: serde_json::to_string(&document).map_err(Error::from)?,
+        expression: document.expression,
+        total: document.total,
+    })
+}
+
+fn simulation(exp: &Exp, trials: usize) -> PyResult<Simulation> {
+    let Summary {
+        samples,
+        mean,
+        std_dev,
+        median,
+        percentiles,
+        counts,
+        ..
+    } = crate::simulate::simulate(exp, trials, &mut rand::thread_rng(), &Limits::default())?;
+    Ok(Simulation {
+        samples,
+        mean,
+        std_dev,
+        median,
+        percentiles: percentiles.into_iter().collect(),
+        counts,
+    })
+}
+
+fn to_python(py: Python<'_>, value: &serde_json::Value) -> PyResult<Py<PyAny>> {
+    use serde_json::Value;
+    Ok(match value {
+        Value::Null => py.None(),
+        Value::Bool(b) => b.into_pyobject(py)?.to_owned().into_any().unbind(),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => n.into_pyobject(py)?.into_any().unbind(),
+            None => n.as_f64().into_pyobject(py)?.into_any().unbind(),
+        },
+        Value::String(s) => s.into_pyobject(py)?.into_any().unbind(),
+        Value::Array(values) => {
+            let list = PyList::empty(py);
+            for value in values {
+                list.append(to_python(py, value)?)?;
+            }
+            list.into_any().unbind()
+        }
+        Value::Object(fields) => {
+            let dict = PyDict::new(py);
+            for (key, value) in fields {
+                dict.set_item(key, to_python(py, value)?)?;
+            }
+            dict.into_any().unbind()
+        }
+    })
+}
+
+#[pymodule]
+fn rdr(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Expression>()?;
+    m.add_class::<Roll>()?;
+    m.add_class::<Simulation>()?;
+    m.add_function(wrap_pyfunction!(parse, m)?)?;
+    m.add_function(wrap_pyfunction!(evaluate, m)?)?;
+    m.add_function(wrap_pyfunction!(simulate, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Rationing for the rollers that many people share, so that nobody can tie
+//! one up by sending `999999d999999` over and over. Every client has an
+//! allowance of dice that refills steadily, and each roll costs the most dice
+//! it could possibly roll (plus one, so that even constants aren't free).
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::eval::{Exp, Limits};
+use crate::stats;
+
+/// Past this many clients, the ones with a full allowance are forgotten
+const MAX_CLIENTS: usize = 10_000;
+
+pub struct Quota {
+    /// The most dice anyone can have saved up
+    capacity: u64,
+    /// How many dice come back every second
+    per_second: u64,
+    allowances: HashMap<String, Allowance>,
+}
+
+struct Allowance {
+    dice: f64,
+    updated: Instant,
+}
+
+impl Quota {
+    pub fn new(capacity: u64, per_second: u64) -> Self {
+        Quota {
+            capacity,
+            per_second,
+            allowances: HashMap::new(),
+        }
+    }
+
+    /// Takes the cost of the expression, rolled within the limits, out of
+    /// the client's allowance, or explains why it can't be rolled yet
+    pub fn charge(&mut self, client: &str, exp: &Exp, limits: &Limits) -> Result<(), String> {
+        let dice = stats::max_dice_within(exp, limits);
+        self.charge_at(client, dice, Instant::now())
+    }
+
+    fn charge_at(&mut self, client: &str, dice: u64, now: Instant) -> Result<(), String> {
+        let cost = dice.saturating_add(1);
+        if cost > self.capacity {
+            return Err(format!(
+                "That could roll {dice} dice, which is more than the {} allowed at once",
+                self.capacity
+            ));
+        }
+        if self.allowances.len() >= MAX_CLIENTS {
+            let (capacity, per_second) = (self.capacity, self.per_second);
+            self.allowances.retain(|_, allowance| {
+                allowance.refilled(capacity, per_second, now) < capacity as f64
+            });
+        }
+        let allowance = self
+            .allowances
+            .entry(client.to_string())
+            .or_insert(Allowance {
+                dice: self.capacity as f64,
+                updated: now,
+            });
+        allowance.dice = allowance.refilled(self.capacity, self.per_second, now);
+        allowance.updated = now;
+        if allowance.dice < cost as f64 {
+            let wait = (cost as f64 - allowance.dice) / self.per_second as f64;
+            return Err(format!(
+                "Slow down! That roll can be made in {:.1}s",
+                wait.max(0.1)
+            ));
+        }
+        allowance.dice -= cost as f64;
+        Ok(())
+    }
+}
+
+impl Allowance {
+    /// How many dice the allowance will have had by `now`
+    fn refilled(&self, capacity: u64, per_second: u64, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        (self.dice + elapsed * per_second as f64).min(capacity as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse;
+    use crate::quota::*;
+    use std::time::Duration;
+
+    #[test]
+    fn allowances_refill() {
+        let mut quota = Quota::new(100, 10);
+        let start = Instant::now();
+        assert!(quota.charge_at("alice", 59, start).is_ok());
+        assert!(quota.charge_at("alice", 59, start).is_err());
+        // everyone gets their own allowance
+        assert!(quota.charge_at("bob", 59, start).is_ok());
+        assert!(quota
+            .charge_at("alice", 59, start + Duration::from_secs(2))
+            .is_ok());
+    }
+
+    #[test]
+    fn expensive_rolls() {
+        let mut quota = Quota::new(100, 10);
+        let limits = Limits::default();
+        let error = quota
+            .charge("alice", &parse("999999d999999").unwrap(), &limits)
+            .unwrap_err();
+        assert!(error.contains("999999 dice"), "{error}");
+        assert!(quota
+            .charge("alice", &parse("4d6k3").unwrap(), &limits)
+            .is_ok());
+    }
+
+    #[test]
+    fn exploding_dice_cost_their_explosions() {
+        let mut quota = Quota::new(100, 10);
+        let exp = parse("3d6!").unwrap();
+        // with the usual limit, each die could explode a hundred times
+        assert!(quota.charge("alice", &exp, &Limits::default()).is_err());
+        let limits = Limits {
+            max_explosions: 20,
+            ..Limits::default()
+        };
+        assert!(quota.charge("alice", &exp, &limits).is_ok());
+    }
+}
@@ -1,148 +1,930 @@
+//! Draws an evaluated expression as a tree, the way the command line shows
+//! it. Other formats live in [`formats`](crate::formats).
+
 use itertools::Itertools;
-use rand::rngs::ThreadRng;
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use std::io::Write;
 
-use crate::eval::{KeptRule, Operation, Value};
+use crate::error::Error;
+use crate::eval::{Comparison, Int, KeptRule, Operation, Pick, Rolled, Value};
+use crate::stats;
+
+/// Knobs for controlling what ends up in the rendered tree
+#[derive(Debug, Default, Clone)]
+pub struct RenderOptions {
+    /// Annotate each result with the expected value of the expression that
+    /// produced it, e.g. `[3, 4, 4] => 11 (avg 10.5)`
+    pub expected_values: bool,
+    /// Draw six-sided dice as die faces (⚀ through ⚅) and every other die as
+    /// 🎲N, which reads nicely in chat applications
+    pub emoji_faces: bool,
+    /// Dice are normally listed in the order they were rolled. If a seed is
+    /// provided, they are shuffled for display instead; the same seed always
+    /// produces the same presentation.
+    pub shuffle_seed: Option<u64>,
+    /// The maximum size of the rendered output, in bytes. Anything past the
+    /// limit is replaced with a note saying how many lines were left out.
+    pub max_output: Option<usize>,
+    /// Finish with a line showing the expression collapsing one layer of
+    /// rolls at a time, e.g. `(3d4)d8 → (7)d8 → 31`
+    pub resolution: bool,
+    /// Show the running total after each term of a sum (or difference, or
+    /// product) so the arithmetic is easier to follow
+    pub subtotals: bool,
+    /// How much the renderer should say about each result
+    pub verbosity: Verbosity,
+    /// Stick to plain ASCII, for consoles that would garble the box-drawing
+    /// characters
+    pub ascii: bool,
+    /// Follow each die that landed on its highest face with ▲, and on its
+    /// lowest with ▼, so crits and fumbles stand out without any color
+    pub marks: bool,
+    /// The seed the dice were rolled with, which the JSON and Discord formats
+    /// pass along so the roll can be made again with `--seed`
+    pub seed: Option<u64>,
+}
 
-#[derive(Debug, Default)]
-struct RenderNode {
-    expression: String,
-    output: Option<String>,
-    children: Vec<RenderNode>,
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only the total
+    Quiet,
+    #[default]
+    Normal,
+    /// Spell out how each roll's dice were counted
+    Verbose,
+    /// Everything, including diagnostics about the run itself
+    Debug,
 }
 
 pub const VERTICAL_PIPE: char = '\u{2502}';
 pub const HORIZONTAL_PIPE: char = '\u{2500}';
 pub const RIGHT_FORK: char = '\u{251C}';
+pub const CRIT_MARK: char = '\u{25B2}';
+pub const FUMBLE_MARK: char = '\u{25BC}';
 
-impl RenderNode {
-    fn create(value: &Value, parent_op: Option<&Operation>, first: bool) -> Option<Self> {
-        match value {
-            Value::Const(c) => match parent_op {
-                Some(op) => {
-                    let operator = match op {
-                        Operation::Add => '+',
-                        Operation::Sub => '-',
-                        Operation::Mul => '\u{00D7}',
-                    };
-                    Some(RenderNode {
-                        expression: if first {
-                            format!("({c})")
-                        } else {
-                            format!("({operator}{c})")
-                        },
-                        output: None,
-                        children: Vec::new(),
-                    })
+/// Draws a value and everything beneath it, a line at a time, as it goes.
+/// `parent_op` and `first` say how a constant should be labelled inside an
+/// operation, and `subtotal` is the running total to show alongside the
+/// result. Nothing is drawn for a lone constant outside of any operation.
+#[allow(clippy::too_many_arguments)]
+fn draw(
+    lines: &mut Lines,
+    value: &Value,
+    parent_op: Option<&Operation>,
+    first: bool,
+    subtotal: Option<Int>,
+    depth: usize,
+    options: &RenderOptions,
+    shuffler: &mut Option<StdRng>,
+) -> Result<(), std::io::Error> {
+    let with_subtotal = |output: String| match subtotal {
+        Some(running) => format!("{output} (subtotal {running})"),
+        None => output,
+    };
+    match value {
+        Value::Const(c) => {
+            let Some(op) = parent_op else {
+                return Ok(());
+            };
+            let operator = match op {
+                Operation::Add => '+',
+                Operation::Sub => '-',
+                Operation::Mul => '\u{00D7}',
+            };
+            let expression = if first {
+                format!("({c})")
+            } else {
+                format!("({operator}{c})")
+            };
+            heading(lines, &expression, depth)?;
+            let output = subtotal.map(|running| format!("subtotal {running}"));
+            result(lines, output.as_deref(), depth, true)
+        }
+        Value::Rolled(rolled) => {
+            heading(lines, &format!("Rolling {value}"), depth)?;
+            // constants that say how many dice (or sides) there are go
+            // without saying
+            let children = rolled
+                .parts()
+                .into_iter()
+                .filter(|v| !matches!(v, Value::Const(_)))
+                .collect_vec();
+            for child in &children {
+                draw(
+                    lines,
+                    child,
+                    None,
+                    false,
+                    None,
+                    depth + 1,
+                    options,
+                    shuffler,
+                )?;
+            }
+
+            let output = annotate(rolled.val(), value, options);
+            let sides = rolled.sides();
+            let face = |(die, id): (&i32, Option<&usize>)| {
+                let face = die_face(*die, sides, options);
+                let face = match mark(*die, sides, options) {
+                    Some(mark) => format!("{face}{mark}"),
+                    None => face,
+                };
+                let face = exploded_face(rolled, id, face, options);
+                rerolled_face(rolled, id, face, options, false)
+            };
+            let (highest_ids, lowest_ids) = rolled.kept.ids_by_side();
+            let mut highest = with_ids(&rolled.kept.highest, highest_ids).collect_vec();
+            let mut lowest = with_ids(&rolled.kept.lowest, lowest_ids).collect_vec();
+            if let Some(rng) = shuffler {
+                highest.shuffle(rng);
+                lowest.shuffle(rng);
+            }
+            let highest = highest.into_iter().map(face).join(", ");
+            let lowest = lowest.into_iter().map(face).join(", ");
+            let output = match options.verbosity >= Verbosity::Verbose {
+                true => format!("{output} ({})", roll_detail(rolled)),
+                false => output,
+            };
+            let output = match &rolled.exploded {
+                Some(exploded) if exploded.capped => {
+                    format!("{output}, stopped at the explosion limit")
+                }
+                _ => output,
+            };
+            let output = match &rolled.rerolled {
+                Some(rerolled) if rerolled.capped => {
+                    format!("{output}, stopped at the reroll limit")
                 }
-                None => None,
-            },
+                _ => output,
+            };
+            let output = match (&rolled.kept.summary, &rolled.kept.keep) {
+                (Some(summary), _) => format!("[{summary}] => {output}"),
+                (None, KeptRule::All) => format!("[{highest}] => {output}"),
+                (None, _) => format!("[{highest} | {lowest}] => {output}"),
+            };
+            result(
+                lines,
+                Some(&with_subtotal(output)),
+                depth,
+                children.is_empty(),
+            )
+        }
+        Value::Op { op, values } => {
+            heading(lines, &format!("Evaluating {value}"), depth)?;
+            let mut running = 0;
+            for (i, v) in values.iter().enumerate() {
+                running = match i {
+                    0 => v.value(),
+                    _ => op.saturating(running, v.value()),
+                };
+                let subtotal = (options.subtotals && i > 0).then_some(running);
+                draw(
+                    lines,
+                    v,
+                    Some(op),
+                    i == 0,
+                    subtotal,
+                    depth + 1,
+                    options,
+                    shuffler,
+                )?;
+            }
+            let output = with_subtotal(annotate(value.value(), value, options));
+            result(lines, Some(&output), depth, values.is_empty())
+        }
+        Value::Repeated { pick, values } => {
+            heading(lines, &format!("Repeating {value}"), depth)?;
+            for v in values {
+                draw(lines, v, None, false, None, depth + 1, options, shuffler)?;
+            }
+            let (kept, dropped) = totals(pick, values);
+            let output = annotate(value.value(), value, options);
+            let output = match dropped.is_empty() {
+                true => format!("[{}] => {output}", kept.iter().join(", ")),
+                false => format!(
+                    "[{} | {}] => {output}",
+                    kept.iter().join(", "),
+                    dropped.iter().join(", ")
+                ),
+            };
+            result(
+                lines,
+                Some(&with_subtotal(output)),
+                depth,
+                values.is_empty(),
+            )
+        }
+    }
+}
+
+/// For every list of dice (or of totals) the tree draws, in the order it
+/// draws them, whether the ones that don't count come before the `|`. A roll
+/// always lists its highest dice first, so that's when it keeps the lowest.
+pub fn dropped_first(value: &Value) -> Vec<bool> {
+    fn walk(value: &Value, out: &mut Vec<bool>) {
+        match value {
+            Value::Const(_) => {}
             Value::Rolled(rolled) => {
-                let components: [&Value; 3] = [
-                    rolled.dice.as_ref(),
-                    rolled.sides.as_ref(),
-                    &rolled.kept.retained,
-                ];
-                let children: Vec<RenderNode> = components
-                    .into_iter()
-                    .enumerate()
-                    .filter_map(|(i, v)| RenderNode::create(v, None, i == 0))
-                    .collect();
-                let output = rolled.val();
-                match &rolled.kept.keep {
-                    KeptRule::All => {
-                        let mut shuffled = rolled.kept.highest.clone();
-                        shuffled.shuffle(&mut ThreadRng::default());
-                        Some(RenderNode {
-                            expression: format!("Rolling {value}"),
-                            output: Some(format!("{:?} => {output}", shuffled)),
-                            children,
-                        })
-                    }
-                    _ => {
-                        let mut highest = rolled.kept.highest.iter().collect_vec();
-                        let mut lowest = rolled.kept.lowest.iter().collect_vec();
-                        let mut rng = ThreadRng::default();
-                        highest.shuffle(&mut rng);
-                        lowest.shuffle(&mut rng);
-                        let highest = highest.iter().join(", ");
-                        let lowest = lowest.iter().join(", ");
-                        Some(RenderNode {
-                            expression: format!("Rolling {value}"),
-                            output: Some(format!("[{highest} | {lowest}] => {output}",)),
-                            children,
-                        })
-                    }
+                for part in rolled.parts() {
+                    walk(part, out);
                 }
+                let lowest = rolled.kept.keep.keeps_lowest();
+                out.push(lowest && rolled.kept.summary.is_none());
             }
-            Value::Op { op, values, .. } => {
-                let children = values
-                    .iter()
-                    .enumerate()
-                    .filter_map(|(i, v)| RenderNode::create(v, Some(op), i == 0))
-                    .collect();
-                Some(RenderNode {
-                    expression: format!("Evaluating {value}"),
-                    output: Some(format!("{}", value.value())),
-                    children,
-                })
+            Value::Op { values, .. } => values.iter().for_each(|v| walk(v, out)),
+            Value::Repeated { values, .. } => {
+                values.iter().for_each(|v| walk(v, out));
+                out.push(false);
             }
         }
     }
+    let mut out = Vec::new();
+    walk(value, &mut out);
+    out
 }
 
-pub fn no_color(value: &Value) -> Result<String, std::io::Error> {
-    let render: Option<RenderNode> = RenderNode::create(value, None, true);
-    let mut buf = Vec::new();
-    match render {
-        Some(render) => draw(&mut buf, &render, 0)?,
-        None => writeln!(&mut buf, "{}", value.value())?,
+/// The totals of a repeated expression that count, and then the ones that
+/// were dropped, each in the order they were rolled
+pub(crate) fn totals(pick: &Pick, values: &[Value]) -> (Vec<Int>, Vec<Int>) {
+    let (kept, dropped): (Vec<_>, Vec<_>) = values
+        .iter()
+        .zip(pick.counted(values))
+        .partition(|(_, counted)| *counted);
+    let totals = |values: Vec<(&Value, bool)>| values.iter().map(|(v, _)| v.value()).collect();
+    (totals(kept), totals(dropped))
+}
+
+/// The first line of a node, forking off of its parent's branch
+fn heading(lines: &mut Lines, expression: &str, depth: usize) -> Result<(), std::io::Error> {
+    match depth {
+        0 => lines.line(expression),
+        _ => lines.line(&format!(
+            "{}{RIGHT_FORK}{HORIZONTAL_PIPE}{HORIZONTAL_PIPE} {expression}",
+            indent(depth)
+        )),
     }
-    let output = String::from_utf8(buf).unwrap();
-    Ok(output)
 }
 
-fn draw(buf: &mut Vec<u8>, node: &RenderNode, depth: i32) -> Result<(), std::io::Error> {
-    let indent: String = format!("{VERTICAL_PIPE}   ")
+/// A node's result, drawn after its children. Leaves (and nodes with a
+/// result deeper in the tree) are followed by a blank line to space them out.
+fn result(
+    lines: &mut Lines,
+    output: Option<&str>,
+    depth: usize,
+    leaf: bool,
+) -> Result<(), std::io::Error> {
+    let indent = indent(depth);
+    match (depth, output) {
+        (0, Some(output)) if leaf => lines.line(&format!("{indent}{output}"))?,
+        (0, Some(output)) => return lines.line(output),
+        (0, None) if !leaf => return Ok(()),
+        (_, Some(output)) => lines.line(&format!("{indent}{VERTICAL_PIPE}   {output}"))?,
+        (_, None) if !leaf => return Ok(()),
+        _ => {}
+    }
+    match depth {
+        0 => lines.line(&indent),
+        _ => lines.line(&format!("{indent}{VERTICAL_PIPE}")),
+    }
+}
+
+/// The pipes that run down past every ancestor of a node at this depth
+fn indent(depth: usize) -> String {
+    format!("{VERTICAL_PIPE}   ")
         .chars()
         .cycle()
-        .take((depth - 1).max(0) as usize * 4)
-        .collect();
-    if depth == 0 {
-        writeln!(buf, "{}", node.expression)?;
-    } else {
-        writeln!(
-            buf,
-            "{indent}{RIGHT_FORK}{HORIZONTAL_PIPE}{HORIZONTAL_PIPE} {}",
-            node.expression
-        )?;
-    }
-    for child in &node.children {
-        draw(buf, child, depth + 1)?;
-    }
-    if node.children.is_empty() {
-        if depth == 0 {
-            if let Some(output) = &node.output {
-                writeln!(buf, "{indent}{output}")?;
+        .take(depth.saturating_sub(1) * 4)
+        .collect()
+}
+
+/// Writes output a line at a time. Past `max` bytes, the line that straddles
+/// the limit is cut short, and every line after it is only counted, so the
+/// memory used stays the same however much there would have been to show.
+struct Lines<'a> {
+    out: &'a mut dyn std::io::Write,
+    max: Option<usize>,
+    ascii: bool,
+    written: usize,
+    /// How many lines have been left out, once the limit has been reached
+    omitted: Option<usize>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(out: &'a mut dyn std::io::Write, max: Option<usize>, ascii: bool) -> Self {
+        Lines {
+            out,
+            max,
+            ascii,
+            written: 0,
+            omitted: None,
+        }
+    }
+
+    fn line(&mut self, line: &str) -> Result<(), std::io::Error> {
+        if let Some(omitted) = self.omitted.as_mut() {
+            *omitted += 1;
+            return Ok(());
+        }
+        let ascii;
+        let line = match self.ascii {
+            true => {
+                ascii = asciify(line);
+                ascii.as_str()
             }
-            writeln!(buf, "{indent}")?;
-        } else {
-            if let Some(output) = &node.output {
-                writeln!(buf, "{indent}{VERTICAL_PIPE}   {output}")?;
+            false => line,
+        };
+        let max = self.max.unwrap_or(usize::MAX);
+        if self.written.saturating_add(line.len()) < max {
+            self.written += line.len() + 1;
+            return writeln!(self.out, "{line}");
+        }
+        let remaining = max - self.written;
+        if remaining > 0 {
+            let cut = (0..=remaining)
+                .rev()
+                .find(|&i| line.is_char_boundary(i))
+                .unwrap_or(0);
+            writeln!(self.out, "{}\u{2026}", &line[..cut])?;
+        }
+        self.omitted = Some(0);
+        Ok(())
+    }
+
+    /// Notes how many lines were left out, if any were
+    fn finish(self) -> Result<(), std::io::Error> {
+        match self.omitted {
+            Some(omitted) if omitted > 0 => {
+                let plural = if omitted == 1 { "line" } else { "lines" };
+                writeln!(
+                    self.out,
+                    "\u{2026} {} more {plural} omitted",
+                    thousands(omitted)
+                )
             }
-            writeln!(buf, "{indent}{VERTICAL_PIPE}")?;
+            _ => Ok(()),
         }
+    }
+}
+
+/// Formats a single die for the breakdown line
+pub(crate) fn die_face(die: i32, sides: u32, options: &RenderOptions) -> String {
+    if !options.emoji_faces {
+        return die.to_string();
+    }
+    match (sides, die) {
+        // the die face characters are contiguous, starting from U+2680
+        (6, 1..=6) => char::from_u32(0x2680 + die as u32 - 1)
+            .expect("die faces are valid characters")
+            .to_string(),
+        _ => format!("\u{1F3B2}{die}"),
+    }
+}
+
+/// Pairs each die with its id, for as long as there are ids to go around
+pub(crate) fn with_ids<'a>(
+    faces: &'a [i32],
+    ids: &'a [usize],
+) -> impl Iterator<Item = (&'a i32, Option<&'a usize>)> {
+    faces
+        .iter()
+        .zip(ids.iter().map(Some).chain(std::iter::repeat(None)))
+}
+
+/// Formats a die that might have exploded, by its id, leading with every
+/// face it landed on when it did, e.g. `6+6+3 → 15`
+pub(crate) fn exploded_face(
+    rolled: &Rolled,
+    id: Option<&usize>,
+    face: String,
+    options: &RenderOptions,
+) -> String {
+    let sides = rolled.sides();
+    match id.and_then(|id| rolled.chain(*id)) {
+        Some(chain) => format!(
+            "{} \u{2192} {face}",
+            chain
+                .iter()
+                .map(|link| die_face(*link, sides, options))
+                .join("+")
+        ),
+        None => face,
+    }
+}
 
-        return Ok(());
+/// Formats a die that might have been rerolled, by its id, leading with the
+/// faces it was rerolled from. They're either bracketed, like `(1 1) 4`, or
+/// struck through in markdown, like `~~1~~ ~~1~~ 4`.
+pub(crate) fn rerolled_face(
+    rolled: &Rolled,
+    id: Option<&usize>,
+    face: String,
+    options: &RenderOptions,
+    struck: bool,
+) -> String {
+    let sides = rolled.sides();
+    let Some(discarded) = id.and_then(|id| rolled.discarded(*id)) else {
+        return face;
+    };
+    let mut discarded = discarded.iter().map(|die| die_face(*die, sides, options));
+    match struck {
+        true => format!(
+            "{} {face}",
+            discarded.map(|die| format!("~~{die}~~")).join(" ")
+        ),
+        false => format!("({}) {face}", discarded.join(" ")),
     }
-    if depth == 0 {
-        if let Some(output) = &node.output {
-            writeln!(buf, "{output}")?;
+}
+
+/// The mark for a die on its highest or lowest face, if it's on either and
+/// marks were asked for. A d1 has nothing to mark.
+fn mark(die: i32, sides: u32, options: &RenderOptions) -> Option<char> {
+    match die.unsigned_abs() {
+        _ if !options.marks || sides < 2 => None,
+        face if face == sides => Some(CRIT_MARK),
+        1 => Some(FUMBLE_MARK),
+        _ => None,
+    }
+}
+
+/// Describes how a roll's dice were counted, e.g. `4 × d6, kept highest 3,
+/// dropped 2`
+fn roll_detail(rolled: &Rolled) -> String {
+    let dice = rolled.kept.kept().len() + rolled.kept.dropped().len();
+    let sides = rolled.sides();
+    if let Some(summary) = &rolled.kept.summary {
+        return format!("{} \u{00D7} d{sides}, too many to list", summary.count());
+    }
+    let kept = rolled.kept.kept().len();
+    let dropped = rolled.kept.dropped().iter().join(", ");
+    match &rolled.kept.keep {
+        KeptRule::All => format!("{dice} \u{00D7} d{sides}"),
+        _ if dropped.is_empty() => format!("{dice} \u{00D7} d{sides}, kept all {kept}"),
+        keep if keep.keeps_lowest() => {
+            format!("{dice} \u{00D7} d{sides}, kept lowest {kept}, dropped {dropped}")
         }
-    } else if let Some(output) = &node.output {
-        writeln!(buf, "{indent}{VERTICAL_PIPE}   {}", output)?;
-        writeln!(buf, "{indent}{VERTICAL_PIPE}")?;
+        _ => format!("{dice} \u{00D7} d{sides}, kept highest {kept}, dropped {dropped}"),
     }
+}
+
+/// Formats a node's result, tacking on its expected value if requested
+fn annotate(output: Int, value: &Value, options: &RenderOptions) -> String {
+    if !options.expected_values {
+        return output.to_string();
+    }
+    match stats::expected_value(&value.expression()) {
+        Some(expected) => format!("{output} (avg {})", format_average(expected)),
+        None => output.to_string(),
+    }
+}
+
+/// Prints at most two decimal places, and none at all for whole numbers
+pub fn format_average(average: f64) -> String {
+    let formatted = format!("{average:.2}");
+    formatted
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+pub fn no_color(value: &Value, options: &RenderOptions) -> Result<String, Error> {
+    let mut buf = Vec::new();
+    write(&mut buf, value, options)?;
+    Ok(String::from_utf8(buf).expect("everything drawn is valid UTF-8"))
+}
+
+/// Draws the tree straight into a writer, a line at a time, so an enormous
+/// roll never has to be held in memory all at once
+pub fn write(out: &mut dyn Write, value: &Value, options: &RenderOptions) -> Result<(), Error> {
+    let mut shuffler = options.shuffle_seed.map(StdRng::seed_from_u64);
+    let mut lines = Lines::new(out, options.max_output, options.ascii);
+    match value {
+        Value::Const(c) => lines.line(&c.to_string())?,
+        _ => draw(
+            &mut lines,
+            value,
+            None,
+            true,
+            None,
+            0,
+            options,
+            &mut shuffler,
+        )?,
+    }
+    if options.resolution {
+        lines.line(&resolution(value))?;
+    }
+    lines.finish()?;
     Ok(())
 }
+
+/// Swaps every character we draw outside of ASCII for a lookalike
+pub(crate) fn asciify(output: &str) -> String {
+    output
+        .replace(VERTICAL_PIPE, "|")
+        .replace(HORIZONTAL_PIPE, "-")
+        .replace(RIGHT_FORK, "+")
+        .replace('\u{00D7}', "*")
+        .replace(CRIT_MARK, "^")
+        .replace(FUMBLE_MARK, "_")
+        .replace('\u{2192}', "->")
+}
+
+/// Shows how the expression collapsed into its final result. Each step
+/// replaces the innermost remaining rolls and operations with their results.
+pub fn resolution(value: &Value) -> String {
+    (0..=height(value))
+        .map(|level| echo(value, level))
+        .dedup()
+        .join(" \u{2192} ")
+}
+
+/// The number of substitution steps it takes to collapse a value to a number
+fn height(value: &Value) -> u32 {
+    match value {
+        Value::Const(_) => 0,
+        Value::Rolled(rolled) => 1 + rolled.parts().into_iter().map(height).max().unwrap_or(0),
+        Value::Op { values, .. } | Value::Repeated { values, .. } => {
+            1 + values.iter().map(height).max().unwrap_or(0)
+        }
+    }
+}
+
+/// Formats a value with everything at or below the given height replaced by
+/// its result. Sub-expressions that were parenthesized originally stay that
+/// way, so `(3d4)d8` becomes `(7)d8` rather than `7d8`.
+fn echo(value: &Value, level: u32) -> String {
+    expression_with(value, &mut |v, formatted| {
+        if height(v) <= level {
+            v.value().to_string()
+        } else {
+            formatted
+        }
+    })
+}
+
+/// Formats the expression a value was evaluated from, giving `visit` the
+/// chance to rewrite each node once it has been formatted. Nodes are visited
+/// from the bottom up, and parentheses are placed around whatever `visit`
+/// returns wherever the original expression needed them.
+pub(crate) fn expression_with(
+    value: &Value,
+    visit: &mut dyn FnMut(&Value, String) -> String,
+) -> String {
+    let formatted = match value {
+        Value::Const(c) => c.to_string(),
+        Value::Rolled(rolled) => {
+            let mut operand = |v: &Value| match v {
+                Value::Const(_) => expression_with(v, visit),
+                _ => format!("({})", expression_with(v, visit)),
+            };
+            let dice = operand(&rolled.dice);
+            let sides = operand(&rolled.sides);
+            let reroll = match &rolled.rerolled {
+                Some(rerolled) => match rerolled.on.comparison {
+                    Comparison::Equal => format!("r{}", operand(&rerolled.on.against)),
+                    comparison => format!("r{comparison}{}", operand(&rerolled.on.against)),
+                },
+                None => String::new(),
+            };
+            let explode = match &rolled.exploded {
+                Some(exploded) => match &exploded.on {
+                    Some(on) => format!(
+                        "{}{}{}",
+                        exploded.explode,
+                        on.comparison,
+                        operand(&on.against)
+                    ),
+                    None => exploded.explode.to_string(),
+                },
+                None => String::new(),
+            };
+            match &rolled.kept.keep {
+                KeptRule::All => format!("{dice}d{sides}{reroll}{explode}"),
+                KeptRule::Lowest(_) => {
+                    format!(
+                        "{dice}d{sides}{reroll}{explode}kl{}",
+                        operand(&rolled.kept.retained)
+                    )
+                }
+                KeptRule::Highest(_) => {
+                    format!(
+                        "{dice}d{sides}{reroll}{explode}k{}",
+                        operand(&rolled.kept.retained)
+                    )
+                }
+                KeptRule::LowestShare(percent, _) => {
+                    format!("{dice}d{sides}{reroll}{explode}kl{percent}%")
+                }
+                KeptRule::HighestShare(percent, _) => {
+                    format!("{dice}d{sides}{reroll}{explode}k{percent}%")
+                }
+                KeptRule::DropLowest(_) => {
+                    format!(
+                        "{dice}d{sides}{reroll}{explode}dl{}",
+                        operand(&rolled.kept.retained)
+                    )
+                }
+                KeptRule::DropHighest(_) => {
+                    format!(
+                        "{dice}d{sides}{reroll}{explode}dh{}",
+                        operand(&rolled.kept.retained)
+                    )
+                }
+            }
+        }
+        Value::Op { op, values } => {
+            let operator = match op {
+                Operation::Add => " + ",
+                Operation::Sub => " - ",
+                Operation::Mul => " * ",
+            };
+            values
+                .iter()
+                .map(|v| {
+                    if v.precedence() < value.precedence() {
+                        format!("({})", expression_with(v, visit))
+                    } else {
+                        expression_with(v, visit)
+                    }
+                })
+                .join(operator)
+        }
+        // every time it was rolled, since each can come to something else
+        Value::Repeated { pick, values } => format!(
+            "{pick} of ({})",
+            values.iter().map(|v| expression_with(v, visit)).join(", ")
+        ),
+    };
+    visit(value, formatted)
+}
+
+/// Cuts the output down to at most `max` bytes (give or take the summary
+/// line). A line that straddles the limit is cut short rather than dropped so
+/// that a single enormous roll still shows something useful.
+pub(crate) fn truncate(output: String, max: usize) -> String {
+    if output.len() <= max {
+        return output;
+    }
+    let mut buf = Vec::new();
+    let mut lines = Lines::new(&mut buf, Some(max), false);
+    for line in output.lines() {
+        lines.line(line).expect("writing to a Vec never fails");
+    }
+    lines.finish().expect("writing to a Vec never fails");
+    String::from_utf8(buf).expect("lines are only cut on character boundaries")
+}
+
+/// Formats a number with commas between each group of three digits
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{
+        vec_deque, Comparison, Condition, Exp, Explode, Exploded, Kept, KeptRule, Rerolled, Roll,
+        Rolled, Value, MAX_EXPLOSIONS,
+    };
+    use crate::render::*;
+    use std::collections::{BTreeMap, VecDeque};
+
+    fn four_d6_keep_three() -> Value {
+        Value::Rolled(Rolled {
+            dice: Box::new(Value::Const(4)),
+            sides: Box::new(Value::Const(6)),
+            kept: Box::new(Kept {
+                keep: KeptRule::Highest(Value::Const(3)),
+                retained: Value::Const(3),
+                lowest: vec![1],
+                highest: vec![5, 2, 6],
+                ids: vec![0, 1, 2, 3],
+                summary: None,
+            }),
+            exploded: None,
+            rerolled: None,
+        })
+    }
+
+    #[test]
+    fn rendering_is_deterministic() -> Result<(), Error> {
+        let value = four_d6_keep_three();
+        let options = RenderOptions::default();
+        let rendered = no_color(&value, &options)?;
+        assert_eq!("Rolling 4d6k3\n[5, 2, 6 | 1] => 13\n\n", rendered);
+        assert_eq!(rendered, no_color(&value, &options)?);
+        Ok(())
+    }
+
+    #[test]
+    fn output_is_capped() -> Result<(), Error> {
+        let value = Exp::add(vec_deque![
+            Exp::roll(Roll::simple(Exp::Const(1000), Exp::Const(6))),
+            Exp::roll(Roll::simple(Exp::Const(1000), Exp::Const(6))),
+            Exp::roll(Roll::simple(Exp::Const(1000), Exp::Const(6)))
+        ])
+        .evaluate(&mut rand::thread_rng());
+        let options = RenderOptions {
+            max_output: Some(100),
+            ..Default::default()
+        };
+        let rendered = no_color(&value, &options)?;
+        assert!(rendered.len() < 200);
+        assert!(rendered.ends_with("more lines omitted\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn streamed_output_is_capped() -> Result<(), Error> {
+        let value = Exp::roll(Roll::simple(
+            Exp::roll(Roll::simple(Exp::Const(200), Exp::Const(1))),
+            Exp::Const(6),
+        ))
+        .evaluate(&mut rand::thread_rng());
+        let options = RenderOptions {
+            max_output: Some(30),
+            ..Default::default()
+        };
+        let mut streamed = Vec::new();
+        write(&mut streamed, &value, &options)?;
+        let streamed = String::from_utf8(streamed).unwrap();
+        assert_eq!(no_color(&value, &options)?, streamed);
+        assert_eq!(
+            "Rolling (200d1)d6\n\u{251C}\u{2500}\u{2500} Ro\u{2026}\n\u{2026} 3 more lines omitted\n",
+            streamed
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolution_collapses_inner_rolls_first() {
+        let inner = Value::Rolled(Rolled {
+            dice: Box::new(Value::Const(3)),
+            sides: Box::new(Value::Const(4)),
+            kept: Box::new(Kept {
+                keep: KeptRule::All,
+                retained: Value::Const(3),
+                lowest: vec![],
+                highest: vec![2, 1, 4],
+                ids: vec![0, 1, 2],
+                summary: None,
+            }),
+            exploded: None,
+            rerolled: None,
+        });
+        let outer = Value::Rolled(Rolled {
+            dice: Box::new(inner),
+            sides: Box::new(Value::Const(8)),
+            kept: Box::new(Kept {
+                keep: KeptRule::All,
+                retained: Value::Const(7),
+                lowest: vec![],
+                highest: vec![8, 1, 3, 7, 2, 6, 4],
+                ids: (3..10).collect(),
+                summary: None,
+            }),
+            exploded: None,
+            rerolled: None,
+        });
+        assert_eq!("(3d4)d8 \u{2192} (7)d8 \u{2192} 31", resolution(&outer));
+        let sum = Value::Op {
+            op: crate::eval::Operation::Add,
+            values: vec![four_d6_keep_three(), Value::Const(2)],
+        };
+        assert_eq!("4d6k3 + 2 \u{2192} 13 + 2 \u{2192} 15", resolution(&sum));
+        assert_eq!("5", resolution(&Value::Const(5)));
+    }
+
+    #[test]
+    fn subtotals_after_each_term() -> Result<(), Error> {
+        let sum = Value::Op {
+            op: crate::eval::Operation::Add,
+            values: vec![four_d6_keep_three(), four_d6_keep_three(), Value::Const(5)],
+        };
+        let options = RenderOptions {
+            subtotals: true,
+            ..Default::default()
+        };
+        let rendered = no_color(&sum, &options)?;
+        assert!(rendered.contains("[5, 2, 6 | 1] => 13 (subtotal 26)"));
+        assert!(rendered.contains("subtotal 31"));
+        Ok(())
+    }
+
+    #[test]
+    fn verbose_roll_detail() -> Result<(), Error> {
+        let options = RenderOptions {
+            verbosity: Verbosity::Verbose,
+            ..Default::default()
+        };
+        let rendered = no_color(&four_d6_keep_three(), &options)?;
+        assert_eq!(
+            "Rolling 4d6k3\n[5, 2, 6 | 1] => 13 (4 \u{00D7} d6, kept highest 3, dropped 1)\n\n",
+            rendered
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn crits_and_fumbles_are_marked() -> Result<(), Error> {
+        let options = RenderOptions {
+            marks: true,
+            ..Default::default()
+        };
+        let rendered = no_color(&four_d6_keep_three(), &options)?;
+        assert!(rendered.contains("[5, 2, 6\u{25B2} | 1\u{25BC}] => 13"));
+        let ascii = RenderOptions {
+            ascii: true,
+            ..options
+        };
+        assert!(no_color(&four_d6_keep_three(), &ascii)?.contains("[5, 2, 6^ | 1_] => 13"));
+        Ok(())
+    }
+
+    #[test]
+    fn compounded_dice_show_every_face() -> Result<(), Error> {
+        let mut value = four_d6_keep_three();
+        if let Value::Rolled(rolled) = &mut value {
+            rolled.kept.highest = vec![5, 15, 6];
+            rolled.exploded = Some(Exploded {
+                explode: Explode::Compound,
+                on: None,
+                chains: BTreeMap::from([(1, vec![6, 6, 3])]),
+                capped: false,
+                most: MAX_EXPLOSIONS,
+            });
+        }
+        let rendered = no_color(&value, &RenderOptions::default())?;
+        assert_eq!(
+            "Rolling 4d6!!k3\n[5, 6+6+3 \u{2192} 15, 6 | 1] => 26\n\n",
+            rendered
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rerolled_dice_show_what_they_left_behind() -> Result<(), Error> {
+        let mut value = four_d6_keep_three();
+        if let Value::Rolled(rolled) = &mut value {
+            rolled.rerolled = Some(Rerolled {
+                on: Condition {
+                    comparison: Comparison::Equal,
+                    against: Box::new(Value::Const(1)),
+                },
+                discarded: BTreeMap::from([(0, vec![1, 1])]),
+                capped: false,
+                most: MAX_EXPLOSIONS,
+            });
+        }
+        let rendered = no_color(&value, &RenderOptions::default())?;
+        assert_eq!("Rolling 4d6r1k3\n[(1 1) 5, 2, 6 | 1] => 13\n\n", rendered);
+        Ok(())
+    }
+
+    #[test]
+    fn ascii_tree() -> Result<(), Error> {
+        let sum = Value::Op {
+            op: crate::eval::Operation::Mul,
+            values: vec![four_d6_keep_three(), Value::Const(2)],
+        };
+        let options = RenderOptions {
+            ascii: true,
+            resolution: true,
+            ..Default::default()
+        };
+        let rendered = no_color(&sum, &options)?;
+        assert!(rendered.is_ascii(), "{rendered}");
+        assert!(rendered.contains("+-- Rolling 4d6k3\n|   [5, 2, 6 | 1] => 13"));
+        assert!(rendered.contains("4d6k3 * 2 -> 13 * 2 -> 26"));
+        Ok(())
+    }
+
+    #[test]
+    fn thousands_separators() {
+        assert_eq!("12", thousands(12));
+        assert_eq!("4,312", thousands(4312));
+        assert_eq!("1,000,000", thousands(1_000_000));
+    }
+
+    #[test]
+    fn seeded_shuffle_is_repeatable() -> Result<(), Error> {
+        let value = Exp::roll(Roll::simple(Exp::Const(20), Exp::Const(6)))
+            .evaluate(&mut rand::thread_rng());
+        let options = RenderOptions {
+            shuffle_seed: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(no_color(&value, &options)?, no_color(&value, &options)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,272 @@
+//! An interactive prompt for rolling one expression after another. Lines that
+//! start with a `.` are commands for the REPL itself rather than rolls, and
+//! lines that start with a `!` bring back earlier ones.
+
+use rand::rngs::StdRng;
+
+use crate::alias::Aliases;
+use crate::console;
+use crate::eval::{Exp, Limits};
+use crate::history::History;
+use crate::lifetime::Lifetime;
+use crate::parse::{parse, split};
+use crate::render::RenderOptions;
+use crate::resources::Resources;
+use crate::results::Results;
+use crate::session::Session;
+use crate::transcript::Transcript;
+use crate::vars::Variables;
+
+const HELP: &str = "\
+Type a dice expression to roll it, or one of these commands. Earlier results
+can be used in later rolls as $1, $2, and so on, or $last. Earlier lines come
+back with Up and Down, Ctrl-R searches them, and !! repeats the last one (!3
+the third in .history, !-2 the one before last, and !4d6 the latest that
+started with 4d6).
+  .buff +d4     add a modifier to every roll with a d20 in it
+  .debuff -2    same thing, but subtracted
+  .clear        remove every buff and debuff
+  .stats        show totals for everything rolled so far
+  .resource slot3 4
+                track something that runs out, starting with 4
+  .use slot3    spend one of it (or .use slot3 2 for two)
+  .cost fireball slot3
+                spend one whenever the fireball alias is rolled
+  .resources    show what's left of everything
+  .rest         fill every resource back up
+  .history 20   show the last 20 lines typed (or all of them)
+  .help         show this message
+  .quit         leave (so does Ctrl-D)";
+
+pub struct Repl {
+    pub aliases: Aliases,
+    pub variables: Variables,
+    pub options: RenderOptions,
+    pub limits: Limits,
+    pub rng: StdRng,
+    pub session: Session,
+    /// Terms like `+d4` that get tacked onto every roll of a d20, as with
+    /// bless or bane
+    pub modifiers: Vec<String>,
+    /// Everything rolled so far, for `$1` and `$last`
+    pub results: Results,
+    /// Where rolls are written down, with `--transcript`
+    pub transcript: Option<Transcript>,
+    /// Statistics for `rdr mystats`, if they're switched on
+    pub lifetime: Lifetime,
+    /// Every line typed, this time and before
+    pub history: History,
+    /// Spell slots, ammunition, and the like, for this session only
+    pub resources: Resources,
+}
+
+impl Repl {
+    pub fn run(&mut self) -> Result<(), String> {
+        loop {
+            let Some(line) =
+                console::read_line("> ", &self.history.lines).map_err(|e| e.to_string())?
+            else {
+                return Ok(());
+            };
+            let line = match self.history.recall(line.trim()) {
+                Ok(recalled) if recalled != line.trim() => {
+                    println!("{recalled}");
+                    recalled
+                }
+                Ok(recalled) => recalled,
+                Err(message) => {
+                    eprintln!("{message}");
+                    continue;
+                }
+            };
+            self.history.push(&line);
+            // like the transcript, saved as it goes
+            if let Err(message) = self.history.save() {
+                eprintln!("warning: {message}");
+            }
+            let outcome = match line.strip_prefix('.') {
+                Some(command) => self.command(command),
+                None if line.is_empty() => continue,
+                None => self.roll(&line).map(|_| true),
+            };
+            match outcome {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(message) => eprintln!("{message}"),
+            }
+        }
+    }
+
+    /// Carries out a command, returning whether to keep going
+    fn command(&mut self, command: &str) -> Result<bool, String> {
+        let (name, argument) = command
+            .trim()
+            .split_once(' ')
+            .unwrap_or((command.trim(), ""));
+        match name {
+            "buff" => self.modify(argument, '+')?,
+            "debuff" => self.modify(argument, '-')?,
+            "clear" => self.modifiers.clear(),
+            "stats" => print!("{}", self.session.report()),
+            "history" => {
+                let count = match argument.trim() {
+                    "" => usize::MAX,
+                    count => count
+                        .parse()
+                        .map_err(|_| format!("'{count}' isn't a number of lines"))?,
+                };
+                print!("{}", self.history.report(count));
+            }
+            "resource" => {
+                let (resource, amount) = argument
+                    .trim()
+                    .split_once(' ')
+                    .ok_or("How much of what? For example, .resource slot3 4".to_string())?;
+                self.resources.track(resource, amount_of(amount)?)?;
+                println!("{resource}: {0} of {0} left", amount.trim());
+            }
+            "use" => {
+                let (resource, amount) = argument
+                    .trim()
+                    .split_once(' ')
+                    .unwrap_or((argument.trim(), "1"));
+                if resource.is_empty() {
+                    return Err("Use what? For example, .use slot3".to_string());
+                }
+                self.spend(resource, amount_of(amount)?)?;
+            }
+            "cost" => {
+                let mut words = argument.split_whitespace();
+                let (Some(alias), Some(resource)) = (words.next(), words.next()) else {
+                    return Err(
+                        "What does what cost? For example, .cost fireball slot3".to_string()
+                    );
+                };
+                if !self.aliases.iter().any(|(name, _)| name == alias) {
+                    return Err(format!("No alias named '{alias}'"));
+                }
+                let amount = amount_of(words.next().unwrap_or("1"))?;
+                self.resources.charge(alias, resource, amount)?;
+                println!("rolling {alias} now spends {amount} {resource}");
+            }
+            "resources" => print!("{}", self.resources.report()),
+            "rest" => {
+                self.resources.rest();
+                print!("{}", self.resources.report());
+            }
+            "help" => println!("{HELP}"),
+            "quit" | "exit" => return Ok(false),
+            other => return Err(format!("Unknown command '.{other}'; try .help")),
+        }
+        Ok(true)
+    }
+
+    /// Adds a modifier, using the sign given if the modifier doesn't have
+    /// one of its own
+    fn modify(&mut self, modifier: &str, sign: char) -> Result<(), String> {
+        let modifier = modifier.trim();
+        if modifier.is_empty() {
+            return Err("What should the modifier be? For example, .buff +d4".to_string());
+        }
+        let modifier = match modifier.starts_with(['+', '-']) {
+            true => modifier.to_string(),
+            false => format!("{sign}{modifier}"),
+        };
+        parse(&format!("0 {modifier}"))
+            .map_err(|_| format!("'{modifier}' isn't a modifier like +d4 or -2"))?;
+        println!("every d20 roll now gets {}", modifier);
+        self.modifiers.push(modifier);
+        Ok(())
+    }
+
+    /// Spends some of a resource, saying what's left and warning when it's
+    /// run out
+    fn spend(&mut self, resource: &str, amount: u32) -> Result<(), String> {
+        let spent = self.resources.spend(resource, amount)?;
+        if let Some(warning) = spent.warning {
+            eprintln!("warning: {warning}");
+        }
+        println!("{}", spent.report);
+        Ok(())
+    }
+
+    /// Parses the expression, appending the modifiers if it rolls a d20
+    fn apply_modifiers(&self, expression: &str) -> Result<Exp, String> {
+        let parsed = parse(expression)?;
+        if self.modifiers.is_empty() || !rolls_d20(&parsed) {
+            return Ok(parsed);
+        }
+        Ok(parse(&format!(
+            "{} {}",
+            expression.trim(),
+            self.modifiers.join(" ")
+        ))?)
+    }
+
+    fn roll(&mut self, line: &str) -> Result<(), String> {
+        let expanded = self.variables.substitute(&self.aliases.expand(line)?)?;
+        let expressions = split(&expanded)?;
+        // make sure the whole line is good before rolling any of it
+        for expression in &expressions {
+            parse(&Results::placeholders(expression)?)?;
+        }
+        // named after what was typed, not what the aliases expanded into
+        let costs = self.resources.costs(line);
+        for expression in expressions {
+            let substituted = self.results.substitute(expression)?;
+            let parsed = self.apply_modifiers(&substituted)?;
+            let evaluated = match self.transcript.as_mut() {
+                Some(transcript) => transcript.roll(&parsed, &mut self.rng, &self.limits)?,
+                None => parsed.evaluate_within(&mut self.rng, &self.limits)?,
+            };
+            console::colorful(&evaluated, &self.options)?;
+            println!("${} = {}", self.results.next_number(), evaluated.value());
+            self.results.push(evaluated.value());
+            self.session.record(&evaluated);
+            self.lifetime.record(&substituted, &evaluated);
+        }
+        for (resource, amount) in costs {
+            self.spend(&resource, amount)?;
+        }
+        // saved after every line, so nothing is lost to a closed terminal
+        if let Some(transcript) = &self.transcript {
+            transcript.save()?;
+        }
+        self.lifetime.save_if_enabled()
+    }
+}
+
+/// A count of something spent or tracked, which has to be a whole number
+fn amount_of(amount: &str) -> Result<u32, String> {
+    let amount = amount.trim();
+    amount
+        .parse()
+        .map_err(|_| format!("'{amount}' isn't a number of uses"))
+}
+
+fn rolls_d20(exp: &Exp) -> bool {
+    match exp {
+        Exp::Const(_) => false,
+        Exp::Op(op) => op.arguments.borrow().iter().any(rolls_d20),
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            let keep = roll.keep.count().is_some_and(rolls_d20);
+            roll.sides == Exp::Const(20) || rolls_d20(&roll.dice) || rolls_d20(&roll.sides) || keep
+        }
+        Exp::Repeat(repeat) => rolls_d20(&repeat.exp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse;
+    use crate::repl::rolls_d20;
+
+    #[test]
+    fn qualifying_rolls() {
+        assert!(rolls_d20(&parse("d20 + 5").unwrap()));
+        assert!(rolls_d20(&parse("2d20kl1").unwrap()));
+        assert!(rolls_d20(&parse("(d20)d6").unwrap()));
+        assert!(!rolls_d20(&parse("2d6 + 20").unwrap()));
+    }
+}
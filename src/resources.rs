@@ -0,0 +1,167 @@
+//! Counters the REPL keeps for things that run out, like spell slots, arrows,
+//! or luck points. They're spent with `.use`, or whenever an alias that's
+//! been given a cost with `.cost` is rolled, and filled back up with `.rest`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Default)]
+pub struct Resources {
+    pools: BTreeMap<String, Pool>,
+    /// What rolling each alias spends, by alias name
+    costs: BTreeMap<String, (String, u32)>,
+}
+
+#[derive(Debug)]
+struct Pool {
+    left: u32,
+    most: u32,
+}
+
+/// What spending came to: a line saying what's left, and a warning if there
+/// wasn't enough to spend
+#[derive(Debug, PartialEq, Eq)]
+pub struct Spent {
+    pub report: String,
+    pub warning: Option<String>,
+}
+
+impl Resources {
+    /// Starts tracking a resource (or starts it over), full
+    pub fn track(&mut self, name: &str, most: u32) -> Result<(), String> {
+        let well_formed = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !well_formed {
+            return Err(format!(
+                "'{name}' is not a valid resource name; use letters, digits, and underscores"
+            ));
+        }
+        self.pools
+            .insert(name.to_string(), Pool { left: most, most });
+        Ok(())
+    }
+
+    /// Spends some of a resource. Spending more than is left still goes
+    /// ahead (the roll has already been made) but leaves nothing, with a
+    /// warning.
+    pub fn spend(&mut self, name: &str, amount: u32) -> Result<Spent, String> {
+        let pool = self.pools.get_mut(name).ok_or(format!(
+            "No resource named '{name}'; add one with .resource"
+        ))?;
+        let warning = match pool.left {
+            0 => Some(format!("{name} was already used up")),
+            left if left < amount => Some(format!("{name} only had {left} left")),
+            _ => None,
+        };
+        pool.left = pool.left.saturating_sub(amount);
+        let mut report = format!("{name}: {} of {} left", pool.left, pool.most);
+        if pool.left == 0 && warning.is_none() {
+            report.push_str(", all used up");
+        }
+        Ok(Spent { report, warning })
+    }
+
+    /// Has rolling the alias spend some of a resource from now on
+    pub fn charge(&mut self, alias: &str, name: &str, amount: u32) -> Result<(), String> {
+        if !self.pools.contains_key(name) {
+            return Err(format!(
+                "No resource named '{name}'; add one with .resource"
+            ));
+        }
+        self.costs
+            .insert(alias.to_string(), (name.to_string(), amount));
+        Ok(())
+    }
+
+    /// What rolling the line costs, going by the aliases written in it
+    pub fn costs(&self, line: &str) -> Vec<(String, u32)> {
+        let mut costs = Vec::new();
+        let mut word = String::new();
+        let mut after = '\0';
+        for c in line.chars().chain(std::iter::once('\0')) {
+            if c.is_ascii_alphanumeric() || c == '_' || (c == ':' && !word.is_empty()) {
+                word.push(c);
+                continue;
+            }
+            // `@name` is a variable and `$name` an earlier result, never an
+            // alias
+            if !matches!(after, '@' | '$') {
+                costs.extend(self.costs.get(&word).cloned());
+            }
+            word.clear();
+            after = c;
+        }
+        costs
+    }
+
+    /// Fills every resource back up
+    pub fn rest(&mut self) {
+        for pool in self.pools.values_mut() {
+            pool.left = pool.most;
+        }
+    }
+
+    pub fn report(&self) -> String {
+        if self.pools.is_empty() {
+            return "no resources yet; add one with .resource\n".to_string();
+        }
+        let mut output = String::new();
+        for (name, pool) in &self.pools {
+            write!(output, "{name}: {} of {}", pool.left, pool.most).unwrap();
+            let aliases: Vec<_> = self
+                .costs
+                .iter()
+                .filter(|(_, (resource, _))| resource == name)
+                .map(|(alias, (_, amount))| format!("{alias} ({amount})"))
+                .collect();
+            if !aliases.is_empty() {
+                write!(output, ", spent by {}", aliases.join(", ")).unwrap();
+            }
+            writeln!(output).unwrap();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resources::*;
+
+    #[test]
+    fn spending() {
+        let mut resources = Resources::default();
+        assert!(resources.track("slot 3", 2).is_err());
+        resources.track("slot3", 2).unwrap();
+        assert!(resources.spend("arrows", 1).is_err());
+
+        let spent = resources.spend("slot3", 1).unwrap();
+        assert_eq!("slot3: 1 of 2 left", spent.report);
+        assert_eq!(None, spent.warning);
+        let spent = resources.spend("slot3", 1).unwrap();
+        assert_eq!("slot3: 0 of 2 left, all used up", spent.report);
+        let spent = resources.spend("slot3", 1).unwrap();
+        assert_eq!(Some("slot3 was already used up".to_string()), spent.warning);
+        assert_eq!("slot3: 0 of 2\n", resources.report());
+
+        resources.rest();
+        assert_eq!("slot3: 2 of 2\n", resources.report());
+        let spent = resources.spend("slot3", 3).unwrap();
+        assert_eq!(Some("slot3 only had 2 left".to_string()), spent.warning);
+    }
+
+    #[test]
+    fn alias_costs() {
+        let mut resources = Resources::default();
+        assert!(resources.charge("fireball", "slot3", 1).is_err());
+        resources.track("slot3", 2).unwrap();
+        resources.charge("fireball", "slot3", 1).unwrap();
+        assert_eq!(vec![("slot3".to_string(), 1)], resources.costs("fireball"));
+        assert_eq!(2, resources.costs("fireball; fireball * 2").len());
+        assert!(resources
+            .costs("fireballs + @fireball + $fireball")
+            .is_empty());
+        assert!(resources
+            .report()
+            .contains("slot3: 2 of 2, spent by fireball (1)"));
+    }
+}
@@ -0,0 +1,97 @@
+//! Earlier results, referred to from later expressions as `$1`, `$2`, and so
+//! on (counting from the first roll), or `$last` for the most recent one
+
+use crate::eval::Int;
+
+#[derive(Debug, Default)]
+pub struct Results {
+    values: Vec<Int>,
+}
+
+impl Results {
+    pub fn push(&mut self, value: Int) {
+        self.values.push(value);
+    }
+
+    /// The number the next result will be referred to by
+    pub fn next_number(&self) -> usize {
+        self.values.len() + 1
+    }
+
+    /// Whether the input refers to any earlier results
+    pub fn referenced(input: &str) -> bool {
+        input.contains('$')
+    }
+
+    /// Replaces every reference in the input with its (parenthesized) value
+    pub fn substitute(&self, input: &str) -> Result<String, String> {
+        substitute_with(input, |reference| {
+            let value = match reference {
+                "last" => self.values.last(),
+                number => match number.parse::<usize>() {
+                    Ok(n) if n > 0 => self.values.get(n - 1),
+                    _ => return Err(format!("${reference} isn't a result; try $1 or $last")),
+                },
+            };
+            value
+                .map(|value| value.to_string())
+                .ok_or(format!("There's no ${reference} yet"))
+        })
+    }
+
+    /// Fills every reference with a zero, which is enough to check that an
+    /// expression will parse before anything has been rolled
+    pub fn placeholders(input: &str) -> Result<String, String> {
+        substitute_with(input, |_| Ok("0".to_string()))
+    }
+}
+
+fn substitute_with(
+    input: &str,
+    mut value: impl FnMut(&str) -> Result<String, String>,
+) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            output.push(c);
+            continue;
+        }
+        let mut reference = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_alphanumeric) {
+            reference.push(c);
+        }
+        if reference.is_empty() {
+            return Err("'$' must be followed by a result number or 'last'".to_string());
+        }
+        output.push_str(&format!("({})", value(&reference)?));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::results::Results;
+
+    fn results() -> Results {
+        let mut results = Results::default();
+        results.push(17);
+        results.push(-2);
+        results
+    }
+
+    #[test]
+    fn references() {
+        assert_eq!("2d6 + (17)", results().substitute("2d6 + $1").unwrap());
+        assert_eq!("(-2) * 2", results().substitute("$last * 2").unwrap());
+        assert_eq!("(17)+(-2)", results().substitute("$1+$2").unwrap());
+    }
+
+    #[test]
+    fn bad_references() {
+        for input in ["$3", "$0", "$first", "$ + 1"] {
+            assert!(results().substitute(input).is_err(), "{input} was accepted");
+        }
+        assert!(Results::default().substitute("$last").is_err());
+    }
+}
@@ -0,0 +1,120 @@
+//! Dice scripts: a file of rolls to make in one go, like everything needed to
+//! run an encounter. Each line is one of
+//!
+//! ```text
+//! # a comment, which is ignored (as are blank lines)
+//! @prof = 3                   # gives a value to a variable
+//! alias attack = d20 + @prof  # defines an alias for the rest of the file
+//! Goblin initiative: d20 + 2  # rolls an expression under a label
+//! 4d6k3                       # rolls an expression, labelled with itself
+//! ```
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Statement {
+    Variable { assignment: String },
+    Alias { name: String, expression: String },
+    Roll { label: String, expression: String },
+}
+
+/// A statement along with the line it came from, for error messages
+#[derive(Debug, PartialEq, Eq)]
+pub struct Line {
+    pub number: usize,
+    pub statement: Statement,
+}
+
+pub fn parse_script(source: &str) -> Result<Vec<Line>, String> {
+    let mut lines = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line = match line.split_once('#') {
+            Some((code, _comment)) => code,
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        let statement = parse_line(line).map_err(|e| format!("line {}: {e}", i + 1))?;
+        lines.push(Line {
+            number: i + 1,
+            statement,
+        });
+    }
+    Ok(lines)
+}
+
+fn parse_line(line: &str) -> Result<Statement, String> {
+    if line.starts_with('@') {
+        return Ok(Statement::Variable {
+            assignment: line.to_string(),
+        });
+    }
+    if let Some(definition) = line.strip_prefix("alias ") {
+        let (name, expression) = definition
+            .split_once('=')
+            .ok_or("aliases should look like `alias name = expression`")?;
+        return Ok(Statement::Alias {
+            name: name.trim().to_string(),
+            expression: expression.trim().to_string(),
+        });
+    }
+    let (label, expression) = match line.split_once(':') {
+        Some((label, expression)) => (label.trim(), expression.trim()),
+        None => (line, line),
+    };
+    if expression.is_empty() {
+        return Err(format!("'{label}' has nothing to roll"));
+    }
+    Ok(Statement::Roll {
+        label: label.to_string(),
+        expression: expression.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::*;
+
+    #[test]
+    fn every_kind_of_line() {
+        let script = "\
+            # the goblins\n\
+            \n\
+            @prof = 2\n\
+            alias scimitar = d6 + 2\n\
+            Initiative: d20 + 2  # dex\n\
+            scimitar * 2\n";
+        let statements = parse_script(script)
+            .unwrap()
+            .into_iter()
+            .map(|line| line.statement)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                Statement::Variable {
+                    assignment: "@prof = 2".to_string()
+                },
+                Statement::Alias {
+                    name: "scimitar".to_string(),
+                    expression: "d6 + 2".to_string()
+                },
+                Statement::Roll {
+                    label: "Initiative".to_string(),
+                    expression: "d20 + 2".to_string()
+                },
+                Statement::Roll {
+                    label: "scimitar * 2".to_string(),
+                    expression: "scimitar * 2".to_string()
+                },
+            ],
+            statements
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let error = parse_script("d20\nalias oops\n").unwrap_err();
+        assert!(error.starts_with("line 2:"), "{error}");
+        assert!(parse_script("Nothing:").is_err());
+    }
+}
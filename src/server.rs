@@ -0,0 +1,338 @@
+//! `rdr serve`: a JSON-RPC 2.0 server on stdin and stdout, one message per
+//! line, for editor plugins and assistants that would rather call the roller
+//! as a tool than scrape its output. Besides the plain `roll`, `validate`, and
+//! `simulate` methods, it answers the Model Context Protocol's `initialize`,
+//! `tools/list`, and `tools/call`, so it can be registered as an MCP server.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, Write};
+
+use crate::collection::Collection;
+use crate::document::Document;
+use crate::error::Error;
+use crate::eval::Limits;
+use crate::integrity;
+use crate::parse::parse;
+use crate::simulate;
+
+/// Simulations are run while the client waits, so they're kept short
+const MAX_TRIALS: usize = 1_000_000;
+
+const DEFAULT_TRIALS: usize = 10_000;
+
+/// The version of the Model Context Protocol this speaks
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+pub struct Server {
+    pub limits: Limits,
+    pub rng: StdRng,
+    /// Saved rolls, usable by name in any expression
+    pub rolls: Collection,
+}
+
+/// A JSON-RPC error, with one of the codes the spec reserves
+struct Failure {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl Failure {
+    const PARSE_ERROR: i64 = -32700;
+    const INVALID_REQUEST: i64 = -32600;
+    const METHOD_NOT_FOUND: i64 = -32601;
+    const INVALID_PARAMS: i64 = -32602;
+    /// For expressions that don't parse, roll too many dice, and so on
+    const ROLL_FAILED: i64 = -32000;
+
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Failure {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure {
+            code: Failure::ROLL_FAILED,
+            message: error.to_string(),
+            data: Some(json!({ "kind": error.kind(), "position": error.position() })),
+        }
+    }
+}
+
+impl Server {
+    /// Answers requests until stdin is closed
+    pub fn run(&mut self) -> Result<(), String> {
+        let mut stdout = io::stdout().lock();
+        for line in io::stdin().lock().lines() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.respond(&line) {
+                writeln!(stdout, "{response}").map_err(|e| e.to_string())?;
+                stdout.flush().map_err(|e| e.to_string())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The response to one line of input, or nothing if it was a notification
+    fn respond(&mut self, line: &str) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => {
+                let failure = Failure::new(Failure::PARSE_ERROR, e.to_string());
+                return Some(response(Value::Null, Err(failure)));
+            }
+        };
+        let id = request.get("id").cloned();
+        let outcome = match request.get("method").and_then(Value::as_str) {
+            Some(method) => self.call(method, request.get("params").unwrap_or(&Value::Null)),
+            None => Err(Failure::new(
+                Failure::INVALID_REQUEST,
+                "Requests need a method",
+            )),
+        };
+        // notifications don't get an answer, even when they go wrong
+        id.map(|id| response(id, outcome))
+    }
+
+    fn call(&mut self, method: &str, params: &Value) -> Result<Value, Failure> {
+        match method {
+            "roll" => self.roll(&self.rolls.expand(expression(params)?)?),
+            "validate" => Ok(validate(self.rolls.expand(expression(params)?))),
+            "simulate" => self.simulate(&self.rolls.expand(expression(params)?)?, trials(params)?),
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "rdr", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "tools/list" => Ok(json!({ "tools": tools() })),
+            "tools/call" => Ok(self.call_tool(params)),
+            "ping" => Ok(json!({})),
+            other => Err(Failure::new(
+                Failure::METHOD_NOT_FOUND,
+                format!("There is no method called '{other}'"),
+            )),
+        }
+    }
+
+    /// Runs one of the tools from [`tools`]. Following MCP, a tool that fails
+    /// still answers, just with `isError` set.
+    fn call_tool(&mut self, params: &Value) -> Value {
+        let name = params.get("name").and_then(Value::as_str).unwrap_or("");
+        let arguments = params.get("arguments").unwrap_or(&Value::Null);
+        let outcome = match name {
+            "roll" | "validate" | "simulate" => self.call(name, arguments),
+            other => Err(Failure::new(
+                Failure::INVALID_PARAMS,
+                format!("There is no tool called '{other}'"),
+            )),
+        };
+        let (text, is_error) = match outcome {
+            Ok(result) => (result.to_string(), false),
+            Err(failure) => (failure.message, true),
+        };
+        json!({ "content": [{ "type": "text", "text": text }], "isError": is_error })
+    }
+
+    /// Rolls with a seed of its own, which goes back with the result so the
+    /// roll can be made again with `rdr --seed`
+    fn roll(&mut self, expression: &str) -> Result<Value, Failure> {
+        let seed = self.rng.gen();
+        let parsed = parse(expression)?;
+        let evaluated = parsed.evaluate_within(&mut StdRng::seed_from_u64(seed), &self.limits)?;
+        let document = Document {
+            seed: Some(seed),
+            hash: Some(integrity::hash(&evaluated, seed)),
+            ..Document::new(&evaluated)
+        };
+        serde_json::to_value(document).map_err(|e| Failure::from(Error::from(e)))
+    }
+
+    fn simulate(&mut self, expression: &str, trials: usize) -> Result<Value, Failure> {
+        let parsed = parse(expression)?;
+        let summary = simulate::simulate(&parsed, trials, &mut self.rng, &self.limits)?;
+        serde_json::to_value(summary).map_err(|e| Failure::from(Error::from(e)))
+    }
+}
+
+fn validate(expression: Result<String, Error>) -> Value {
+    match expression.and_then(|expression| parse(&expression)) {
+        Ok(_) => json!({ "ok": true }),
+        Err(e) => json!({ "ok": false, "message": e.to_string(), "position": e.position() }),
+    }
+}
+
+fn response(id: Value, outcome: Result<Value, Failure>) -> Value {
+    match outcome {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(failure) => {
+            let mut error = json!({ "code": failure.code, "message": failure.message });
+            if let Some(data) = failure.data {
+                error["data"] = data;
+            }
+            json!({ "jsonrpc": "2.0", "id": id, "error": error })
+        }
+    }
+}
+
+fn expression(params: &Value) -> Result<&str, Failure> {
+    params
+        .get("expression")
+        .and_then(Value::as_str)
+        .ok_or(Failure::new(
+            Failure::INVALID_PARAMS,
+            "An expression is required, like {\"expression\": \"2d20k1 + 5\"}",
+        ))
+}
+
+fn trials(params: &Value) -> Result<usize, Failure> {
+    let trials = match params.get("trials") {
+        None | Some(Value::Null) => return Ok(DEFAULT_TRIALS),
+        Some(trials) => trials.as_u64().ok_or(Failure::new(
+            Failure::INVALID_PARAMS,
+            "trials should be a positive whole number",
+        ))?,
+    };
+    match usize::try_from(trials) {
+        Ok(0) => Err(Failure::new(
+            Failure::INVALID_PARAMS,
+            "trials should be a positive whole number",
+        )),
+        Ok(trials) if trials <= MAX_TRIALS => Ok(trials),
+        _ => Err(Failure::new(
+            Failure::INVALID_PARAMS,
+            format!("At most {MAX_TRIALS} trials can be run at once"),
+        )),
+    }
+}
+
+/// The tools offered to MCP clients, which are the plain methods again
+fn tools() -> Value {
+    let expression = json!({
+        "type": "string",
+        "description": "A dice expression, like 2d20k1 + 5 or 4d6kh3",
+    });
+    json!([
+        {
+            "name": "roll",
+            "description": "Roll a dice expression, giving the total and every die rolled",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "expression": expression },
+                "required": ["expression"],
+            },
+        },
+        {
+            "name": "validate",
+            "description": "Check whether a dice expression is well formed without rolling it",
+            "inputSchema": {
+                "type": "object",
+                "properties": { "expression": expression },
+                "required": ["expression"],
+            },
+        },
+        {
+            "name": "simulate",
+            "description": "Roll a dice expression many times and summarize the results",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "expression": expression,
+                    "trials": { "type": "integer", "minimum": 1, "maximum": MAX_TRIALS },
+                },
+                "required": ["expression"],
+            },
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::*;
+    use rand::SeedableRng;
+
+    fn server() -> Server {
+        Server {
+            limits: Limits::default(),
+            rng: StdRng::seed_from_u64(0),
+            rolls: Collection::parse("fireball = 8d6").unwrap(),
+        }
+    }
+
+    #[test]
+    fn methods() {
+        let mut server = server();
+        let rolled = server
+            .respond(r#"{"jsonrpc":"2.0","id":1,"method":"roll","params":{"expression":"2d6+3"}}"#)
+            .unwrap();
+        assert_eq!(1, rolled["id"]);
+        assert_eq!("add", rolled["result"]["breakdown"]["operation"]);
+        // the seed rolls the same dice again
+        let seed = rolled["result"]["seed"].as_u64().unwrap();
+        let again = parse("2d6+3")
+            .unwrap()
+            .evaluate(&mut StdRng::seed_from_u64(seed));
+        assert_eq!(rolled["result"]["total"], json!(again.value()));
+        let invalid = server
+            .respond(r#"{"jsonrpc":"2.0","id":2,"method":"validate","params":{"expression":"2d"}}"#)
+            .unwrap();
+        assert_eq!(false, invalid["result"]["ok"]);
+        let simulated = server
+            .respond(r#"{"id":3,"method":"simulate","params":{"expression":"d4","trials":100}}"#)
+            .unwrap();
+        assert_eq!(100, simulated["result"]["samples"]);
+        let saved = server
+            .respond(r#"{"id":4,"method":"roll","params":{"expression":"fireball + 1"}}"#)
+            .unwrap();
+        assert_eq!("add", saved["result"]["breakdown"]["operation"]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut server = server();
+        let failed = server
+            .respond(r#"{"jsonrpc":"2.0","id":1,"method":"roll","params":{"expression":"2d"}}"#)
+            .unwrap();
+        assert_eq!(-32000, failed["error"]["code"]);
+        assert_eq!("syntax", failed["error"]["data"]["kind"]);
+        // totals and numbers too big to count are errors like any other
+        let mut roll = |expression: &str| {
+            let request =
+                json!({ "id": 1, "method": "roll", "params": { "expression": expression } });
+            server.respond(&request.to_string()).unwrap()
+        };
+        let huge = "2147483647 * 2147483647 * 2147483647 * 2147483647 * 2147483647";
+        assert_eq!("limit", roll(huge)["error"]["data"]["kind"]);
+        assert_eq!("syntax", roll(&"9".repeat(40))["error"]["data"]["kind"]);
+        assert_eq!(-32700, server.respond("{").unwrap()["error"]["code"]);
+        assert_eq!(
+            -32601,
+            server.respond(r#"{"id":1,"method":"nope"}"#).unwrap()["error"]["code"]
+        );
+        assert!(server
+            .respond(r#"{"method":"notifications/initialized"}"#)
+            .is_none());
+    }
+
+    #[test]
+    fn tools() {
+        let mut server = server();
+        let listed = server.respond(r#"{"id":1,"method":"tools/list"}"#).unwrap();
+        assert_eq!(3, listed["result"]["tools"].as_array().unwrap().len());
+        let call = r#"{"id":2,"method":"tools/call","params":{
+            "name": "validate", "arguments": {"expression": "2d20k1"}
+        }}"#;
+        let called = server.respond(call).unwrap();
+        assert_eq!(false, called["result"]["isError"]);
+        assert_eq!(r#"{"ok":true}"#, called["result"]["content"][0]["text"]);
+    }
+}
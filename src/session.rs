@@ -0,0 +1,135 @@
+//! Running statistics for everything rolled during a REPL session
+
+use std::fmt::Write;
+
+use crate::eval::{Int, Value};
+
+#[derive(Debug, Default)]
+pub struct Session {
+    rolls: usize,
+    total: i64,
+    highest: Option<Int>,
+    lowest: Option<Int>,
+    d20s: usize,
+    d20_sum: i64,
+    nat_twenties: usize,
+    nat_ones: usize,
+}
+
+impl Session {
+    pub fn record(&mut self, value: &Value) {
+        let result = value.value();
+        self.rolls += 1;
+        self.total += result as i64;
+        self.highest = Some(self.highest.map_or(result, |h| h.max(result)));
+        self.lowest = Some(self.lowest.map_or(result, |l| l.min(result)));
+        self.record_d20s(value);
+    }
+
+    /// Only the d20s that counted are tallied, so the low die of a roll with
+    /// advantage doesn't show up as a natural 1
+    fn record_d20s(&mut self, value: &Value) {
+        match value {
+            Value::Const(_) => {}
+            Value::Rolled(rolled) => {
+                for component in [&rolled.dice, &rolled.sides] {
+                    self.record_d20s(component);
+                }
+                self.record_d20s(&rolled.kept.retained);
+                if rolled.sides.value().abs() != 20 {
+                    return;
+                }
+                for &die in rolled.kept.kept() {
+                    self.d20s += 1;
+                    self.d20_sum += die as i64;
+                    match die {
+                        20 => self.nat_twenties += 1,
+                        1 => self.nat_ones += 1,
+                        _ => {}
+                    }
+                }
+            }
+            Value::Op { values, .. } => {
+                for value in values {
+                    self.record_d20s(value);
+                }
+            }
+            Value::Repeated { pick, values } => {
+                for (value, counted) in values.iter().zip(pick.counted(values)) {
+                    if counted {
+                        self.record_d20s(value);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn report(&self) -> String {
+        let mut output = String::new();
+        writeln!(output, "rolls: {}", self.rolls).unwrap();
+        if self.rolls == 0 {
+            return output;
+        }
+        writeln!(output, "sum of results: {}", self.total).unwrap();
+        writeln!(
+            output,
+            "average result: {:.2}",
+            self.total as f64 / self.rolls as f64
+        )
+        .unwrap();
+        if let (Some(highest), Some(lowest)) = (self.highest, self.lowest) {
+            writeln!(output, "highest: {highest}, lowest: {lowest}").unwrap();
+        }
+        if self.d20s > 0 {
+            writeln!(
+                output,
+                "d20s: {} (average {:.2}, {} natural 20s, {} natural 1s)",
+                self.d20s,
+                self.d20_sum as f64 / self.d20s as f64,
+                self.nat_twenties,
+                self.nat_ones
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{Int, Kept, KeptRule, Rolled, Value};
+    use crate::session::Session;
+
+    fn d20s(highest: Vec<i32>, lowest: Vec<i32>) -> Value {
+        Value::Rolled(Rolled {
+            dice: Box::new(Value::Const((highest.len() + lowest.len()) as Int)),
+            sides: Box::new(Value::Const(20)),
+            kept: Box::new(Kept {
+                keep: KeptRule::Highest(Value::Const(highest.len() as Int)),
+                retained: Value::Const(highest.len() as Int),
+                ids: (0..highest.len() + lowest.len()).collect(),
+                summary: None,
+                lowest,
+                highest,
+            }),
+            exploded: None,
+            rerolled: None,
+        })
+    }
+
+    #[test]
+    fn tallies() {
+        let mut session = Session::default();
+        session.record(&d20s(vec![20], vec![1]));
+        session.record(&d20s(vec![9], vec![]));
+        session.record(&Value::Const(4));
+        assert_eq!(3, session.rolls);
+        assert_eq!(33, session.total);
+        assert_eq!(2, session.d20s);
+        assert_eq!(1, session.nat_twenties);
+        // the 1 was dropped, so it doesn't count
+        assert_eq!(0, session.nat_ones);
+        assert_eq!(Some(20), session.highest);
+        assert_eq!(Some(4), session.lowest);
+    }
+}
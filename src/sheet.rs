@@ -0,0 +1,138 @@
+//! Character sheets. A TOML or JSON file of a character's numbers, loaded
+//! with `--character`, whose every field becomes a variable. Nested tables
+//! are flattened with underscores, so `[mods] str = 3` is `@mods_str`.
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+#[derive(Debug, Default)]
+pub struct Sheet {
+    fields: BTreeMap<String, String>,
+}
+
+/// A field's value, whichever format it was read from
+enum Field<'a> {
+    Scalar(String),
+    Table(Vec<(&'a str, FieldRef<'a>)>),
+    Other,
+}
+
+#[derive(Clone, Copy)]
+enum FieldRef<'a> {
+    Toml(&'a toml::Value),
+    Json(&'a serde_json::Value),
+}
+
+impl<'a> FieldRef<'a> {
+    fn field(self) -> Field<'a> {
+        match self {
+            FieldRef::Toml(value) => match value {
+                toml::Value::Integer(n) => Field::Scalar(n.to_string()),
+                toml::Value::String(s) => Field::Scalar(s.clone()),
+                toml::Value::Table(table) => Field::Table(
+                    table
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), FieldRef::Toml(v)))
+                        .collect(),
+                ),
+                _ => Field::Other,
+            },
+            FieldRef::Json(value) => match value {
+                serde_json::Value::Number(n) if n.is_i64() => Field::Scalar(n.to_string()),
+                serde_json::Value::String(s) => Field::Scalar(s.clone()),
+                serde_json::Value::Object(object) => Field::Table(
+                    object
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), FieldRef::Json(v)))
+                        .collect(),
+                ),
+                _ => Field::Other,
+            },
+        }
+    }
+}
+
+impl Sheet {
+    /// Reads the sheet as JSON if the file ends in `.json`, and as TOML
+    /// otherwise
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        let unparsable =
+            |e: &dyn std::fmt::Display| format!("Could not parse {}: {e}", path.display());
+        match path.extension().is_some_and(|e| e == "json") {
+            true => {
+                let value: serde_json::Value =
+                    serde_json::from_str(&contents).map_err(|e| unparsable(&e))?;
+                Sheet::from_fields(FieldRef::Json(&value))
+            }
+            false => {
+                let value: toml::Value = toml::from_str(&contents).map_err(|e| unparsable(&e))?;
+                Sheet::from_fields(FieldRef::Toml(&value))
+            }
+        }
+    }
+
+    fn from_fields(root: FieldRef) -> Result<Self, String> {
+        let mut sheet = Sheet::default();
+        match root.field() {
+            Field::Table(fields) => sheet.flatten("", fields)?,
+            _ => return Err("A character sheet should be a table of fields".to_string()),
+        }
+        Ok(sheet)
+    }
+
+    fn flatten(&mut self, prefix: &str, fields: Vec<(&str, FieldRef)>) -> Result<(), String> {
+        for (key, value) in fields {
+            let name = format!("{prefix}{}", key.replace('-', "_"));
+            match value.field() {
+                Field::Scalar(value) => {
+                    self.fields.insert(name, value);
+                }
+                Field::Table(fields) => self.flatten(&format!("{name}_"), fields)?,
+                Field::Other => {
+                    return Err(format!(
+                        "'{name}' should be a whole number or an expression like \"2d6\""
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Every field, as a variable name and its value
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sheet::*;
+
+    #[test]
+    fn nested_fields_are_flattened() {
+        let toml: toml::Value = toml::from_str(
+            "name = \"Brunhilde\"\nprof = 3\n[mods]\nstr = 4\ndex-save = \"1d4 + 1\"",
+        )
+        .unwrap();
+        let sheet = Sheet::from_fields(FieldRef::Toml(&toml)).unwrap();
+        assert_eq!(
+            vec![
+                ("mods_dex_save", "1d4 + 1"),
+                ("mods_str", "4"),
+                ("name", "Brunhilde"),
+                ("prof", "3")
+            ],
+            sheet.fields().collect::<Vec<_>>()
+        );
+
+        let json = serde_json::json!({ "str_mod": 3, "spells": { "slots": 2 } });
+        let sheet = Sheet::from_fields(FieldRef::Json(&json)).unwrap();
+        assert_eq!(
+            vec![("spells_slots", "2"), ("str_mod", "3")],
+            sheet.fields().collect::<Vec<_>>()
+        );
+        let json = serde_json::json!({ "speed": 30.5 });
+        assert!(Sheet::from_fields(FieldRef::Json(&json)).is_err());
+    }
+}
@@ -0,0 +1,410 @@
+//! Monte Carlo statistics. Where the `stats` module works things out exactly,
+//! this one just rolls the expression a lot of times and reports what came
+//! up, which works for any expression no matter how deeply it recurses.
+
+use rand::Rng;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+
+use crate::error::Error;
+use crate::eval::{Exp, Int, Limits};
+use crate::stats;
+
+/// The widest a histogram bar is allowed to get
+const BAR_WIDTH: usize = 40;
+
+/// Past this many distinct results, the histogram groups them into buckets
+const MAX_ROWS: usize = 30;
+
+pub const PERCENTILES: &[u32] = &[5, 25, 75, 95];
+
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub samples: usize,
+    pub mean: f64,
+    pub std_dev: f64,
+    /// What the mean and standard deviation ought to be, when they can be
+    /// worked out exactly, to check the samples against
+    pub exact_mean: Option<f64>,
+    pub exact_std_dev: Option<f64>,
+    pub median: Int,
+    pub percentiles: Vec<(u32, Int)>,
+    /// Each row is the (inclusive) span of results it covers and how many
+    /// samples landed in it
+    pub histogram: Vec<(Int, Int, usize)>,
+    /// How many times each result came up, for anyone drawing their own chart
+    pub counts: BTreeMap<Int, usize>,
+}
+
+/// One result from a simulation, for charting elsewhere
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Row {
+    pub outcome: Int,
+    pub count: usize,
+    pub probability: f64,
+    /// The chance of rolling this result or anything lower
+    pub cumulative: f64,
+}
+
+/// Rolls the expression `samples` times, each time within the limits
+pub fn simulate(
+    exp: &Exp,
+    samples: usize,
+    rng: &mut impl Rng,
+    limits: &Limits,
+) -> Result<Summary, Error> {
+    let mut simulation = Simulation::new(exp.clone(), samples);
+    simulation.run(samples, rng, limits)?;
+    simulation.summary()
+}
+
+/// How each sample's total is written when a simulation is recorded
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Record {
+    /// A `total` column, one sample per row
+    Csv,
+    /// Four bytes per sample (sixteen with the `wide` feature): the total as a
+    /// little-endian integer
+    Binary,
+}
+
+/// A simulation that can be run a few samples at a time, so that whatever
+/// else needs the thread (a web page's UI, say) gets a turn in between. Only
+/// how many times each total came up is kept, so even millions of samples
+/// take next to no memory; the samples themselves can be recorded to a
+/// writer as they're rolled.
+pub struct Simulation {
+    exp: Exp,
+    samples: usize,
+    rolled: usize,
+    counts: BTreeMap<Int, usize>,
+    recording: Option<(Box<dyn io::Write>, Record)>,
+}
+
+impl Simulation {
+    pub fn new(exp: Exp, samples: usize) -> Self {
+        Simulation {
+            exp,
+            samples,
+            rolled: 0,
+            counts: BTreeMap::new(),
+            recording: None,
+        }
+    }
+
+    /// Writes every sample's total to `out` as it's rolled. The writer is
+    /// flushed once the last sample is in.
+    pub fn recording(mut self, mut out: Box<dyn io::Write>, record: Record) -> io::Result<Self> {
+        if record == Record::Csv {
+            writeln!(out, "total")?;
+        }
+        self.recording = Some((out, record));
+        Ok(self)
+    }
+
+    /// Rolls up to `count` more samples, returning whether all of them have
+    /// now been rolled
+    pub fn run(
+        &mut self,
+        count: usize,
+        rng: &mut impl Rng,
+        limits: &Limits,
+    ) -> Result<bool, Error> {
+        let count = count.min(self.samples - self.rolled);
+        for _ in 0..count {
+            let total = self.exp.evaluate_within(rng, limits)?.value();
+            *self.counts.entry(total).or_insert(0) += 1;
+            self.rolled += 1;
+            match &mut self.recording {
+                Some((out, Record::Csv)) => writeln!(out, "{total}")?,
+                Some((out, Record::Binary)) => out.write_all(&total.to_le_bytes())?,
+                None => {}
+            }
+        }
+        if let (true, Some((out, _))) = (self.is_done(), &mut self.recording) {
+            out.flush()?;
+        }
+        Ok(self.is_done())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.rolled == self.samples
+    }
+
+    /// The fraction of samples rolled so far, from 0 to 1
+    pub fn progress(&self) -> f64 {
+        match self.samples {
+            0 => 1.0,
+            samples => self.rolled as f64 / samples as f64,
+        }
+    }
+
+    /// Statistics for the samples rolled so far, which can be asked for
+    /// before they've all been rolled
+    pub fn summary(&self) -> Result<Summary, Error> {
+        let mut summary = summarize(&self.counts).ok_or(Error::Input(
+            "At least one sample is needed to compute statistics".into(),
+        ))?;
+        summary.exact_mean = stats::expected_value(&self.exp);
+        summary.exact_std_dev = stats::std_dev(&self.exp);
+        Ok(summary)
+    }
+}
+
+fn summarize(counts: &BTreeMap<Int, usize>) -> Option<Summary> {
+    let samples = counts.values().sum::<usize>();
+    if samples == 0 {
+        return None;
+    }
+    let n = samples as f64;
+    let mean = counts
+        .iter()
+        .map(|(&r, &count)| r as f64 * count as f64)
+        .sum::<f64>()
+        / n;
+    let variance = counts
+        .iter()
+        .map(|(&r, &count)| (r as f64 - mean).powi(2) * count as f64)
+        .sum::<f64>()
+        / n;
+    Some(Summary {
+        samples,
+        mean,
+        std_dev: variance.sqrt(),
+        exact_mean: None,
+        exact_std_dev: None,
+        median: percentile(counts, samples, 50),
+        percentiles: PERCENTILES
+            .iter()
+            .map(|&p| (p, percentile(counts, samples, p)))
+            .collect(),
+        histogram: histogram(counts),
+        counts: counts.clone(),
+    })
+}
+
+/// Nearest-rank percentile of the samples, given how many times each result
+/// came up
+fn percentile(counts: &BTreeMap<Int, usize>, samples: usize, p: u32) -> Int {
+    let rank = (p as usize * samples).div_ceil(100).max(1);
+    let mut seen = 0;
+    for (&result, &count) in counts {
+        seen += count;
+        if seen >= rank {
+            return result;
+        }
+    }
+    *counts
+        .keys()
+        .next_back()
+        .expect("there is at least one sample")
+}
+
+// worked out in i128 so the span of an i32 can't overflow, which is the same
+// type with the `wide` feature
+#[allow(clippy::unnecessary_cast)]
+fn histogram(counts: &BTreeMap<Int, usize>) -> Vec<(Int, Int, usize)> {
+    let (min, max) = (
+        *counts.keys().next().expect("there is at least one sample") as i128,
+        *counts
+            .keys()
+            .next_back()
+            .expect("there is at least one sample") as i128,
+    );
+    let span = max - min + 1;
+    let width = (span as usize).div_ceil(MAX_ROWS) as i128;
+    let mut rows = Vec::new();
+    let mut low = min;
+    while low <= max {
+        let high = (low + width - 1).min(max);
+        let count = counts.range(low as Int..=high as Int).map(|(_, c)| c).sum();
+        rows.push((low as Int, high as Int, count));
+        low = high + 1;
+    }
+    rows
+}
+
+impl Summary {
+    /// Every result that came up, lowest first
+    pub fn rows(&self) -> Vec<Row> {
+        let mut seen = 0;
+        self.counts
+            .iter()
+            .map(|(&outcome, &count)| {
+                seen += count;
+                Row {
+                    outcome,
+                    count,
+                    probability: count as f64 / self.samples as f64,
+                    cumulative: seen as f64 / self.samples as f64,
+                }
+            })
+            .collect()
+    }
+
+    pub fn csv(&self) -> String {
+        let mut output = String::from("outcome,count,probability,cumulative\n");
+        for row in self.rows() {
+            writeln!(
+                output,
+                "{},{},{},{}",
+                row.outcome, row.count, row.probability, row.cumulative
+            )
+            .unwrap();
+        }
+        output
+    }
+
+    pub fn report(&self) -> String {
+        let mut output = String::new();
+        writeln!(output, "samples: {}", self.samples).unwrap();
+        let exact = |value: Option<f64>| match value {
+            Some(value) => format!(" (exact {value:.2})"),
+            None => String::new(),
+        };
+        writeln!(output, "mean: {:.2}{}", self.mean, exact(self.exact_mean)).unwrap();
+        writeln!(
+            output,
+            "std dev: {:.2}{}",
+            self.std_dev,
+            exact(self.exact_std_dev)
+        )
+        .unwrap();
+        writeln!(output, "median: {}", self.median).unwrap();
+        for (p, value) in &self.percentiles {
+            writeln!(output, "p{p}: {value}").unwrap();
+        }
+        output.push('\n');
+
+        let labels = self
+            .histogram
+            .iter()
+            .map(|(low, high, _)| match low == high {
+                true => low.to_string(),
+                false => format!("{low}..{high}"),
+            })
+            .collect::<Vec<_>>();
+        let label_width = labels.iter().map(String::len).max().unwrap_or(0);
+        let tallest = self.histogram.iter().map(|row| row.2).max().unwrap_or(0);
+        for (label, (_, _, count)) in labels.iter().zip(&self.histogram) {
+            let bar = count * BAR_WIDTH / tallest.max(1);
+            let percent = 100.0 * *count as f64 / self.samples as f64;
+            writeln!(
+                output,
+                "{label:>label_width$} | {:<BAR_WIDTH$} {percent:.2}%",
+                "#".repeat(bar)
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulate::*;
+
+    fn counted(results: impl IntoIterator<Item = Int>) -> BTreeMap<Int, usize> {
+        results.into_iter().fold(BTreeMap::new(), |mut counts, r| {
+            *counts.entry(r).or_insert(0) += 1;
+            counts
+        })
+    }
+
+    #[test]
+    fn percentiles() {
+        let counts = counted(1..=100);
+        assert_eq!(1, percentile(&counts, 100, 0));
+        assert_eq!(5, percentile(&counts, 100, 5));
+        assert_eq!(50, percentile(&counts, 100, 50));
+        assert_eq!(100, percentile(&counts, 100, 100));
+        assert_eq!(2, percentile(&counted([2, 2, 2, 9]), 4, 75));
+    }
+
+    #[test]
+    fn summary() {
+        let summary = summarize(&counted([4, 2, 2, 4])).unwrap();
+        assert_eq!(3.0, summary.mean);
+        assert_eq!(1.0, summary.std_dev);
+        assert_eq!(2, summary.median);
+        assert_eq!(vec![(2, 2, 2), (3, 3, 0), (4, 4, 2)], summary.histogram);
+        assert_eq!(BTreeMap::from([(2, 2), (4, 2)]), summary.counts);
+    }
+
+    #[test]
+    fn wide_results_are_bucketed() {
+        let summary = summarize(&counted(0..1000)).unwrap();
+        assert!(summary.histogram.len() <= MAX_ROWS);
+        assert_eq!(
+            1000,
+            summary.histogram.iter().map(|row| row.2).sum::<usize>()
+        );
+    }
+
+    #[test]
+    fn in_chunks() {
+        let mut rng = rand::thread_rng();
+        let mut simulation = Simulation::new(Exp::Const(3), 10);
+        assert!(!simulation.run(4, &mut rng, &Limits::default()).unwrap());
+        assert_eq!(0.4, simulation.progress());
+        assert!(simulation.run(100, &mut rng, &Limits::default()).unwrap());
+        assert_eq!(10, simulation.summary().unwrap().samples);
+    }
+
+    #[test]
+    fn exported() {
+        let summary = summarize(&counted([4, 2, 2, 4, 4])).unwrap();
+        assert_eq!(
+            Row {
+                outcome: 2,
+                count: 2,
+                probability: 0.4,
+                cumulative: 0.4
+            },
+            summary.rows()[0]
+        );
+        assert_eq!(
+            "outcome,count,probability,cumulative\n2,2,0.4,0.4\n4,3,0.6,1\n",
+            summary.csv()
+        );
+    }
+
+    #[test]
+    fn recorded() {
+        /// Lets the test read back what the simulation wrote
+        #[derive(Clone, Default)]
+        struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+        impl io::Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        let written = Shared::default();
+        let mut simulation = Simulation::new(Exp::Const(3), 2)
+            .recording(Box::new(written.clone()), Record::Csv)
+            .unwrap();
+        simulation.run(2, &mut rng, &Limits::default()).unwrap();
+        assert_eq!(b"total\n3\n3\n", written.0.borrow().as_slice());
+
+        let written = Shared::default();
+        let mut simulation = Simulation::new(Exp::Const(-2), 1)
+            .recording(Box::new(written.clone()), Record::Binary)
+            .unwrap();
+        simulation.run(1, &mut rng, &Limits::default()).unwrap();
+        assert_eq!((-2 as Int).to_le_bytes(), written.0.borrow().as_slice());
+    }
+
+    #[test]
+    fn no_samples() {
+        assert!(summarize(&BTreeMap::new()).is_none());
+    }
+}
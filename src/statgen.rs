@@ -0,0 +1,126 @@
+//! Ability score generation for fifth edition style characters. The rolled
+//! methods are ordinary dice expressions, evaluated once per ability.
+
+use itertools::Itertools;
+use rand::Rng;
+use std::fmt::Write;
+
+use crate::eval::{Exp, Pick, Repeat, Roll, Value};
+
+pub const ABILITIES: [&str; 6] = ["STR", "DEX", "CON", "INT", "WIS", "CHA"];
+
+/// How many points a point-buy array is allowed to spend
+pub const POINT_BUY_BUDGET: u32 = 27;
+
+pub const METHODS: &[&str] = &["4d6", "3d6", "point-buy"];
+
+/// Rolls six ability scores using one of the rolled methods. Scores from
+/// `4d6` are sorted best-first, ready to be assigned; scores from `3d6` stay
+/// in the order they were rolled, one per ability.
+#[allow(clippy::unnecessary_cast)]
+pub fn roll(method: &str, rng: &mut impl Rng) -> Result<Vec<i32>, String> {
+    let score = match method {
+        "4d6" => Roll::keep_highest(Exp::Const(4), Exp::Const(6), Exp::Const(3)),
+        "3d6" => Roll::simple(Exp::Const(3), Exp::Const(6)),
+        _ => return Err(format!("'{method}' is not a rolled method")),
+    };
+    // one roll for each ability, every one of which counts
+    let abilities = ABILITIES.len() as u32;
+    let exp = Exp::Repeat(Repeat {
+        exp: Box::new(Exp::roll(score)),
+        times: abilities,
+        pick: Pick::Best(abilities),
+    });
+    let scores = match exp.evaluate(rng) {
+        Value::Repeated { values, .. } => values.into_iter().map(|v| v.value() as i32),
+        _ => unreachable!("a repeat rolls every one of its repetitions"),
+    };
+    match method {
+        "4d6" => Ok(scores.sorted_by(|a, b| b.cmp(a)).collect()),
+        _ => Ok(scores.collect()),
+    }
+}
+
+/// The number of points the scores cost, or an error if they couldn't have
+/// been bought
+pub fn point_cost(scores: &[i32]) -> Result<u32, String> {
+    if scores.len() != ABILITIES.len() {
+        return Err(format!(
+            "Point buy needs exactly {} scores, not {}",
+            ABILITIES.len(),
+            scores.len()
+        ));
+    }
+    let mut total = 0;
+    for score in scores {
+        total += match score {
+            8..=13 => *score as u32 - 8,
+            14 => 7,
+            15 => 9,
+            _ => return Err(format!("{score} can't be bought; scores must be 8-15")),
+        };
+    }
+    if total > POINT_BUY_BUDGET {
+        return Err(format!(
+            "Those scores cost {total} points, but only {POINT_BUY_BUDGET} are available"
+        ));
+    }
+    Ok(total)
+}
+
+pub fn modifier(score: i32) -> i32 {
+    (score - 10).div_euclid(2)
+}
+
+/// A table of scores and their modifiers. When `assigned` is set the scores
+/// are labelled with the ability they belong to.
+pub fn format(scores: &[i32], assigned: bool) -> String {
+    let mut output = String::new();
+    for (i, score) in scores.iter().enumerate() {
+        let label = match assigned {
+            true => ABILITIES[i].to_string(),
+            false => format!("#{}", i + 1),
+        };
+        writeln!(output, "{label:<4}{score:>3} ({:+})", modifier(*score)).unwrap();
+    }
+    writeln!(output, "total: {}", scores.iter().sum::<i32>()).unwrap();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use crate::statgen::*;
+
+    #[test]
+    fn modifiers() {
+        assert_eq!(-1, modifier(8));
+        assert_eq!(-1, modifier(9));
+        assert_eq!(0, modifier(10));
+        assert_eq!(2, modifier(15));
+        assert_eq!(4, modifier(18));
+        assert_eq!(-5, modifier(1));
+    }
+
+    #[test]
+    fn point_buy() {
+        assert_eq!(Ok(27), point_cost(&[15, 15, 15, 8, 8, 8]));
+        assert_eq!(Ok(27), point_cost(&[15, 14, 13, 12, 10, 8]));
+        assert!(point_cost(&[15, 15, 15, 15, 8, 8]).is_err());
+        assert!(point_cost(&[16, 8, 8, 8, 8, 8]).is_err());
+        assert!(point_cost(&[10, 10, 10]).is_err());
+    }
+
+    #[test]
+    fn rolled_methods() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let scores = roll("4d6", &mut rng).unwrap();
+        assert_eq!(6, scores.len());
+        assert!(scores.iter().all(|s| (3..=18).contains(s)));
+        assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(6, roll("3d6", &mut rng).unwrap().len());
+        assert!(roll("point-buy", &mut rng).is_err());
+    }
+}
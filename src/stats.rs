@@ -0,0 +1,1588 @@
+//! Analytic statistics for dice expressions. Nothing in here rolls any dice;
+//! everything is computed from the structure of the expression itself, which
+//! means the answers are exact (floating point notwithstanding) and the same
+//! every time you ask.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::error::Error;
+use crate::eval::{
+    self, share, Condition, Exp, Explode, Explosion, Keep, Limits, Operation, Pick, Repeat, Roll,
+    MAX_EXPLOSIONS,
+};
+
+/// The maximum amount of work (roughly, inner loop iterations) we're willing
+/// to spend on a single exact calculation. Expressions like `1000d1000` have
+/// enormous supports, and it's better to give up than to lock up the caller.
+const WORK_LIMIT: usize = 50_000_000;
+
+/// The smallest and largest values an expression can possibly produce
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Range {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Range {
+    fn constant(value: i64) -> Self {
+        Range {
+            min: value,
+            max: value,
+        }
+    }
+
+    fn contains(&self, value: i64) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+/// The smallest and largest values an expression can produce, for dice that
+/// explode as many times as the default [`Limits`] allow
+pub fn range(exp: &Exp) -> Range {
+    range_within(exp, &Limits::default())
+}
+
+/// Like [`range`], for dice held to the given [`Limits::max_explosions`]
+pub fn range_within(exp: &Exp, limits: &Limits) -> Range {
+    match exp {
+        Exp::Const(c) => Range::constant(*c as i64),
+        Exp::Op(op) => {
+            let arguments = op.arguments.borrow();
+            let mut ranges = arguments.iter().map(|exp| range_within(exp, limits));
+            let first = ranges
+                .next()
+                .expect("operations always have at least one argument");
+            ranges.fold(first, |acc, r| match op.operation {
+                Operation::Add => Range {
+                    min: acc.min.saturating_add(r.min),
+                    max: acc.max.saturating_add(r.max),
+                },
+                Operation::Sub => Range {
+                    min: acc.min.saturating_sub(r.max),
+                    max: acc.max.saturating_sub(r.min),
+                },
+                Operation::Mul => {
+                    let products = [
+                        acc.min.saturating_mul(r.min),
+                        acc.min.saturating_mul(r.max),
+                        acc.max.saturating_mul(r.min),
+                        acc.max.saturating_mul(r.max),
+                    ];
+                    Range {
+                        min: *products.iter().min().unwrap(),
+                        max: *products.iter().max().unwrap(),
+                    }
+                }
+            })
+        }
+        Exp::Roll(roll) => roll_range(&roll.borrow(), limits),
+        Exp::Repeat(repeat) => {
+            let once = range_within(&repeat.exp, limits);
+            let kept = repeat.pick.kept(repeat.times) as i64;
+            Range {
+                min: once.min.saturating_mul(kept),
+                max: once.max.saturating_mul(kept),
+            }
+        }
+    }
+}
+
+fn roll_range(roll: &Roll, limits: &Limits) -> Range {
+    // negative dice counts mean we don't roll anything at all
+    let dice = range_within(&roll.dice, limits);
+    let explode = roll.explode.as_ref().map(|explosion| explosion.explode);
+    // every die can add as many extra dice as it's allowed to explode
+    let extra = match explode {
+        Some(Explode::Extra) => (limits.max_explosions as i64).saturating_add(1),
+        _ => 1,
+    };
+    let count = Range {
+        min: dice.min.max(0),
+        max: dice.max.max(0).saturating_mul(extra),
+    };
+
+    // the sign of the number of sides is ignored, and a zero-sided die always
+    // shows a zero
+    let sides = range_within(&roll.sides, limits);
+    let largest_face = sides.min.unsigned_abs().max(sides.max.unsigned_abs()) as i64;
+    // a die can compound onto itself as many times as it's allowed to explode
+    let largest_face = match explode {
+        Some(Explode::Compound) => {
+            largest_face.saturating_mul((limits.max_explosions as i64).saturating_add(1))
+        }
+        _ => largest_face,
+    };
+    let smallest_face = if sides.contains(0) { 0 } else { 1 };
+
+    let kept = match &roll.keep {
+        Keep::All => count,
+        Keep::Highest(exp) | Keep::Lowest(exp) => {
+            let keep = range_within(exp, limits);
+            Range {
+                min: keep.min.max(0).min(count.min),
+                max: keep.max.max(0).min(count.max),
+            }
+        }
+        Keep::HighestShare(percent) | Keep::LowestShare(percent) => {
+            let share = |count: i64| (share(*percent, count as usize) as i64).min(count);
+            Range {
+                min: share(count.min),
+                max: share(count.max),
+            }
+        }
+        Keep::DropLowest(exp) | Keep::DropHighest(exp) => {
+            let drop = range_within(exp, limits);
+            Range {
+                min: count.min.saturating_sub(drop.max.max(0)).max(0),
+                max: count.max.saturating_sub(drop.min.max(0)).max(0),
+            }
+        }
+    };
+
+    Range {
+        min: kept.min.saturating_mul(smallest_face),
+        max: kept.max.saturating_mul(largest_face),
+    }
+}
+
+/// The most dice an expression could roll, counting the ones rolled to find
+/// out how many dice (or sides) other rolls have, for dice that explode as
+/// many times as the default [`Limits`] allow
+pub fn max_dice(exp: &Exp) -> u64 {
+    max_dice_within(exp, &Limits::default())
+}
+
+/// Like [`max_dice`], for dice held to the given [`Limits::max_explosions`].
+/// This is what a roll costs when a shared roller is rationing them.
+pub fn max_dice_within(exp: &Exp, limits: &Limits) -> u64 {
+    match exp {
+        Exp::Const(_) => 0,
+        Exp::Op(op) => op
+            .arguments
+            .borrow()
+            .iter()
+            .map(|exp| max_dice_within(exp, limits))
+            .fold(0, u64::saturating_add),
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            let keep = roll
+                .keep
+                .count()
+                .map_or(0, |exp| max_dice_within(exp, limits));
+            // every die can be rerolled, and explode, as many times as it's
+            // allowed to
+            let (explosions, threshold) = match &roll.explode {
+                Some(explosion) => (
+                    limits.max_explosions as u64,
+                    explosion
+                        .on
+                        .as_ref()
+                        .map_or(0, |on| max_dice_within(&on.against, limits)),
+                ),
+                None => (0, 0),
+            };
+            let (rerolls, reroll_threshold) = match &roll.reroll {
+                Some(on) => (
+                    limits.max_explosions as u64,
+                    max_dice_within(&on.against, limits),
+                ),
+                None => (0, 0),
+            };
+            (range_within(&roll.dice, limits).max.max(0) as u64)
+                .saturating_mul(1 + explosions + rerolls)
+                .saturating_add(max_dice_within(&roll.dice, limits))
+                .saturating_add(max_dice_within(&roll.sides, limits))
+                .saturating_add(threshold)
+                .saturating_add(reroll_threshold)
+                .saturating_add(keep)
+        }
+        Exp::Repeat(repeat) => {
+            max_dice_within(&repeat.exp, limits).saturating_mul(repeat.times as u64)
+        }
+    }
+}
+
+/// The exact probability distribution of an expression's outcomes
+#[derive(Debug, PartialEq, Clone)]
+pub struct Distribution {
+    outcomes: BTreeMap<i64, f64>,
+}
+
+impl Distribution {
+    fn constant(value: i64) -> Self {
+        Distribution {
+            outcomes: BTreeMap::from([(value, 1.0)]),
+        }
+    }
+
+    fn uniform(sides: i64) -> Self {
+        let p = 1.0 / sides as f64;
+        Distribution {
+            outcomes: (1..=sides).map(|face| (face, p)).collect(),
+        }
+    }
+
+    /// What a die that's rerolled ends up showing. Each reroll is a fresh
+    /// chance at a face that stays, and the faces that are rerolled only
+    /// stay when the die runs out of rerolls.
+    fn rerolled(sides: i64, rerolling: Option<Rerolling>) -> Self {
+        let Some(rerolling) = rerolling else {
+            return Distribution::uniform(sides);
+        };
+        let p = 1.0 / sides as f64;
+        let q = (1..=sides).filter(|&face| rerolling.rerolls(face)).count() as f64 * p;
+        let stays: f64 = (0..=MAX_EXPLOSIONS as i32).map(|j| q.powi(j)).sum();
+        let kept = q.powi(MAX_EXPLOSIONS as i32);
+        Distribution {
+            outcomes: (1..=sides)
+                .map(|face| match rerolling.rerolls(face) {
+                    true => (face, p * kept),
+                    false => (face, p * stays),
+                })
+                .filter(|(_, p)| *p > 0.0)
+                .collect(),
+        }
+    }
+
+    /// A single die, along with every die it explodes into. Either way the
+    /// dice explode, that's what they all add up to. Only the first of them
+    /// is rerolled.
+    fn die(
+        sides: i64,
+        exploding: Option<Exploding>,
+        rerolling: Option<Rerolling>,
+        budget: &mut usize,
+    ) -> Option<Self> {
+        let first = Distribution::rerolled(sides, rerolling);
+        let Some(exploding) = exploding else {
+            return Some(first);
+        };
+        let uniform = Distribution::uniform(sides);
+        let mut outcomes = BTreeMap::new();
+        // what the faces that exploded so far add up to, after `j` of them
+        let mut reached = BTreeMap::from([(0, 1.0)]);
+        for j in 0..=MAX_EXPLOSIONS {
+            spend(budget, reached.len().saturating_mul(sides as usize))?;
+            // the faces that explode stop it too, once it can't explode any
+            // more
+            let last = j == MAX_EXPLOSIONS;
+            let rolled = if j == 0 { &first } else { &uniform };
+            let mut next = BTreeMap::new();
+            for (&total, &q) in &reached {
+                for (face, p) in rolled.iter() {
+                    let onwards = match last || !exploding.explodes(face) {
+                        true => &mut outcomes,
+                        false => &mut next,
+                    };
+                    *onwards.entry(total + face).or_insert(0.0) += q * p;
+                }
+            }
+            reached = next;
+            if reached.values().sum::<f64>() < NEGLIGIBLE {
+                break;
+            }
+        }
+        Some(Distribution { outcomes })
+    }
+
+    pub fn probability(&self, outcome: i64) -> f64 {
+        self.outcomes.get(&outcome).copied().unwrap_or(0.0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.outcomes.iter().map(|(&v, &p)| v as f64 * p).sum()
+    }
+
+    pub fn variance(&self) -> f64 {
+        let mean = self.mean();
+        self.outcomes
+            .iter()
+            .map(|(&v, &p)| p * (v as f64 - mean).powi(2))
+            .sum()
+    }
+
+    /// The smallest outcome rolled at least a `p` share of the time or
+    /// less, for `p` between 0 and 1
+    pub fn quantile(&self, p: f64) -> i64 {
+        let mut at_most = 0.0;
+        for (&outcome, &probability) in &self.outcomes {
+            at_most += probability;
+            // a little slack, so a d6's median comes out as 3 even when its
+            // sixths don't quite add up to a half
+            if at_most >= p - 1e-9 {
+                return outcome;
+            }
+        }
+        *self
+            .outcomes
+            .keys()
+            .next_back()
+            .expect("distributions have at least one outcome")
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.outcomes.iter().map(|(&v, &p)| (v, p))
+    }
+
+    /// Every outcome, lowest first, with the chance of rolling exactly that
+    /// and the chance of rolling at least that
+    pub fn table(&self) -> Table {
+        let mut at_least = 0.0;
+        let mut rows = self
+            .outcomes
+            .iter()
+            .rev()
+            .map(|(&outcome, &probability)| {
+                // summing from the top keeps the long tail from being
+                // swallowed by rounding
+                at_least += probability;
+                Row {
+                    outcome,
+                    probability,
+                    at_least: at_least.min(1.0),
+                }
+            })
+            .collect::<Vec<_>>();
+        rows.reverse();
+        Table { rows }
+    }
+
+    fn combine(
+        &self,
+        other: &Distribution,
+        budget: &mut usize,
+        f: impl Fn(i64, i64) -> i64,
+    ) -> Option<Distribution> {
+        spend(budget, self.outcomes.len() * other.outcomes.len())?;
+        let mut outcomes = BTreeMap::new();
+        for (&a, &pa) in &self.outcomes {
+            for (&b, &pb) in &other.outcomes {
+                *outcomes.entry(f(a, b)).or_insert(0.0) += pa * pb;
+            }
+        }
+        Some(Distribution { outcomes })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Row {
+    pub outcome: i64,
+    pub probability: f64,
+    pub at_least: f64,
+}
+
+/// The widest a bar gets in [`Table::at_least`]
+const CDF_WIDTH: usize = 40;
+
+/// An outcome-by-outcome probability table, like AnyDice prints
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Table {
+    pub rows: Vec<Row>,
+}
+
+impl Table {
+    /// The table in aligned columns, with the probabilities as percentages
+    pub fn report(&self) -> String {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.outcome.to_string().len())
+            .chain(["outcome".len()])
+            .max()
+            .unwrap_or(0);
+        let mut output = String::new();
+        writeln!(output, "{:>width$} {:>8} {:>8}", "outcome", "%", "at least").unwrap();
+        for row in &self.rows {
+            writeln!(
+                output,
+                "{:>width$} {:>8.4} {:>8.4}",
+                row.outcome,
+                100.0 * row.probability,
+                100.0 * row.at_least
+            )
+            .unwrap();
+        }
+        output
+    }
+
+    /// The chance of rolling at least each outcome, drawn as bars, which is
+    /// what matters when deciding whether a check is worth attempting
+    pub fn at_least(&self) -> String {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.outcome.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let mut output = String::new();
+        for row in &self.rows {
+            let bar = (row.at_least * CDF_WIDTH as f64).round() as usize;
+            writeln!(
+                output,
+                "{:>width$}+ {:>8.4}% {}",
+                row.outcome,
+                100.0 * row.at_least,
+                "#".repeat(bar)
+            )
+            .unwrap();
+        }
+        output
+    }
+
+    pub fn csv(&self) -> String {
+        let mut output = String::from("outcome,probability,at_least\n");
+        for row in &self.rows {
+            writeln!(
+                output,
+                "{},{},{}",
+                row.outcome, row.probability, row.at_least
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+/// The probability table of an expression, if its exact distribution can be
+/// worked out
+pub fn table(exp: &Exp) -> Result<Table, Error> {
+    distribution(exp)
+        .map(|distribution| distribution.table())
+        .ok_or_else(|| Error::Input("The expression is too complex to compute a table for".into()))
+}
+
+/// The outcome `p` of the way through an expression's distribution, so that
+/// `quantile(exp, 0.5)` is its median
+pub fn quantile(exp: &Exp, p: f64) -> Result<i64, Error> {
+    if !(0.0..=1.0).contains(&p) {
+        return Err(Error::Input(format!("{p} is not between 0 and 1").into()));
+    }
+    distribution(exp)
+        .map(|distribution| distribution.quantile(p))
+        .ok_or_else(|| {
+            Error::Input("The expression is too complex to compute a quantile for".into())
+        })
+}
+
+/// The chance of succeeding against each of a range of target numbers
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Odds {
+    /// Each target, lowest first, with the chance of succeeding against it
+    pub rows: Vec<(i64, f64)>,
+}
+
+/// The chance that one roll of the expression meets or beats each target
+/// from `lowest` to `highest`, or with `under`, that it rolls at most each
+/// one, as in roll-under systems
+pub fn odds(exp: &Exp, lowest: i64, highest: i64, under: bool) -> Result<Odds, Error> {
+    if lowest > highest {
+        let message =
+            format!("The lowest target ({lowest}) is higher than the highest ({highest})");
+        return Err(Error::Input(message.into()));
+    }
+    let distribution = distribution(exp).ok_or_else(|| {
+        Error::Input("The expression is too complex to compute the odds for".into())
+    })?;
+    let rows = (lowest..=highest)
+        .map(|target| {
+            let outcomes = match under {
+                true => distribution.outcomes.range(..=target).collect::<Vec<_>>(),
+                // summed from the top, like the table's at least column
+                false => distribution.outcomes.range(target..).rev().collect(),
+            };
+            // folded from 0 rather than summed, since an empty sum is -0
+            let chance = outcomes.into_iter().fold(0.0, |chance, (_, p)| chance + p);
+            (target, f64::min(chance, 1.0))
+        })
+        .collect();
+    Ok(Odds { rows })
+}
+
+impl Odds {
+    /// A row per target, with its chance as a percentage and a bar.
+    /// `target` is what the targets are called, like `DC`.
+    pub fn report(&self, target: &str) -> String {
+        let width = self
+            .rows
+            .iter()
+            .map(|(target, _)| target.to_string().len())
+            .chain([target.len()])
+            .max()
+            .unwrap_or(0);
+        let mut output = String::new();
+        writeln!(output, "{target:>width$} {:>8}", "chance").unwrap();
+        for (target, chance) in &self.rows {
+            let bar = (chance * CDF_WIDTH as f64).round() as usize;
+            writeln!(
+                output,
+                "{target:>width$} {:>7.2}% {}",
+                100.0 * chance,
+                "#".repeat(bar)
+            )
+            .unwrap();
+        }
+        output
+    }
+}
+
+/// The widest a bar gets on either side of a comparison's histogram
+const BAR_WIDTH: usize = 20;
+
+/// How two expressions measure up against one another
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Comparison {
+    pub means: (f64, f64),
+    pub variances: (f64, f64),
+    /// How much of the two distributions lies on top of one another, from 0
+    /// (they never roll the same thing) to 1 (they're identical)
+    pub overlap: f64,
+    /// The chance that the first rolls higher than the second
+    pub first_wins: f64,
+    pub tie: f64,
+    /// Every outcome either can roll, with its chance under each
+    pub outcomes: Vec<(i64, f64, f64)>,
+}
+
+/// Compares the exact distributions of two expressions, as though each were
+/// rolled once
+pub fn compare(first: &Exp, second: &Exp) -> Result<Comparison, Error> {
+    let too_complex = |which| {
+        Error::Input(
+            format!("The {which} expression is too complex to compute a distribution for").into(),
+        )
+    };
+    let a = distribution(first).ok_or_else(|| too_complex("first"))?;
+    let b = distribution(second).ok_or_else(|| too_complex("second"))?;
+
+    let mut outcomes = BTreeMap::new();
+    for (v, p) in a.iter() {
+        outcomes.entry(v).or_insert((0.0, 0.0)).0 = p;
+    }
+    for (v, p) in b.iter() {
+        outcomes.entry(v).or_insert((0.0, 0.0)).1 = p;
+    }
+
+    // walking up through the outcomes, the chance that the second rolled
+    // lower than each one accumulates as we go
+    let (mut below, mut first_wins, mut tie) = (0.0, 0.0, 0.0);
+    for (pa, pb) in outcomes.values() {
+        first_wins += pa * below;
+        tie += pa * pb;
+        below += pb;
+    }
+    Ok(Comparison {
+        means: (a.mean(), b.mean()),
+        variances: (a.variance(), b.variance()),
+        overlap: outcomes.values().map(|(pa, pb)| pa.min(*pb)).sum(),
+        first_wins,
+        tie,
+        outcomes: outcomes
+            .into_iter()
+            .map(|(v, (pa, pb))| (v, pa, pb))
+            .collect(),
+    })
+}
+
+impl Comparison {
+    /// The figures, then a histogram with the first expression's bars growing
+    /// to the left and the second's to the right
+    pub fn report(&self, first: &str, second: &str) -> String {
+        let mut output = String::new();
+        let percent = |p: f64| format!("{:.2}%", 100.0 * p);
+        writeln!(output, "mean: {:.2} vs {:.2}", self.means.0, self.means.1).unwrap();
+        writeln!(
+            output,
+            "variance: {:.2} vs {:.2}",
+            self.variances.0, self.variances.1
+        )
+        .unwrap();
+        writeln!(output, "overlap: {}", percent(self.overlap)).unwrap();
+        writeln!(output, "{first} wins: {}", percent(self.first_wins)).unwrap();
+        writeln!(output, "tie: {}", percent(self.tie)).unwrap();
+        let second_wins = (1.0 - self.first_wins - self.tie).max(0.0);
+        writeln!(output, "{second} wins: {}", percent(second_wins)).unwrap();
+        output.push('\n');
+
+        let width = self
+            .outcomes
+            .iter()
+            .map(|(v, _, _)| v.to_string().len())
+            .max()
+            .unwrap_or(0);
+        let tallest = self
+            .outcomes
+            .iter()
+            .map(|(_, pa, pb)| pa.max(*pb))
+            .fold(0.0, f64::max);
+        let bar = |p: f64| "#".repeat((p / tallest * BAR_WIDTH as f64).round() as usize);
+        writeln!(output, "{first:>BAR_WIDTH$} {:width$} {second}", "").unwrap();
+        for (v, pa, pb) in &self.outcomes {
+            writeln!(output, "{:>BAR_WIDTH$} {v:>width$} {}", bar(*pa), bar(*pb)).unwrap();
+        }
+        output
+    }
+}
+
+/// A chance too small to make any difference. A die that has exploded this
+/// unlikely a number of times is left out of its distribution.
+const NEGLIGIBLE: f64 = 1e-15;
+
+/// How a pool's dice explode, once what they're held up against is known
+#[derive(Debug, Clone, Copy)]
+struct Exploding {
+    explode: Explode,
+    comparison: eval::Comparison,
+    against: i64,
+}
+
+impl Exploding {
+    /// For dice with `sides` sides, which explode on their highest face
+    /// unless the explosion says otherwise
+    fn new(explosion: &Explosion, against: i64, sides: i64) -> Self {
+        match &explosion.on {
+            Some(on) => Exploding {
+                explode: explosion.explode,
+                comparison: on.comparison,
+                against,
+            },
+            None => Exploding {
+                explode: explosion.explode,
+                comparison: eval::Comparison::Equal,
+                against: sides,
+            },
+        }
+    }
+
+    fn explodes(&self, face: i64) -> bool {
+        self.comparison.holds(face, self.against)
+    }
+}
+
+/// How a pool's dice are rerolled, once what they're held up against is
+/// known
+#[derive(Debug, Clone, Copy)]
+struct Rerolling {
+    comparison: eval::Comparison,
+    against: i64,
+}
+
+impl Rerolling {
+    fn new(on: &Condition<Exp>, against: i64) -> Self {
+        Rerolling {
+            comparison: on.comparison,
+            against,
+        }
+    }
+
+    fn rerolls(&self, face: i64) -> bool {
+        self.comparison.holds(face, self.against)
+    }
+}
+
+/// The distribution of what a roll's dice are compared to when they explode
+/// or are rerolled, which is nothing in particular when they aren't
+fn thresholds(on: Option<&Condition<Exp>>, budget: &mut usize) -> Option<Distribution> {
+    match on {
+        Some(on) => exact(&on.against, budget),
+        None => Some(Distribution::constant(0)),
+    }
+}
+
+/// What a roll's dice are compared to when they explode
+fn explosion(roll: &Roll) -> Option<&Condition<Exp>> {
+    roll.explode
+        .as_ref()
+        .and_then(|explosion| explosion.on.as_ref())
+}
+
+/// Deducts work from the budget, returning `None` once it has been exhausted
+fn spend(budget: &mut usize, amount: usize) -> Option<()> {
+    *budget = budget.checked_sub(amount)?;
+    Some(())
+}
+
+/// Computes the exact distribution of an expression, or `None` if doing so
+/// would be prohibitively expensive
+pub fn distribution(exp: &Exp) -> Option<Distribution> {
+    let mut budget = WORK_LIMIT;
+    exact(exp, &mut budget)
+}
+
+fn exact(exp: &Exp, budget: &mut usize) -> Option<Distribution> {
+    match exp {
+        Exp::Const(c) => Some(Distribution::constant(*c as i64)),
+        Exp::Op(op) => {
+            let arguments = op.arguments.borrow();
+            let mut arguments = arguments.iter();
+            let first = arguments
+                .next()
+                .expect("operations always have at least one argument");
+            let mut acc = exact(first, budget)?;
+            for argument in arguments {
+                let next = exact(argument, budget)?;
+                acc = match op.operation {
+                    Operation::Add => acc.combine(&next, budget, |a, b| a.saturating_add(b))?,
+                    Operation::Sub => acc.combine(&next, budget, |a, b| a.saturating_sub(b))?,
+                    Operation::Mul => acc.combine(&next, budget, |a, b| a.saturating_mul(b))?,
+                };
+            }
+            Some(acc)
+        }
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            let sides = exact(&roll.sides, budget)?;
+            let dice = exact(&roll.dice, budget)?;
+            let keep = match roll.keep.count() {
+                Some(exp) => exact(exp, budget)?,
+                // shares are worked out from each number of dice below
+                None => Distribution::constant(0),
+            };
+            let rerolls = thresholds(roll.reroll.as_ref(), budget)?;
+            let thresholds = thresholds(explosion(&roll), budget)?;
+            let mut outcomes = BTreeMap::new();
+            for (s, ps) in sides.iter() {
+                for (n, pn) in dice.iter() {
+                    for (t, pt) in thresholds.iter() {
+                        let exploding = roll
+                            .explode
+                            .as_ref()
+                            .map(|explosion| Exploding::new(explosion, t, s.abs()));
+                        for (r, pr) in rerolls.iter() {
+                            let rerolling = roll.reroll.as_ref().map(|on| Rerolling::new(on, r));
+                            for (k, pk) in keep.iter() {
+                                let rule = Pool::new(&roll.keep, k, n);
+                                let pool =
+                                    pool(n.max(0), s.abs(), rule, exploding, rerolling, budget)?;
+                                for (v, p) in pool.iter() {
+                                    *outcomes.entry(v).or_insert(0.0) += ps * pn * pt * pr * pk * p;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Some(Distribution { outcomes })
+        }
+        Exp::Repeat(repeat) => {
+            let once = exact(&repeat.exp, budget)?;
+            repeated(repeat, &once, |_| 1.0, budget)
+        }
+    }
+}
+
+/// The distribution of a repeated expression, given the distribution of a
+/// single repetition. Each total that's kept has its chance scaled by
+/// `factor`, which [`spared`] uses to count only the repetitions spared a
+/// face.
+fn repeated(
+    repeat: &Repeat,
+    once: &Distribution,
+    factor: impl Fn(i64) -> f64,
+    budget: &mut usize,
+) -> Option<Distribution> {
+    let mut outcomes: Vec<(i64, f64)> = once.iter().filter(|(_, p)| *p > 0.0).collect();
+    if let Pick::Best(_) = repeat.pick {
+        outcomes.reverse();
+    }
+    let n = repeat.times as i64;
+    taken(
+        &outcomes,
+        n,
+        repeat.pick.kept(repeat.times) as i64,
+        factor,
+        budget,
+    )
+}
+
+/// Which dice in a pool count towards the total
+#[derive(Debug, Clone, Copy)]
+enum Pool {
+    All,
+    Highest(i64),
+    Lowest(i64),
+}
+
+impl Pool {
+    /// The rule for a pool of `n` dice, keeping `k` of them unless the keep
+    /// is a share of however many there are, or dropping `k` of them
+    fn new(keep: &Keep, k: i64, n: i64) -> Self {
+        let share = |percent| share(percent, n.max(0) as usize) as i64;
+        match keep {
+            Keep::All => Pool::All,
+            Keep::Highest(_) => Pool::Highest(k),
+            Keep::Lowest(_) => Pool::Lowest(k),
+            Keep::HighestShare(percent) => Pool::Highest(share(*percent)),
+            Keep::LowestShare(percent) => Pool::Lowest(share(*percent)),
+            Keep::DropLowest(_) => Pool::Highest(n.max(0) - k.clamp(0, n.max(0))),
+            Keep::DropHighest(_) => Pool::Lowest(n.max(0) - k.clamp(0, n.max(0))),
+        }
+    }
+
+    /// The number of dice that will actually be kept from a pool of `n`
+    fn kept(&self, n: i64) -> i64 {
+        match self {
+            Pool::All => n,
+            Pool::Highest(k) | Pool::Lowest(k) => (*k).clamp(0, n),
+        }
+    }
+}
+
+/// The distribution of a pool of `n` dice with `s` sides. This mirrors the
+/// evaluator: a zero-sided die always shows zero.
+fn pool(
+    n: i64,
+    s: i64,
+    rule: Pool,
+    exploding: Option<Exploding>,
+    rerolling: Option<Rerolling>,
+    budget: &mut usize,
+) -> Option<Distribution> {
+    let k = rule.kept(n);
+    if s == 0 || k == 0 {
+        return Some(Distribution::constant(0));
+    }
+    // there's no telling how many dice explode into extra ones, so there's
+    // no working out which of them would be kept
+    if let (
+        Some(Exploding {
+            explode: Explode::Extra,
+            ..
+        }),
+        Pool::Highest(_) | Pool::Lowest(_),
+    ) = (exploding, rule)
+    {
+        return None;
+    }
+    let die = Distribution::die(s, exploding, rerolling, budget)?;
+    if let Pool::All = rule {
+        let mut acc = Distribution::constant(0);
+        for _ in 0..n {
+            acc = acc.combine(&die, budget, |a, b| a + b)?;
+        }
+        return Some(acc);
+    }
+
+    let mut faces: Vec<(i64, f64)> = die.iter().collect();
+    if let Pool::Highest(_) = rule {
+        faces.reverse();
+    }
+    taken(&faces, n, k, |_| 1.0, budget)
+}
+
+/// The total of the first `k` of `n` independent draws, when the draws are
+/// taken in the order the outcomes are listed (highest first, when keeping
+/// the highest). Each draw taken has its chance scaled by `factor`.
+fn taken(
+    outcomes: &[(i64, f64)],
+    n: i64,
+    k: i64,
+    factor: impl Fn(i64) -> f64,
+    budget: &mut usize,
+) -> Option<Distribution> {
+    if k == 0 {
+        return Some(Distribution::constant(0));
+    }
+    // Walk the outcomes in order, deciding how many of the remaining draws
+    // land on each. The first `k` draws assigned are the ones that count.
+    // Probabilities are tracked in log space since the binomial coefficients
+    // get huge.
+    let ln_factorial = ln_factorials(n);
+    // states are keyed by (draws assigned so far, total of the ones taken)
+    let mut states: BTreeMap<(i64, i64), f64> = BTreeMap::from([((0, 0), 1.0)]);
+    for (i, &(outcome, p)) in outcomes.iter().enumerate() {
+        let last = i == outcomes.len() - 1;
+        let (ln_p, factor) = (p.ln(), factor(outcome));
+        let mut next = BTreeMap::new();
+        for (&(assigned, total), &p) in &states {
+            let remaining = n - assigned;
+            spend(budget, remaining as usize + 1)?;
+            // the final outcome has to soak up every draw that's left
+            let counts = if last {
+                remaining..=remaining
+            } else {
+                0..=remaining
+            };
+            for m in counts {
+                let ln_choose = ln_factorial[remaining as usize]
+                    - ln_factorial[m as usize]
+                    - ln_factorial[(remaining - m) as usize];
+                let weight = (ln_choose + m as f64 * ln_p).exp();
+                if weight == 0.0 {
+                    continue;
+                }
+                let newly_kept = (assigned + m).min(k) - assigned.min(k);
+                *next
+                    .entry((assigned + m, total + newly_kept * outcome))
+                    .or_insert(0.0) += p * weight * factor.powi(newly_kept as i32);
+            }
+        }
+        states = next;
+    }
+    let mut outcomes = BTreeMap::new();
+    for ((_, total), p) in states {
+        *outcomes.entry(total).or_insert(0.0) += p;
+    }
+    Some(Distribution { outcomes })
+}
+
+/// Which end of a die a crit chance is about
+#[derive(Debug, Clone, Copy)]
+enum Face {
+    Highest,
+    Lowest,
+}
+
+/// The chance that at least one kept die in an expression lands on its
+/// highest face (a critical hit, usually) and on its lowest. Dice that get
+/// dropped don't count, so `2d20k1` crits whenever either die shows a 20 but
+/// `2d20kl1` only when both do.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct Crits {
+    pub highest: f64,
+    pub lowest: f64,
+}
+
+/// The crit chances of an expression, if its exact distribution can be
+/// worked out
+pub fn crits(exp: &Exp) -> Result<Crits, Error> {
+    let chance = |face| {
+        let mut budget = WORK_LIMIT;
+        let spared = spared(exp, face, &mut budget)?;
+        Some((1.0 - spared.iter().map(|(_, p)| p).sum::<f64>()).clamp(0.0, 1.0))
+    };
+    match (chance(Face::Highest), chance(Face::Lowest)) {
+        (Some(highest), Some(lowest)) => Ok(Crits { highest, lowest }),
+        _ => Err(Error::Input(
+            "The expression is too complex to compute crit chances for".into(),
+        )),
+    }
+}
+
+/// The distribution of an expression over only the rolls where no kept die
+/// shows the face, so its probabilities add up to the chance of that
+fn spared(exp: &Exp, face: Face, budget: &mut usize) -> Option<Distribution> {
+    match exp {
+        Exp::Const(c) => Some(Distribution::constant(*c as i64)),
+        Exp::Op(op) => {
+            let arguments = op.arguments.borrow();
+            let mut arguments = arguments.iter();
+            let first = arguments
+                .next()
+                .expect("operations always have at least one argument");
+            let mut acc = spared(first, face, budget)?;
+            for argument in arguments {
+                let next = spared(argument, face, budget)?;
+                acc = match op.operation {
+                    Operation::Add => acc.combine(&next, budget, |a, b| a.saturating_add(b))?,
+                    Operation::Sub => acc.combine(&next, budget, |a, b| a.saturating_sub(b))?,
+                    Operation::Mul => acc.combine(&next, budget, |a, b| a.saturating_mul(b))?,
+                };
+            }
+            Some(acc)
+        }
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            // a die that explodes goes right past its highest face, and what
+            // it comes to isn't a face of the die at all, while a die that's
+            // rerolled isn't as likely to show one face as another
+            if roll.explode.is_some() || roll.reroll.is_some() {
+                return None;
+            }
+            let sides = spared(&roll.sides, face, budget)?;
+            let dice = spared(&roll.dice, face, budget)?;
+            let keep = match roll.keep.count() {
+                Some(exp) => spared(exp, face, budget)?,
+                None => Distribution::constant(0),
+            };
+            let mut outcomes = BTreeMap::new();
+            for (s, ps) in sides.iter() {
+                for (n, pn) in dice.iter() {
+                    for (k, pk) in keep.iter() {
+                        let rule = Pool::new(&roll.keep, k, n);
+                        let pool = spared_pool(n.max(0), s.abs(), rule, face, budget)?;
+                        for (v, p) in pool.iter() {
+                            *outcomes.entry(v).or_insert(0.0) += ps * pn * pk * p;
+                        }
+                    }
+                }
+            }
+            Some(Distribution { outcomes })
+        }
+        // a repetition's dice only count if its total does, and whether they
+        // spared the face is independent of which of several equal totals
+        // gets kept
+        Exp::Repeat(repeat) => {
+            let once = exact(&repeat.exp, budget)?;
+            let spared = spared(&repeat.exp, face, budget)?;
+            let factor = |total| spared.probability(total) / once.probability(total);
+            repeated(repeat, &once, factor, budget)
+        }
+    }
+}
+
+/// A pool's distribution over only the rolls where none of its kept dice
+/// show the face. If `j` dice show it and none of them are kept, the rest
+/// are a pool of dice with one face fewer.
+fn spared_pool(n: i64, s: i64, rule: Pool, face: Face, budget: &mut usize) -> Option<Distribution> {
+    let k = rule.kept(n);
+    if s == 0 || k == 0 {
+        return Some(Distribution::constant(0));
+    }
+    // a one-sided die shows its highest and lowest face at once
+    if s == 1 {
+        return Some(Distribution {
+            outcomes: BTreeMap::new(),
+        });
+    }
+    // when the kept dice are taken from the face's end, any die showing it
+    // is kept; from the other end, it takes more than `n - k` of them
+    let most = match (rule, face) {
+        (Pool::Lowest(_), Face::Highest) | (Pool::Highest(_), Face::Lowest) => n - k,
+        _ => 0,
+    };
+    let ln_factorial = ln_factorials(n);
+    let (ln_hit, ln_miss) = (-(s as f64).ln(), ((s - 1) as f64 / s as f64).ln());
+    let mut outcomes = BTreeMap::new();
+    for j in 0..=most {
+        let rest = n - j;
+        let ln_choose =
+            ln_factorial[n as usize] - ln_factorial[j as usize] - ln_factorial[rest as usize];
+        let weight = (ln_choose + j as f64 * ln_hit + rest as f64 * ln_miss).exp();
+        // without their lowest face, the dice run from 2 instead of 1
+        let shift = match face {
+            Face::Highest => 0,
+            Face::Lowest => rule.kept(rest),
+        };
+        for (v, p) in pool(rest, s - 1, rule, None, None, budget)?.iter() {
+            *outcomes.entry(v + shift).or_insert(0.0) += weight * p;
+        }
+    }
+    Some(Distribution { outcomes })
+}
+
+fn ln_factorials(n: i64) -> Vec<f64> {
+    let mut table = vec![0.0; n as usize + 1];
+    for i in 1..table.len() {
+        table[i] = table[i - 1] + (i as f64).ln();
+    }
+    table
+}
+
+/// Computes the expected value of an expression. This is much cheaper than
+/// building the full distribution, because sums and products of independent
+/// sub-expressions can be handled by linearity. `None` is returned if the
+/// calculation would be prohibitively expensive.
+pub fn expected_value(exp: &Exp) -> Option<f64> {
+    let mut budget = WORK_LIMIT;
+    mean(exp, &mut budget)
+}
+
+fn mean(exp: &Exp, budget: &mut usize) -> Option<f64> {
+    match exp {
+        Exp::Const(c) => Some(*c as f64),
+        Exp::Op(op) => {
+            let arguments = op.arguments.borrow();
+            let mut means = arguments.iter().map(|exp| mean(exp, budget));
+            let first = means
+                .next()
+                .expect("operations always have at least one argument")?;
+            means.try_fold(first, |acc, m| {
+                let m = m?;
+                // every sub-expression rolls its own dice, so they are all
+                // independent and the product of the means is the mean of the
+                // product
+                Some(match op.operation {
+                    Operation::Add => acc + m,
+                    Operation::Sub => acc - m,
+                    Operation::Mul => acc * m,
+                })
+            })
+        }
+        Exp::Roll(roll) => roll_mean(&roll.borrow(), budget),
+        Exp::Repeat(_) => Some(exact(exp, budget)?.mean()),
+    }
+}
+
+fn roll_mean(roll: &Roll, budget: &mut usize) -> Option<f64> {
+    // In the overwhelmingly common case, we're keeping every die and the
+    // number of dice and sides can't be negative or zero. Then the mean is
+    // just the product of the means, which works no matter how big the pool.
+    if let (Keep::All, None, None) = (&roll.keep, &roll.explode, &roll.reroll) {
+        let dice = range(&roll.dice);
+        let sides = range(&roll.sides);
+        if dice.min >= 0 && sides.min > 0 {
+            let dice = mean(&roll.dice, budget)?;
+            let sides = mean(&roll.sides, budget)?;
+            return Some(dice * (sides + 1.0) / 2.0);
+        }
+    }
+
+    // otherwise, consider every combination of dice, sides, and keep counts
+    let sides = exact(&roll.sides, budget)?;
+    let dice = exact(&roll.dice, budget)?;
+    let keep = match roll.keep.count() {
+        Some(exp) => exact(exp, budget)?,
+        None => Distribution::constant(0),
+    };
+    let rerolls = thresholds(roll.reroll.as_ref(), budget)?;
+    let thresholds = thresholds(explosion(roll), budget)?;
+    let mut total = 0.0;
+    for (s, ps) in sides.iter() {
+        for (n, pn) in dice.iter() {
+            for (t, pt) in thresholds.iter() {
+                let exploding = roll
+                    .explode
+                    .as_ref()
+                    .map(|explosion| Exploding::new(explosion, t, s.abs()));
+                for (r, pr) in rerolls.iter() {
+                    let rerolling = roll.reroll.as_ref().map(|on| Rerolling::new(on, r));
+                    for (k, pk) in keep.iter() {
+                        let rule = Pool::new(&roll.keep, k, n);
+                        let mean =
+                            pool_mean(n.max(0), s.abs(), rule, exploding, rerolling, budget)?;
+                        total += ps * pn * pt * pr * pk * mean;
+                    }
+                }
+            }
+        }
+    }
+    Some(total)
+}
+
+/// The mean of a pool of dice, computed from order statistics. For each face
+/// `x`, the number of kept dice showing at least `x` depends only on how many
+/// dice in the whole pool clear that bar, which is binomially distributed.
+/// Summing those counts over every face gives the total of the kept dice.
+fn pool_mean(
+    n: i64,
+    s: i64,
+    rule: Pool,
+    exploding: Option<Exploding>,
+    rerolling: Option<Rerolling>,
+    budget: &mut usize,
+) -> Option<f64> {
+    let k = rule.kept(n);
+    if s == 0 || k == 0 {
+        return Some(0.0);
+    }
+    match (rule, exploding, rerolling) {
+        (Pool::All, None, None) => return Some(n as f64 * (s as f64 + 1.0) / 2.0),
+        (Pool::All, _, _) => {
+            let die = Distribution::die(s, exploding, rerolling, budget)?;
+            return Some(n as f64 * die.mean());
+        }
+        // exploding and rerolled dice don't have evenly likely faces to
+        // count off
+        (_, Some(_), _) | (_, _, Some(_)) => {
+            return Some(pool(n, s, rule, exploding, rerolling, budget)?.mean())
+        }
+        _ => {}
+    }
+    spend(budget, (s as usize).saturating_mul(n as usize + 1))?;
+    let ln_factorial = ln_factorials(n);
+    let binomial = |j: i64, p: f64| -> f64 {
+        if p <= 0.0 {
+            return if j == 0 { 1.0 } else { 0.0 };
+        }
+        if p >= 1.0 {
+            return if j == n { 1.0 } else { 0.0 };
+        }
+        let ln_choose =
+            ln_factorial[n as usize] - ln_factorial[j as usize] - ln_factorial[(n - j) as usize];
+        (ln_choose + j as f64 * p.ln() + (n - j) as f64 * (1.0 - p).ln()).exp()
+    };
+    let mut total = 0.0;
+    for x in 1..=s {
+        total += match rule {
+            // the number of kept dice showing at least x is min(k, B) where B
+            // is the number of dice in the pool showing at least x
+            Pool::Highest(_) => {
+                let p = (s - x + 1) as f64 / s as f64;
+                (0..=n)
+                    .map(|j| binomial(j, p) * j.min(k) as f64)
+                    .sum::<f64>()
+            }
+            // the number of kept dice showing at least x is k minus however
+            // many of the pool's dice fall below x
+            Pool::Lowest(_) => {
+                let p = (x - 1) as f64 / s as f64;
+                (0..=n)
+                    .map(|j| binomial(j, p) * (k - j).max(0) as f64)
+                    .sum::<f64>()
+            }
+            Pool::All => unreachable!("variant was handled earlier"),
+        };
+    }
+    Some(total)
+}
+
+/// Computes the variance of an expression, exactly and without building its
+/// whole distribution wherever sums and products of independent pools allow
+/// it. `None` is returned if the calculation would be prohibitively
+/// expensive.
+pub fn variance(exp: &Exp) -> Option<f64> {
+    let mut budget = WORK_LIMIT;
+    moments(exp, &mut budget).map(|(_, variance)| variance)
+}
+
+/// The standard deviation of an expression, computed like its [`variance`]
+pub fn std_dev(exp: &Exp) -> Option<f64> {
+    variance(exp).map(f64::sqrt)
+}
+
+/// The mean and variance of an expression
+fn moments(exp: &Exp, budget: &mut usize) -> Option<(f64, f64)> {
+    match exp {
+        Exp::Const(c) => Some((*c as f64, 0.0)),
+        Exp::Op(op) => {
+            let arguments = op.arguments.borrow();
+            let mut moments = arguments.iter().map(|exp| moments(exp, budget));
+            let first = moments
+                .next()
+                .expect("operations always have at least one argument")?;
+            moments.try_fold(first, |(m1, v1), next| {
+                let (m2, v2) = next?;
+                // the arguments are independent, so their variances add, and
+                // a product's comes from its arguments' second moments
+                Some(match op.operation {
+                    Operation::Add => (m1 + m2, v1 + v2),
+                    Operation::Sub => (m1 - m2, v1 + v2),
+                    Operation::Mul => (m1 * m2, product_variance((m1, v1), (m2, v2))),
+                })
+            })
+        }
+        Exp::Roll(roll) => {
+            let roll = roll.borrow();
+            if let (Keep::All, None, None) = (&roll.keep, &roll.explode, &roll.reroll) {
+                let (dice, sides) = (range(&roll.dice), range(&roll.sides));
+                if dice.min >= 0 && sides.min > 0 {
+                    let (n, vn) = moments(&roll.dice, budget)?;
+                    let (s, vs) = moments(&roll.sides, budget)?;
+                    // given the pool's size and die, each die adds (s + 1) / 2
+                    // on average and varies by (s^2 - 1) / 12; the variance
+                    // of the total is the average of the latter plus the
+                    // variance of the former
+                    let within = n * (vs + s * s - 1.0) / 12.0;
+                    let between = product_variance((n, vn), ((s + 1.0) / 2.0, vs / 4.0));
+                    return Some((n * (s + 1.0) / 2.0, within + between));
+                }
+            }
+            let distribution = exact(exp, budget)?;
+            Some((distribution.mean(), distribution.variance()))
+        }
+        Exp::Repeat(_) => {
+            let distribution = exact(exp, budget)?;
+            Some((distribution.mean(), distribution.variance()))
+        }
+    }
+}
+
+/// The variance of the product of two independent variables, given each's
+/// mean and variance
+fn product_variance((m1, v1): (f64, f64), (m2, v2): (f64, f64)) -> f64 {
+    (v1 + m1 * m1) * (v2 + m2 * m2) - m1 * m1 * m2 * m2
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse;
+    use crate::stats::*;
+
+    fn assert_close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "expected {expected} but got {actual}"
+        );
+    }
+
+    #[test]
+    fn range_of_simple_roll() -> Result<(), String> {
+        assert_eq!(Range { min: 3, max: 18 }, range(&parse("3d6")?));
+        assert_eq!(Range { min: -3, max: 10 }, range(&parse("2d6 - d4 - 1")?));
+        assert_eq!(Range { min: 1, max: 20 }, range(&parse("2d20k1")?));
+        Ok(())
+    }
+
+    #[test]
+    fn range_of_recursive_roll() -> Result<(), String> {
+        assert_eq!(Range { min: 3, max: 96 }, range(&parse("(3d4)d8")?));
+        Ok(())
+    }
+
+    #[test]
+    fn most_dice() -> Result<(), String> {
+        assert_eq!(0, max_dice(&parse("5 * 3")?));
+        assert_eq!(5, max_dice(&parse("3d6 + 2d20k1")?));
+        // two to find the count, and then as many as eight more
+        assert_eq!(10, max_dice(&parse("(2d4)d8")?));
+        assert_eq!(999_999, max_dice(&parse("999999d999999")?));
+        Ok(())
+    }
+
+    #[test]
+    fn quantiles() -> Result<(), String> {
+        let exp = parse("3d6")?;
+        assert_eq!(3, quantile(&exp, 0.0)?);
+        assert_eq!(10, quantile(&exp, 0.5)?);
+        assert_eq!(18, quantile(&exp, 1.0)?);
+        assert_eq!(3, quantile(&parse("d6")?, 0.5)?);
+        assert_eq!(15, quantile(&parse("2d20k1")?, 0.5)?);
+        assert!(quantile(&exp, 1.5).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn variances() -> Result<(), String> {
+        assert_close(35.0 / 4.0, variance(&parse("3d6")?).unwrap());
+        assert_close(0.0, variance(&parse("2 * 3 + 1")?).unwrap());
+        // these agree with the exact distributions
+        for input in [
+            "2d6 - d4 * 3",
+            "(d4)d6",
+            "d(d8)",
+            "4d6k3 + d8",
+            "2 * (d4 + 1)",
+        ] {
+            let exp = parse(input)?;
+            let distribution = distribution(&exp).unwrap();
+            assert_close(distribution.variance(), variance(&exp).unwrap());
+        }
+        assert_close(9129.0, std_dev(&parse("1000d1000")?).unwrap().round());
+        Ok(())
+    }
+
+    #[test]
+    fn expected_value_of_sum() -> Result<(), String> {
+        assert_close(10.5, expected_value(&parse("3d6")?).unwrap());
+        assert_close(12.0, expected_value(&parse("2d6 + 5")?).unwrap());
+        assert_close(4.5 * 3.5, expected_value(&parse("(d8)d6")?).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn expected_value_of_keep() -> Result<(), String> {
+        assert_close(13.825, expected_value(&parse("2d20k1")?).unwrap());
+        assert_close(7.175, expected_value(&parse("2d20kl1")?).unwrap());
+        assert_close(15869.0 / 1296.0, expected_value(&parse("4d6k3")?).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn expected_value_of_huge_pool() -> Result<(), String> {
+        assert_close(500500.0, expected_value(&parse("1000d1000")?).unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn compounding_dice() -> Result<(), String> {
+        // a d6 is rolled 6/5 times on average, and comes to 3.5 each time
+        assert_close(4.2, expected_value(&parse("d6!!")?).unwrap());
+        assert_close(8.4, expected_value(&parse("2d6!!")?).unwrap());
+        let d6 = distribution(&parse("d6!!")?).unwrap();
+        assert_close(1.0 / 36.0, d6.probability(8));
+        assert_eq!(0.0, d6.probability(6));
+        let exp = parse("3d6!!k2")?;
+        assert_close(
+            distribution(&exp).unwrap().mean(),
+            expected_value(&exp).unwrap(),
+        );
+        assert_eq!(Range { min: 2, max: 1212 }, range(&parse("2d6!!")?));
+        assert_eq!(101, max_dice(&parse("d6!!")?));
+        let limits = Limits {
+            max_explosions: 20,
+            ..Limits::default()
+        };
+        assert_eq!(21, max_dice_within(&parse("d6!!")?, &limits));
+        assert_eq!(
+            Range { min: 2, max: 252 },
+            range_within(&parse("2d6!!")?, &limits)
+        );
+        assert!(crits(&parse("d6!!")?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn explosion_thresholds() -> Result<(), String> {
+        // kept whole, extra dice add up the same as compounded ones
+        assert_close(4.2, expected_value(&parse("d6!")?).unwrap());
+        // a d10 is rolled 5/4 times on average when it explodes on 9 or 10
+        assert_close(6.875, expected_value(&parse("d10!>=9")?).unwrap());
+        let ones = distribution(&parse("d6!!=1")?).unwrap();
+        assert_eq!(0.0, ones.probability(1));
+        // a 3, or a 1 and then a 2
+        assert_close(7.0 / 36.0, ones.probability(3));
+        // the threshold is rolled too
+        let exp = parse("2d6!>(d4+2)")?;
+        assert_close(
+            distribution(&exp).unwrap().mean(),
+            expected_value(&exp).unwrap(),
+        );
+        // there's no telling which of a pool that grows are kept
+        assert!(distribution(&parse("4d6!k3")?).is_none());
+        assert_eq!(Range { min: 2, max: 1212 }, range(&parse("2d6!")?));
+        assert_eq!(102, max_dice(&parse("d6!>(d4)")?));
+        Ok(())
+    }
+
+    #[test]
+    fn rerolls() -> Result<(), String> {
+        // a d6 that's rerolled on 1s and 2s all but never shows them
+        assert_close(4.5, expected_value(&parse("d6r<3")?).unwrap());
+        let ones = distribution(&parse("d4r1")?).unwrap();
+        assert_close(1.0 / 3.0, ones.probability(2));
+        assert!(ones.probability(1) < NEGLIGIBLE);
+        // when every face is rerolled, the last one has to stay
+        assert_close(3.5, expected_value(&parse("d6r>=1")?).unwrap());
+        let exp = parse("4d6r(d2)k3")?;
+        assert_close(
+            distribution(&exp).unwrap().mean(),
+            expected_value(&exp).unwrap(),
+        );
+        assert!(crits(&parse("d20r1")?).is_err());
+        assert_eq!(201, max_dice(&parse("d6r1!")?));
+        Ok(())
+    }
+
+    #[test]
+    fn dropping_dice() -> Result<(), String> {
+        // dropping the lowest of four is keeping the highest three
+        assert_eq!(
+            distribution(&parse("4d6k3")?),
+            distribution(&parse("4d6dl1")?)
+        );
+        assert_close(
+            expected_value(&parse("4d6kl3")?).unwrap(),
+            expected_value(&parse("4d6dh1")?).unwrap(),
+        );
+        assert_eq!(Range { min: 0, max: 18 }, range(&parse("(1d4)d6dl(1d2)")?));
+        let exp = parse("(1d4)d6dh1")?;
+        assert_close(
+            distribution(&exp).unwrap().mean(),
+            expected_value(&exp).unwrap(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn distribution_of_two_dice() -> Result<(), String> {
+        let distribution = distribution(&parse("2d6")?).unwrap();
+        assert_close(1.0 / 36.0, distribution.probability(2));
+        assert_close(6.0 / 36.0, distribution.probability(7));
+        assert_close(0.0, distribution.probability(13));
+        assert_close(7.0, distribution.mean());
+        Ok(())
+    }
+
+    #[test]
+    fn distribution_of_keep_matches_expected_value() -> Result<(), String> {
+        for input in ["4d6k3", "3d8kl2", "(d3)d6k(d2)", "(d4)d6khhalf"] {
+            let exp = parse(input)?;
+            let distribution = distribution(&exp).unwrap();
+            let total: f64 = distribution.iter().map(|(_, p)| p).sum();
+            assert_close(1.0, total);
+            assert_close(expected_value(&exp).unwrap(), distribution.mean());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn distribution_of_repetitions() -> Result<(), String> {
+        // the best of two d4s is 4 unless neither is
+        let best = distribution(&parse("best 1 of 2x(d4)")?).unwrap();
+        assert_close(7.0 / 16.0, best.probability(4));
+        assert_close(1.0 / 16.0, best.probability(1));
+        // keeping every total is only a sum
+        let all = distribution(&parse("worst 3 of 3x(2d6)")?).unwrap();
+        let sum = distribution(&parse("6d6")?).unwrap();
+        assert_close(sum.probability(21), all.probability(21));
+        assert_close(sum.variance(), all.variance());
+        let worst = parse("worst 2 of 3x(d6)")?;
+        assert_close(
+            distribution(&parse("3d6kl2")?).unwrap().mean(),
+            expected_value(&worst).unwrap(),
+        );
+        assert_eq!(Range { min: 2, max: 12 }, range(&worst));
+        assert_eq!(3, max_dice(&worst));
+        Ok(())
+    }
+
+    #[test]
+    fn table_of_two_dice() -> Result<(), String> {
+        let table = table(&parse("2d6")?)?;
+        assert_eq!(11, table.rows.len());
+        let seven = &table.rows[5];
+        assert_eq!(7, seven.outcome);
+        assert_close(6.0 / 36.0, seven.probability);
+        assert_close(21.0 / 36.0, seven.at_least);
+        assert_close(1.0, table.rows[0].at_least);
+        assert!(table.csv().starts_with("outcome,probability,at_least\n2,"));
+        assert!(table.report().lines().nth(6).unwrap().contains("16.6667"));
+        let at_least = table.at_least();
+        assert!(at_least.starts_with(" 2+ 100.0000% ########################################\n"));
+        assert!(at_least.ends_with("12+   2.7778% #\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn odds_against_targets() -> Result<(), String> {
+        let d20 = odds(&parse("d20 + 7")?, 5, 30, false)?;
+        assert_eq!(26, d20.rows.len());
+        assert_eq!((5, 1.0), d20.rows[0]);
+        // a 15 needs an 8 or better
+        assert_close(0.65, d20.rows[10].1);
+        assert_eq!((30, 0.0), d20.rows[25]);
+        let report = d20.report("DC");
+        assert!(report.starts_with("DC   chance\n 5  100.00% #"));
+        assert!(report.contains("\n15   65.00% ##########################\n"));
+
+        let under = odds(&parse("d100")?, 50, 50, true)?;
+        assert_close(0.5, under.rows[0].1);
+        assert!(odds(&parse("d6")?, 6, 1, false).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn crit_chances() -> Result<(), String> {
+        let d20 = crits(&parse("d20")?)?;
+        assert_close(0.05, d20.highest);
+        assert_close(0.05, d20.lowest);
+        let advantage = crits(&parse("2d20k1")?)?;
+        assert_close(1.0 - 0.95 * 0.95, advantage.highest);
+        assert_close(1.0 / 400.0, advantage.lowest);
+        let disadvantage = crits(&parse("2d20kl1")?)?;
+        assert_close(advantage.highest, disadvantage.lowest);
+        assert_close(advantage.lowest, disadvantage.highest);
+        assert_close(advantage.highest, crits(&parse("d20 + d20")?)?.highest);
+        // a kept die shows a 1 only when at least two of the four do
+        let p = 1.0 - (5.0f64 / 6.0).powi(4) - 4.0 * (5.0f64 / 6.0).powi(3) / 6.0;
+        assert_close(p, crits(&parse("4d6k3")?)?.lowest);
+        assert_close(0.0, crits(&parse("3 + 4")?)?.highest);
+        assert_close(1.0, crits(&parse("2d1")?)?.highest);
+        // only the dice of the total that's kept count
+        let best = crits(&parse("best 1 of 2x(d20)")?)?;
+        assert_close(advantage.highest, best.highest);
+        assert_close(advantage.lowest, best.lowest);
+        Ok(())
+    }
+
+    #[test]
+    fn comparison() -> Result<(), String> {
+        let comparison = compare(&parse("2d6 + 3")?, &parse("1d12 + 3")?)?;
+        assert_close(10.0, comparison.means.0);
+        assert_close(9.5, comparison.means.1);
+        assert_close(35.0 / 6.0, comparison.variances.0);
+        assert_close(143.0 / 12.0, comparison.variances.1);
+        // each face of the d12 ties with the 2d6 at that chance over 12
+        assert_close(1.0 / 12.0, comparison.tie);
+        let total: f64 = comparison.outcomes.iter().map(|(_, pa, _)| pa).sum();
+        assert_close(1.0, total);
+
+        let same = compare(&parse("d6")?, &parse("d6")?)?;
+        assert_close(1.0, same.overlap);
+        assert_close(15.0 / 36.0, same.first_wins);
+        Ok(())
+    }
+}
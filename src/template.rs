@@ -0,0 +1,124 @@
+//! User-supplied output templates like `{expr} => {total} ({min}-{max})`, for
+//! status bars, stream overlays, and logs that want exactly one line per roll
+
+use itertools::Itertools;
+use std::time::SystemTime;
+
+use crate::eval::Value;
+use crate::render;
+use crate::stats;
+
+pub const PLACEHOLDERS: &[&str] = &[
+    "expr",
+    "total",
+    "min",
+    "max",
+    "avg",
+    "dice",
+    "seed",
+    "timestamp",
+];
+
+/// Replaces every `{placeholder}` in the template. Braces can be written
+/// literally by doubling them up, as in `{{` and `}}`.
+pub fn fill(template: &str, value: &Value, seed: u64) -> Result<String, String> {
+    let mut output = String::new();
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.as_str().starts_with('{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.as_str().starts_with('}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let rest = chars.as_str();
+                let end = rest
+                    .find('}')
+                    .ok_or("The template has a '{' that is never closed")?;
+                output.push_str(&field(&rest[..end], value, seed)?);
+                chars = rest[end + 1..].chars();
+            }
+            c => output.push(c),
+        }
+    }
+    Ok(output)
+}
+
+fn field(name: &str, value: &Value, seed: u64) -> Result<String, String> {
+    let field = match name {
+        "expr" => value.to_string(),
+        "total" => value.value().to_string(),
+        "min" => stats::range(&value.expression()).min.to_string(),
+        "max" => stats::range(&value.expression()).max.to_string(),
+        "avg" => stats::expected_value(&value.expression())
+            .map(render::format_average)
+            .unwrap_or_else(|| "?".to_string()),
+        "dice" => value.faces().iter().join(", "),
+        "seed" => seed.to_string(),
+        "timestamp" => humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        _ => {
+            return Err(format!(
+                "{{{name}}} is not a template placeholder; try one of {}",
+                PLACEHOLDERS.iter().map(|p| format!("{{{p}}}")).join(", ")
+            ))
+        }
+    };
+    Ok(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::{Kept, KeptRule, Operation, Rolled, Value};
+    use crate::template::fill;
+
+    fn attack() -> Value {
+        Value::Op {
+            op: Operation::Add,
+            values: vec![
+                Value::Rolled(Rolled {
+                    dice: Box::new(Value::Const(1)),
+                    sides: Box::new(Value::Const(20)),
+                    kept: Box::new(Kept {
+                        keep: KeptRule::All,
+                        retained: Value::Const(1),
+                        lowest: vec![],
+                        highest: vec![17],
+                        ids: vec![0],
+                        summary: None,
+                    }),
+                    exploded: None,
+                    rerolled: None,
+                }),
+                Value::Const(5),
+            ],
+        }
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            "1d20 + 5 => 22 (6-25) [17] #7",
+            fill(
+                "{expr} => {total} ({min}-{max}) [{dice}] #{seed}",
+                &attack(),
+                7
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn escaped_braces() {
+        assert_eq!("{22}", fill("{{{total}}}", &attack(), 0).unwrap());
+    }
+
+    #[test]
+    fn bad_templates() {
+        assert!(fill("{nope}", &attack(), 0).is_err());
+        assert!(fill("{total", &attack(), 0).is_err());
+    }
+}
@@ -1,13 +1,73 @@
-use crate::eval::{Exp, Operation};
+//! Splits an expression into tokens for the parser.
+
+use crate::eval::{Comparison, Exp, Explode, Int, Operation};
+use crate::messages::Message;
 use std::{iter::Peekable, str::Chars};
 
+/// The characters of the input, which keeps count of how many of them have
+/// been read. One that's only been peeked at hasn't been read yet.
+pub struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    read: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            read: 0,
+        }
+    }
+
+    pub fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    pub fn next_if(&mut self, func: impl FnOnce(&char) -> bool) -> Option<char> {
+        let next = self.chars.next_if(func);
+        self.read += next.is_some() as usize;
+        next
+    }
+
+    pub fn next_if_eq(&mut self, expected: &char) -> Option<char> {
+        self.next_if(|c| c == expected)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let next = self.chars.next();
+        self.read += next.is_some() as usize;
+        next
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
-    Number(i32),
+    Number(Int),
     Operation(Operation),
     Die,
     KeepHighest,
     KeepLowest,
+    /// `dh` and `dl`, which drop dice instead of keeping them
+    DropHighest,
+    DropLowest,
+    /// `!` or `!!`, after the dice that explode
+    Explode(Explode),
+    /// `r`, after the dice that are rerolled
+    Reroll,
+    /// What an explosion's or a reroll's dice are compared to, as in `!>=8`
+    Compare(Comparison),
+    /// A keep count that's a percentage of the pool, like `50%` or `half`
+    Share(u32),
+    /// `best` and `worst`, as in `best 1 of 3x(4d6k3)`
+    Best,
+    Worst,
+    Of,
+    /// The `x` in `3x(4d6k3)`
+    Times,
     OpenParen,
     CloseParen,
     Expression(Exp),
@@ -18,8 +78,14 @@ impl Token {
     pub fn precedence(&self) -> u32 {
         match self {
             Token::Operation(op) => op.precedence(),
+            Token::Times => 3,
             Token::Die => 10,
-            Token::KeepHighest | Token::KeepLowest => 20,
+            Token::KeepHighest
+            | Token::KeepLowest
+            | Token::DropHighest
+            | Token::DropLowest
+            | Token::Explode(_)
+            | Token::Reroll => 20,
             _ => 0,
         }
     }
@@ -32,29 +98,70 @@ impl Token {
 /// stream. This means that we never have to store all of the tokens in memory,
 /// and can jump immediately into building the abstract syntax tree.
 pub struct Tokenizer<'a> {
-    chars: Peekable<Chars<'a>>,
+    chars: Cursor<'a>,
+    /// Where the last token started, past any whitespace before it
+    start: usize,
     has_passed_eof: bool,
+    /// Whether the last token was something with a value, in which case a
+    /// minus sign is subtraction rather than part of a negative number
+    after_operand: bool,
+    /// Whether an `of` has been read without the `x` that goes with it, which
+    /// is the only place an `x` belongs
+    repeating: bool,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Self {
-        let chars = input.chars().peekable();
         Self {
-            chars,
+            chars: Cursor::new(input),
+            start: 0,
             has_passed_eof: false,
+            after_operand: false,
+            repeating: false,
         }
     }
 }
 
+impl Tokenizer<'_> {
+    /// How many characters have been read so far. After an error, the last of
+    /// them is the one that caused it.
+    pub fn position(&self) -> usize {
+        self.chars.read
+    }
+
+    /// Where the last token started, which is after any whitespace that came
+    /// before it
+    pub fn start(&self) -> usize {
+        self.start
+    }
+}
+
 impl Iterator for Tokenizer<'_> {
-    type Item = Result<Token, String>;
+    type Item = Result<Token, Message>;
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.chars.peek().is_some() {
-            return Some(Self::next_token(&mut self.chars));
+            while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+            self.start = self.chars.read;
+            let token = Self::next_token(&mut self.chars, self.after_operand, self.repeating);
+            self.after_operand = matches!(
+                token,
+                Ok(Token::Number(_)
+                    | Token::Share(_)
+                    | Token::Explode(_)
+                    | Token::CloseParen
+                    | Token::Expression(_))
+            );
+            match token {
+                Ok(Token::Of) => self.repeating = true,
+                Ok(Token::Times) => self.repeating = false,
+                _ => {}
+            }
+            return Some(token);
         }
         if !self.has_passed_eof {
             self.has_passed_eof = true;
+            self.start = self.chars.read;
             return Some(Ok(Token::EndOfStream));
         }
         None
@@ -62,7 +169,11 @@ impl Iterator for Tokenizer<'_> {
 }
 
 impl Tokenizer<'_> {
-    pub fn next_token(chars: &mut Peekable<impl Iterator<Item = char>>) -> Result<Token, String> {
+    pub fn next_token(
+        chars: &mut Cursor,
+        after_operand: bool,
+        repeating: bool,
+    ) -> Result<Token, Message> {
         while let Some(c) = chars.next() {
             if c.is_whitespace() {
                 continue;
@@ -76,11 +187,35 @@ impl Tokenizer<'_> {
                 }
                 digit @ '0'..='9' => {
                     let number = Self::parse_number(digit, chars)?;
+                    if chars.next_if_eq(&'%').is_some() {
+                        let percent = u32::try_from(number).map_err(|_| Message::Unparsable)?;
+                        return Ok(Token::Share(percent));
+                    }
                     return Ok(Token::Number(number));
                 }
+                // as in `khhalf`, the same as `50%`
+                'h' => {
+                    Self::rest_of_word("alf", chars)?;
+                    return Ok(Token::Share(50));
+                }
+                'b' => {
+                    Self::rest_of_word("est", chars)?;
+                    return Ok(Token::Best);
+                }
+                'w' => {
+                    Self::rest_of_word("orst", chars)?;
+                    return Ok(Token::Worst);
+                }
+                'o' => {
+                    Self::rest_of_word("f", chars)?;
+                    return Ok(Token::Of);
+                }
+                'x' if repeating => {
+                    return Ok(Token::Times);
+                }
                 '-' => {
                     // parse the actual number
-                    if chars.peek().map(char::is_ascii_digit).unwrap_or(false) {
+                    if !after_operand && chars.peek().map(char::is_ascii_digit).unwrap_or(false) {
                         let first = chars.next().unwrap();
                         let number = Self::parse_number(first, chars)?;
                         return Ok(Token::Number(-1 * number));
@@ -93,8 +228,38 @@ impl Tokenizer<'_> {
                 '*' => {
                     return Ok(Token::Operation(Operation::Mul));
                 }
+                // a `d` is a die unless it's the start of a drop, as in the
+                // `dl` of `4d6dl1`
                 'd' => {
-                    return Ok(Token::Die);
+                    return match chars.next_if(|c| matches!(c, 'h' | 'l')) {
+                        Some('h') => Ok(Token::DropHighest),
+                        Some(_) => Ok(Token::DropLowest),
+                        None => Ok(Token::Die),
+                    };
+                }
+                '!' => {
+                    return match chars.next_if_eq(&'!') {
+                        Some(_) => Ok(Token::Explode(Explode::Compound)),
+                        None => Ok(Token::Explode(Explode::Extra)),
+                    };
+                }
+                'r' => {
+                    return Ok(Token::Reroll);
+                }
+                '=' => {
+                    return Ok(Token::Compare(Comparison::Equal));
+                }
+                '<' => {
+                    return match chars.next_if_eq(&'=') {
+                        Some(_) => Ok(Token::Compare(Comparison::AtMost)),
+                        None => Ok(Token::Compare(Comparison::Less)),
+                    };
+                }
+                '>' => {
+                    return match chars.next_if_eq(&'=') {
+                        Some(_) => Ok(Token::Compare(Comparison::AtLeast)),
+                        None => Ok(Token::Compare(Comparison::Greater)),
+                    };
                 }
                 'k' => {
                     // figure out which expression is next; we can even allow
@@ -110,27 +275,34 @@ impl Tokenizer<'_> {
                             chars.next();
                             Ok(Token::KeepLowest)
                         }
-                        Some(c) => Err(format!(
-                            "Encountered unexpected symbol '{c}' while tokenizing input"
-                        )),
-                        None => Err(
-                            "Character stream completed before token was fully assembled".into(),
-                        ),
+                        Some(&c) => {
+                            chars.next();
+                            Err(Message::UnexpectedSymbol(c))
+                        }
+                        None => Err(Message::Incomplete),
                     };
                 }
                 _ => {
-                    let msg = format!("Encountered unexpected symbol '{c}' while tokenizing input");
-                    return Err(msg);
+                    return Err(Message::UnexpectedSymbol(c));
                 }
             }
         }
-        Err("Character stream completed before token was fully assembled".into())
+        Err(Message::Incomplete)
+    }
+
+    /// Reads the letters of a word that come after its first one
+    fn rest_of_word(rest: &str, chars: &mut Cursor) -> Result<(), Message> {
+        for expected in rest.chars() {
+            match chars.next() {
+                Some(c) if c == expected => {}
+                Some(c) => return Err(Message::UnexpectedSymbol(c)),
+                None => return Err(Message::Incomplete),
+            }
+        }
+        Ok(())
     }
 
-    fn parse_number(
-        first: char,
-        remaining: &mut Peekable<impl Iterator<Item = char>>,
-    ) -> Result<i32, String> {
+    fn parse_number(first: char, remaining: &mut Cursor) -> Result<Int, Message> {
         // corral digits
         let mut digit_buffer = vec![first];
         while let Some(c) = remaining.peek() {
@@ -141,14 +313,11 @@ impl Tokenizer<'_> {
                 break;
             }
         }
-        let value: i32 = digit_buffer
-            .iter()
-            .rev()
-            .map(|c| c.to_digit(10).expect("digit is guaranteed numeric") as i32)
-            .enumerate()
-            .map(|(i, digit)| digit * 10i32.pow(i as u32))
-            .sum();
-
-        return Ok(value);
+        let value = digit_buffer.iter().try_fold(0 as Int, |value, c| {
+            let digit = c.to_digit(10).expect("digit is guaranteed numeric") as Int;
+            value.checked_mul(10)?.checked_add(digit)
+        });
+
+        return value.ok_or_else(|| Message::NumberTooBig(digit_buffer.iter().collect()));
     }
 }
@@ -0,0 +1,139 @@
+//! Transcripts of everything rolled, for settling disputes and for editing
+//! recordings of actual play. Each roll is made with a seed of its own, so
+//! `rdr replay` can bring back exactly the same dice for any of them, even
+//! after other entries have been cut from the file.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::eval::{Exp, Int, Limits, Value};
+use crate::parse::parse;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Transcript {
+    #[serde(skip)]
+    path: PathBuf,
+    #[serde(default)]
+    pub rolls: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub expression: String,
+    pub seed: u64,
+    /// What the roll came to in the end, after any rerolls
+    pub total: Int,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rerolls: Vec<Reroll>,
+}
+
+/// Dice rerolled after the fact (by their position in
+/// [`Value::faces`]), with a seed of their own
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reroll {
+    pub dice: Vec<usize>,
+    pub seed: u64,
+}
+
+impl Transcript {
+    /// Opens the transcript at the path, which is started afresh if it
+    /// doesn't exist yet. New rolls are added to the end.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let mut transcript = match path.exists() {
+            true => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+                serde_json::from_str(&contents)
+                    .map_err(|e| format!("Could not parse {}: {e}", path.display()))?
+            }
+            false => Transcript::default(),
+        };
+        transcript.path = path.to_path_buf();
+        Ok(transcript)
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let contents = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, contents + "\n")
+            .map_err(|e| format!("Could not write {}: {e}", self.path.display()))
+    }
+
+    /// Rolls the expression with a seed drawn from `rng`, and writes it down
+    pub fn roll(&mut self, exp: &Exp, rng: &mut StdRng, limits: &Limits) -> Result<Value, Error> {
+        let seed = rng.gen();
+        let evaluated = exp.evaluate_within(&mut StdRng::seed_from_u64(seed), limits)?;
+        self.rolls.push(Entry {
+            expression: evaluated.to_string(),
+            seed,
+            total: evaluated.value(),
+            rerolls: Vec::new(),
+        });
+        Ok(evaluated)
+    }
+
+    /// Rerolls the chosen dice of the last roll with a seed drawn from `rng`,
+    /// and writes that down along with the roll's new total
+    pub fn reroll(&mut self, value: &mut Value, chosen: &[usize], rng: &mut StdRng) {
+        let seed = rng.gen();
+        value.reroll(chosen, &mut StdRng::seed_from_u64(seed));
+        if let Some(entry) = self.rolls.last_mut() {
+            entry.rerolls.push(Reroll {
+                dice: chosen.to_vec(),
+                seed,
+            });
+            entry.total = value.value();
+        }
+    }
+}
+
+impl Entry {
+    /// Rolls the entry's expression again with the same seed, and rerolls the
+    /// same dice with theirs, which gives the same dice as the first time
+    pub fn replay(&self, limits: &Limits) -> Result<Value, Error> {
+        let mut evaluated = parse(&self.expression)?
+            .evaluate_within(&mut StdRng::seed_from_u64(self.seed), limits)?;
+        for reroll in &self.rerolls {
+            evaluated.reroll(&reroll.dice, &mut StdRng::seed_from_u64(reroll.seed));
+        }
+        Ok(evaluated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transcript::*;
+
+    #[test]
+    fn replaying() {
+        let mut transcript = Transcript::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        for expression in ["4d6k3", "(d4)d6 + 2", "2d20kl1 - 1"] {
+            let exp = parse(expression).unwrap();
+            transcript.roll(&exp, &mut rng, &Limits::default()).unwrap();
+        }
+        for entry in &transcript.rolls {
+            let replayed = entry.replay(&Limits::default()).unwrap();
+            assert_eq!(entry.total, replayed.value());
+            assert_eq!(entry.expression, replayed.to_string());
+        }
+    }
+
+    #[test]
+    fn replaying_rerolls() {
+        let mut transcript = Transcript::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        let exp = parse("4d6k3").unwrap();
+        let mut value = transcript.roll(&exp, &mut rng, &Limits::default()).unwrap();
+        transcript.reroll(&mut value, &[0, 2], &mut rng);
+        transcript.reroll(&mut value, &[1], &mut rng);
+        let entry = &transcript.rolls[0];
+        assert_eq!(value.value(), entry.total);
+        assert_eq!(2, entry.rerolls.len());
+        let replayed = entry.replay(&Limits::default()).unwrap();
+        assert_eq!(value.faces(), replayed.faces());
+    }
+}
@@ -0,0 +1,142 @@
+//! Variables are written `@name` inside an expression and given values on the
+//! command line, so that one alias like `d20+@prof+@str` can serve every
+//! character at the table.
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Default)]
+pub struct Variables {
+    values: BTreeMap<String, String>,
+}
+
+impl Variables {
+    /// Records an assignment written as `name=value`. The value may be any
+    /// expression, not just a number.
+    pub fn assign(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = parse_assignment(assignment)?;
+        self.values.insert(name, value);
+        Ok(())
+    }
+
+    /// Like [`Variables::assign`], but leaves a variable alone if it already
+    /// has a value. This lets values from the command line take precedence.
+    pub fn assign_default(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, value) = parse_assignment(assignment)?;
+        self.values.entry(name).or_insert(value);
+        Ok(())
+    }
+
+    /// Fills every variable with a zero, which is enough to check that an
+    /// expression will parse once its variables are known
+    pub fn placeholders(input: &str) -> Result<String, String> {
+        let mut names = Variables::default();
+        let mut chars = input.chars().peekable();
+        while chars.find(|c| *c == '@').is_some() {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            if !name.is_empty() {
+                names.values.insert(name, "0".to_string());
+            }
+        }
+        names.substitute(input)
+    }
+
+    /// Replaces every `@name` in the input with its (parenthesized) value
+    pub fn substitute(&self, input: &str) -> Result<String, String> {
+        let mut output = String::new();
+        let mut chars = input.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '@' {
+                output.push(c);
+                continue;
+            }
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            match self.values.get(&name) {
+                Some(value) => output.push_str(&format!("({value})")),
+                None if name.is_empty() => return Err("'@' must be followed by a name".to_string()),
+                None => {
+                    return Err(format!(
+                        "@{name} has no value; pass one with --var {name}=..."
+                    ))
+                }
+            }
+        }
+        Ok(output)
+    }
+}
+
+fn parse_assignment(assignment: &str) -> Result<(String, String), String> {
+    let (name, value) = assignment
+        .split_once('=')
+        .ok_or(format!("'{assignment}' should look like name=value"))?;
+    let name = name.trim().trim_start_matches('@');
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!(
+            "'{name}' is not a valid variable name; use letters, digits, and underscores"
+        ));
+    }
+    if value.trim().is_empty() {
+        return Err(format!("No value was given for '{name}'"));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vars::Variables;
+
+    fn variables() -> Variables {
+        let mut variables = Variables::default();
+        variables.assign("prof=3").unwrap();
+        variables.assign("@str = 2").unwrap();
+        variables.assign("sneak=3d6").unwrap();
+        variables
+    }
+
+    #[test]
+    fn substitution() {
+        assert_eq!(
+            "d20+(3)+(2)",
+            variables().substitute("d20+@prof+@str").unwrap()
+        );
+        assert_eq!("d6 + (3d6)", variables().substitute("d6 + @sneak").unwrap());
+    }
+
+    #[test]
+    fn missing_variables() {
+        assert!(variables().substitute("d20 + @dex").is_err());
+        assert!(variables().substitute("d20 + @").is_err());
+    }
+
+    #[test]
+    fn defaults_do_not_overwrite() {
+        let mut variables = variables();
+        variables.assign_default("prof=5").unwrap();
+        variables.assign_default("dex=1").unwrap();
+        assert_eq!("(3)+(1)", variables.substitute("@prof+@dex").unwrap());
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            "d20+(0)+(0)",
+            Variables::placeholders("d20+@prof+@str").unwrap()
+        );
+    }
+
+    #[test]
+    fn bad_assignments() {
+        let mut variables = Variables::default();
+        for assignment in ["prof", "=3", "prof=", "a b=1"] {
+            assert!(
+                variables.assign(assignment).is_err(),
+                "{assignment} was accepted"
+            );
+        }
+    }
+}
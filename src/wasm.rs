@@ -0,0 +1,527 @@
+//! The bindings for the web page, built with the `wasm` feature. Everything
+//! here takes the expression as a string and hands back either a string or a
+//! plain JavaScript object. Whatever rolls dice also takes an optional
+//! `RollOptions` object last, with the limits to roll within; anything left
+//! out of it gets the defaults below.
+
+use rand::rngs::ThreadRng;
+use wasm_bindgen::prelude::*;
+
+use crate::collection::Collection;
+use crate::dialect::{self, Dialect};
+use crate::document::{Document, Named};
+use crate::error::Error;
+use crate::eval::Limits;
+use crate::formats;
+use crate::messages::{self, Locale};
+use crate::parse::{parse, parse_mapped, parse_named};
+use crate::render::{self, RenderOptions};
+use crate::simulate::{self, Simulation};
+use serde::{Deserialize, Serialize};
+
+/// The web page has no use for megabytes of dice results, so we trim the tree
+/// down before handing it back across the wasm boundary
+const MAX_OUTPUT: usize = 64 * 1024;
+
+/// How many dice a single roll may use, and how deeply it may nest, unless
+/// the call's options say otherwise
+const MAX_DICE: u64 = 100_000;
+const MAX_DEPTH: usize = 100;
+
+/// Enough trials for a smooth chart, without locking up the page
+const MAX_TRIALS: usize = 1_000_000;
+
+#[wasm_bindgen(typescript_custom_section)]
+const TYPES: &'static str = r#"
+/** What `evaluate` returns: the whole roll, ready to be presented */
+export interface RollDocument {
+    expression: string;
+    total: number;
+    breakdown: RollNode;
+}
+
+export type RollNode =
+    | { kind: "const"; value: number; span: Span }
+    | {
+          kind: "roll";
+          /** numbers the rolls in the order their dice were rolled */
+          id: number;
+          expression: string;
+          result: number;
+          dice: RollNode;
+          sides: RollNode;
+          /** `count` is how many are dropped for the drop rules, and kept otherwise */
+          keep: {
+              rule: "all" | "lowest" | "highest" | "drop_lowest" | "drop_highest";
+              count: RollNode;
+              /** the percentage of the pool kept, for rules like `kh50%` */
+              share?: number;
+          };
+          kept: number[];
+          dropped: number[];
+          /** which die each of `kept` and `dropped` is, stable through rerolls */
+          kept_ids: number[];
+          dropped_ids: number[];
+          /** how many dice showed each face, for rolls too big to list */
+          summary?: { kept: Record<string, number>; dropped: Record<string, number> };
+          /** for exploding dice, every face each compounding die that exploded landed on, by its id */
+          explode?: {
+              rule: "extra" | "compound";
+              /** which faces explode, when it's more than the highest one */
+              comparison?: "=" | "<" | "<=" | ">" | ">=";
+              threshold?: RollNode;
+              chains: Record<string, number[]>;
+              capped: boolean;
+          };
+          /** for rerolled dice, every face each die was rerolled from, by its id */
+          reroll?: {
+              comparison: "=" | "<" | "<=" | ">" | ">=";
+              threshold: RollNode;
+              discarded: Record<string, number[]>;
+              capped: boolean;
+          };
+          span: Span;
+      }
+    | {
+          kind: "op";
+          expression: string;
+          result: number;
+          operation: "add" | "sub" | "mul";
+          terms: RollNode[];
+          span: Span;
+      }
+    | {
+          kind: "repeat";
+          expression: string;
+          result: number;
+          pick: "best" | "worst";
+          keep: number;
+          /** every time the expression was rolled */
+          repetitions: RollNode[];
+          /** which of `repetitions` count towards the result */
+          counted: boolean[];
+          span: Span;
+      };
+
+/**
+ * The characters of the input a node was written with, counted in code points
+ * from `start` up to but not including `end`. A roll written without a count,
+ * like `d20`, has an empty span for its count.
+ */
+export interface Span {
+    start: number;
+    end: number;
+}
+
+/** What `simulate` returns */
+export interface Simulation {
+    samples: number;
+    mean: number;
+    std_dev: number;
+    /** the true mean and standard deviation, or null if they're too costly to work out */
+    exact_mean: number | null;
+    exact_std_dev: number | null;
+    median: number;
+    /** pairs of [percentile, result] */
+    percentiles: [number, number][];
+    /** rows of [lowest, highest, count], grouped when there are many results */
+    histogram: [number, number, number][];
+    /** how many times each result came up, keyed by the result */
+    counts: Record<string, number>;
+}
+
+/**
+ * Safety limits for a single call, passed as the last argument of the
+ * functions that roll. Anything left out gets its default.
+ */
+export interface RollOptions {
+    /** the most dice one roll may use (100,000) */
+    max_dice?: number;
+    /** how deeply rolls and operations may nest (100) */
+    max_depth?: number;
+    /** the longest tree `evaluate_and_draw` draws, in bytes, before it's cut short (65,536) */
+    max_output?: number;
+}
+
+export type Validation = { ok: true } | { ok: false; message: string; position: number };
+
+/** Thrown by every function that can fail */
+export interface RollError {
+    kind: "syntax" | "limit" | "input" | "render";
+    /** the message's name in the catalog, the same in every language, like
+     * "unexpected_symbol" or "too_many_dice" ("other" for the rest) */
+    code?: string;
+    /** in the language picked with `set_locale` */
+    message: string;
+    /** which characters of the input to underline, `end` excluded */
+    span?: { start: number; end: number };
+}
+"#;
+
+/// Every function here fails with one of these: the `kind` of problem, its
+/// `code`, a `message` to show, and for syntax errors the `span` of
+/// characters to underline
+#[derive(Serialize)]
+struct ErrorObject {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span: Option<Span>,
+}
+
+/// Character offsets into the input, `end` excluded
+#[derive(Serialize)]
+struct Span {
+    start: usize,
+    end: usize,
+}
+
+impl ErrorObject {
+    /// The span to underline is one character long, or empty when the
+    /// problem is at the very end of the input
+    fn new(error: Error, input: &str) -> Self {
+        let length = input.chars().count();
+        let span = error.position().map(|position| Span {
+            start: position.min(length),
+            end: (position + 1).min(length),
+        });
+        ErrorObject {
+            kind: error.kind(),
+            code: error.code(),
+            message: error.to_string(),
+            span,
+        }
+    }
+}
+
+/// Converts a failure to roll or render into the object that gets thrown
+fn thrown(input: &str) -> impl Fn(Error) -> JsValue + '_ {
+    move |error| {
+        let error = ErrorObject::new(error, input);
+        serde_wasm_bindgen::to_value(&error).unwrap_or_else(|_| JsValue::from_str(&error.message))
+    }
+}
+
+/// The limits a call was given, each one optional, as a [`RollOptions`]
+/// object
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct Options {
+    max_dice: Option<u64>,
+    max_depth: Option<usize>,
+    max_output: Option<usize>,
+}
+
+impl Options {
+    /// Reads the options, which the caller may have left out altogether
+    fn read(options: Option<JsValue>) -> Result<Options, Error> {
+        match options {
+            Some(options) if !options.is_null() && !options.is_undefined() => {
+                serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| Error::Input(format!("Bad roll options: {e}").into()))
+            }
+            _ => Ok(Options::default()),
+        }
+    }
+
+    fn limits(&self) -> Limits {
+        Limits {
+            max_dice: self.max_dice.unwrap_or(MAX_DICE),
+            max_depth: self.max_depth.unwrap_or(MAX_DEPTH),
+            ..Default::default()
+        }
+    }
+
+    fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            max_output: Some(self.max_output.unwrap_or(MAX_OUTPUT)),
+            ..Default::default()
+        }
+    }
+}
+
+/// For the errors that come from handing things back across the boundary
+fn unrenderable(error: impl ToString) -> Error {
+    Error::Render(error.to_string())
+}
+
+#[wasm_bindgen]
+pub fn evaluate_and_draw(
+    input: &str,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = Options::read(options).map_err(thrown(input))?;
+    let evaluated = parse(input)
+        .and_then(|parsed| parsed.evaluate_within(&mut ThreadRng::default(), &options.limits()))
+        .map_err(thrown(input))?;
+    render::no_color(&evaluated, &options.render_options()).map_err(thrown(input))
+}
+
+/// Draws the tree as nested HTML lists, with classes on every roll and die so
+/// that the page can style them
+#[wasm_bindgen]
+pub fn evaluate_and_draw_html(
+    input: &str,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> Result<String, JsValue> {
+    let options = Options::read(options).map_err(thrown(input))?;
+    let evaluated = parse(input)
+        .and_then(|parsed| parsed.evaluate_within(&mut ThreadRng::default(), &options.limits()))
+        .map_err(thrown(input))?;
+    let renderer = formats::renderer("html").expect("the html renderer is registered");
+    (renderer.render)(&evaluated, &options.render_options()).map_err(thrown(input))
+}
+
+/// Like [`evaluate_and_draw`], but hands back the result as an object with the
+/// `expression`, the `total`, and a `breakdown` tree (including every die that
+/// was kept or dropped) so that the page can present it however it likes
+#[wasm_bindgen(unchecked_return_type = "RollDocument")]
+pub fn evaluate(
+    input: &str,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let limits = Options::read(options).map_err(thrown(input))?.limits();
+    let (parsed, map) = parse_mapped(input).map_err(thrown(input))?;
+    let evaluated = parsed
+        .evaluate_within(&mut ThreadRng::default(), &limits)
+        .map_err(thrown(input))?;
+    serde_wasm_bindgen::to_value(&Document::mapped(&evaluated, &map))
+        .map_err(|e| thrown(input)(unrenderable(e)))
+}
+
+/// Rolls several named expressions at once, like `hit = d20 + 7; dmg = 1d8 +
+/// 4`, handing back an object with each one's document under its name (or
+/// its position, counting from 1, if it has none)
+#[wasm_bindgen(unchecked_return_type = "Record<string, RollDocument>")]
+pub fn evaluate_named(
+    input: &str,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let limits = Options::read(options).map_err(thrown(input))?.limits();
+    let mut rng = ThreadRng::default();
+    let results = parse_named(input)
+        .map_err(thrown(input))?
+        .into_iter()
+        .map(|(name, exp)| {
+            Ok((
+                name,
+                Document::new(&exp.evaluate_within(&mut rng, &limits)?),
+            ))
+        })
+        .collect::<Result<_, Error>>()
+        .map_err(thrown(input))?;
+    serde_wasm_bindgen::to_value(&Named { results }).map_err(|e| thrown(input)(unrenderable(e)))
+}
+
+/// Like [`evaluate`], but calls `on_die(sides, value, node, id)` for each die
+/// as it's rolled, so that the page can animate the dice before showing the
+/// result. `node` matches the `id` of the roll node in the document, and `id`
+/// the die's entry in that node's `kept_ids` or `dropped_ids`. If the
+/// callback throws, the roll is abandoned and the exception is rethrown.
+#[wasm_bindgen(unchecked_return_type = "RollDocument")]
+pub fn evaluate_with_callback(
+    input: &str,
+    #[wasm_bindgen(
+        unchecked_param_type = "(sides: number, value: number, node: number, id: number) => void"
+    )]
+    on_die: &js_sys::Function,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let limits = Options::read(options).map_err(thrown(input))?.limits();
+    let (parsed, map) = parse_mapped(input).map_err(thrown(input))?;
+    let mut exception = None;
+    let evaluated = parsed
+        .evaluate_observed(&mut ThreadRng::default(), &limits, &mut |die| {
+            if exception.is_some() {
+                return;
+            }
+            let called = on_die.call4(
+                &JsValue::NULL,
+                &die.sides.into(),
+                &die.value.into(),
+                &(die.node as u32).into(),
+                &(die.id as u32).into(),
+            );
+            exception = called.err();
+        })
+        .map_err(thrown(input))?;
+    if let Some(exception) = exception {
+        return Err(exception);
+    }
+    serde_wasm_bindgen::to_value(&Document::mapped(&evaluated, &map))
+        .map_err(|e| thrown(input)(unrenderable(e)))
+}
+
+/// The same document as [`evaluate`], serialized to a JSON string, which can
+/// be passed through `postMessage` without any conversion. Errors come back
+/// as `{"error": {...}}`, with the same fields as the other functions' errors.
+#[wasm_bindgen]
+pub fn evaluate_json(
+    input: &str,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> String {
+    let fail = |error| serde_json::json!({ "error": ErrorObject::new(error, input) }).to_string();
+    let rolled = Options::read(options).and_then(|options| {
+        let (ast, map) = parse_mapped(input)?;
+        Ok((
+            ast.evaluate_within(&mut ThreadRng::default(), &options.limits())?,
+            map,
+        ))
+    });
+    let (evaluated, map) = match rolled {
+        Ok(rolled) => rolled,
+        Err(error) => return fail(error),
+    };
+    match serde_json::to_string(&Document::mapped(&evaluated, &map)) {
+        Ok(json) => json,
+        Err(e) => fail(e.into()),
+    }
+}
+
+/// Rolls the expression `trials` times, giving back the `counts` of each
+/// result along with the `mean`, `std_dev`, `median`, and `percentiles`
+#[wasm_bindgen(unchecked_return_type = "Simulation")]
+pub fn simulate(
+    input: &str,
+    trials: usize,
+    #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+) -> Result<JsValue, JsValue> {
+    let limits = Options::read(options).map_err(thrown(input))?.limits();
+    if trials > MAX_TRIALS {
+        let message =
+            format!("At most {MAX_TRIALS} trials can be run at once; use a Simulator for more");
+        return Err(thrown(input)(Error::Input(message.into())));
+    }
+    let parsed = parse(input).map_err(thrown(input))?;
+    let summary = simulate::simulate(&parsed, trials, &mut ThreadRng::default(), &limits)
+        .map_err(thrown(input))?;
+    // a plain object of counts is easier to work with than a Map
+    summary
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| thrown(input)(unrenderable(e)))
+}
+
+/// A simulation that JavaScript runs a chunk at a time, for when the number
+/// of trials is large enough that doing them all at once would freeze the
+/// page:
+///
+/// ```js
+/// const simulator = new Simulator("8d6", 1_000_000);
+/// while (!simulator.step(10_000)) {
+///     progress.value = simulator.progress;
+///     await new Promise(requestAnimationFrame);
+/// }
+/// chart(simulator.result());
+/// ```
+#[wasm_bindgen]
+pub struct Simulator {
+    input: String,
+    simulation: Simulation,
+    limits: Limits,
+}
+
+#[wasm_bindgen]
+impl Simulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(
+        input: &str,
+        trials: usize,
+        #[wasm_bindgen(unchecked_param_type = "RollOptions")] options: Option<JsValue>,
+    ) -> Result<Simulator, JsValue> {
+        let limits = Options::read(options).map_err(thrown(input))?.limits();
+        let parsed = parse(input).map_err(thrown(input))?;
+        Ok(Simulator {
+            input: input.to_string(),
+            simulation: Simulation::new(parsed, trials),
+            limits,
+        })
+    }
+
+    /// Rolls up to `chunk` more trials, returning true once every trial has
+    /// been rolled
+    pub fn step(&mut self, chunk: usize) -> Result<bool, JsValue> {
+        self.simulation
+            .run(chunk, &mut ThreadRng::default(), &self.limits)
+            .map_err(thrown(&self.input))
+    }
+
+    /// How far along the simulation is, from 0 to 1
+    #[wasm_bindgen(getter)]
+    pub fn progress(&self) -> f64 {
+        self.simulation.progress()
+    }
+
+    /// The statistics so far, in the same shape as [`simulate`] gives back
+    #[wasm_bindgen(unchecked_return_type = "Simulation")]
+    pub fn result(&self) -> Result<JsValue, JsValue> {
+        let summary = self.simulation.summary().map_err(thrown(&self.input))?;
+        summary
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| thrown(&self.input)(unrenderable(e)))
+    }
+}
+
+#[derive(Serialize)]
+struct Validation {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+}
+
+/// Checks that the input parses without rolling anything, which is cheap
+/// enough to do on every keystroke. Gives back `{ ok: true }`, or `ok: false`
+/// with a `message` and the (character) `position` of the problem.
+#[wasm_bindgen(unchecked_return_type = "Validation")]
+pub fn validate(input: &str) -> JsValue {
+    let validation = match parse(input) {
+        Ok(_) => Validation {
+            ok: true,
+            message: None,
+            position: None,
+        },
+        Err(e) => Validation {
+            ok: false,
+            position: e.position(),
+            message: Some(e.to_string()),
+        },
+    };
+    serde_wasm_bindgen::to_value(&validation).unwrap_or(JsValue::NULL)
+}
+
+/// Rewrites an expression from one dialect (`rdr`, `roll20`, or `foundry`)
+/// into another
+#[wasm_bindgen]
+pub fn translate(input: &str, from: &str, to: &str) -> Result<String, JsValue> {
+    let (from, to) = (Dialect::named(from), Dialect::named(to));
+    dialect::convert(
+        input,
+        from.map_err(thrown(input))?,
+        to.map_err(thrown(input))?,
+    )
+    .map_err(thrown(input))
+}
+
+/// Fills in the saved rolls and macros of a `.dice` collection, like
+/// `smite(2) + fireball`, leaving an expression that the other functions can
+/// roll. Only the untagged rolls and those tagged for the `system`, if one is
+/// given, are used.
+#[wasm_bindgen]
+pub fn expand_rolls(input: &str, rolls: &str, system: Option<String>) -> Result<String, JsValue> {
+    Collection::parse(rolls)
+        .map_err(thrown(rolls))?
+        .select(system.as_deref())
+        .expand(input)
+        .map_err(thrown(input))
+}
+
+/// Picks the language error messages are written in, from a tag like `es` or
+/// the browser's `navigator.language`. Languages we don't have fall back to
+/// English.
+#[wasm_bindgen]
+pub fn set_locale(tag: &str) {
+    messages::set_locale(Locale::named(tag).unwrap_or_default());
+}
@@ -0,0 +1,24 @@
+//! The command line tool, run the way a script runs it: with its output going
+//! to a pipe rather than a terminal
+
+use std::process::{Command, Output};
+
+fn rdr(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_recursive-dice-roller"))
+        .args(args)
+        .output()
+        .expect("the command line tool runs")
+}
+
+#[test]
+fn verdicts_when_piped() {
+    let passed = rdr(&["--seed", "1", "--dc", "1", "d20"]);
+    let stdout = String::from_utf8(passed.stdout).unwrap();
+    assert!(passed.status.success());
+    assert!(stdout.ends_with("SUCCESS (DC 1)\n"), "{stdout}");
+
+    let failed = rdr(&["--seed", "1", "--dc", "21", "d20"]);
+    let stdout = String::from_utf8(failed.stdout).unwrap();
+    assert!(!failed.status.success());
+    assert!(stdout.ends_with("FAILURE (DC 21)\n"), "{stdout}");
+}